@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Map, Value};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use url::Url;
+
+use crate::notifier::Severity;
+use crate::redact::redact;
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+// The same kind of alert is sent at most this often
+const REPEAT_AFTER: Duration = Duration::from_secs(15 * 60);
+// A scheduler that has not ticked for this long (or never, e.g. the gateway never connected) is dead
+const SCHEDULER_DEAD_AFTER: Duration = Duration::from_secs(5 * 60);
+const WATCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Failures worth waking someone up for, sent to `ALERT_WEBHOOK_URL`. Unlike Discord messages
+/// these still arrive when the Discord connection is what broke.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Alert {
+    CookieExpired,
+    DatabaseSaveFailed,
+    QueueCrashed,
+    SchedulerDead,
+    SchedulerRecovered,
+}
+
+impl Alert {
+    fn event(&self) -> &'static str {
+        match self {
+            Alert::CookieExpired => "cookie_expired",
+            Alert::DatabaseSaveFailed => "database_save_failed",
+            Alert::QueueCrashed => "queue_crashed",
+            Alert::SchedulerDead => "scheduler_dead",
+            Alert::SchedulerRecovered => "scheduler_recovered",
+        }
+    }
+
+    fn severity(&self) -> &'static str {
+        match self {
+            Alert::SchedulerRecovered => "info",
+            _ => "critical",
+        }
+    }
+}
+
+enum Outgoing {
+    Alert(Alert, Value),
+    // Run outcomes from the webhook notification sink; never throttled
+    Notification(Value),
+    Flush(oneshot::Sender<()>),
+}
+
+static SENDER: OnceLock<mpsc::UnboundedSender<Outgoing>> = OnceLock::new();
+
+/// Enables alerts when `ALERT_WEBHOOK_URL` is set. Must be called from inside the Tokio runtime.
+pub fn init() {
+    let url = match std::env::var("ALERT_WEBHOOK_URL").ok().filter(|u| !u.trim().is_empty()) {
+        Some(url) => url,
+        None => return,
+    };
+    let url = match Url::parse(url.trim()) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+        _ => {
+            log!("[WARN] Webhook alerts disabled: ALERT_WEBHOOK_URL is not an http(s) URL.");
+            return;
+        }
+    };
+    let (tx, rx) = mpsc::unbounded_channel();
+    if SENDER.set(tx).is_err() {
+        return;
+    }
+    tokio::spawn(send_loop(url, rx));
+    log!("[INFO] Webhook alerts enabled.");
+}
+
+/// Queues an alert. Does nothing unless `init` enabled alerts. Text is redacted before it is sent.
+pub fn send(alert: Alert, message: &str, details: &[(&str, String)]) {
+    let tx = match SENDER.get() {
+        Some(tx) => tx,
+        None => return,
+    };
+    let details: Map<String, Value> = details.iter()
+        .map(|(k, v)| (k.to_string(), Value::String(redact(v))))
+        .collect();
+    let payload = json!({
+        "event": alert.event(),
+        "severity": alert.severity(),
+        "message": redact(message),
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "source": "evertext-bot",
+        "version": env!("CARGO_PKG_VERSION"),
+        "details": details,
+    });
+    let _ = tx.send(Outgoing::Alert(alert, payload));
+}
+
+/// Queues a run outcome for the webhook sink of /set_notify. Does nothing unless `init` enabled
+/// alerts. Unlike `send`, every notification is delivered.
+pub fn notify(severity: Severity, message: &str) {
+    let tx = match SENDER.get() {
+        Some(tx) => tx,
+        None => return,
+    };
+    let payload = json!({
+        "event": format!("run_{}", severity.label()),
+        "severity": match severity {
+            Severity::Success => "info",
+            Severity::Failure => "warning",
+            Severity::Critical => "critical",
+        },
+        "message": redact(&message.replace("**", "")),
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "source": "evertext-bot",
+        "version": env!("CARGO_PKG_VERSION"),
+        "details": {},
+    });
+    let _ = tx.send(Outgoing::Notification(payload));
+}
+
+/// Waits (briefly) until alerts queued so far have been sent. Used before the process exits.
+pub async fn flush() {
+    let tx = match SENDER.get() {
+        Some(tx) => tx,
+        None => return,
+    };
+    let (done_tx, done_rx) = oneshot::channel();
+    if tx.send(Outgoing::Flush(done_tx)).is_ok() {
+        let _ = tokio::time::timeout(FLUSH_TIMEOUT, done_rx).await;
+    }
+}
+
+/// Watches the scheduler's heartbeat and alerts once when it stops (or never starts because
+/// Discord never connected), and once more when it comes back.
+pub fn watch_scheduler(last_tick: Arc<Mutex<Option<Instant>>>) {
+    if SENDER.get().is_none() {
+        return;
+    }
+    let started = Instant::now();
+    tokio::spawn(async move {
+        let mut dead = false;
+        let mut interval = tokio::time::interval(WATCH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let last = *last_tick.lock().await;
+            let silent_for = last.map(|t| t.elapsed()).unwrap_or_else(|| started.elapsed());
+            if silent_for > SCHEDULER_DEAD_AFTER && !dead {
+                dead = true;
+                let detail = match last {
+                    Some(_) => format!("last tick {}s ago", silent_for.as_secs()),
+                    None => format!("no tick in the {}s since startup; is the Discord gateway connected?", silent_for.as_secs()),
+                };
+                log!("[CRITICAL] Scheduler appears dead: {}", detail);
+                send(Alert::SchedulerDead, "The scheduler has stopped ticking; daily resets and schedules will not run.", &[("detail", detail)]);
+            } else if silent_for <= SCHEDULER_DEAD_AFTER && dead {
+                dead = false;
+                log!("[INFO] Scheduler is ticking again.");
+                send(Alert::SchedulerRecovered, "The scheduler is ticking again.", &[]);
+            }
+        }
+    });
+}
+
+async fn send_loop(url: Url, mut rx: mpsc::UnboundedReceiver<Outgoing>) {
+    let http = reqwest::Client::new();
+    let mut last_sent: HashMap<Alert, Instant> = HashMap::new();
+    while let Some(outgoing) = rx.recv().await {
+        let (alert, payload) = match outgoing {
+            Outgoing::Alert(alert, payload) => (Some(alert), payload),
+            Outgoing::Notification(payload) => (None, payload),
+            Outgoing::Flush(done) => {
+                let _ = done.send(());
+                continue;
+            }
+        };
+        if let Some(alert) = alert {
+            if last_sent.get(&alert).map(|t| t.elapsed() < REPEAT_AFTER).unwrap_or(false) {
+                continue;
+            }
+            last_sent.insert(alert, Instant::now());
+        }
+        let result = http.post(url.clone())
+            .timeout(SEND_TIMEOUT)
+            .json(&payload)
+            .send().await
+            .and_then(|r| r.error_for_status());
+        if let Err(e) = result {
+            let event = payload["event"].as_str().unwrap_or_default();
+            log!("[WARN] Failed to send webhook alert ({}): {}", event, e);
+        }
+    }
+}
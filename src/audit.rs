@@ -0,0 +1,79 @@
+use serenity::all::{CommandDataOption, CommandDataOptionValue};
+
+/// Commands that change shared state or control the queue. Every invocation is recorded,
+/// including ones rejected for missing permissions.
+const AUDITED_COMMANDS: &[&str] = &[
+    "set_cookies",
+    "set_admin_role",
+    "reset_admin_role",
+    "set_role_tier",
+    "set_log_channel",
+    "set_base_url",
+    "set_digest_day",
+    "set_timezone",
+    "set_language",
+    "maintenance",
+    "catch_up_first",
+    "preflight_halt",
+    "run_threads",
+    "set_notify",
+    "set_log_level",
+    "trace",
+    "mute_bot",
+    "unmute_bot",
+    "allow_channel",
+    "deny_channel",
+    "add_schedule",
+    "remove_schedule",
+    "force_run_all",
+    "debug_run",
+    "force_stop_all",
+    "pause_queue",
+    "resume_queue",
+    "queue_promote",
+    "queue_demote",
+    "remove_account",
+    "restore_account",
+    "transfer_account",
+    "claim_account",
+    "detach_account",
+    "purge_deleted",
+    "purge_done",
+    "backup_now",
+    "restore_backup",
+    "export_db",
+    "export_settings",
+    "import_settings",
+];
+
+// Option values never written to the audit log verbatim
+const SECRET_OPTIONS: &[&str] = &["cookie", "code", "passphrase"];
+
+pub fn is_audited(command: &str) -> bool {
+    AUDITED_COMMANDS.contains(&command)
+}
+
+/// Renders command options as `name=value` pairs, masking secrets.
+pub fn describe_options(options: &[CommandDataOption]) -> String {
+    options.iter()
+        .map(|o| {
+            let value = if SECRET_OPTIONS.contains(&o.name.as_str()) {
+                let len = o.value.as_str().map(|v| v.len()).unwrap_or(0);
+                format!("<redacted, {} chars>", len)
+            } else {
+                match &o.value {
+                    CommandDataOptionValue::String(v) => v.clone(),
+                    CommandDataOptionValue::Integer(v) => v.to_string(),
+                    CommandDataOptionValue::Boolean(v) => v.to_string(),
+                    CommandDataOptionValue::Number(v) => v.to_string(),
+                    CommandDataOptionValue::Channel(id) => format!("<#{}>", id),
+                    CommandDataOptionValue::Role(id) => format!("<@&{}>", id),
+                    CommandDataOptionValue::User(id) => format!("<@{}>", id),
+                    other => format!("{:?}", other),
+                }
+            };
+            format!("{}={}", o.name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
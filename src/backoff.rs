@@ -0,0 +1,40 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// Exponential backoff with "equal jitter": each delay is half the exponential step plus
+/// a random share of the other half, capped at `max`. Call `reset` after a success.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+/// Uses the retry delays from the config.
+impl Default for Backoff {
+    fn default() -> Self {
+        let config = crate::config::get();
+        Self::new(config.retry_base_delay, config.retry_max_delay)
+    }
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self { base, max, attempt: 0 }
+    }
+
+    /// Delay to wait before the next attempt; advances the attempt counter.
+    pub fn next_delay(&mut self) -> Duration {
+        let factor = 2u32.saturating_pow(self.attempt.min(16));
+        let step = self.base.saturating_mul(factor).min(self.max);
+        self.attempt = self.attempt.saturating_add(1);
+
+        let half = step / 2;
+        let jitter_ms = rand::thread_rng().gen_range(0..=half.as_millis() as u64);
+        half + Duration::from_millis(jitter_ms)
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
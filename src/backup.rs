@@ -0,0 +1,253 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, Utc};
+use ring::{digest, hmac};
+use tokio::sync::Mutex;
+
+use crate::config::{self, S3Target};
+use crate::db::{Database, DbData};
+use crate::secrets::Secrets;
+
+/// Local time the nightly backup is taken, just before the daily reset changes every status.
+pub const BACKUP_TIME: &str = "23:55";
+// Backup ids are the UTC time they were taken, so they sort chronologically
+const ID_FORMAT: &str = "%Y%m%d-%H%M%S";
+const ID_LEN: usize = 15;
+const DB_FILE: &str = "db.json";
+const SECRETS_FILE: &str = "secrets.json";
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Result of `create`.
+pub struct BackupReport {
+    pub id: String,
+    /// Old backups deleted to stay within `backup.keep`
+    pub removed: usize,
+    /// Outcome of the S3 upload, if one is configured
+    pub upload: Option<Result<(), String>>,
+}
+
+fn backup_dir() -> PathBuf {
+    PathBuf::from(&config::get().backup_dir)
+}
+
+fn is_backup_id(name: &str) -> bool {
+    name.len() == ID_LEN && NaiveDateTime::parse_from_str(name, ID_FORMAT).is_ok()
+}
+
+/// Ids of the local backups, newest first.
+pub fn list() -> Vec<String> {
+    let mut ids: Vec<String> = fs::read_dir(backup_dir()).into_iter().flatten()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_dir())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .filter(|name| is_backup_id(name))
+        .collect();
+    ids.sort_unstable_by(|a, b| b.cmp(a));
+    ids
+}
+
+/// Writes the current database and its secrets to a new backup directory, deletes the oldest
+/// backups beyond `backup.keep` and uploads the new one if S3 is configured. The secrets file is
+/// encrypted exactly like the live one, so a backup is no more readable than secrets.json.
+pub async fn create(db: &Mutex<Database>) -> Result<BackupReport, String> {
+    let (content, secrets) = {
+        let db = db.lock().await;
+        (serde_json::to_string_pretty(&db.data).map_err(|e| e.to_string())?, Secrets::extract(&db.data))
+    };
+
+    let mut id = Utc::now().format(ID_FORMAT).to_string();
+    while backup_dir().join(&id).exists() {
+        // Two backups in the same second; wait for the next id rather than overwrite
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        id = Utc::now().format(ID_FORMAT).to_string();
+    }
+    let dir = backup_dir().join(&id);
+    fs::create_dir_all(&dir).map_err(|e| format!("Cannot create {}: {}", dir.display(), e))?;
+    let written = fs::write(dir.join(DB_FILE), &content)
+        .map_err(|e| format!("Cannot write {}: {}", dir.join(DB_FILE).display(), e))
+        .and_then(|_| secrets.write(&dir.join(SECRETS_FILE)));
+    if let Err(e) = written {
+        // Never leave a half-written backup that looks restorable
+        let _ = fs::remove_dir_all(&dir);
+        return Err(e);
+    }
+
+    let mut removed = 0;
+    for old in list().into_iter().skip(config::get().backup_keep) {
+        match fs::remove_dir_all(backup_dir().join(&old)) {
+            Ok(()) => removed += 1,
+            Err(e) => log!("[WARN] Backup: could not delete old backup {}: {}", old, e),
+        }
+    }
+
+    let upload = match &config::get().backup_s3 {
+        Some(target) => Some(upload(target, &id, &dir).await),
+        None => None,
+    };
+    Ok(BackupReport { id, removed, upload })
+}
+
+/// Reads a local backup, with its secrets applied, ready to replace the live database.
+pub fn load(id: &str) -> Result<DbData, String> {
+    let id = id.trim();
+    // Only ids from the listing, so the argument can't point outside the backup directory
+    if !list().iter().any(|known| known == id) {
+        return Err(format!("No backup with id `{}`.", id));
+    }
+    let dir = backup_dir().join(id);
+    let content = fs::read_to_string(dir.join(DB_FILE)).map_err(|e| format!("Cannot read backup {}: {}", id, e))?;
+    let mut data: DbData = serde_json::from_str(&content).map_err(|e| format!("Backup {} is not a valid database: {}", id, e))?;
+    if let Some(secrets) = Secrets::read(&dir.join(SECRETS_FILE))? {
+        secrets.apply(&mut data);
+    }
+    data.validate().map_err(|e| format!("Backup {} is invalid: {}", id, e))?;
+    Ok(data)
+}
+
+async fn upload(target: &S3Target, id: &str, dir: &Path) -> Result<(), String> {
+    let access_key = std::env::var("S3_ACCESS_KEY_ID").ok().filter(|k| !k.is_empty()).ok_or("S3_ACCESS_KEY_ID is not set")?;
+    let secret_key = std::env::var("S3_SECRET_ACCESS_KEY").ok().filter(|k| !k.is_empty()).ok_or("S3_SECRET_ACCESS_KEY is not set")?;
+    let client = reqwest::Client::builder().timeout(UPLOAD_TIMEOUT).build().map_err(|e| e.to_string())?;
+    for file in [DB_FILE, SECRETS_FILE] {
+        let body = fs::read(dir.join(file)).map_err(|e| format!("Cannot read {}: {}", file, e))?;
+        let key = format!("{}{}/{}", target.prefix, id, file);
+        put_object(&client, target, &access_key, &secret_key, &key, body).await
+            .map_err(|e| format!("Upload of {} failed: {}", key, e))?;
+    }
+    Ok(())
+}
+
+/// PUTs one object, signed with AWS Signature Version 4.
+async fn put_object(client: &reqwest::Client, target: &S3Target, access_key: &str, secret_key: &str, key: &str, body: Vec<u8>) -> Result<(), String> {
+    let path = format!("/{}/{}", target.bucket, key).split('/').map(uri_encode).collect::<Vec<_>>().join("/");
+    let url = url::Url::parse(&format!("{}{}", target.endpoint, path)).map_err(|e| e.to_string())?;
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return Err("endpoint has no host".to_string()),
+    };
+
+    let now = Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let payload_hash = hex(digest::digest(&digest::SHA256, &body).as_ref());
+    let headers = [("host", host.as_str()), ("x-amz-content-sha256", payload_hash.as_str()), ("x-amz-date", amz_date.as_str())];
+    let signed = sign_v4("PUT", url.path(), &headers, &payload_hash, &amz_date, &target.region, secret_key);
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        access_key, signed.scope, signed.signed_headers, signed.signature
+    );
+
+    let response = client.put(url)
+        .header("x-amz-content-sha256", payload_hash)
+        .header("x-amz-date", amz_date)
+        .header("authorization", authorization)
+        .body(body)
+        .send().await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let detail = response.text().await.unwrap_or_default();
+        return Err(format!("HTTP {}: {}", status, detail.chars().take(200).collect::<String>()));
+    }
+    Ok(())
+}
+
+/// The parts of an AWS Signature Version 4 an S3 request needs, kept apart so they can be
+/// checked against AWS's published examples.
+struct SignedRequest {
+    // The intermediate steps are only compared in the tests
+    #[cfg_attr(not(test), allow(dead_code))]
+    canonical_request: String,
+    #[cfg_attr(not(test), allow(dead_code))]
+    string_to_sign: String,
+    scope: String,
+    signed_headers: String,
+    signature: String,
+}
+
+/// Signs a request without a query string. `headers` are the signed headers, lowercase;
+/// `amz_date` is the request time as `YYYYMMDDTHHMMSSZ`.
+fn sign_v4(method: &str, path: &str, headers: &[(&str, &str)], payload_hash: &str, amz_date: &str, region: &str, secret_key: &str) -> SignedRequest {
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let mut headers = headers.to_vec();
+    headers.sort_by_key(|(name, _)| *name);
+    let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+    let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+    let canonical_request = format!("{}\n{}\n\n{}\n{}\n{}", method, path, canonical_headers, signed_headers, payload_hash);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date, scope, hex(digest::digest(&digest::SHA256, canonical_request.as_bytes()).as_ref())
+    );
+    let mut signing_key = format!("AWS4{}", secret_key).into_bytes();
+    for part in [date, region, "s3", "aws4_request"] {
+        signing_key = hmac_sha256(&signing_key, part.as_bytes());
+    }
+    let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+    SignedRequest { canonical_request, string_to_sign, scope, signed_headers, signature }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), data).as_ref().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Percent-encodes one path segment the way SigV4 expects (unreserved characters kept).
+fn uri_encode(segment: &str) -> String {
+    segment.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "Example: PUT Object" from AWS's "Signature Calculations for the Authorization Header" (S3 API reference)
+    #[test]
+    fn sign_v4_matches_aws_put_object_example() {
+        let body = b"Welcome to Amazon S3.";
+        let payload_hash = hex(digest::digest(&digest::SHA256, body).as_ref());
+        assert_eq!(payload_hash, "44ce7dd67c959e0d3524ffac1771dfbba87d2b6b4b4e99e42034a8b803f8b072");
+
+        let path = "/test$file.text".split('/').map(uri_encode).collect::<Vec<_>>().join("/");
+        let headers = [
+            ("host", "examplebucket.s3.amazonaws.com"),
+            ("date", "Fri, 24 May 2013 00:00:00 GMT"),
+            ("x-amz-date", "20130524T000000Z"),
+            ("x-amz-storage-class", "REDUCED_REDUNDANCY"),
+            ("x-amz-content-sha256", payload_hash.as_str()),
+        ];
+        let signed = sign_v4("PUT", &path, &headers, &payload_hash, "20130524T000000Z", "us-east-1", "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY");
+
+        assert_eq!(signed.canonical_request, "PUT\n\
+            /test%24file.text\n\
+            \n\
+            date:Fri, 24 May 2013 00:00:00 GMT\n\
+            host:examplebucket.s3.amazonaws.com\n\
+            x-amz-content-sha256:44ce7dd67c959e0d3524ffac1771dfbba87d2b6b4b4e99e42034a8b803f8b072\n\
+            x-amz-date:20130524T000000Z\n\
+            x-amz-storage-class:REDUCED_REDUNDANCY\n\
+            \n\
+            date;host;x-amz-content-sha256;x-amz-date;x-amz-storage-class\n\
+            44ce7dd67c959e0d3524ffac1771dfbba87d2b6b4b4e99e42034a8b803f8b072");
+        assert_eq!(signed.string_to_sign, "AWS4-HMAC-SHA256\n\
+            20130524T000000Z\n\
+            20130524/us-east-1/s3/aws4_request\n\
+            9e0e90d9c76de8fa5b200d8c849cd5b8dc7a3be3951ddb7f6a76b4158342019d");
+        assert_eq!(signed.scope, "20130524/us-east-1/s3/aws4_request");
+        assert_eq!(signed.signed_headers, "date;host;x-amz-content-sha256;x-amz-date;x-amz-storage-class");
+        assert_eq!(signed.signature, "98ad721746da40c64f1a55b78f14c238d841ea1380cd77a1b5971af0ece108bd");
+    }
+
+    #[test]
+    fn uri_encode_keeps_only_unreserved_characters() {
+        assert_eq!(uri_encode("db-2024_01.json~"), "db-2024_01.json~");
+        assert_eq!(uri_encode("a b+c$"), "a%20b%2Bc%24");
+    }
+}
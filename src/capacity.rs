@@ -0,0 +1,57 @@
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{BTreeMap, VecDeque};
+
+// SERVER_FULL occurrences older than this are forgotten
+const HISTORY: Duration = Duration::hours(24);
+
+/// When each target server last turned a session away with SERVER_FULL. The queue runs accounts
+/// on other servers first while one is cooling down (`queue.server_full_cooldown_mins`).
+/// Kept in memory; a restart starts with every server assumed to have room.
+#[derive(Default)]
+pub struct ServerCapacity {
+    // Server name -> SERVER_FULL times within `HISTORY`, oldest first
+    full_at: BTreeMap<String, VecDeque<DateTime<Utc>>>,
+}
+
+/// What /stats shows about one server.
+pub struct ServerState {
+    pub server: String,
+    /// SERVER_FULL occurrences in the last 24 hours
+    pub recent: usize,
+    pub last_full: DateTime<Utc>,
+    /// Accounts targeting the server are deferred until then
+    pub cooling_until: Option<DateTime<Utc>>,
+}
+
+impl ServerCapacity {
+    pub fn record_full(&mut self, server: &str, at: DateTime<Utc>) {
+        let times = self.full_at.entry(server.to_string()).or_default();
+        times.push_back(at);
+        while times.front().map(|t| at - *t > HISTORY).unwrap_or(false) {
+            times.pop_front();
+        }
+    }
+
+    /// Until when `server` is considered full, `None` if it has room (or the cooldown is off).
+    pub fn full_until(&self, server: &str, now: DateTime<Utc>, cooldown: std::time::Duration) -> Option<DateTime<Utc>> {
+        let last = *self.full_at.get(server)?.back()?;
+        let until = last + Duration::from_std(cooldown).ok()?;
+        (until > now).then_some(until)
+    }
+
+    /// Servers that were full in the last 24 hours, by name.
+    pub fn states(&self, now: DateTime<Utc>, cooldown: std::time::Duration) -> Vec<ServerState> {
+        self.full_at.iter()
+            .filter_map(|(server, times)| {
+                let recent = times.iter().filter(|t| now - **t <= HISTORY).count();
+                let last_full = *times.back()?;
+                (recent > 0).then(|| ServerState {
+                    server: server.clone(),
+                    recent,
+                    last_full,
+                    cooling_until: self.full_until(server, now, cooldown),
+                })
+            })
+            .collect()
+    }
+}
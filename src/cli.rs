@@ -0,0 +1,353 @@
+use std::time::Instant;
+
+use clap::{ArgGroup, Parser};
+
+use crate::backoff::Backoff;
+use crate::config;
+use crate::db::{Account, AccountFilter, Database, RunDetail, STATUS_INVALID_CODE, STATUS_NO_COOKIE, STATUS_WORKER_CRASHED};
+use crate::history;
+use crate::legacy;
+use crate::protocol::endpoint::resolve_base_url;
+use crate::protocol::client::GameClient;
+use crate::protocol::socket::RunOutcome;
+use crate::protocol::SessionClient;
+use crate::queue::{batch_order, is_transient_error, min_retry_delay, BatchQueue, RetryDecision, RetryTracker};
+use crate::run_id;
+use crate::secrets::{self, Secrets};
+use crate::snapshot::QueueSnapshot;
+
+// Exit codes for the one-shot mode:
+// 0 = every selected account finished, 1 = at least one account failed,
+// 2 = usage / configuration problem (nothing was run).
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_RUN_FAILED: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+
+/// Without arguments the Discord bot is started; each option runs one task instead and exits.
+#[derive(Debug, Parser)]
+#[command(name = "evertext_bot_rust", about = "EverText automation bot. Without options the Discord bot is started.")]
+#[command(group(ArgGroup::new("mode").multiple(false)))]
+pub struct Args {
+    /// Run a single account (by name) or 'all' pending accounts once, print the results and
+    /// exit. No DISCORD_TOKEN is required.
+    #[arg(long, value_name = "ACCOUNT|all", value_parser = non_blank, group = "mode")]
+    run: Option<String>,
+    /// Move restore codes and cookies out of db.json into secrets.json (encrypted with
+    /// ENCRYPTION_KEY) and exit.
+    #[arg(long, group = "mode")]
+    migrate_secrets: bool,
+    /// Add the accounts from an accounts file of the old Node version and exit. Accounts whose
+    /// name or restore code already exists are skipped.
+    #[arg(long, value_name = "FILE", group = "mode")]
+    import_legacy: Option<String>,
+    /// Apply the queue's ordering rules to a /debug_snapshot file and print how they compare
+    /// with the recorded order. Nothing is run or changed.
+    #[arg(long, value_name = "FILE", group = "mode")]
+    replay_snapshot: Option<String>,
+}
+
+fn non_blank(value: &str) -> Result<String, String> {
+    if value.trim().is_empty() {
+        return Err("requires an account name or 'all'".to_string());
+    }
+    Ok(value.to_string())
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CliCommand {
+    Run(String),
+    MigrateSecrets,
+    ImportLegacy(String),
+    ReplaySnapshot(String),
+}
+
+impl Args {
+    /// The one-shot task requested, `None` when the bot should start normally.
+    pub fn command(self) -> Option<CliCommand> {
+        if let Some(target) = self.run {
+            Some(CliCommand::Run(target))
+        } else if self.migrate_secrets {
+            Some(CliCommand::MigrateSecrets)
+        } else if let Some(path) = self.import_legacy {
+            Some(CliCommand::ImportLegacy(path))
+        } else {
+            self.replay_snapshot.map(CliCommand::ReplaySnapshot)
+        }
+    }
+}
+
+/// Parses the process arguments. `--help` and usage errors print clap's message and exit
+/// (with `EXIT_USAGE` for errors).
+pub fn parse_args() -> Option<CliCommand> {
+    Args::parse().command()
+}
+
+pub async fn execute(command: CliCommand) -> i32 {
+    match command {
+        CliCommand::Run(target) => run(&target).await,
+        CliCommand::MigrateSecrets => migrate_secrets(),
+        CliCommand::ImportLegacy(path) => import_legacy(&path),
+        CliCommand::ReplaySnapshot(path) => replay_snapshot(&path),
+    }
+}
+
+/// Loads the database (secrets from db.json, or from secrets.json if already migrated) and
+/// saves it, which writes the secrets to secrets.json and strips them from db.json.
+fn migrate_secrets() -> i32 {
+    let mut db = match Database::load() {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("[CRITICAL] Failed to load database: {}", e);
+            return EXIT_USAGE;
+        }
+    };
+    let path = match db.path() {
+        Some(path) => path.to_string(),
+        None => {
+            eprintln!("[ERROR] No database file found; nothing to migrate.");
+            return EXIT_USAGE;
+        }
+    };
+    let extracted = Secrets::extract(&db.data);
+    if let Err(e) = db.save() {
+        eprintln!("[ERROR] Migration failed, db.json was left unchanged: {}", e);
+        return EXIT_RUN_FAILED;
+    }
+    let saved_to = db.path().unwrap_or(&path).to_string();
+    log!("[INFO] Secrets for {} account(s){} are in {} ({}). {} no longer contains them.",
+        extracted.code_count(),
+        if db.data.settings.cookies.as_deref().map(|c| !c.is_empty()).unwrap_or(false) { " and the session cookie" } else { "" },
+        secrets::path_for(&saved_to).display(),
+        if secrets::encryption_enabled() { "encrypted" } else { "NOT encrypted: set ENCRYPTION_KEY to encrypt it" },
+        saved_to);
+    EXIT_OK
+}
+
+/// Prints the replay of a /debug_snapshot file. Works without a database or Discord token.
+fn replay_snapshot(path: &str) -> i32 {
+    let snapshot: QueueSnapshot = match std::fs::read_to_string(path).map_err(|e| e.to_string())
+        .and_then(|text| serde_json::from_str(&text).map_err(|e| format!("not a queue snapshot: {}", e))) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            eprintln!("[ERROR] Cannot read {}: {}", path, e);
+            return EXIT_USAGE;
+        }
+    };
+    for line in snapshot.replay() {
+        log!("{}", line);
+    }
+    EXIT_OK
+}
+
+/// Merges the accounts of a Node-version accounts file into the database.
+fn import_legacy(path: &str) -> i32 {
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("[ERROR] Cannot read {}: {}", path, e);
+            return EXIT_USAGE;
+        }
+    };
+    let mut db = match Database::load() {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("[CRITICAL] Failed to load database: {}", e);
+            return EXIT_USAGE;
+        }
+    };
+    let report = match legacy::import(&mut db, &text) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("[ERROR] {}: {}", path, e);
+            return EXIT_USAGE;
+        }
+    };
+    for (name, reason) in &report.skipped {
+        log!("[WARN] Skipped {}: {}", name, reason);
+    }
+    if report.added.is_empty() {
+        log!("[INFO] No accounts imported from {} ({} skipped).", path, report.skipped.len());
+        return EXIT_OK;
+    }
+    if let Err(e) = db.save() {
+        eprintln!("[ERROR] Import failed, the database was left unchanged: {}", e);
+        return EXIT_RUN_FAILED;
+    }
+    log!("[INFO] Imported {} account(s) from {} ({} skipped): {}", report.added.len(), path, report.skipped.len(), report.added.join(", "));
+    EXIT_OK
+}
+
+async fn run(target: &str) -> i32 {
+    let mut db = match Database::load() {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("[CRITICAL] Failed to load database: {}", e);
+            return EXIT_USAGE;
+        }
+    };
+
+    let global_cookie = db.data.settings.cookies.clone();
+    let base_url = resolve_base_url(db.data.settings.base_url.as_deref());
+
+    let accounts: Vec<Account> = if target.to_lowercase() == "all" {
+        // Same ordering as the queue manager, catch-up setting included
+        let last_completed = db.data.settings.catch_up_first()
+            .then(|| history::last_completed_days(&db.data.run_history, &db.data.settings.timezone()));
+        let runnable = db.active_accounts().filter(|a| a.status != "done" && a.status != STATUS_INVALID_CODE);
+        batch_order(runnable, &AccountFilter::All, last_completed.as_ref()).iter()
+            .filter_map(|name| db.find_account(name).cloned())
+            .collect()
+    } else {
+        match db.find_account(target) {
+            Some(acc) => vec![acc.clone()],
+            None => {
+                eprintln!("[ERROR] Account '{}' not found.", target);
+                return EXIT_USAGE;
+            }
+        }
+    };
+
+    if accounts.is_empty() {
+        log!("[INFO] Nothing to run: all accounts are already done.");
+        return EXIT_OK;
+    }
+
+    // Same retry budget as the queue manager: transient failures are retried inline, then the
+    // account goes to the back of the batch, and after that it is given up on.
+    let mut batch = BatchQueue::default();
+    batch.extend(accounts.iter().map(|a| a.name.clone()).collect());
+    let mut retries = RetryTracker::default();
+    let mut backoff = Backoff::default();
+    let mut succeeded = 0;
+    let mut failed = Vec::new();
+
+    while let Some(name) = batch.pop_front() {
+        let Some(acc) = accounts.iter().find(|a| a.name == name) else { continue };
+        let attempt = retries.start_attempt(&acc.name);
+        log!("[INFO] Running account: {} (attempt {})", acc.name, attempt);
+        if let Err(reason) = Account::validate_code(&acc.decrypt_code()) {
+            retries.finish(&acc.name);
+            let _ = db.update_status(&acc.name, STATUS_INVALID_CODE);
+            log!("[ERROR] {} skipped: {}", acc.name, reason);
+            failed.push(acc.name.clone());
+            continue;
+        }
+
+        // Without a shared cookie, only the accounts that have their own can run
+        let cookie = acc.session_cookie(global_cookie.as_deref());
+        if cookie.is_empty() {
+            retries.finish(&acc.name);
+            let _ = db.update_status(&acc.name, STATUS_NO_COOKIE);
+            log!("[ERROR] {} skipped: no session cookie. Set one with /set_cookies or /set_my_cookie.", acc.name);
+            failed.push(acc.name.clone());
+            continue;
+        }
+
+        let started_at = chrono::Utc::now();
+        let started = Instant::now();
+        let run_id = run_id::generate();
+        let _ = db.mark_running(&acc.name, started_at);
+        // Its own task, so a panicking session fails this account instead of the whole run
+        let session = match tokio::spawn(run_id::scope(run_id.clone(), run_session(acc.clone(), cookie, base_url.clone(), started))).await {
+            Ok(session) => session,
+            Err(e) => {
+                let message = match e.try_into_panic() {
+                    Ok(panic) => panic.downcast_ref::<&str>().map(|m| m.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown panic".to_string()),
+                    Err(e) => e.to_string(),
+                };
+                retries.finish(&acc.name);
+                let _ = db.record_run(&acc.name, started_at, started.elapsed(), Some(format!("crashed: {}", message)), attempt, RunDetail::default());
+                let _ = db.update_status(&acc.name, STATUS_WORKER_CRASHED);
+                log!("[CRITICAL] {} crashed (run {}): {}", acc.name, run_id, message);
+                failed.push(acc.name.clone());
+                continue;
+            }
+        };
+        let _ = db.record_known_servers(&session.servers);
+
+        match session.result {
+            Ok(summary) => {
+                retries.finish(&acc.name);
+                backoff.reset();
+                let _ = db.record_run(&acc.name, started_at, started.elapsed(), None, attempt, session.detail);
+                let _ = db.update_status(&acc.name, "done");
+                if let (Some(missing), Some(server)) = (acc.target_server.as_deref(), summary.fallback_server.as_deref()) {
+                    if let Ok(true) = db.adopt_fallback_server(&acc.name, missing, server) {
+                        log!("[INFO] {}: target server '{}' was not listed; it now targets '{}'.", acc.name, missing, server);
+                    }
+                }
+                log!("[SUCCESS] {} completed (run {}). {} {}", acc.name, run_id, summary.short_summary(), summary.stats_summary());
+                if let Some(servers) = summary.server_summary() {
+                    log!("    {}", servers);
+                }
+                for reward in &summary.rewards {
+                    log!("    - {}", reward);
+                }
+                if let Some(path) = &summary.transcript_path {
+                    log!("    Transcript: {}", path);
+                }
+                succeeded += 1;
+            }
+            Err(err_str) if is_transient_error(&err_str) => match retries.on_transient_failure(&acc.name) {
+                RetryDecision::RetryInline => {
+                    let delay = backoff.next_delay().max(min_retry_delay(&err_str));
+                    log!("[WARN] {} failed (run {}): {}. Retrying in {:?}...", acc.name, run_id, err_str, delay);
+                    batch.push_front(&acc.name);
+                    tokio::time::sleep(delay).await;
+                }
+                RetryDecision::Requeue => {
+                    let _ = db.update_status(&acc.name, &format!("error: {} (requeued after {} attempts)", err_str, attempt));
+                    log!("[WARN] {} requeued after {} attempts (run {}): {}", acc.name, attempt, run_id, err_str);
+                    batch.push_back(&acc.name);
+                }
+                RetryDecision::GiveUp => {
+                    let _ = db.record_run(&acc.name, started_at, started.elapsed(), Some(err_str.clone()), attempt, session.detail);
+                    let _ = db.update_status(&acc.name, &format!("error: {} (gave up after {} attempts)", err_str, attempt));
+                    log!("[ERROR] {} gave up after {} attempts (run {}): {}", acc.name, attempt, run_id, err_str);
+                    failed.push(acc.name.clone());
+                }
+            },
+            Err(err_str) => {
+                retries.finish(&acc.name);
+                let _ = db.record_run(&acc.name, started_at, started.elapsed(), Some(err_str.clone()), attempt, session.detail);
+                let _ = db.update_status(&acc.name, &format!("error: {}", err_str));
+                let hint = session.failure_hint.map(|h| format!(" ({})", h)).unwrap_or_default();
+                log!("[ERROR] {} failed (run {}): {}{}", acc.name, run_id, err_str, hint);
+                failed.push(acc.name.clone());
+            }
+        }
+    }
+
+    log!("[INFO] Finished: {} succeeded, {} failed.", succeeded, failed.len());
+    if failed.is_empty() {
+        EXIT_OK
+    } else {
+        log!("[INFO] Failed accounts: {}", failed.join(", "));
+        EXIT_RUN_FAILED
+    }
+}
+
+/// What one session of `--run` left behind.
+struct Session {
+    result: Result<RunOutcome, String>,
+    detail: RunDetail,
+    failure_hint: Option<&'static str>,
+    servers: Vec<String>,
+}
+
+/// Connects and runs `acc` once; the run timeout counts from `started`.
+async fn run_session(acc: Account, cookie: String, base_url: String, started: Instant) -> Session {
+    let mut client = match SessionClient::connect(&cookie, &base_url).await {
+        Ok(client) => client,
+        Err(e) => return Session { result: Err(format!("Connection failed: {}", e)), detail: RunDetail::default(), failure_hint: None, servers: Vec::new() },
+    };
+    let limit = config::get().run_timeout.saturating_sub(started.elapsed());
+    let result = match tokio::time::timeout(limit, client.run_loop(&acc, &acc.decrypt_code(), acc.flow, None, false)).await {
+        Ok(Ok(summary)) => Ok(summary),
+        Ok(Err(e)) => Err(e.to_string()),
+        Err(_) => Err("run timeout".to_string()),
+    };
+    Session { result, detail: client.run_detail(), failure_hint: client.failure_hint(), servers: client.discovered_servers().to_vec() }
+}
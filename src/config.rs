@@ -0,0 +1,328 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use crate::cooldown::DEFAULT_COOLDOWNS;
+use crate::protocol::connector::IpFamily;
+use crate::protocol::endpoint::normalize_base_url;
+use crate::protocol::tls::{self, SpkiPin};
+use crate::templates::Templates;
+
+// Read from the working directory unless CONFIG_PATH points elsewhere
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+// Used when present and `messages.templates_file` is not set
+const DEFAULT_TEMPLATES_PATH: &str = "templates.toml";
+
+/// Operational settings fixed at startup. Each value comes from its environment variable if
+/// set, otherwise from `config.toml`, otherwise the default. Secrets (tokens, keys) stay in `.env`;
+/// anything admins change at runtime lives in the database settings instead.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub database_path: String,
+    /// Game endpoint used when /set_base_url has not been set
+    pub websocket_url: Option<String>,
+    pub handshake_timeout: Duration,
+    /// IP versions the game connection may use, and which is tried first
+    pub ip_family: IpFamily,
+    /// Silence after an event-phase command before the rest of the sequence is sent without prompts
+    pub prompt_step_timeout: Duration,
+    pub retry_base_delay: Duration,
+    pub retry_max_delay: Duration,
+    /// Immediate retries of an account after a transient failure
+    pub inline_retries: u32,
+    /// How often one account may be sent to the back of the queue in a single batch
+    pub max_requeues: u32,
+    /// Run requests that may wait for the current batch
+    pub max_pending_runs: usize,
+    /// Sessions the queue may start per hour; 0 means no limit
+    pub max_runs_per_hour: u32,
+    /// Wall-clock limit of one session; longer runs are aborted and the account marked errored
+    pub run_timeout: Duration,
+    /// How long after a successful run /force_run refuses to run the account again; 0 never refuses
+    pub run_cooldown: Duration,
+    /// How long accounts targeting a server that reported SERVER_FULL wait behind the others; 0 never defers
+    pub server_full_cooldown: Duration,
+    /// Times a crashed queue worker is restarted before the queue is stopped; 0 never restarts
+    pub crash_restarts: u32,
+    /// Consecutive days of failed runs after which the owner gets a DM; 0 never sends one
+    pub failure_streak_days: u32,
+    /// Gateway connections to Discord; the queue and scheduler run once however many there are
+    pub shards: ShardCount,
+    /// Port of the live log WebSocket; 0 keeps it off
+    pub live_port: u16,
+    /// Where nightly and manual backups are kept
+    pub backup_dir: String,
+    /// Backups kept locally; older ones are deleted
+    pub backup_keep: usize,
+    /// Optional off-site copy of every backup
+    pub backup_s3: Option<S3Target>,
+    /// Per-user cooldown of each command listed in `DEFAULT_COOLDOWNS`
+    pub cooldowns: HashMap<String, Duration>,
+    /// Public keys the game server's certificate must match; empty accepts any trusted certificate
+    pub tls_pins: Vec<SpkiPin>,
+    /// PEM file with extra root certificates for the game connection
+    pub tls_ca_file: Option<String>,
+    /// Where each run's terminal output is saved
+    pub transcript_dir: String,
+    /// Transcript files kept; older ones are deleted. 0 turns saving off
+    pub transcript_keep: usize,
+    /// JSON list of failure suggestions tried before the built-in ones; compiled by `patterns::init`
+    pub failure_patterns_file: Option<String>,
+    /// Wording of run and queue messages, from `messages.templates_file`
+    pub templates: Templates,
+}
+
+/// How many shards (gateway connections) the bot runs. One is enough below ~2500 servers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShardCount {
+    /// As many as Discord recommends for the bot's server count
+    Auto,
+    Fixed(u32),
+}
+
+/// S3-compatible bucket backups are uploaded to (path-style URLs, so MinIO and the like work).
+/// The access keys are secrets and come from the environment only.
+#[derive(Debug, Clone)]
+pub struct S3Target {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub prefix: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            database_path: "db.json".to_string(),
+            websocket_url: None,
+            handshake_timeout: Duration::from_secs(10),
+            ip_family: IpFamily::default(),
+            prompt_step_timeout: Duration::from_secs(60),
+            retry_base_delay: Duration::from_secs(5),
+            retry_max_delay: Duration::from_secs(300),
+            inline_retries: 2,
+            max_requeues: 2,
+            max_pending_runs: 10,
+            max_runs_per_hour: 0,
+            run_timeout: Duration::from_secs(30 * 60),
+            run_cooldown: Duration::from_secs(2 * 60 * 60),
+            server_full_cooldown: Duration::from_secs(10 * 60),
+            crash_restarts: 1,
+            failure_streak_days: 3,
+            shards: ShardCount::Fixed(1),
+            live_port: 0,
+            backup_dir: "backups".to_string(),
+            backup_keep: 7,
+            backup_s3: None,
+            cooldowns: DEFAULT_COOLDOWNS.iter().map(|(command, secs)| (command.to_string(), Duration::from_secs(*secs))).collect(),
+            tls_pins: Vec::new(),
+            tls_ca_file: None,
+            transcript_dir: "transcripts".to_string(),
+            transcript_keep: 200,
+            failure_patterns_file: None,
+            templates: Templates::bundled().expect("built-in translations"),
+        }
+    }
+}
+
+/// `config.toml` key (`section.key`) and the environment variable that overrides it.
+const KEYS: &[(&str, &str)] = &[
+    ("storage.database_path", "DATABASE_PATH"),
+    ("game.websocket_url", "EVERTEXT_WS_URL"),
+    ("game.handshake_timeout_secs", "EVERTEXT_HANDSHAKE_TIMEOUT_SECS"),
+    ("game.ip_family", "EVERTEXT_IP_FAMILY"),
+    ("game.prompt_step_timeout_secs", "EVERTEXT_PROMPT_STEP_TIMEOUT_SECS"),
+    ("queue.retry_base_delay_secs", "EVERTEXT_RETRY_BASE_DELAY_SECS"),
+    ("queue.retry_max_delay_secs", "EVERTEXT_RETRY_MAX_DELAY_SECS"),
+    ("queue.inline_retries", "EVERTEXT_INLINE_RETRIES"),
+    ("queue.max_requeues", "EVERTEXT_MAX_REQUEUES"),
+    ("queue.max_pending_runs", "EVERTEXT_MAX_PENDING_RUNS"),
+    ("queue.max_runs_per_hour", "EVERTEXT_MAX_RUNS_PER_HOUR"),
+    ("queue.run_timeout_mins", "EVERTEXT_RUN_TIMEOUT_MINS"),
+    ("queue.run_cooldown_mins", "EVERTEXT_RUN_COOLDOWN_MINS"),
+    ("queue.server_full_cooldown_mins", "EVERTEXT_SERVER_FULL_COOLDOWN_MINS"),
+    ("queue.crash_restarts", "EVERTEXT_CRASH_RESTARTS"),
+    ("notifications.failure_streak_days", "EVERTEXT_FAILURE_STREAK_DAYS"),
+    ("discord.shards", "EVERTEXT_SHARDS"),
+    ("dashboard.live_port", "EVERTEXT_LIVE_PORT"),
+    ("transcripts.directory", "EVERTEXT_TRANSCRIPT_DIR"),
+    ("transcripts.keep", "EVERTEXT_TRANSCRIPT_KEEP"),
+    ("backup.directory", "EVERTEXT_BACKUP_DIR"),
+    ("backup.keep", "EVERTEXT_BACKUP_KEEP"),
+    ("backup.s3_endpoint", "EVERTEXT_BACKUP_S3_ENDPOINT"),
+    ("backup.s3_bucket", "EVERTEXT_BACKUP_S3_BUCKET"),
+    ("backup.s3_region", "EVERTEXT_BACKUP_S3_REGION"),
+    ("backup.s3_prefix", "EVERTEXT_BACKUP_S3_PREFIX"),
+    ("tls.spki_pins", "EVERTEXT_TLS_SPKI_PINS"),
+    ("tls.ca_file", "EVERTEXT_TLS_CA_FILE"),
+    ("failures.patterns_file", "EVERTEXT_FAILURE_PATTERNS_FILE"),
+    ("messages.templates_file", "EVERTEXT_TEMPLATES_FILE"),
+];
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Loads and validates the configuration; call once at startup before anything reads `get`.
+/// A missing `config.toml` is fine, an unreadable or invalid one is an error.
+pub fn init() -> Result<(), String> {
+    let path = std::env::var("CONFIG_PATH").unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string());
+    let file = match std::fs::read_to_string(&path) {
+        Ok(text) => parse_toml(&text).map_err(|e| format!("{}: {}", path, e))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+        Err(e) => return Err(format!("{}: {}", path, e)),
+    };
+    let config = build(&file)?;
+    let _ = CONFIG.set(config);
+    Ok(())
+}
+
+/// The active configuration (defaults if `init` was never called).
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+fn build(file: &BTreeMap<String, String>) -> Result<Config, String> {
+    // `[cooldowns]` takes one key per command that has a cooldown
+    let is_cooldown = |key: &str| key.strip_prefix("cooldowns.").map(|c| DEFAULT_COOLDOWNS.iter().any(|(command, _)| *command == c)).unwrap_or(false);
+    if let Some(unknown) = file.keys().find(|k| !KEYS.iter().any(|(key, _)| key == k) && !is_cooldown(k)) {
+        return Err(format!("unknown setting '{}'. Known settings: {}, cooldowns.<{}>", unknown,
+            KEYS.iter().map(|(k, _)| *k).collect::<Vec<_>>().join(", "),
+            DEFAULT_COOLDOWNS.iter().map(|(c, _)| *c).collect::<Vec<_>>().join("|")));
+    }
+    // Environment first, then the file; empty values count as unset
+    let lookup = |key: &str| -> Option<(String, String)> {
+        let var = match key.strip_prefix("cooldowns.") {
+            Some(command) => format!("EVERTEXT_COOLDOWN_{}", command.to_ascii_uppercase()),
+            None => KEYS.iter().find(|(k, _)| *k == key)?.1.to_string(),
+        };
+        std::env::var(&var).ok().filter(|v| !v.trim().is_empty()).map(|v| (var.clone(), v))
+            .or_else(|| file.get(key).filter(|v| !v.trim().is_empty()).map(|v| (format!("config.toml {}", key), v.clone())))
+    };
+    let number = |key: &str, default: u64| -> Result<u64, String> {
+        match lookup(key) {
+            Some((source, value)) => value.trim().replace('_', "").parse::<u64>()
+                .map_err(|_| format!("{}: '{}' is not a whole number", source, value.trim())),
+            None => Ok(default),
+        }
+    };
+    let positive = |key: &str, default: u64| -> Result<u64, String> {
+        let value = number(key, default)?;
+        if value == 0 {
+            return Err(format!("{} must be greater than 0", key));
+        }
+        Ok(value)
+    };
+
+    let text = |key: &str| lookup(key).map(|(_, v)| v.trim().to_string());
+
+    let defaults = Config::default();
+    let config = Config {
+        database_path: text("storage.database_path").unwrap_or(defaults.database_path),
+        websocket_url: match lookup("game.websocket_url") {
+            Some((source, value)) => Some(normalize_base_url(&value).map_err(|e| format!("{}: {}", source, e))?),
+            None => None,
+        },
+        handshake_timeout: Duration::from_secs(positive("game.handshake_timeout_secs", defaults.handshake_timeout.as_secs())?),
+        ip_family: match lookup("game.ip_family") {
+            Some((source, value)) => IpFamily::parse(&value).map_err(|e| format!("{}: {}", source, e))?,
+            None => defaults.ip_family,
+        },
+        prompt_step_timeout: Duration::from_secs(positive("game.prompt_step_timeout_secs", defaults.prompt_step_timeout.as_secs())?),
+        retry_base_delay: Duration::from_secs(positive("queue.retry_base_delay_secs", defaults.retry_base_delay.as_secs())?),
+        retry_max_delay: Duration::from_secs(positive("queue.retry_max_delay_secs", defaults.retry_max_delay.as_secs())?),
+        inline_retries: number("queue.inline_retries", defaults.inline_retries as u64)?.try_into().map_err(|_| "queue.inline_retries is too large".to_string())?,
+        max_requeues: number("queue.max_requeues", defaults.max_requeues as u64)?.try_into().map_err(|_| "queue.max_requeues is too large".to_string())?,
+        max_pending_runs: positive("queue.max_pending_runs", defaults.max_pending_runs as u64)? as usize,
+        max_runs_per_hour: number("queue.max_runs_per_hour", defaults.max_runs_per_hour as u64)?.try_into().map_err(|_| "queue.max_runs_per_hour is too large".to_string())?,
+        run_timeout: Duration::from_secs(positive("queue.run_timeout_mins", defaults.run_timeout.as_secs() / 60)?.saturating_mul(60)),
+        run_cooldown: Duration::from_secs(number("queue.run_cooldown_mins", defaults.run_cooldown.as_secs() / 60)?.saturating_mul(60)),
+        server_full_cooldown: Duration::from_secs(number("queue.server_full_cooldown_mins", defaults.server_full_cooldown.as_secs() / 60)?.saturating_mul(60)),
+        crash_restarts: number("queue.crash_restarts", defaults.crash_restarts as u64)?.try_into().map_err(|_| "queue.crash_restarts is too large".to_string())?,
+        failure_streak_days: number("notifications.failure_streak_days", defaults.failure_streak_days as u64)?.try_into().map_err(|_| "notifications.failure_streak_days is too large".to_string())?,
+        shards: match lookup("discord.shards") {
+            Some((_, value)) if value.trim().eq_ignore_ascii_case("auto") => ShardCount::Auto,
+            Some(_) => ShardCount::Fixed(positive("discord.shards", 1)?.try_into().map_err(|_| "discord.shards is too large".to_string())?),
+            None => defaults.shards,
+        },
+        live_port: number("dashboard.live_port", defaults.live_port as u64)?.try_into().map_err(|_| "dashboard.live_port is not a valid port".to_string())?,
+        backup_dir: text("backup.directory").unwrap_or(defaults.backup_dir),
+        transcript_dir: text("transcripts.directory").unwrap_or(defaults.transcript_dir),
+        transcript_keep: number("transcripts.keep", defaults.transcript_keep as u64)? as usize,
+        backup_keep: positive("backup.keep", defaults.backup_keep as u64)? as usize,
+        backup_s3: match (text("backup.s3_endpoint"), text("backup.s3_bucket")) {
+            (None, None) => None,
+            (Some(endpoint), Some(bucket)) => {
+                let parsed = url::Url::parse(&endpoint).map_err(|e| format!("backup.s3_endpoint: '{}' is not a valid URL: {}", endpoint, e))?;
+                if !matches!(parsed.scheme(), "http" | "https") {
+                    return Err(format!("backup.s3_endpoint: '{}' must start with http:// or https://", endpoint));
+                }
+                Some(S3Target {
+                    endpoint: endpoint.trim_end_matches('/').to_string(),
+                    bucket,
+                    region: text("backup.s3_region").unwrap_or_else(|| "us-east-1".to_string()),
+                    prefix: text("backup.s3_prefix").unwrap_or_default(),
+                })
+            }
+            _ => return Err("backup.s3_endpoint and backup.s3_bucket must be set together".to_string()),
+        },
+        cooldowns: DEFAULT_COOLDOWNS.iter()
+            .map(|(command, secs)| Ok((command.to_string(), Duration::from_secs(number(&format!("cooldowns.{}", command), *secs)?))))
+            .collect::<Result<_, String>>()?,
+        // Several pins (e.g. the current key and its planned successor) are comma-separated
+        tls_pins: match lookup("tls.spki_pins") {
+            Some((source, value)) => value.split(',').filter(|p| !p.trim().is_empty())
+                .map(|p| tls::parse_pin(p).map_err(|e| format!("{}: {}", source, e)))
+                .collect::<Result<_, String>>()?,
+            None => Vec::new(),
+        },
+        tls_ca_file: match lookup("tls.ca_file") {
+            Some((source, path)) => {
+                tls::read_ca_file(path.trim()).map_err(|e| format!("{}: {}", source, e))?;
+                Some(path.trim().to_string())
+            }
+            None => None,
+        },
+        failure_patterns_file: text("failures.patterns_file"),
+        templates: match lookup("messages.templates_file") {
+            Some((source, path)) => Templates::load(path.trim()).map_err(|e| format!("{}: {}", source, e))?,
+            None if std::path::Path::new(DEFAULT_TEMPLATES_PATH).exists() => Templates::load(DEFAULT_TEMPLATES_PATH)?,
+            None => Templates::bundled()?,
+        },
+    };
+    if config.retry_base_delay > config.retry_max_delay {
+        return Err(format!("queue.retry_base_delay_secs ({}) is larger than queue.retry_max_delay_secs ({})",
+            config.retry_base_delay.as_secs(), config.retry_max_delay.as_secs()));
+    }
+    Ok(config)
+}
+
+/// Reads a TOML file made of `[section]` tables of quoted strings, integers or booleans.
+/// Values are returned as text, keyed `section.key`.
+pub fn parse_toml(text: &str) -> Result<BTreeMap<String, String>, String> {
+    let table: toml::Table = text.parse().map_err(|e: toml::de::Error| e.to_string().trim_end().to_string())?;
+    let mut values = BTreeMap::new();
+    for (key, value) in table {
+        match value {
+            toml::Value::Table(section) => {
+                for (inner, value) in section {
+                    let full_key = format!("{}.{}", key, inner);
+                    let value = scalar(&value).ok_or_else(|| format!("{}: unsupported value (use a quoted string, a whole number or true/false)", full_key))?;
+                    values.insert(full_key, value);
+                }
+            }
+            value => {
+                let value = scalar(&value).ok_or_else(|| format!("{}: unsupported value (use a quoted string, a whole number or true/false)", key))?;
+                values.insert(key, value);
+            }
+        }
+    }
+    Ok(values)
+}
+
+fn scalar(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(s) => Some(s.clone()),
+        toml::Value::Integer(n) => Some(n.to_string()),
+        toml::Value::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
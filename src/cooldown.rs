@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serenity::all::UserId;
+
+use crate::config;
+
+/// Commands with a per-user cooldown and its default length in seconds. Each can be changed
+/// under `[cooldowns]` in `config.toml` (or EVERTEXT_COOLDOWN_<COMMAND>); 0 turns it off.
+pub const DEFAULT_COOLDOWNS: &[(&str, u64)] = &[
+    ("force_run_all", 30),
+    ("force_run", 10),
+    ("purge_done", 10),
+    ("diagnose", 30),
+    ("backup_now", 60),
+];
+
+/// When each user last used each command that has a cooldown.
+#[derive(Default)]
+pub struct Cooldowns {
+    last_used: HashMap<(UserId, String), Instant>,
+}
+
+impl Cooldowns {
+    /// Records a use of `command` by `user`, or returns how long they still have to wait.
+    pub fn try_use(&mut self, user: UserId, command: &str) -> Result<(), Duration> {
+        let cooldown = match config::get().cooldowns.get(command) {
+            Some(cooldown) if !cooldown.is_zero() => *cooldown,
+            _ => return Ok(()),
+        };
+        let now = Instant::now();
+        let key = (user, command.to_string());
+        if let Some(last) = self.last_used.get(&key) {
+            let elapsed = now.duration_since(*last);
+            if elapsed < cooldown {
+                return Err(cooldown - elapsed);
+            }
+        }
+        // Forget uses whose cooldown has run out so the map stays small
+        let longest = config::get().cooldowns.values().max().copied().unwrap_or_default();
+        self.last_used.retain(|_, last| now.duration_since(*last) < longest);
+        self.last_used.insert(key, now);
+        Ok(())
+    }
+}
@@ -1,10 +1,25 @@
 use serde::{Deserialize, Serialize};
 use std::fs;
-use chrono;
+
+use crate::permissions::Tier;
+use crate::protocol::flow::Flow;
+use crate::protocol::servers::ServerResult;
+use crate::redact;
+use crate::alerts::{self, Alert};
+use crate::notifier::{LogLevel, Severity, Sink};
+use crate::reporting;
+use crate::secrets::{self, Secrets};
+use crate::i18n::Locale;
+use crate::settings::{parse_timezone, Settings};
+use chrono_tz::Tz;
+use std::collections::BTreeMap;
+use serenity::all::{ChannelId, RoleId};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Account {
     pub name: String,
+    // Secrets are written to secrets.json, never back into db.json
+    #[serde(skip_serializing, default)]
     pub code: String,
     #[serde(rename = "targetServer")]
     pub target_server: Option<String>,
@@ -16,51 +31,464 @@ pub struct Account {
     #[serde(rename = "pingEnabled")]
     pub ping_enabled: bool,
     pub status: String,
-    #[serde(rename = "lastRun")]
-    pub last_run: Option<String>,
+    #[serde(rename = "lastRun", with = "crate::timestamp::optional", default)]
+    pub last_run: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default)]
+    pub deleted: bool,
+    #[serde(rename = "deletedAt", with = "crate::timestamp::optional", default)]
+    pub deleted_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // The owner's own site session, used instead of the global cookie. Encrypted like `code`.
+    #[serde(rename = "cookieOverride", skip_serializing, default)]
+    pub cookie_override: Option<String>,
+    #[serde(default)]
+    pub flow: Flow,
+    /// Free-form remarks from the owner or an admin, e.g. "friend's account, stop after June"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    /// When the session in progress started; only set while the status is "running"
+    #[serde(rename = "runningSince", with = "crate::timestamp::optional", default, skip_serializing_if = "Option::is_none")]
+    pub running_since: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Which accounts a queue run or schedule applies to.
+/// Stored as a string: "all", "tag:<name>", "user:<discord id>" or "names:<a>,<b>,...".
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum AccountFilter {
+    All,
+    Tag(String),
+    User(String),
+    /// Exactly these accounts, run in this order
+    Names(Vec<String>),
+}
+
+impl AccountFilter {
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let input = input.trim();
+        if input.eq_ignore_ascii_case("all") {
+            return Ok(AccountFilter::All);
+        }
+        if let Some(tag) = input.strip_prefix("tag:") {
+            let tag = tag.trim().to_lowercase();
+            if tag.is_empty() {
+                return Err("Tag filter needs a tag name, e.g. 'tag:guild'.".to_string());
+            }
+            return Ok(AccountFilter::Tag(tag));
+        }
+        if let Some(user) = input.strip_prefix("user:") {
+            // Accept raw ids as well as pasted mentions like <@123> / <@!123>
+            let id = user.trim().trim_start_matches("<@").trim_start_matches('!').trim_end_matches('>');
+            if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) {
+                return Err(format!("'{}' is not a Discord user id or mention.", user.trim()));
+            }
+            return Ok(AccountFilter::User(id.to_string()));
+        }
+        if let Some(names) = input.strip_prefix("names:") {
+            let names: Vec<String> = names.split(',').map(|n| n.trim().to_string()).filter(|n| !n.is_empty()).collect();
+            if names.is_empty() {
+                return Err("Names filter needs at least one account name, e.g. 'names:alt1,alt2'.".to_string());
+            }
+            return Ok(AccountFilter::Names(names));
+        }
+        Err(format!("Unknown filter '{}'. Use 'all', 'tag:<name>', 'user:<id>' or 'names:<a>,<b>'.", input))
+    }
+
+    pub fn matches(&self, account: &Account) -> bool {
+        match self {
+            AccountFilter::All => true,
+            AccountFilter::Tag(tag) => account.tags.iter().any(|t| t == tag),
+            AccountFilter::User(uid) => account.user_id.as_deref() == Some(uid.as_str()),
+            AccountFilter::Names(names) => names.contains(&account.name),
+        }
+    }
+
+    /// Position in an explicit name list; other filters have no order of their own.
+    pub fn position(&self, account: &Account) -> Option<usize> {
+        match self {
+            AccountFilter::Names(names) => names.iter().position(|n| *n == account.name),
+            _ => None,
+        }
+    }
+}
+
+/// Shell-style match: `*` is any run of characters, `?` a single one. Case-insensitive.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last '*' was and how much of the name it has swallowed so far
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+impl std::fmt::Display for AccountFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccountFilter::All => write!(f, "all"),
+            AccountFilter::Tag(tag) => write!(f, "tag:{}", tag),
+            AccountFilter::User(uid) => write!(f, "user:{}", uid),
+            AccountFilter::Names(names) => write!(f, "names:{}", names.join(",")),
+        }
+    }
+}
+
+impl TryFrom<String> for AccountFilter {
+    type Error = String;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        AccountFilter::parse(&value)
+    }
+}
+
+impl From<AccountFilter> for String {
+    fn from(filter: AccountFilter) -> Self {
+        filter.to_string()
+    }
+}
+
+/// A recurring queue run at a fixed local time, in addition to the midnight daily reset.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Schedule {
+    pub id: u32,
+    /// "HH:MM", in the scheduler's timezone
+    pub time: String,
+    pub filter: AccountFilter,
+    /// Runs this flow instead of each account's own one
+    #[serde(default)]
+    pub flow: Option<Flow>,
+}
+
+impl Schedule {
+    pub fn flow_label(&self) -> &'static str {
+        self.flow.map(|f| f.name()).unwrap_or("account flow")
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditEntry {
+    #[serde(with = "crate::timestamp::required")]
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    #[serde(rename = "userId")]
+    pub user_id: String,
+    pub username: String,
+    pub command: String,
+    pub args: String,
+    pub outcome: String,
+}
+
+/// Reads a list of log entries, dropping (with a warning) the ones that can't be read, e.g. with
+/// a damaged timestamp, so a single bad entry doesn't keep the whole database from loading.
+fn readable_entries<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::de::DeserializeOwned,
+{
+    let values = Vec::<serde_json::Value>::deserialize(deserializer)?;
+    let total = values.len();
+    let mut first_error = None;
+    let entries: Vec<T> = values.into_iter()
+        .filter_map(|value| match serde_json::from_value(value) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                first_error.get_or_insert(e.to_string());
+                None
+            }
+        })
+        .collect();
+    if let Some(error) = first_error {
+        log!("[WARN] Database: dropped {} unreadable log entries out of {} (first error: {}).", total - entries.len(), total, error);
+    }
+    Ok(entries)
 }
 
+// Oldest audit entries are dropped beyond this
+pub const AUDIT_LOG_LIMIT: usize = 500;
+
+/// One finished session (success or a terminal failure). Transient connection retries are not
+/// recorded on their own; `attempts` counts how many sessions it took to reach this outcome.
 #[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct Settings {
-    #[serde(rename = "cookies")]
-    pub cookies: Option<String>,
-    #[serde(rename = "adminRoleId")]
-    pub admin_role_id: Option<String>,
-    #[serde(rename = "logChannelId")]
-    pub log_channel_id: Option<String>,
-    #[serde(rename = "muteBotMessages")]
-    pub mute_bot_messages: Option<bool>,
+pub struct RunRecord {
+    pub account: String,
+    #[serde(rename = "startedAt", with = "crate::timestamp::required")]
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    #[serde(rename = "durationSecs")]
+    pub duration_secs: u64,
+    pub success: bool,
+    pub error: Option<String>,
+    #[serde(default = "one_attempt")]
+    pub attempts: u32,
+    #[serde(flatten)]
+    pub detail: RunDetail,
+}
+
+/// What a session reported besides its result. Stored inline in the run record; every field
+/// is optional, so older records load unchanged.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RunDetail {
+    /// Per-server results of a run on target server "All"
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub servers: Vec<ServerResult>,
+    #[serde(rename = "commandsSent", default, skip_serializing_if = "is_zero")]
+    pub commands_sent: u32,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rewards: Vec<String>,
+    /// Things that went wrong without failing the run, e.g. a target server that wasn't listed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+    #[serde(rename = "transcriptPath", default, skip_serializing_if = "Option::is_none")]
+    pub transcript_path: Option<String>,
+}
+
+fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+fn one_attempt() -> u32 {
+    1
 }
 
+pub const RUN_HISTORY_LIMIT: usize = 5000;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DbData {
     pub accounts: Vec<Account>,
     pub settings: Settings,
+    #[serde(rename = "auditLog", default, deserialize_with = "readable_entries")]
+    pub audit_log: Vec<AuditEntry>,
+    #[serde(rename = "runHistory", default, deserialize_with = "readable_entries")]
+    pub run_history: Vec<RunRecord>,
+    // Discord user ID -> that user's own preferences
+    #[serde(rename = "userPreferences", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub user_preferences: BTreeMap<String, UserPreferences>,
+    // Scheduler trigger key -> the last occurrence it handled, so a restart doesn't miss or repeat one
+    #[serde(rename = "schedulerHandled", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub scheduler_handled: BTreeMap<String, chrono::DateTime<chrono::Utc>>,
+}
+
+/// Per-user choices that only change what that user sees, not how the bot runs.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct UserPreferences {
+    /// IANA name times are shown in to this user; unset = the bot's timezone
+    #[serde(default)]
+    pub timezone: Option<String>,
+    /// Language of replies to this user; unset = the server's language
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<Locale>,
+}
+
+impl UserPreferences {
+    /// The user's timezone, if set and still valid.
+    pub fn timezone(&self) -> Option<Tz> {
+        self.timezone.as_deref().and_then(|name| name.parse().ok())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.timezone.is_none() && self.locale.is_none()
+    }
 }
 
 pub struct Database {
     pub data: DbData,
+    // File the data was last read from / written to, and its content at that moment.
+    // Used to tell our own writes apart from external edits.
+    path: Option<String>,
+    synced_content: Option<String>,
+    // The in-memory state has changes that failed to reach disk
+    dirty: bool,
+    // Saves in a row that failed on every path; reset by the next successful save
+    save_failures: u32,
+}
+
+/// Result of comparing the database file on disk with what we last read/wrote.
+pub enum ReloadOutcome {
+    Unchanged,
+    Reloaded,
+    /// The file changed but we hold changes that were never saved; in-memory state is kept.
+    Conflict,
+    /// The file changed but does not contain a valid database; in-memory state is kept.
+    Invalid(String),
+}
+
+impl DbData {
+    /// Values that must be masked in logs and Discord messages: restore codes
+    /// (stored and decrypted form) and the session cookie.
+    pub fn secrets(&self) -> Vec<String> {
+        let mut secrets: Vec<String> = self.accounts.iter()
+            .flat_map(|a| [Some(a.code.clone()), Some(a.decrypt_code()), a.cookie_override.clone(), a.decrypt_cookie_override()])
+            .flatten()
+            .collect();
+        secrets.extend(self.settings.cookies.clone());
+        secrets
+    }
+
+    /// Sanity checks applied to externally edited files before they replace the live state.
+    pub fn validate(&self) -> Result<(), String> {
+        let mut names = std::collections::HashSet::new();
+        for acc in self.accounts.iter().filter(|a| !a.deleted) {
+            if acc.name.trim().is_empty() {
+                return Err("an account has an empty name".to_string());
+            }
+            if !names.insert(acc.name.as_str()) {
+                return Err(format!("duplicate account name '{}'", acc.name));
+            }
+            if let Some(note) = &acc.notes {
+                Account::validate_note(note).map_err(|e| format!("account '{}': {}", acc.name, e))?;
+            }
+        }
+        for (user, preferences) in &self.user_preferences {
+            if let Some(name) = &preferences.timezone {
+                parse_timezone(name).map_err(|e| format!("user {}: {}", user, e))?;
+            }
+        }
+        self.settings.validate()
+    }
 }
 
 use magic_crypt::MagicCryptTrait;
 
+pub const RESTORE_CODE_MIN_LEN: usize = 6;
+pub const RESTORE_CODE_MAX_LEN: usize = 64;
+// Notes have to fit comfortably in an embed field and a list line
+pub const NOTES_MAX_LEN: usize = 200;
+// Accounts in this state are skipped by the queue until the code is fixed (or the daily reset).
+pub const STATUS_INVALID_CODE: &str = "error: invalid restore code";
+// Set on an account that had neither its own cookie nor the shared one when its turn came
+pub const STATUS_NO_COOKIE: &str = "error: no session cookie";
+// Set on the account a queue worker was running when it panicked
+pub const STATUS_WORKER_CRASHED: &str = "error: bot crashed during the run";
+// Set while a session for the account is in progress
+pub const STATUS_RUNNING: &str = "running";
+// Beyond the run timeout, before a "running" status counts as stale (the watchdog would have ended the run)
+const STALE_RUNNING_GRACE: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+// Failed saves in a row before the bot goes read-only; a single failure may be a passing hiccup
+pub const READ_ONLY_AFTER_FAILURES: u32 = 3;
+
+/// Statuses that only make sense while the bot is running: a session in progress or an
+/// account waiting for a retry. Seeing one at startup means the previous process died.
+pub fn is_interrupted_status(status: &str) -> bool {
+    status.starts_with("running")
+        || (status.starts_with("error") && (status.contains("Retrying") || status.contains("(requeued after")))
+}
+
 impl Account {
+    /// Whether the account claims to be running for longer than any live session can, e.g.
+    /// because the process died mid-run and startup reset is turned off.
+    pub fn is_stale_running(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+        if self.status != STATUS_RUNNING {
+            return false;
+        }
+        let limit = crate::config::get().run_timeout + STALE_RUNNING_GRACE;
+        match self.running_since {
+            Some(since) => (now - since).to_std().map(|age| age > limit).unwrap_or(false),
+            // Set by something other than `mark_running`
+            None => true,
+        }
+    }
+
+    /// Whether the account's last status change (see `update_status`) was at or after `boundary`.
+    pub fn ran_since<Tz: chrono::TimeZone>(&self, boundary: &chrono::DateTime<Tz>) -> bool {
+        self.last_run.map(|t| t >= *boundary).unwrap_or(false)
+    }
+
+    /// Status for lists, with stale "running" entries flagged.
+    pub fn status_label(&self, now: chrono::DateTime<chrono::Utc>) -> String {
+        if self.is_stale_running(now) {
+            let since = self.running_since.map(|t| t.to_rfc3339()).unwrap_or_else(|| "an unknown time".to_string());
+            format!("stale running (since {}; no session is active, reset it with /purge_done)", since)
+        } else {
+            self.status.clone()
+        }
+    }
+
+    /// Checks that a (decrypted) restore code looks plausible before it is stored or sent.
+    /// Codes are alphanumeric (dashes allowed) and between 6 and 64 characters long.
+    pub fn validate_code(code: &str) -> Result<(), String> {
+        let code = code.trim();
+        if code.is_empty() {
+            return Err("Restore code is empty.".to_string());
+        }
+        if code.len() < RESTORE_CODE_MIN_LEN || code.len() > RESTORE_CODE_MAX_LEN {
+            return Err(format!(
+                "Restore code must be between {} and {} characters long (got {}).",
+                RESTORE_CODE_MIN_LEN, RESTORE_CODE_MAX_LEN, code.len()
+            ));
+        }
+        if let Some(bad) = code.chars().find(|c| !c.is_ascii_alphanumeric() && *c != '-') {
+            return Err(format!(
+                "Restore code contains an invalid character '{}'. Only letters, digits and '-' are allowed.",
+                bad
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks a note before it is stored: one line of at most `NOTES_MAX_LEN` characters.
+    pub fn validate_note(note: &str) -> Result<(), String> {
+        let length = note.chars().count();
+        if length > NOTES_MAX_LEN {
+            return Err(format!("Notes can be at most {} characters long (got {}).", NOTES_MAX_LEN, length));
+        }
+        if note.chars().any(|c| c.is_control()) {
+            return Err("Notes must be a single line of text.".to_string());
+        }
+        Ok(())
+    }
+
+    /// Form of a restore code used to compare accounts: surrounding whitespace, dashes and case are ignored.
+    pub fn normalize_code(code: &str) -> String {
+        code.trim().chars().filter(|c| *c != '-').map(|c| c.to_ascii_lowercase()).collect()
+    }
+
     pub fn decrypt_code(&self) -> String {
+        Self::decrypt_str(&self.code)
+    }
+
+    fn decrypt_str(stored: &str) -> String {
         let key = std::env::var("ENCRYPTION_KEY").unwrap_or_else(|_| "default_insecure_key".to_string());
         if key == "default_insecure_key" {
             // Warn only once or just proceed? For now, just return raw if likely not encrypted or using default
-             return self.code.clone();
+             return stored.to_string();
         }
         let mc = magic_crypt::new_magic_crypt!(&key, 256);
-        match mc.decrypt_base64_to_string(&self.code) {
+        match mc.decrypt_base64_to_string(stored) {
              Ok(decrypted) => decrypted,
              Err(_) => {
                  // Fallback: maybe it's not encrypted yet? Return raw.
-                 self.code.clone()
+                 stored.to_string()
              }
         }
     }
 
+    pub fn decrypt_cookie_override(&self) -> Option<String> {
+        self.cookie_override.as_deref().map(Self::decrypt_str).filter(|c| !c.is_empty())
+    }
+
+    /// Session cookie to run this account with: its own override, else the global one.
+    pub fn session_cookie(&self, global: Option<&str>) -> String {
+        self.decrypt_cookie_override().unwrap_or_else(|| global.unwrap_or_default().to_string())
+    }
+
+    /// Mention that pings the owner, if they have one and turned pings on with /toggle_ping.
+    pub fn owner_ping(&self) -> Option<String> {
+        self.user_id.as_ref().filter(|_| self.ping_enabled).map(|u| format!("<@{}>", u))
+    }
+
     pub fn encrypt_code_str(raw_code: &str) -> String {
         let key = std::env::var("ENCRYPTION_KEY").unwrap_or_else(|_| "default_insecure_key".to_string());
         if key == "default_insecure_key" {
@@ -72,28 +500,35 @@ impl Account {
 }
 
 impl Database {
+    /// A database that is never read from or written to disk.
+    #[cfg(test)]
+    pub fn in_memory(data: DbData) -> Self {
+        Self { data, path: None, synced_content: None, dirty: false, save_failures: 0 }
+    }
+
     pub fn load() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let path = std::env::var("DATABASE_PATH").unwrap_or_else(|_| "db.json".to_string());
+        let path = crate::config::get().database_path.clone();
         
         // --- Diagnostics ---
         if let Ok(cwd) = std::env::current_dir() {
-            println!("[DEBUG] Current working directory: {:?}", cwd);
+            log!("[DEBUG] Current working directory: {:?}", cwd);
         }
         for dir in [".", "/app", "/"] {
             if let Ok(entries) = fs::read_dir(dir) {
                 let files: Vec<_> = entries.filter_map(|e| e.ok().map(|e| e.file_name().into_string().unwrap_or_default())).collect();
-                println!("[DEBUG] Files in '{}': {:?}", dir, files);
+                log!("[DEBUG] Files in '{}': {:?}", dir, files);
             }
         }
         // --- End Diagnostics ---
 
+        let mut loaded_from = Some(path.clone());
         let content = match fs::read_to_string(&path) {
             Ok(c) => {
-                println!("[INFO] Loading database from file: {}", path);
+                log!("[INFO] Loading database from file: {}", path);
                 c
             },
             Err(_e) => {
-                println!("[WARN] Could not find database at {}. Searching fallbacks...", path);
+                log!("[WARN] Could not find database at {}. Searching fallbacks...", path);
                 // Try several fallback locations
                 let fallbacks = [
                     "db.json", 
@@ -106,19 +541,21 @@ impl Database {
                 
                 for fb in fallbacks {
                     if let Ok(c) = fs::read_to_string(fb) {
-                        println!("[INFO] Found database at fallback: {}", fb);
-                        found_content = Some(c);
+                        log!("[INFO] Found database at fallback: {}", fb);
+                        found_content = Some((fb, c));
                         break;
                     }
                 }
                 
                 match found_content {
-                    Some(c) => {
-                        println!("[INFO] Using database from fallback file.");
+                    Some((fb, c)) => {
+                        log!("[INFO] Using database from fallback file.");
+                        loaded_from = Some(fb.to_string());
                         c
                     },
                     None => {
-                        println!("[WARN] No database file found on disk. Using EMBEDDED database fallback.");
+                        log!("[WARN] No database file found on disk. Using EMBEDDED database fallback.");
+                        loaded_from = None;
                         // Fallback to embedded content so the bot doesn't crash
                         include_str!("../db.json").to_string()
                     }
@@ -127,9 +564,19 @@ impl Database {
         };
 
         match serde_json::from_str::<DbData>(&content) {
-            Ok(data) => Ok(Self { data }),
+            Ok(mut data) => {
+                if let Some(path) = &loaded_from {
+                    // Refuse to start without the secrets rather than saving over them later
+                    if let Some(secrets) = Secrets::read(&secrets::path_for(path))? {
+                        secrets.apply(&mut data);
+                    }
+                }
+                let synced_content = loaded_from.as_ref().map(|_| content.clone());
+                redact::set_secrets(data.secrets());
+                Ok(Self { data, path: loaded_from, synced_content, dirty: false, save_failures: 0 })
+            },
             Err(e) => {
-                println!("[ERROR] Failed to parse database JSON: {}", e);
+                log!("[ERROR] Failed to parse database JSON: {}", e);
                 // If parsing fails, we might as well return the error, 
                 // but at least we tried every path.
                 Err(e.into())
@@ -137,69 +584,467 @@ impl Database {
         }
     }
 
-    pub fn save(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let path = std::env::var("DATABASE_PATH").unwrap_or_else(|_| "db.json".to_string());
+    pub fn save(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let path = crate::config::get().database_path.clone();
         let content = serde_json::to_string_pretty(&self.data)?;
+        redact::set_secrets(self.data.secrets());
         
         // Try to save to multiple locations to ensure persistence if possible
         let paths = [path.as_str(), "db.json", "/app/db.json"];
         let mut saved = false;
 
+        let secrets = Secrets::extract(&self.data);
         for p in paths {
+            // Secrets first: db.json no longer carries them once written
+            if let Err(e) = secrets.write(&secrets::path_for(p)) {
+                log!("[WARN] Failed to save secrets next to {}: {}", p, e);
+                continue;
+            }
             if let Err(e) = fs::write(p, content.clone()) {
-                println!("[WARN] Failed to save database to {}: {}", p, e);
+                log!("[WARN] Failed to save database to {}: {}", p, e);
             } else {
-                println!("[INFO] Successfully saved database to {}", p);
+                log!("[INFO] Successfully saved database to {}", p);
                 saved = true;
+                self.path = Some(p.to_string());
+                self.synced_content = Some(content.clone());
                 // We only need to save to one location successfully
                 break; // Added break here to stop trying once saved
             }
         }
 
         if !saved {
-            println!("[ERROR] Failed to save database to ANY location!");
+            log!("[ERROR] Failed to save database to ANY location!");
+            reporting::report_error("database", "Failed to save database to any location", &[
+                ("paths", paths.join(", ")),
+                ("accounts", self.data.accounts.len().to_string()),
+            ]);
+            alerts::send(Alert::DatabaseSaveFailed, "Failed to save the database to any location; changes are only in memory.", &[
+                ("paths", paths.join(", ")),
+            ]);
+            self.dirty = true;
+            self.save_failures = self.save_failures.saturating_add(1);
             return Err("Failed to save database to any location".into());
         }
+        self.dirty = false;
+        self.save_failures = 0;
         Ok(())
     }
 
+    /// Changes failed to reach disk and are only in memory.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Saving has failed `READ_ONLY_AFTER_FAILURES` times in a row: the bot starts no runs and
+    /// takes no new accounts until a save goes through again.
+    pub fn is_read_only(&self) -> bool {
+        self.save_failures >= READ_ONLY_AFTER_FAILURES
+    }
+
+    /// Picks up edits made to the database file by hand while the bot is running.
+    /// The file is only re-read when its content differs from what we last loaded or saved.
+    pub fn reload_if_changed(&mut self) -> ReloadOutcome {
+        let path = match &self.path {
+            Some(p) => p.clone(),
+            None => return ReloadOutcome::Unchanged,
+        };
+        let content = match fs::read_to_string(&path) {
+            Ok(c) => c,
+            Err(_) => return ReloadOutcome::Unchanged,
+        };
+        if self.synced_content.as_deref() == Some(content.as_str()) {
+            return ReloadOutcome::Unchanged;
+        }
+
+        let mut data = match serde_json::from_str::<DbData>(&content) {
+            Ok(data) => data,
+            Err(e) => return ReloadOutcome::Invalid(format!("JSON error: {}", e)),
+        };
+        match Secrets::read(&secrets::path_for(&path)) {
+            Ok(Some(secrets)) => secrets.apply(&mut data),
+            Ok(None) => {}
+            Err(e) => return ReloadOutcome::Invalid(e),
+        }
+        if let Err(e) = data.validate() {
+            return ReloadOutcome::Invalid(e);
+        }
+        if self.dirty {
+            return ReloadOutcome::Conflict;
+        }
+
+        redact::set_secrets(data.secrets());
+        self.data = data;
+        self.synced_content = Some(content);
+        ReloadOutcome::Reloaded
+    }
+
+    /// Replaces the whole live state (e.g. with a backup) and saves it.
+    pub fn replace_data(&mut self, data: DbData) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        data.validate()?;
+        self.data = data;
+        self.save()
+    }
+
+    /// Replaces the bot-wide settings, e.g. from another host. The session cookie is kept when
+    /// `settings` has none.
+    pub fn replace_settings(&mut self, mut settings: Settings) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        settings.validate()?;
+        if settings.cookies.is_none() {
+            settings.cookies = self.data.settings.cookies.take();
+            settings.cookies_set_at = self.data.settings.cookies_set_at;
+        } else {
+            settings.cookies_set_at = Some(chrono::Utc::now());
+        }
+        self.data.settings = settings;
+        self.save()
+    }
+
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// Read/write self-test for /diagnose: the database file must parse, and a probe file next
+    /// to it must round-trip. The live database file itself is not touched.
+    pub fn check_storage(&self) -> Result<String, String> {
+        let path = self.path.clone()
+            .unwrap_or_else(|| crate::config::get().database_path.clone());
+        let content = fs::read_to_string(&path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+        serde_json::from_str::<DbData>(&content).map_err(|e| format!("{} is not a valid database: {}", path, e))?;
+        Secrets::read(&secrets::path_for(&path))?;
+
+        let probe_path = format!("{}.probe", path);
+        let probe = chrono::Utc::now().to_rfc3339();
+        fs::write(&probe_path, &probe).map_err(|e| format!("Cannot write next to {}: {}", path, e))?;
+        let read_back = fs::read_to_string(&probe_path);
+        let _ = fs::remove_file(&probe_path);
+        if read_back.map_err(|e| format!("Cannot read back {}: {}", probe_path, e))? != probe {
+            return Err(format!("{} did not read back what was written", probe_path));
+        }
+        if self.is_read_only() {
+            return Err(format!("{} is writable, but {} saves in a row failed; the bot is read-only until the next save succeeds", path, self.save_failures));
+        }
+        if self.dirty {
+            return Err(format!("{} is writable, but the last save failed; changes are only in memory", path));
+        }
+        Ok(format!("{} is readable and writable", path))
+    }
+
+    /// Accounts that have not been soft-deleted.
+    pub fn active_accounts(&self) -> impl Iterator<Item = &Account> {
+        self.data.accounts.iter().filter(|a| !a.deleted)
+    }
+
+    pub fn find_account(&self, name: &str) -> Option<&Account> {
+        self.active_accounts().find(|a| a.name == name)
+    }
+
+    /// Another active account (not named `name`) that uses the same restore code.
+    pub fn find_duplicate_code(&self, code: &str, name: &str) -> Option<&Account> {
+        let code = Account::normalize_code(code);
+        self.active_accounts().find(|a| a.name != name && Account::normalize_code(&a.decrypt_code()) == code)
+    }
+
     pub fn update_status(&mut self, name: &str, status: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         if let Some(acc) = self.data.accounts.iter_mut().find(|a| a.name == name) {
             acc.status = status.to_string();
-            acc.last_run = Some(chrono::Utc::now().to_rfc3339());
+            acc.last_run = Some(chrono::Utc::now());
+            if status != STATUS_RUNNING {
+                acc.running_since = None;
+            }
             self.save()?;
         }
         Ok(())
     }
 
+    /// Marks the start of a session: status "running" since `started_at`.
+    pub fn mark_running(&mut self, name: &str, started_at: chrono::DateTime<chrono::Utc>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if let Some(acc) = self.data.accounts.iter_mut().find(|a| a.name == name) {
+            acc.status = STATUS_RUNNING.to_string();
+            acc.running_since = Some(started_at);
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Puts an account that is still "running" after its session ended (an outcome that sets
+    /// no status of its own, e.g. a retry or a stopped queue) back to pending.
+    pub fn release_running(&mut self, name: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match self.data.accounts.iter().find(|a| a.name == name) {
+            Some(acc) if acc.status == STATUS_RUNNING => self.update_status(name, "pending"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Adds a new account. A name that is taken, also by a soft-deleted account, is refused so
+    /// the deleted account's restore code isn't lost.
     pub fn add_account(&mut self, account: Account) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        self.data.accounts.retain(|a| a.name != account.name);
+        if let Some(existing) = self.data.accounts.iter().find(|a| a.name == account.name) {
+            let state = if existing.deleted { "a deleted account" } else { "an account" };
+            return Err(format!("There is already {} named '{}'", state, account.name).into());
+        }
         self.data.accounts.push(account);
         self.save()
     }
 
+    /// Replaces the active account with the same name (e.g. re-added with a new restore code).
+    /// Returns false if there is none.
+    pub fn replace_account(&mut self, account: Account) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let found = match self.data.accounts.iter_mut().find(|a| a.name == account.name && !a.deleted) {
+            Some(acc) => {
+                *acc = account;
+                true
+            }
+            None => false,
+        };
+        if found {
+            self.save()?;
+        }
+        Ok(found)
+    }
+
+    /// Whether a soft-deleted account still holds `name`.
+    pub fn is_deleted_name(&self, name: &str) -> bool {
+        self.data.accounts.iter().any(|a| a.name == name && a.deleted)
+    }
+
+    /// Soft-deletes an account: it disappears from lists and the queue but keeps its
+    /// restore code until `purge_deleted` is run, so it can be brought back with `restore_account`.
     pub fn remove_account(&mut self, name: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let found = match self.data.accounts.iter_mut().find(|a| a.name == name && !a.deleted) {
+            Some(acc) => {
+                acc.deleted = true;
+                acc.deleted_at = Some(chrono::Utc::now());
+                true
+            }
+            None => false,
+        };
+        if found {
+            self.save()?;
+        }
+        Ok(found)
+    }
+
+    pub fn restore_account(&mut self, name: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let found = match self.data.accounts.iter_mut().find(|a| a.name == name && a.deleted) {
+            Some(acc) => {
+                acc.deleted = false;
+                acc.deleted_at = None;
+                true
+            }
+            None => false,
+        };
+        if found {
+            self.save()?;
+        }
+        Ok(found)
+    }
+
+    pub fn deleted_accounts(&self) -> Vec<Account> {
+        self.data.accounts.iter().filter(|a| a.deleted).cloned().collect()
+    }
+
+    /// Permanently removes every soft-deleted account. Returns how many were purged.
+    pub fn purge_deleted(&mut self) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
         let len_before = self.data.accounts.len();
-        self.data.accounts.retain(|a| a.name != name);
-        let found = self.data.accounts.len() < len_before;
+        self.data.accounts.retain(|a| !a.deleted);
+        let purged = len_before - self.data.accounts.len();
+        if purged > 0 {
+            self.save()?;
+        }
+        Ok(purged)
+    }
+
+    /// Sets matching accounts back to pending so a scheduled run picks them up again.
+    pub fn reset_statuses_matching(&mut self, filter: &AccountFilter) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let mut count = 0;
+        for acc in self.data.accounts.iter_mut().filter(|a| !a.deleted && filter.matches(a)) {
+            acc.status = "pending".to_string();
+            acc.running_since = None;
+            count += 1;
+        }
+        self.save()?;
+        Ok(count)
+    }
+
+    pub fn set_tags(&mut self, name: &str, tags: Vec<String>) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let found = match self.data.accounts.iter_mut().find(|a| a.name == name && !a.deleted) {
+            Some(acc) => {
+                acc.tags = tags;
+                true
+            }
+            None => false,
+        };
+        if found {
+            self.save()?;
+        }
+        Ok(found)
+    }
+
+    /// Sets or (with `None` or blank text) clears the notes of an account.
+    pub fn set_note(&mut self, name: &str, note: Option<String>) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let note = note.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+        if let Some(note) = &note {
+            Account::validate_note(note)?;
+        }
+        let found = match self.data.accounts.iter_mut().find(|a| a.name == name && !a.deleted) {
+            Some(acc) => {
+                acc.notes = note;
+                true
+            }
+            None => false,
+        };
         if found {
             self.save()?;
         }
         Ok(found)
     }
 
+    pub fn set_flow(&mut self, name: &str, flow: Flow) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let found = match self.data.accounts.iter_mut().find(|a| a.name == name && !a.deleted) {
+            Some(acc) => {
+                acc.flow = flow;
+                true
+            }
+            None => false,
+        };
+        if found {
+            self.save()?;
+        }
+        Ok(found)
+    }
+
+    pub fn add_schedule(&mut self, time: String, filter: AccountFilter, flow: Option<Flow>) -> Result<u32, Box<dyn std::error::Error + Send + Sync>> {
+        chrono::NaiveTime::parse_from_str(&time, "%H:%M").map_err(|_| format!("Invalid time '{}'. Use 24h HH:MM, e.g. 18:30.", time))?;
+        let id = self.data.settings.schedules.iter().map(|s| s.id).max().unwrap_or(0) + 1;
+        self.data.settings.schedules.push(Schedule { id, time, filter, flow });
+        self.save()?;
+        Ok(id)
+    }
+
+    pub fn remove_schedule(&mut self, id: u32) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let len_before = self.data.settings.schedules.len();
+        self.data.settings.schedules.retain(|s| s.id != id);
+        let found = self.data.settings.schedules.len() < len_before;
+        if found {
+            self.save()?;
+        }
+        Ok(found)
+    }
+
+    /// Sets matching accounts back to pending so the queue runs them again. With `errors_only`,
+    /// only accounts whose last run failed are reset. Returns the names that changed.
+    pub fn reset_statuses(&mut self, filter: &AccountFilter, errors_only: bool) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut reset = Vec::new();
+        for acc in self.data.accounts.iter_mut().filter(|a| !a.deleted && a.status != "pending" && filter.matches(a)) {
+            if errors_only && !acc.status.starts_with("error") {
+                continue;
+            }
+            acc.status = "pending".to_string();
+            acc.running_since = None;
+            reset.push(acc.name.clone());
+        }
+        if !reset.is_empty() {
+            self.save()?;
+        }
+        Ok(reset)
+    }
+
     pub fn reset_all_statuses(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         for acc in self.data.accounts.iter_mut() {
             acc.status = "pending".to_string();
+            acc.running_since = None;
         }
         self.save()
     }
 
+    /// Expands a comma-separated list of names and glob patterns ("alt1,guild-*") into account
+    /// names, in the order given. Returns the names and the entries that matched nothing.
+    /// With `owner` set, only that user's accounts match; anyone else's count as no match.
+    pub fn resolve_account_list(&self, spec: &str, owner: Option<&str>) -> (Vec<String>, Vec<String>) {
+        let mut names: Vec<String> = Vec::new();
+        let mut unmatched = Vec::new();
+        let owned = |a: &&Account| owner.is_none() || a.user_id.as_deref() == owner;
+        for entry in spec.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()) {
+            let matched: Vec<String> = if entry.contains('*') || entry.contains('?') {
+                self.active_accounts().filter(owned).filter(|a| glob_match(entry, &a.name)).map(|a| a.name.clone()).collect()
+            } else {
+                self.find_account(entry).filter(owned).map(|a| vec![a.name.clone()]).unwrap_or_default()
+            };
+            if matched.is_empty() {
+                unmatched.push(entry.to_string());
+            }
+            for name in matched {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        (names, unmatched)
+    }
+
+    /// Finds accounts left in an interrupted state and, if `reset` is set, puts them back to pending.
+    /// Returns each affected account with the status it was found in.
+    pub fn reconcile_interrupted(&mut self, reset: bool) -> Result<Vec<(String, String)>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut found = Vec::new();
+        for acc in self.data.accounts.iter_mut().filter(|a| !a.deleted && is_interrupted_status(&a.status)) {
+            found.push((acc.name.clone(), acc.status.clone()));
+            if reset {
+                acc.status = "pending".to_string();
+                acc.running_since = None;
+            }
+        }
+        if reset && !found.is_empty() {
+            self.save()?;
+        }
+        Ok(found)
+    }
+
+    /// Hands an active account to another Discord user. Returns false if it doesn't exist.
+    pub fn set_owner(&mut self, name: &str, user_id: &str, username: &str, nickname: Option<String>) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        match self.data.accounts.iter_mut().find(|a| a.name == name && !a.deleted) {
+            Some(acc) => {
+                acc.user_id = Some(user_id.to_string());
+                acc.username = Some(username.to_string());
+                acc.discord_nickname = nickname;
+                self.save()?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Gives an unowned account (e.g. from a legacy import) to `user_id`. Returns false if the
+    /// account doesn't exist or already has an owner.
+    pub fn claim_account(&mut self, name: &str, user_id: &str, username: &str, nickname: Option<String>) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        if !self.find_account(name).map(|a| a.user_id.is_none()).unwrap_or(false) {
+            return Ok(false);
+        }
+        self.set_owner(name, user_id, username, nickname)
+    }
+
+    /// Clears an active account's owner, keeping the account itself (and its restore code) so it
+    /// can be claimed again. The owner's session cookie goes with them. Returns false if it
+    /// doesn't exist.
+    pub fn detach_account(&mut self, name: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        match self.data.accounts.iter_mut().find(|a| a.name == name && !a.deleted) {
+            Some(acc) => {
+                acc.user_id = None;
+                acc.username = None;
+                acc.discord_nickname = None;
+                acc.ping_enabled = false;
+                acc.cookie_override = None;
+                self.save()?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
     pub fn toggle_ping(&mut self, user_id: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
         let mut new_state = false;
         let mut first = true;
         let accounts: Vec<_> = self.data.accounts.iter_mut()
-            .filter(|a| a.user_id.as_deref() == Some(user_id))
+            .filter(|a| !a.deleted && a.user_id.as_deref() == Some(user_id))
             .collect();
         
         if accounts.is_empty() {
@@ -224,18 +1069,284 @@ impl Database {
         self.save()
     }
 
-    pub fn set_log_channel(&mut self, channel_id: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    pub fn set_maintenance(&mut self, on: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.data.settings.maintenance_mode = Some(on);
+        self.save()
+    }
+
+    pub fn set_catch_up_first(&mut self, on: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.data.settings.catch_up_first = Some(on);
+        self.save()
+    }
+
+    pub fn set_preflight_halt(&mut self, on: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.data.settings.preflight_halt = Some(on);
+        self.save()
+    }
+
+    pub fn set_run_threads(&mut self, on: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.data.settings.run_threads = Some(on);
+        self.save()
+    }
+
+    pub fn set_notify_sinks(&mut self, severity: Severity, sinks: Vec<Sink>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.data.settings.set_notify_sinks(severity, sinks);
+        self.save()
+    }
+
+    pub fn set_log_level(&mut self, level: LogLevel) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.data.settings.log_level = Some(level);
+        self.save()
+    }
+
+    pub fn set_log_channel(&mut self, channel_id: ChannelId) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         self.data.settings.log_channel_id = Some(channel_id);
         self.save()
     }
 
-    pub fn set_admin_role(&mut self, role_id: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        self.data.settings.admin_role_id = Some(role_id);
+    /// Sets the admin role, or clears it with `None` (/reset_admin_role).
+    pub fn set_admin_role(&mut self, role_id: Option<RoleId>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.data.settings.admin_role_id = role_id;
+        self.save()
+    }
+
+    /// Assigns a tier to a role, or removes it with `None`.
+    pub fn set_role_tier(&mut self, role_id: RoleId, tier: Option<Tier>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match tier {
+            Some(tier) => { self.data.settings.role_tiers.insert(role_id, tier); },
+            None => { self.data.settings.role_tiers.remove(&role_id); },
+        }
+        self.save()
+    }
+
+    /// Highest tier granted by any of the given roles.
+    pub fn tier_for_roles<I: IntoIterator<Item = RoleId>>(&self, roles: I) -> Option<Tier> {
+        let admin_role = self.data.settings.admin_role_id;
+        roles.into_iter()
+            .filter_map(|role| {
+                if Some(role) == admin_role {
+                    Some(Tier::Admin)
+                } else {
+                    self.data.settings.role_tiers.get(&role).copied()
+                }
+            })
+            .max()
+    }
+
+    /// Stores (encrypted) or clears an account's own session cookie.
+    pub fn set_cookie_override(&mut self, name: &str, cookie: Option<&str>) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        match self.data.accounts.iter_mut().find(|a| a.name == name && !a.deleted) {
+            Some(acc) => {
+                acc.cookie_override = cookie.map(Account::encrypt_code_str);
+                self.save()?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Sets (normalized) or clears the websocket endpoint. Invalid input is rejected unsaved.
+    pub fn set_base_url(&mut self, base_url: Option<&str>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.data.settings.set_base_url(base_url)?;
+        self.save()
+    }
+
+    pub fn set_timezone(&mut self, name: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.data.settings.set_timezone(name)?;
+        self.save()
+    }
+
+    /// Sets (validated) or clears the timezone one user sees times in.
+    pub fn set_user_timezone(&mut self, user_id: &str, name: Option<&str>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let timezone = name.map(parse_timezone).transpose()?.map(|tz| tz.name().to_string());
+        self.data.user_preferences.entry(user_id.to_string()).or_default().timezone = timezone;
+        self.data.user_preferences.retain(|_, p| !p.is_empty());
+        self.save()
+    }
+
+    /// Stores the scheduler's last handled occurrence per trigger.
+    pub fn set_scheduler_handled(&mut self, handled: BTreeMap<String, chrono::DateTime<chrono::Utc>>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.data.scheduler_handled = handled;
+        self.save()
+    }
+
+    /// Sets or clears (back to the server's language) the language replies to one user are in.
+    pub fn set_user_locale(&mut self, user_id: &str, locale: Option<Locale>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.data.user_preferences.entry(user_id.to_string()).or_default().locale = locale;
+        self.data.user_preferences.retain(|_, p| !p.is_empty());
+        self.save()
+    }
+
+    pub fn set_locale(&mut self, locale: Locale) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.data.settings.locale = Some(locale);
         self.save()
     }
 
+    /// Timezone to show `user_id` times in: their own choice, else the bot's.
+    pub fn user_timezone(&self, user_id: &str) -> Tz {
+        self.data.user_preferences.get(user_id)
+            .and_then(|p| p.timezone())
+            .unwrap_or_else(|| self.data.settings.timezone())
+    }
+
+    pub fn set_digest_day(&mut self, day: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.data.settings.set_digest_day(day)?;
+        self.save()
+    }
+
+    /// Adds a channel to the command allowlist. Returns false if it was already allowed.
+    pub fn allow_channel(&mut self, channel_id: ChannelId) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        if self.data.settings.allowed_channels.contains(&channel_id) {
+            return Ok(false);
+        }
+        self.data.settings.allowed_channels.push(channel_id);
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Removes a channel from the command allowlist. Returns false if it wasn't on it.
+    pub fn deny_channel(&mut self, channel_id: ChannelId) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let len_before = self.data.settings.allowed_channels.len();
+        self.data.settings.allowed_channels.retain(|c| *c != channel_id);
+        if self.data.settings.allowed_channels.len() == len_before {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn is_channel_allowed(&self, channel_id: ChannelId) -> bool {
+        let allowed = &self.data.settings.allowed_channels;
+        allowed.is_empty() || allowed.contains(&channel_id)
+    }
+
+    pub fn record_audit(&mut self, user_id: &str, username: &str, command: &str, args: String, outcome: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.data.audit_log.push(AuditEntry {
+            timestamp: chrono::Utc::now(),
+            user_id: user_id.to_string(),
+            username: username.to_string(),
+            command: command.to_string(),
+            args,
+            outcome,
+        });
+        let excess = self.data.audit_log.len().saturating_sub(AUDIT_LOG_LIMIT);
+        if excess > 0 {
+            self.data.audit_log.drain(..excess);
+        }
+        self.save()
+    }
+
+    pub fn record_run(&mut self, account: &str, started_at: chrono::DateTime<chrono::Utc>, duration: std::time::Duration, error: Option<String>, attempts: u32, detail: RunDetail) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.data.run_history.push(RunRecord {
+            account: account.to_string(),
+            started_at,
+            duration_secs: duration.as_secs(),
+            success: error.is_none(),
+            error,
+            attempts: attempts.max(1),
+            detail,
+        });
+        let excess = self.data.run_history.len().saturating_sub(RUN_HISTORY_LIMIT);
+        if excess > 0 {
+            self.data.run_history.drain(..excess);
+        }
+        self.save()
+    }
+
+    /// Newest first.
+    pub fn recent_audit(&self, count: usize) -> Vec<AuditEntry> {
+        self.data.audit_log.iter().rev().take(count).cloned().collect()
+    }
+
+    /// Adds newly seen server names to the known list. Returns true if anything was added.
+    pub fn record_known_servers(&mut self, servers: &[String]) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let mut added = false;
+        for server in servers {
+            let server = server.trim();
+            if server.is_empty() || self.data.settings.known_servers.iter().any(|k| k.eq_ignore_ascii_case(server)) {
+                continue;
+            }
+            self.data.settings.known_servers.push(server.to_string());
+            added = true;
+        }
+        if added {
+            self.data.settings.known_servers.sort();
+            self.save()?;
+        }
+        Ok(added)
+    }
+
+    /// Normalizes a target server against the known list ("all" is always accepted).
+    /// While no server list has been seen yet, any non-empty value is accepted as-is.
+    pub fn validate_server(&self, input: &str) -> Result<String, String> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Err("Server name is empty.".to_string());
+        }
+        if input.eq_ignore_ascii_case("all") {
+            return Ok("All".to_string());
+        }
+        let known = &self.data.settings.known_servers;
+        if known.is_empty() {
+            return Ok(input.to_string());
+        }
+        if let Some(k) = known.iter().find(|k| k.eq_ignore_ascii_case(input)) {
+            return Ok(k.clone());
+        }
+        let lowered = input.to_lowercase();
+        let suggestions: Vec<&str> = known.iter()
+            .filter(|k| k.to_lowercase().contains(&lowered) || lowered.contains(&k.to_lowercase()))
+            .map(|k| k.as_str())
+            .take(5)
+            .collect();
+        if suggestions.is_empty() {
+            Err(format!("Unknown server '{}'. Known servers: {}, or All.", input, known.join(", ")))
+        } else {
+            Err(format!("Unknown server '{}'. Did you mean: {}?", input, suggestions.join(", ")))
+        }
+    }
+
+    pub fn set_target_server(&mut self, name: &str, server: Option<String>) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let found = match self.data.accounts.iter_mut().find(|a| a.name == name && !a.deleted) {
+            Some(acc) => {
+                acc.target_server = server;
+                true
+            }
+            None => false,
+        };
+        if found {
+            self.save()?;
+        }
+        Ok(found)
+    }
+
+    /// Replaces a target server the game no longer lists with the server a run fell back to.
+    /// Does nothing (returns false) if the target was changed meanwhile, e.g. with /set_server.
+    pub fn adopt_fallback_server(&mut self, name: &str, missing: &str, server: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let unchanged = self.find_account(name).map(|a| a.target_server.as_deref() == Some(missing)).unwrap_or(false);
+        if !unchanged {
+            return Ok(false);
+        }
+        self.set_target_server(name, Some(server.to_string()))
+    }
+
+    /// Turns pings on or off for one account; /toggle_ping flips all of a user's accounts at once.
+    pub fn set_ping(&mut self, name: &str, enabled: bool) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let found = match self.data.accounts.iter_mut().find(|a| a.name == name && !a.deleted) {
+            Some(acc) => {
+                acc.ping_enabled = enabled;
+                true
+            }
+            None => false,
+        };
+        if found {
+            self.save()?;
+        }
+        Ok(found)
+    }
+
     pub fn get_user_accounts(&self, user_id: &str) -> Vec<Account> {
-        self.data.accounts.iter()
+        self.active_accounts()
             .filter(|a| a.user_id.as_deref() == Some(user_id))
             .cloned()
             .collect()
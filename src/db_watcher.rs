@@ -0,0 +1,81 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use notify::{Event, RecursiveMode, Watcher};
+use tokio::sync::{mpsc, Mutex};
+
+use crate::db::{Database, ReloadOutcome};
+use crate::secrets;
+
+// Editors and our own saves write in several steps (temp file, rename); wait for them to settle
+const SETTLE_DELAY: Duration = Duration::from_millis(300);
+
+/// Watches the database file and reloads the in-memory data when it is edited by hand.
+pub fn spawn(db: Arc<Mutex<Database>>) {
+    tokio::spawn(async move {
+        let Some(path) = db.lock().await.path().map(PathBuf::from) else { return };
+        let watched = [path.clone(), secrets::path_for(&path.to_string_lossy())];
+        // The directory is watched rather than the file, so a save that replaces it is still seen
+        let directory = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+
+        let (tx, mut changes) = mpsc::unbounded_channel();
+        let mut watcher = match notify::recommended_watcher(move |event: notify::Result<Event>| {
+            if let Ok(event) = event {
+                if event.paths.iter().any(|p| is_watched(p, &watched)) {
+                    let _ = tx.send(());
+                }
+            }
+        }) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                log!("[WARN] Database: Cannot watch {} for external edits: {}", path.display(), e);
+                return;
+            }
+        };
+        if let Err(e) = watcher.watch(&directory, RecursiveMode::NonRecursive) {
+            log!("[WARN] Database: Cannot watch {} for external edits: {}", directory.display(), e);
+            return;
+        }
+
+        // Only report the same rejected edit once instead of on every change
+        let mut last_problem: Option<String> = None;
+        while changes.recv().await.is_some() {
+            tokio::time::sleep(SETTLE_DELAY).await;
+            while changes.try_recv().is_ok() {}
+
+            let mut db = db.lock().await;
+            let path = db.path().unwrap_or("?").to_string();
+            match db.reload_if_changed() {
+                ReloadOutcome::Unchanged => {}
+                ReloadOutcome::Reloaded => {
+                    log!("[INFO] Database: External edit detected in {}. Reloaded {} accounts.", path, db.data.accounts.len());
+                    last_problem = None;
+                }
+                ReloadOutcome::Conflict => {
+                    let problem = "conflict".to_string();
+                    if last_problem.as_ref() != Some(&problem) {
+                        log!("[WARN] Database: {} was edited externally, but the bot has unsaved changes. Keeping in-memory state; the next successful save will overwrite the file.", path);
+                        last_problem = Some(problem);
+                    }
+                }
+                ReloadOutcome::Invalid(reason) => {
+                    if last_problem.as_ref() != Some(&reason) {
+                        log!("[WARN] Database: Ignoring external edit to {}: {}", path, reason);
+                        last_problem = Some(reason);
+                    }
+                }
+            }
+        }
+        drop(watcher);
+    });
+}
+
+/// Whether an event path is the database or its secrets file. Events carry absolute paths
+/// on some platforms, so only the file names are compared.
+fn is_watched(event_path: &Path, watched: &[PathBuf]) -> bool {
+    watched.iter().any(|w| w.file_name().is_some() && w.file_name() == event_path.file_name())
+}
@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use serenity::all::{Channel, ChannelId, Colour, CreateEmbed, Http, Permissions, UserId};
+
+use crate::db::{Database, STATUS_RUNNING};
+use crate::protocol::client::GameClient;
+use crate::protocol::SessionClient;
+use crate::redact::redact;
+
+// The scheduler ticks every minute; a longer silence means its task died or is stuck
+const SCHEDULER_STALE_AFTER: Duration = Duration::from_secs(150);
+// What the bot needs in the log channel for its messages and digest embeds
+const LOG_CHANNEL_PERMISSIONS: &[(Permissions, &str)] = &[
+    (Permissions::VIEW_CHANNEL, "View Channel"),
+    (Permissions::SEND_MESSAGES, "Send Messages"),
+    (Permissions::EMBED_LINKS, "Embed Links"),
+];
+
+/// Check outcome, least severe first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Pass,
+    /// Not broken, but something is not configured
+    Warn,
+    Fail,
+}
+
+/// One line of the /diagnose checklist.
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    pub fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Self {
+        Self { name, status, detail: detail.into() }
+    }
+
+    fn from_result(name: &'static str, result: Result<String, String>) -> Self {
+        match result {
+            Ok(detail) => Self::new(name, Status::Pass, detail),
+            Err(detail) => Self::new(name, Status::Fail, detail),
+        }
+    }
+}
+
+pub fn check_database(db: &Database) -> Check {
+    Check::from_result("Database", db.check_storage())
+}
+
+/// Handshake with the game endpoint. The cookie is sent but only checked by the server once a
+/// session starts, so a pass here does not prove it is still valid.
+pub async fn check_endpoint(cookie: &str, base_url: &str) -> Check {
+    if cookie.trim().is_empty() {
+        return Check::new("Game endpoint", Status::Fail, "No session cookie set (/set_cookies)");
+    }
+    let result = SessionClient::probe(cookie, base_url).await
+        .map(|detail| format!("{}\n`{}`", detail, base_url))
+        .map_err(|e| format!("{}\n`{}`", e, base_url));
+    Check::from_result("Game endpoint", result)
+}
+
+pub async fn check_log_channel(http: &Http, channel: Option<ChannelId>, bot: UserId) -> Check {
+    const NAME: &str = "Log channel";
+    let channel_id = match channel {
+        Some(id) => id,
+        None => return Check::new(NAME, Status::Warn, "Not set (/set_log_channel); automatic messages are not posted"),
+    };
+    let channel = match channel_id.to_channel(http).await {
+        Ok(Channel::Guild(channel)) => channel,
+        Ok(_) => return Check::new(NAME, Status::Fail, format!("<#{}> is not a server channel", channel_id)),
+        Err(e) => return Check::new(NAME, Status::Fail, format!("Cannot open <#{}>: {}", channel_id, e)),
+    };
+    let permissions = async {
+        let guild = channel.guild_id.to_partial_guild(http).await?;
+        let member = channel.guild_id.member(http, bot).await?;
+        Ok::<_, serenity::Error>(guild.user_permissions_in(&channel, &member))
+    }.await;
+    match permissions {
+        Ok(granted) => {
+            let missing: Vec<&str> = LOG_CHANNEL_PERMISSIONS.iter()
+                .filter(|(p, _)| !granted.contains(*p))
+                .map(|(_, label)| *label)
+                .collect();
+            if missing.is_empty() {
+                Check::new(NAME, Status::Pass, format!("<#{}>: can post messages and embeds", channel_id))
+            } else {
+                Check::new(NAME, Status::Fail, format!("<#{}>: missing {}", channel_id, missing.join(", ")))
+            }
+        }
+        Err(e) => Check::new(NAME, Status::Fail, format!("Cannot read permissions for <#{}>: {}", channel_id, e)),
+    }
+}
+
+pub fn check_scheduler(last_tick: Option<Instant>) -> Check {
+    const NAME: &str = "Scheduler";
+    match last_tick {
+        None => Check::new(NAME, Status::Fail, "Has not ticked since startup"),
+        Some(tick) if tick.elapsed() > SCHEDULER_STALE_AFTER => {
+            Check::new(NAME, Status::Fail, format!("Last tick {}s ago; the scheduler task appears to be stuck", tick.elapsed().as_secs()))
+        }
+        Some(tick) => Check::new(NAME, Status::Pass, format!("Last tick {}s ago", tick.elapsed().as_secs())),
+    }
+}
+
+/// Gateway shards that reported ready, for the shard check.
+#[derive(Debug, Default)]
+pub struct ShardStatus {
+    /// Shards the bot runs, as reported by Discord
+    pub total: u32,
+    /// Shard ID -> servers it serves
+    pub ready: BTreeMap<u32, usize>,
+}
+
+pub fn check_shards(status: &ShardStatus) -> Check {
+    const NAME: &str = "Shards";
+    let servers: usize = status.ready.values().sum();
+    let missing: Vec<String> = (0..status.total).filter(|id| !status.ready.contains_key(id)).map(|id| id.to_string()).collect();
+    if missing.is_empty() {
+        Check::new(NAME, Status::Pass, format!("{} of {} ready, {} server(s)", status.ready.len(), status.total, servers))
+    } else {
+        Check::new(NAME, Status::Warn, format!("{} of {} ready, {} server(s); waiting for shard {}", status.ready.len(), status.total, servers, missing.join(", ")))
+    }
+}
+
+/// Accounts marked running longer than the run timeout allows; no session is behind them.
+pub fn check_running(db: &Database) -> Check {
+    const NAME: &str = "Running accounts";
+    let now = chrono::Utc::now();
+    let running: Vec<_> = db.active_accounts().filter(|a| a.status == STATUS_RUNNING).collect();
+    let stale: Vec<&str> = running.iter().filter(|a| a.is_stale_running(now)).map(|a| a.name.as_str()).collect();
+    if stale.is_empty() {
+        Check::new(NAME, Status::Pass, format!("{} running now", running.len()))
+    } else {
+        Check::new(NAME, Status::Warn, format!("Stale (no live session): {}. Reset them with /purge_done.", stale.join(", ")))
+    }
+}
+
+pub fn render(title: &str, checks: &[Check]) -> CreateEmbed {
+    let (colour, summary) = match checks.iter().map(|c| c.status).max() {
+        Some(Status::Fail) => (Colour::RED, "Some checks failed."),
+        Some(Status::Warn) => (Colour::ORANGE, "All checks passed, with warnings."),
+        _ => (Colour::DARK_GREEN, "All checks passed."),
+    };
+    let mut embed = CreateEmbed::new().title(title).description(summary).colour(colour);
+    for check in checks {
+        let mark = match check.status {
+            Status::Pass => "✅",
+            Status::Warn => "⚠️",
+            Status::Fail => "❌",
+        };
+        embed = embed.field(format!("{} {}", mark, check.name), redact(&check.detail), false);
+    }
+    embed
+}
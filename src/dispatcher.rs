@@ -0,0 +1,320 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+
+use serenity::all::{AutoArchiveDuration, ChannelId, ChannelType, CreateActionRow, CreateEmbed, CreateMessage, CreateThread, EditMessage, EditThread, Http, HttpError, Message, StatusCode, UserId};
+use tokio::sync::mpsc;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+
+use crate::progress_message::{format_elapsed, render_progress};
+use crate::protocol::progress::RunStage;
+use crate::redact::redact;
+
+// Discord's message length limit
+const MESSAGE_LIMIT: usize = 2000;
+// Notifications arriving within this window after the first one are handled together
+const BATCH_WINDOW: Duration = Duration::from_millis(1500);
+const MAX_SEND_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+// Discord's thread name length limit
+const THREAD_NAME_LIMIT: usize = 100;
+// Text lines waiting for Discord; beyond this new ones are dropped rather than blocking a run.
+// Everything else (progress messages, threads, embeds, DMs) is always queued.
+const TEXT_CAPACITY: usize = 512;
+
+/// Something the bot wants to tell Discord. Workers only queue these; the notifier task
+/// decides how they are delivered.
+pub enum Notification {
+    /// A line of text. Consecutive lines for the same channel are merged into one message.
+    Text { channel: ChannelId, text: String },
+    Embed { channel: ChannelId, embed: Box<CreateEmbed> },
+    /// A private message to a member.
+    Direct { user: UserId, text: String },
+    /// A session began: post its live progress message (with e.g. a Cancel button).
+    RunStarted { run_id: String, channel: ChannelId, account: String, started: Instant, components: Vec<CreateActionRow> },
+    /// The session reached a new stage: edit its progress message.
+    RunStage { run_id: String, stage: RunStage },
+    /// Replace the progress message with the outcome and drop its components.
+    RunFinished { run_id: String, outcome: String },
+    /// A session began: open a thread for its details under `parent` (the log channel).
+    /// `private_for` makes it a private thread with only that member (and the bot) in it.
+    ThreadStarted { run_id: String, parent: ChannelId, account: String, private_for: Option<UserId> },
+    /// A line for the run's thread. Dropped if the thread could not be opened.
+    ThreadLine { run_id: String, text: String },
+    /// Post the outcome in the run's thread, put it in the thread's title and archive it.
+    ThreadFinished { run_id: String, outcome: String },
+}
+
+/// Queue between the bot's workers and Discord. A single task sends everything, so a slow or
+/// unavailable Discord never holds up a run; after Discord reports a rate limit the queue holds
+/// until it expires, and the backlog goes out in as few messages as possible.
+///
+/// Notifications can be queued right away; nothing is sent until `start` is called with the client's HTTP handle.
+#[derive(Clone)]
+pub struct MessageDispatcher {
+    tx: mpsc::UnboundedSender<Notification>,
+    rx: Arc<Mutex<Option<mpsc::UnboundedReceiver<Notification>>>>,
+    hold_until: Arc<StdMutex<Option<Instant>>>,
+    queued_text: Arc<AtomicUsize>,
+}
+
+impl MessageDispatcher {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Self { tx, rx: Arc::new(Mutex::new(Some(rx))), hold_until: Arc::new(StdMutex::new(None)), queued_text: Arc::new(AtomicUsize::new(0)) }
+    }
+
+    /// Spawns the notifier task. Later calls (e.g. after a gateway reconnect) do nothing.
+    pub async fn start(&self, http: Arc<Http>) {
+        let rx = match self.rx.lock().await.take() {
+            Some(rx) => rx,
+            None => return,
+        };
+        let hold_until = Arc::clone(&self.hold_until);
+        tokio::spawn(run(http, rx, hold_until, Arc::clone(&self.queued_text)));
+    }
+
+    /// Queues a notification without waiting. A text line is dropped (and logged) once
+    /// `TEXT_CAPACITY` lines are waiting; progress messages and threads never are.
+    pub fn notify(&self, notification: Notification) {
+        if notification.is_text() && self.queued_text.fetch_add(1, Ordering::Relaxed) >= TEXT_CAPACITY {
+            self.queued_text.fetch_sub(1, Ordering::Relaxed);
+            log!("[WARN] Notification queue is full ({} lines waiting); dropping a Discord message.", TEXT_CAPACITY);
+            return;
+        }
+        // Only fails once the notifier task is gone, when there is nobody left to deliver it
+        let _ = self.tx.send(notification);
+    }
+
+    pub fn send(&self, channel: ChannelId, text: impl Into<String>) {
+        self.notify(Notification::Text { channel, text: text.into() });
+    }
+
+    pub fn send_dm(&self, user: UserId, text: impl Into<String>) {
+        self.notify(Notification::Direct { user, text: text.into() });
+    }
+
+    pub fn send_embed(&self, channel: ChannelId, embed: CreateEmbed) {
+        self.notify(Notification::Embed { channel, embed: Box::new(embed) });
+    }
+
+    /// Called from the rate-limit event: holds the queue until the limit has passed.
+    pub fn note_rate_limit(&self, timeout: Duration) {
+        if let Ok(mut hold) = self.hold_until.lock() {
+            let until = Instant::now() + timeout;
+            if hold.map(|h| h < until).unwrap_or(true) {
+                *hold = Some(until);
+            }
+        }
+    }
+}
+
+impl Notification {
+    /// Plain lines, which may be dropped when too many are waiting.
+    fn is_text(&self) -> bool {
+        matches!(self, Notification::Text { .. } | Notification::ThreadLine { .. })
+    }
+}
+
+/// A progress message the notifier keeps up to date.
+struct LiveRun {
+    channel: ChannelId,
+    account: String,
+    started: Instant,
+    reached: Vec<RunStage>,
+    message: Option<Message>,
+}
+
+/// A run's thread under the log channel.
+struct LiveThread {
+    id: ChannelId,
+    account: String,
+    started: Instant,
+}
+
+async fn run(http: Arc<Http>, mut rx: mpsc::UnboundedReceiver<Notification>, hold_until: Arc<StdMutex<Option<Instant>>>, queued_text: Arc<AtomicUsize>) {
+    let mut runs: HashMap<String, LiveRun> = HashMap::new();
+    let mut threads: HashMap<String, LiveThread> = HashMap::new();
+    while let Some(first) = rx.recv().await {
+        let mut pending = vec![first];
+        let deadline = Instant::now() + BATCH_WINDOW;
+        while let Ok(Some(next)) = tokio::time::timeout_at(deadline, rx.recv()).await {
+            pending.push(next);
+        }
+
+        let hold = hold_until.lock().ok().and_then(|mut h| h.take());
+        if let Some(until) = hold.filter(|u| *u > Instant::now()) {
+            tokio::time::sleep_until(until).await;
+            while let Ok(next) = rx.try_recv() {
+                pending.push(next);
+            }
+        }
+        queued_text.fetch_sub(pending.iter().filter(|n| n.is_text()).count(), Ordering::Relaxed);
+
+        // Open new threads first, so their lines become plain text that is merged like any other
+        let mut resolved = Vec::with_capacity(pending.len());
+        for notification in pending {
+            match notification {
+                Notification::ThreadStarted { run_id, parent, account, private_for } => {
+                    if let Some(id) = open_thread(&http, parent, &account, &run_id, private_for).await {
+                        threads.insert(run_id, LiveThread { id, account, started: Instant::now() });
+                    }
+                }
+                Notification::ThreadLine { run_id, text } => {
+                    if let Some(thread) = threads.get(&run_id) {
+                        resolved.push(Notification::Text { channel: thread.id, text });
+                    }
+                }
+                other => resolved.push(other),
+            }
+        }
+
+        // Stage changes only mark the message; it is edited once per batch
+        let mut stale: HashSet<String> = HashSet::new();
+        for notification in batch(resolved) {
+            match notification {
+                Notification::Text { channel, text } => send_with_retry(&http, channel, CreateMessage::new().content(text)).await,
+                Notification::Embed { channel, embed } => send_with_retry(&http, channel, CreateMessage::new().embed(*embed)).await,
+                Notification::Direct { user, text } => match user.create_dm_channel(&http).await {
+                    Ok(dm) => send_with_retry(&http, dm.id, CreateMessage::new().content(truncate(&redact(&text)))).await,
+                    Err(e) => log!("[WARN] Could not open a DM with user {}: {}", user, e),
+                },
+                Notification::RunStarted { run_id, channel, account, started, components } => {
+                    let reached = vec![RunStage::Connecting];
+                    let initial = CreateMessage::new().content(render_progress(&account, &run_id, &reached, started.elapsed())).components(components);
+                    let message = channel.send_message(&http, initial).await.ok();
+                    runs.insert(run_id, LiveRun { channel, account, started, reached, message });
+                }
+                Notification::RunStage { run_id, stage } => {
+                    if let Some(live) = runs.get_mut(&run_id) {
+                        if !live.reached.contains(&stage) {
+                            live.reached.push(stage);
+                            stale.insert(run_id);
+                        }
+                    }
+                }
+                Notification::RunFinished { run_id, outcome } => {
+                    stale.remove(&run_id);
+                    if let Some(mut live) = runs.remove(&run_id) {
+                        let text = format!("{} (run `{}`, {})", redact(&outcome), run_id, format_elapsed(live.started.elapsed()));
+                        match live.message.as_mut() {
+                            Some(msg) => { let _ = msg.edit(&http, EditMessage::new().content(text).components(Vec::new())).await; }
+                            // The initial post failed; make sure the outcome is still visible
+                            None => send_with_retry(&http, live.channel, CreateMessage::new().content(text)).await,
+                        }
+                    }
+                }
+                Notification::ThreadFinished { run_id, outcome } => {
+                    if let Some(thread) = threads.remove(&run_id) {
+                        let text = format!("{} ({})", redact(&outcome), format_elapsed(thread.started.elapsed()));
+                        send_with_retry(&http, thread.id, CreateMessage::new().content(truncate(&text))).await;
+                        let title = thread_name(&thread.account, outcome_label(&outcome));
+                        if let Err(e) = thread.id.edit_thread(&http, EditThread::new().name(title).archived(true)).await {
+                            log!("[WARN] Could not archive the thread of run {}: {}", run_id, e);
+                        }
+                    }
+                }
+                // Resolved before batching
+                Notification::ThreadStarted { .. } | Notification::ThreadLine { .. } => {}
+            }
+        }
+        for run_id in stale {
+            if let Some(live) = runs.get_mut(&run_id) {
+                let text = render_progress(&live.account, &run_id, &live.reached, live.started.elapsed());
+                if let Some(msg) = live.message.as_mut() {
+                    let _ = msg.edit(&http, EditMessage::new().content(text)).await;
+                }
+            }
+        }
+    }
+}
+
+/// Opens the thread of a run; `None` (logged) if the bot may not create threads there.
+async fn open_thread(http: &Http, parent: ChannelId, account: &str, run_id: &str, private_for: Option<UserId>) -> Option<ChannelId> {
+    let builder = CreateThread::new(thread_name(account, "running"))
+        .kind(if private_for.is_some() { ChannelType::PrivateThread } else { ChannelType::PublicThread })
+        .auto_archive_duration(AutoArchiveDuration::OneDay);
+    match parent.create_thread(http, builder).await {
+        Ok(thread) => {
+            if let Some(user) = private_for {
+                if let Err(e) = thread.id.add_thread_member(http, user).await {
+                    log!("[WARN] Could not add user {} to the thread of run {}: {}", user, run_id, e);
+                }
+            }
+            Some(thread.id)
+        }
+        Err(e) => {
+            log!("[WARN] Could not open a thread for run {} in channel {}: {}", run_id, parent, e);
+            None
+        }
+    }
+}
+
+fn thread_name(account: &str, state: &str) -> String {
+    let name = format!("{} · {}", account, state);
+    name.chars().take(THREAD_NAME_LIMIT).collect()
+}
+
+/// Short result for a thread's title, from the tag the outcome message starts with.
+fn outcome_label(outcome: &str) -> &'static str {
+    match outcome.trim_start() {
+        o if o.starts_with("[SUCCESS]") => "✅ done",
+        o if o.starts_with("[CANCELLED]") => "cancelled",
+        o if o.starts_with("[WARN]") => "⚠️ retrying",
+        o if o.starts_with("[ERROR]") || o.starts_with("⚠️") => "❌ failed",
+        _ => "finished",
+    }
+}
+
+/// Merges consecutive text lines for the same channel, keeping each message under the length limit.
+fn batch(notifications: Vec<Notification>) -> Vec<Notification> {
+    let mut out: Vec<Notification> = Vec::new();
+    for notification in notifications {
+        let (channel, text) = match notification {
+            Notification::Text { channel, text } => (channel, truncate(&redact(&text))),
+            other => {
+                out.push(other);
+                continue;
+            }
+        };
+        match out.last_mut() {
+            Some(Notification::Text { channel: last_channel, text: last }) if *last_channel == channel && last.len() + 1 + text.len() <= MESSAGE_LIMIT => {
+                last.push('\n');
+                last.push_str(&text);
+            }
+            _ => out.push(Notification::Text { channel, text }),
+        }
+    }
+    out
+}
+
+fn truncate(text: &str) -> String {
+    if text.len() <= MESSAGE_LIMIT {
+        return text.to_string();
+    }
+    let mut end = MESSAGE_LIMIT - '…'.len_utf8();
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &text[..end])
+}
+
+async fn send_with_retry(http: &Http, channel: ChannelId, message: CreateMessage) {
+    for attempt in 1..=MAX_SEND_ATTEMPTS {
+        match channel.send_message(http, message.clone()).await {
+            Ok(_) => return,
+            Err(serenity::Error::Http(HttpError::UnsuccessfulRequest(resp)))
+                if resp.status_code == StatusCode::TOO_MANY_REQUESTS && attempt < MAX_SEND_ATTEMPTS =>
+            {
+                log!("[WARN] Rate limited sending to channel {}. Retrying in {:?}...", channel, RETRY_DELAY);
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+            Err(e) => {
+                log!("[WARN] Failed to send message to channel {}: {}", channel, e);
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use chrono::{DateTime, Utc};
+use serenity::all::{Colour, CreateEmbed, CreateEmbedFooter};
+
+use crate::db::{Account, STATUS_RUNNING};
+
+// Discord's limit for an embed description
+const DESCRIPTION_LIMIT: usize = 4096;
+
+/// How an account's status is shown in embeds, worst last.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StatusKind {
+    Done,
+    Pending,
+    Running,
+    /// "running" with no session behind it (see `Account::is_stale_running`)
+    StaleRunning,
+    Error,
+}
+
+impl StatusKind {
+    pub const ALL: [StatusKind; 5] = [StatusKind::Done, StatusKind::Pending, StatusKind::Running, StatusKind::StaleRunning, StatusKind::Error];
+
+    pub fn of(account: &Account, now: DateTime<Utc>) -> Self {
+        match account.status.as_str() {
+            "done" => StatusKind::Done,
+            STATUS_RUNNING if account.is_stale_running(now) => StatusKind::StaleRunning,
+            STATUS_RUNNING => StatusKind::Running,
+            s if s.starts_with("error") => StatusKind::Error,
+            _ => StatusKind::Pending,
+        }
+    }
+
+    pub fn emoji(&self) -> &'static str {
+        match self {
+            StatusKind::Done => "✅",
+            StatusKind::Pending => "🟡",
+            StatusKind::Running => "🔵",
+            StatusKind::StaleRunning => "⚠️",
+            StatusKind::Error => "❌",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            StatusKind::Done => "done",
+            StatusKind::Pending => "pending",
+            StatusKind::Running => "running",
+            StatusKind::StaleRunning => "stale running",
+            StatusKind::Error => "error",
+        }
+    }
+
+    pub fn colour(&self) -> Colour {
+        match self {
+            StatusKind::Done => Colour::DARK_GREEN,
+            StatusKind::Pending => Colour::GOLD,
+            StatusKind::Running => Colour::BLUE,
+            StatusKind::StaleRunning => Colour::ORANGE,
+            StatusKind::Error => Colour::RED,
+        }
+    }
+}
+
+/// "✅ done · 🟡 pending · ...", shown in the footer of status embeds.
+pub fn legend() -> String {
+    StatusKind::ALL.iter().map(|k| format!("{} {}", k.emoji(), k.label())).collect::<Vec<_>>().join(" · ")
+}
+
+pub fn legend_footer() -> CreateEmbedFooter {
+    CreateEmbedFooter::new(legend())
+}
+
+/// One account as a list line: emoji, name, status and last run, plus the note if `show_notes`.
+pub fn status_line(account: &Account, now: DateTime<Utc>, show_notes: bool) -> String {
+    let note = account.notes.as_ref().filter(|_| show_notes).map(|n| format!(" 📝 {}", n)).unwrap_or_default();
+    format!("{} **{}**: {} (Last Run: {}){}", StatusKind::of(account, now).emoji(), account.name, account.status_label(now), account.last_run.map(|t| format!("<t:{}:R>", t.timestamp())).unwrap_or_else(|| "Never".to_string()), note)
+}
+
+/// A list of accounts with their statuses. The colour is that of the worst status listed, so an
+/// error stands out without reading every line. Lines beyond Discord's limit are counted instead.
+pub fn account_list<'a>(title: impl Into<String>, accounts: impl IntoIterator<Item = &'a Account>, now: DateTime<Utc>, show_notes: bool) -> CreateEmbed {
+    let accounts: Vec<&Account> = accounts.into_iter().collect();
+    let mut description = String::new();
+    for (shown, account) in accounts.iter().enumerate() {
+        let line = status_line(account, now, show_notes);
+        let more = format!("\n…and {} more", accounts.len() - shown);
+        if description.len() + 1 + line.len() + more.len() > DESCRIPTION_LIMIT {
+            description.push_str(&more);
+            break;
+        }
+        if !description.is_empty() {
+            description.push('\n');
+        }
+        description.push_str(&line);
+    }
+    CreateEmbed::new()
+        .title(title)
+        .description(description)
+        .colour(worst_colour(accounts, now))
+        .footer(legend_footer())
+}
+
+/// Colour of the worst status among `accounts`; grey when there are none.
+pub fn worst_colour<'a>(accounts: impl IntoIterator<Item = &'a Account>, now: DateTime<Utc>) -> Colour {
+    accounts.into_iter().map(|a| StatusKind::of(a, now)).max().map(|k| k.colour()).unwrap_or(Colour::LIGHT_GREY)
+}
+
+/// Starts the detail embed of one account, coloured by its status.
+pub fn account_detail(account: &Account, now: DateTime<Utc>) -> CreateEmbed {
+    let kind = StatusKind::of(account, now);
+    CreateEmbed::new()
+        .title(format!("{} Account: {}", kind.emoji(), account.name))
+        .colour(kind.colour())
+        .footer(legend_footer())
+}
+
+/// Count per status for summaries, e.g. "✅ done: 4", skipping statuses nobody has.
+pub fn status_counts<'a>(accounts: impl IntoIterator<Item = &'a Account>, now: DateTime<Utc>) -> Vec<String> {
+    let mut counts = [0usize; StatusKind::ALL.len()];
+    for account in accounts {
+        counts[StatusKind::of(account, now) as usize] += 1;
+    }
+    StatusKind::ALL.iter().zip(counts)
+        .filter(|(_, count)| *count > 0)
+        .map(|(kind, count)| format!("{} {}: {}", kind.emoji(), kind.label(), count))
+        .collect()
+}
@@ -0,0 +1,77 @@
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+
+use tokio::sync::broadcast::{self, error::RecvError};
+
+// Events a slow subscriber may fall behind by before it starts missing some
+const EVENT_BUFFER: usize = 256;
+
+/// Lifecycle of queue runs, published for any part of the bot that wants to react to them.
+#[derive(Debug, Clone)]
+pub enum QueueEvent {
+    /// `position` of `total` accounts queued in the batch so far, counting this one.
+    RunStarted { account: String, run_id: String, position: usize, total: usize },
+    RunFinished { account: String, run_id: String, duration: Duration },
+    /// Any unsuccessful session, including ones that will be retried or were cancelled.
+    RunFailed { account: String, run_id: String, error: String },
+    /// The queue worker has no accounts and no waiting requests left and has stopped.
+    QueueDrained,
+}
+
+/// Broadcast channel of `QueueEvent`s. Publishing never waits; with no subscribers the
+/// event is simply dropped.
+#[derive(Clone)]
+pub struct QueueEvents {
+    tx: broadcast::Sender<QueueEvent>,
+}
+
+impl QueueEvents {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(EVENT_BUFFER);
+        Self { tx }
+    }
+
+    pub fn emit(&self, event: QueueEvent) {
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<QueueEvent> {
+        self.tx.subscribe()
+    }
+}
+
+/// Run counts since startup, shown in /stats.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SessionCounts {
+    pub started: u64,
+    pub succeeded: u64,
+    pub failed: u64,
+}
+
+/// Subscribes a task that keeps `SessionCounts` up to date.
+pub fn count_sessions(events: &QueueEvents) -> Arc<StdMutex<SessionCounts>> {
+    let counts = Arc::new(StdMutex::new(SessionCounts::default()));
+    let mut rx = events.subscribe();
+    let shared = Arc::clone(&counts);
+    tokio::spawn(async move {
+        loop {
+            let event = match rx.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    log!("[WARN] Run counter missed {} queue events.", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            if let Ok(mut counts) = shared.lock() {
+                match event {
+                    QueueEvent::RunStarted { .. } => counts.started += 1,
+                    QueueEvent::RunFinished { .. } => counts.succeeded += 1,
+                    QueueEvent::RunFailed { .. } => counts.failed += 1,
+                    QueueEvent::QueueDrained => {}
+                }
+            }
+        }
+    });
+    counts
+}
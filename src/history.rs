@@ -0,0 +1,245 @@
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, TimeZone, Weekday};
+
+use crate::db::RunRecord;
+use crate::protocol::servers::ServerResult;
+
+/// Aggregates over one account's run history.
+#[derive(Debug, Default)]
+pub struct AccountStats {
+    pub runs: usize,
+    pub last_error: Option<String>,
+    /// Consecutive successful runs counting back from the most recent one
+    pub success_streak: usize,
+    pub average_duration: Option<Duration>,
+    /// Per-server results of the most recent run on target server "All"
+    pub last_servers: Vec<ServerResult>,
+}
+
+pub fn account_stats(history: &[RunRecord], account: &str) -> AccountStats {
+    let runs: Vec<&RunRecord> = history.iter().filter(|r| r.account == account).collect();
+    if runs.is_empty() {
+        return AccountStats::default();
+    }
+
+    let last_error = runs.iter().rev().find_map(|r| r.error.clone());
+    let success_streak = runs.iter().rev().take_while(|r| r.success).count();
+
+    let successful: Vec<u64> = runs.iter().filter(|r| r.success).map(|r| r.duration_secs).collect();
+    let average_duration = if successful.is_empty() {
+        None
+    } else {
+        Some(Duration::from_secs(successful.iter().sum::<u64>() / successful.len() as u64))
+    };
+
+    let last_servers = runs.iter().rev().find(|r| !r.detail.servers.is_empty()).map(|r| r.detail.servers.clone()).unwrap_or_default();
+
+    AccountStats { runs: runs.len(), last_error, success_streak, average_duration, last_servers }
+}
+
+/// Calendar day (in `tz`) of each account's most recent successful run. Accounts that never
+/// completed a run are missing.
+pub fn last_completed_days<Tz: TimeZone>(history: &[RunRecord], tz: &Tz) -> HashMap<String, NaiveDate> {
+    let mut days: HashMap<String, NaiveDate> = HashMap::new();
+    for run in history.iter().filter(|r| r.success) {
+        let day = run.started_at.with_timezone(tz).date_naive();
+        let entry = days.entry(run.account.clone()).or_insert(day);
+        *entry = (*entry).max(day);
+    }
+    days
+}
+
+/// When `account`'s most recent successful run ended, `None` if it never completed one.
+pub fn last_success_end(history: &[RunRecord], account: &str) -> Option<DateTime<chrono::Utc>> {
+    history.iter()
+        .filter(|r| r.account == account && r.success)
+        .filter_map(|r| Some(r.started_at + chrono::Duration::seconds(r.duration_secs.try_into().ok()?)))
+        .max()
+}
+
+/// Consecutive calendar days (in `now`'s timezone), ending today, on which `account` had failed
+/// runs and no successful one. A day without runs ends the streak.
+pub fn failure_streak<Tz: TimeZone>(history: &[RunRecord], account: &str, now: &DateTime<Tz>) -> usize {
+    let tz = now.timezone();
+    let mut days: HashMap<NaiveDate, bool> = HashMap::new();
+    for run in history.iter().filter(|r| r.account == account) {
+        *days.entry(run.started_at.with_timezone(&tz).date_naive()).or_insert(false) |= run.success;
+    }
+    let mut streak = 0;
+    let mut day = now.date_naive();
+    while days.get(&day) == Some(&false) {
+        streak += 1;
+        match day.pred_opt() {
+            Some(previous) => day = previous,
+            None => break,
+        }
+    }
+    streak
+}
+
+/// What the owner can do about a recorded run error.
+pub fn suggested_fix(error: &str) -> &'static str {
+    let lower = error.to_lowercase();
+    if lower.contains("invalid_restore_code") || lower.contains("restore code") {
+        "Get a fresh restore code from the game's restore screen and add the account again with /add_account."
+    } else if lower.contains("login_required") || lower.contains("cookie") {
+        "The session cookie has expired. Set a new one with /set_my_cookie, or ask an admin to update the bot's cookie."
+    } else if lower.contains("zigza") {
+        "The game flagged the session. Make sure the account isn't added twice or played elsewhere while the bot runs it."
+    } else if lower.contains("server_full") || lower.contains("server full") {
+        "The game server was full every time. Try /force_run at a quieter time."
+    } else if lower.contains("timeout") {
+        "Runs take longer than the time limit. Check the transcript with /search_transcript; the game may be slow or stuck on a prompt."
+    } else if lower.contains("connection") {
+        "The bot could not reach the game. If other accounts fail too, tell an admin."
+    } else {
+        "Check the recent runs with /run_history and tell an admin if it keeps failing."
+    }
+}
+
+// Accounts without a success for this many days are called out in the digest
+pub const STALE_AFTER_DAYS: i64 = 3;
+
+/// Weekday the digest goes out on, from the `digestDay` setting. `None` = disabled.
+pub fn digest_weekday(setting: Option<&str>) -> Option<Weekday> {
+    match setting.map(|s| s.trim().to_lowercase()) {
+        None => Some(Weekday::Mon),
+        Some(day) if day == "off" => None,
+        Some(day) => day.parse().ok(),
+    }
+}
+
+/// One account's week, as shown in the weekly digest.
+#[derive(Debug)]
+pub struct AccountWeek {
+    pub account: String,
+    /// Days with at least one successful run
+    pub days_completed: usize,
+    /// Days with failed runs and no success
+    pub days_failed: usize,
+    pub most_common_error: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct WeeklyDigest {
+    pub accounts: Vec<AccountWeek>,
+    /// Accounts that haven't succeeded in `STALE_AFTER_DAYS` days (or never)
+    pub stale: Vec<String>,
+}
+
+/// Summarizes the 7 days before `now`, bucketing runs by calendar day in `now`'s timezone.
+pub fn weekly_digest<Tz: TimeZone>(history: &[RunRecord], accounts: &[String], now: &DateTime<Tz>) -> WeeklyDigest {
+    let tz = now.timezone();
+    let week_start = now.clone() - chrono::Duration::days(7);
+    let stale_cutoff = now.clone() - chrono::Duration::days(STALE_AFTER_DAYS);
+
+    let mut weeks = Vec::new();
+    let mut stale = Vec::new();
+    for account in accounts {
+        let runs: Vec<(DateTime<Tz>, &RunRecord)> = history.iter()
+            .filter(|r| &r.account == account)
+            .map(|r| (r.started_at.with_timezone(&tz), r))
+            .collect();
+
+        let mut succeeded = BTreeSet::new();
+        let mut failed = BTreeSet::new();
+        let mut errors: HashMap<&str, usize> = HashMap::new();
+        for (time, run) in runs.iter().filter(|(t, _)| *t > week_start && *t <= *now) {
+            if run.success {
+                succeeded.insert(time.date_naive());
+            } else {
+                failed.insert(time.date_naive());
+                if let Some(error) = &run.error {
+                    *errors.entry(error.as_str()).or_insert(0) += 1;
+                }
+            }
+        }
+        let most_common_error = errors.into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(a.0)))
+            .map(|(error, _)| error.to_string());
+
+        let last_success = runs.iter().filter(|(_, r)| r.success).map(|(t, _)| t.clone()).max();
+        if last_success.map(|t| t < stale_cutoff).unwrap_or(true) {
+            stale.push(account.clone());
+        }
+
+        weeks.push(AccountWeek {
+            account: account.clone(),
+            days_completed: succeeded.len(),
+            days_failed: failed.difference(&succeeded).count(),
+            most_common_error,
+        });
+    }
+
+    WeeklyDigest { accounts: weeks, stale }
+}
+
+/// Totals across every account, for /stats.
+#[derive(Debug, Default)]
+pub struct OverallStats {
+    /// Runs started since local midnight
+    pub runs_today: usize,
+    pub week_runs: usize,
+    pub week_successes: usize,
+    /// Mean duration of successful runs in the last 7 days
+    pub average_duration: Option<Duration>,
+    /// The newest login failure is more recent than the newest success
+    pub cookie_rejected: bool,
+    pub last_success: Option<DateTime<chrono::Utc>>,
+}
+
+impl OverallStats {
+    pub fn success_rate(&self) -> Option<f64> {
+        if self.week_runs == 0 {
+            None
+        } else {
+            Some(self.week_successes as f64 * 100.0 / self.week_runs as f64)
+        }
+    }
+}
+
+pub fn overall_stats<Tz: TimeZone>(history: &[RunRecord], now: &DateTime<Tz>) -> OverallStats {
+    let tz = now.timezone();
+    let today = now.date_naive();
+    let week_start = now.clone() - chrono::Duration::days(7);
+
+    let mut stats = OverallStats::default();
+    let mut week_durations = Vec::new();
+    let mut last_success: Option<(DateTime<Tz>, &RunRecord)> = None;
+    let mut last_login_failure: Option<DateTime<Tz>> = None;
+
+    for run in history {
+        let time = run.started_at.with_timezone(&tz);
+        if time.date_naive() == today {
+            stats.runs_today += 1;
+        }
+        if time > week_start {
+            stats.week_runs += 1;
+            if run.success {
+                stats.week_successes += 1;
+                week_durations.push(run.duration_secs);
+            }
+        }
+        if run.success && last_success.as_ref().map(|(t, _)| time > *t).unwrap_or(true) {
+            last_success = Some((time.clone(), run));
+        }
+        if run.error.as_deref().map(|e| e.contains("LOGIN_REQUIRED")).unwrap_or(false)
+            && last_login_failure.as_ref().map(|t| time > *t).unwrap_or(true)
+        {
+            last_login_failure = Some(time);
+        }
+    }
+
+    if !week_durations.is_empty() {
+        stats.average_duration = Some(Duration::from_secs(week_durations.iter().sum::<u64>() / week_durations.len() as u64));
+    }
+    stats.cookie_rejected = match (&last_login_failure, &last_success) {
+        (Some(failure), Some((success, _))) => failure > success,
+        (Some(_), None) => true,
+        _ => false,
+    };
+    stats.last_success = last_success.map(|(_, run)| run.started_at);
+    stats
+}
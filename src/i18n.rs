@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+/// Languages the bot answers in. A message without a translation is sent in English.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    Id,
+}
+
+/// Translations shipped with the bot, in the templates file format (see templates.example.toml).
+pub const BUNDLES: &[(Locale, &str)] = &[
+    (Locale::Id, include_str!("../locales/id.toml")),
+];
+
+impl Locale {
+    pub const ALL: &'static [Locale] = &[Locale::En, Locale::Id];
+
+    /// Section name in templates files and the stored value.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::Id => "id",
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Locale::En => "English",
+            Locale::Id => "Bahasa Indonesia",
+        }
+    }
+
+    pub fn parse(input: &str) -> Result<Locale, String> {
+        let input = input.trim().to_lowercase();
+        match input.as_str() {
+            "en" | "english" => Ok(Locale::En),
+            "id" | "indonesian" | "bahasa" | "bahasa indonesia" => Ok(Locale::Id),
+            _ => Err(format!("Unknown language '{}'. Use one of: {}.", input, Locale::ALL.iter().map(|l| l.code()).collect::<Vec<_>>().join(", "))),
+        }
+    }
+
+    /// The language of a Discord client locale such as "id" or "en-US", if the bot has it.
+    pub fn from_discord(locale: &str) -> Option<Locale> {
+        match locale.split('-').next().unwrap_or("") {
+            "en" => Some(Locale::En),
+            "id" => Some(Locale::Id),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use aes::Aes256;
+use base64::Engine;
+use block_modes::block_padding::Pkcs7;
+use block_modes::{BlockMode, Cbc};
+use md5::{Digest, Md5};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::db::{Account, Database};
+use crate::protocol::flow::Flow;
+use crate::timestamp;
+
+/// Passphrase the Node version encrypted restore codes with (see migrate_db.js).
+/// LEGACY_ENCRYPTION_KEY overrides it for installs that changed it.
+const DEFAULT_LEGACY_KEY: &str = "F3C1A98E3D77C2159E4B1A6FD04C2B7838DA1F457E66BE9F02C91BBE6D7AF29E";
+
+/// The accounts file of the Node automation (automation.js). Its settings are not imported.
+#[derive(Deserialize)]
+struct LegacyFile {
+    accounts: Vec<LegacyAccount>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LegacyAccount {
+    name: String,
+    /// CryptoJS passphrase ciphertext; files already run through migrate_db.js have `code` instead
+    #[serde(default)]
+    encrypted_code: Option<String>,
+    #[serde(default)]
+    code: Option<String>,
+    #[serde(default)]
+    target_server: Option<String>,
+    // Written as a number by some versions
+    #[serde(default)]
+    user_id: Option<Value>,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    discord_nickname: Option<String>,
+    #[serde(default)]
+    ping_enabled: Option<bool>,
+    #[serde(default)]
+    status: Option<String>,
+    // RFC 3339 text or milliseconds since the epoch
+    #[serde(default)]
+    last_run: Option<Value>,
+}
+
+/// What an import did: the accounts added, and the ones left out with the reason.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub added: Vec<String>,
+    pub skipped: Vec<(String, String)>,
+}
+
+/// Merges the accounts of a legacy file into `db`. Existing accounts (deleted ones included) are
+/// never touched; an account whose name or restore code is already present is skipped.
+pub fn import(db: &mut Database, text: &str) -> Result<ImportReport, String> {
+    let file: LegacyFile = serde_json::from_str(text).map_err(|e| format!("not a legacy accounts file: {}", e))?;
+    let key = std::env::var("LEGACY_ENCRYPTION_KEY").unwrap_or_else(|_| DEFAULT_LEGACY_KEY.to_string());
+
+    let mut report = ImportReport::default();
+    for legacy in file.accounts {
+        let name = legacy.name.trim().to_string();
+        if name.is_empty() {
+            report.skipped.push(("(unnamed)".to_string(), "the account has no name".to_string()));
+            continue;
+        }
+        if let Some(existing) = db.data.accounts.iter().find(|a| a.name == name) {
+            let reason = if existing.deleted {
+                "a deleted account has this name; restore or purge it first"
+            } else {
+                "an account with this name already exists"
+            };
+            report.skipped.push((name, reason.to_string()));
+            continue;
+        }
+        let code = match restore_code(&legacy, &key) {
+            Ok(code) => code,
+            Err(e) => {
+                report.skipped.push((name, e));
+                continue;
+            }
+        };
+        if let Some(other) = db.find_duplicate_code(&code, &name) {
+            report.skipped.push((name, format!("same restore code as {}", other.name)));
+            continue;
+        }
+        db.data.accounts.push(convert(legacy, name.clone(), &code));
+        report.added.push(name);
+    }
+    Ok(report)
+}
+
+/// The plain restore code of a legacy account, checked like /add_account does.
+fn restore_code(legacy: &LegacyAccount, key: &str) -> Result<String, String> {
+    let code = match (&legacy.encrypted_code, &legacy.code) {
+        (Some(encrypted), _) if !encrypted.is_empty() => decrypt_cryptojs(encrypted, key)
+            .map_err(|e| format!("cannot decrypt the restore code ({}); set LEGACY_ENCRYPTION_KEY if the old install used another key", e))?,
+        (_, Some(code)) => code.clone(),
+        _ => return Err("the account has no restore code".to_string()),
+    };
+    let code = code.trim().to_string();
+    Account::validate_code(&code)?;
+    Ok(code)
+}
+
+fn convert(legacy: LegacyAccount, name: String, code: &str) -> Account {
+    Account {
+        name,
+        code: Account::encrypt_code_str(code),
+        target_server: legacy.target_server.filter(|s| !s.trim().is_empty()),
+        user_id: legacy.user_id.and_then(|id| match id {
+            Value::String(id) if !id.is_empty() => Some(id),
+            Value::Number(id) => Some(id.to_string()),
+            _ => None,
+        }),
+        username: legacy.username,
+        discord_nickname: legacy.discord_nickname,
+        ping_enabled: legacy.ping_enabled.unwrap_or(false),
+        status: map_status(legacy.status.as_deref().unwrap_or("")),
+        last_run: legacy.last_run.as_ref().and_then(timestamp::parse),
+        deleted: false,
+        deleted_at: None,
+        tags: Vec::new(),
+        cookie_override: None,
+        flow: Flow::default(),
+        notes: None,
+        running_since: None,
+    }
+}
+
+/// Maps the Node version's statuses onto ours. Anything in progress there is just pending here.
+fn map_status(status: &str) -> String {
+    let lower = status.trim().to_lowercase();
+    match lower.as_str() {
+        "done" | "completed" | "complete" | "success" | "finished" => "done".to_string(),
+        "failed" | "failure" => "error: failed (imported)".to_string(),
+        _ if lower.starts_with("error") => status.trim().to_string(),
+        _ => "pending".to_string(),
+    }
+}
+
+/// Decrypts `CryptoJS.AES.encrypt(text, passphrase)` output: base64 of "Salted__", an 8-byte salt
+/// and AES-256-CBC ciphertext, with key and IV derived by OpenSSL's EVP_BytesToKey (MD5, one round).
+fn decrypt_cryptojs(ciphertext: &str, passphrase: &str) -> Result<String, String> {
+    let data = base64::engine::general_purpose::STANDARD.decode(ciphertext.trim()).map_err(|_| "not base64")?;
+    let salt = data.get(8..16).filter(|_| data.starts_with(b"Salted__")).ok_or("no salt header")?;
+
+    let mut derived = Vec::with_capacity(48);
+    let mut block: Vec<u8> = Vec::new();
+    while derived.len() < 48 {
+        let mut md5 = Md5::new();
+        md5.update(&block);
+        md5.update(passphrase.as_bytes());
+        md5.update(salt);
+        block = md5.finalize().to_vec();
+        derived.extend_from_slice(&block);
+    }
+
+    let cipher = Cbc::<Aes256, Pkcs7>::new_from_slices(&derived[..32], &derived[32..48]).map_err(|e| e.to_string())?;
+    let plain = cipher.decrypt_vec(&data[16..]).map_err(|_| "wrong key")?;
+    String::from_utf8(plain).map_err(|_| "wrong key".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::DbData;
+
+    // `CryptoJS.AES.encrypt("abcd-1234-efgh", passphrase).toString()`, made with the same
+    // OpenSSL-compatible format: `openssl enc -aes-256-cbc -md md5 -pass pass:<passphrase> -base64`
+    const DEFAULT_KEY_CIPHERTEXT: &str = "U2FsdGVkX18D4DsQhIArxhdKJdPC0gxKBk2oO+1x1PY=";
+    const OTHER_KEY_CIPHERTEXT: &str = "U2FsdGVkX18WdLmRqZGsKsHCrrP0q+ZSmsMZvUxNR98=";
+
+    fn database(accounts: &str) -> Database {
+        let data: DbData = serde_json::from_str(&format!(
+            r#"{{"accounts":{},"settings":{{"logChannelId":null,"muteBotMessages":null}}}}"#,
+            accounts
+        )).unwrap();
+        Database::in_memory(data)
+    }
+
+    #[test]
+    fn decrypts_cryptojs_passphrase_output() {
+        assert_eq!(decrypt_cryptojs(DEFAULT_KEY_CIPHERTEXT, DEFAULT_LEGACY_KEY).unwrap(), "abcd-1234-efgh");
+        assert_eq!(decrypt_cryptojs(OTHER_KEY_CIPHERTEXT, "secret").unwrap(), "abcd-1234-efgh");
+    }
+
+    #[test]
+    fn rejects_the_wrong_key_and_malformed_input() {
+        assert!(decrypt_cryptojs(OTHER_KEY_CIPHERTEXT, DEFAULT_LEGACY_KEY).is_err());
+        assert_eq!(decrypt_cryptojs("not base64!", DEFAULT_LEGACY_KEY).unwrap_err(), "not base64");
+        // Valid base64 without the "Salted__" header
+        assert_eq!(decrypt_cryptojs("YWJjZGVmZ2hpamtsbW5vcHFyc3R1dnd4", DEFAULT_LEGACY_KEY).unwrap_err(), "no salt header");
+    }
+
+    #[test]
+    fn maps_node_statuses() {
+        assert_eq!(map_status("Completed"), "done");
+        assert_eq!(map_status(" success "), "done");
+        assert_eq!(map_status("failed"), "error: failed (imported)");
+        assert_eq!(map_status("error: LOGIN_REQUIRED"), "error: LOGIN_REQUIRED");
+        assert_eq!(map_status("running"), "pending");
+        assert_eq!(map_status(""), "pending");
+    }
+
+    #[test]
+    fn imports_new_accounts_and_skips_taken_names_and_codes() {
+        let mut db = database(r#"[
+            {"name":"main","code":"main-code-1","pingEnabled":false,"status":"done"},
+            {"name":"old","code":"old-code-1","pingEnabled":false,"status":"pending","deleted":true}
+        ]"#);
+        let file = format!(r#"{{"accounts":[
+            {{"name":"alt1","encryptedCode":"{}","userId":1234,"status":"completed","lastRun":1709269200000}},
+            {{"name":"main","code":"other-code-1"}},
+            {{"name":"old","code":"other-code-2"}},
+            {{"name":"copy","code":"MAIN-CODE-1"}},
+            {{"name":"alt2","code":"abcd-1234-efgh"}},
+            {{"name":"alt3","code":"short"}}
+        ]}}"#, DEFAULT_KEY_CIPHERTEXT);
+
+        let report = import(&mut db, &file).unwrap();
+
+        assert_eq!(report.added, vec!["alt1"]);
+        let skipped: Vec<&str> = report.skipped.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(skipped, vec!["main", "old", "copy", "alt2", "alt3"]);
+        assert!(report.skipped[1].1.contains("deleted"));
+        assert_eq!(report.skipped[2].1, "same restore code as main");
+        assert_eq!(report.skipped[3].1, "same restore code as alt1");
+
+        let alt1 = db.find_account("alt1").unwrap();
+        assert_eq!(alt1.decrypt_code(), "abcd-1234-efgh");
+        assert_eq!(alt1.user_id.as_deref(), Some("1234"));
+        assert_eq!(alt1.status, "done");
+        assert!(alt1.last_run.is_some());
+        // Existing accounts are left alone
+        assert_eq!(db.find_account("main").unwrap().decrypt_code(), "main-code-1");
+        assert_eq!(db.data.accounts.len(), 3);
+    }
+}
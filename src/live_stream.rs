@@ -0,0 +1,151 @@
+use std::sync::OnceLock;
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::events::{QueueEvent, QueueEvents};
+use crate::protocol::progress::ProgressEvent;
+use crate::redact::redact;
+
+// Lines a slow viewer may fall behind by before it is told it missed some
+const FEED_BUFFER: usize = 1024;
+
+static FEED: OnceLock<broadcast::Sender<Value>> = OnceLock::new();
+
+/// Publishes a session's progress event. Does nothing unless the stream is running.
+pub fn publish_progress(run_id: Option<String>, account: &str, event: &ProgressEvent) {
+    if FEED.get().is_none() {
+        return;
+    }
+    let (kind, detail) = match event {
+        ProgressEvent::Stage(stage) => ("stage", json!({ "stage": stage.label() })),
+        ProgressEvent::StateChanged(state) => ("state", json!({ "state": format!("{:?}", state) })),
+        ProgressEvent::PromptDetected(prompt) => ("prompt", json!({ "prompt": prompt })),
+        ProgressEvent::CommandSent(command) => ("command", json!({ "command": command })),
+        ProgressEvent::WaitStarted { secs } => ("wait", json!({ "secs": secs })),
+        ProgressEvent::OutputChunk(text) => ("output", json!({ "text": text })),
+    };
+    let mut message = json!({ "type": kind, "runId": run_id, "account": account });
+    if let (Some(message), Value::Object(detail)) = (message.as_object_mut(), detail) {
+        message.extend(detail);
+    }
+    publish(message);
+}
+
+fn publish(event: Value) {
+    if let Some(feed) = FEED.get() {
+        let _ = feed.send(event);
+    }
+}
+
+fn queue_event_json(event: &QueueEvent) -> Value {
+    match event {
+        QueueEvent::RunStarted { account, run_id, position, total } => json!({ "type": "runStarted", "runId": run_id, "account": account, "position": position, "total": total }),
+        QueueEvent::RunFinished { account, run_id, duration } => json!({ "type": "runFinished", "runId": run_id, "account": account, "durationSecs": duration.as_secs() }),
+        QueueEvent::RunFailed { account, run_id, error } => json!({ "type": "runFailed", "runId": run_id, "account": account, "error": error }),
+        QueueEvent::QueueDrained => json!({ "type": "queueDrained" }),
+    }
+}
+
+/// Serves a WebSocket on `port` that streams session progress (output, prompts, commands, stages)
+/// and queue events as JSON messages. Viewers authenticate with `Authorization: Bearer <token>`
+/// or `?token=<token>`; `?account=<name>` limits the stream to one account. Everything is
+/// redacted before it is sent.
+pub async fn start(port: u16, token: String, queue_events: &QueueEvents) {
+    let listener = match TcpListener::bind(("0.0.0.0", port)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log!("[ERROR] Live stream: cannot listen on port {}: {}", port, e);
+            return;
+        }
+    };
+    let feed = FEED.get_or_init(|| broadcast::channel(FEED_BUFFER).0).clone();
+
+    // Queue events join the same feed
+    let mut queue_rx = queue_events.subscribe();
+    let forward = feed.clone();
+    tokio::spawn(async move {
+        loop {
+            match queue_rx.recv().await {
+                Ok(event) => { let _ = forward.send(queue_event_json(&event)); }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+
+    log!("[INFO] Live stream: listening on port {}.", port);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    let rx = feed.subscribe();
+                    let token = token.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = serve(stream, &token, rx).await {
+                            log!("[WARN] Live stream: viewer {} disconnected: {}", peer, e);
+                        }
+                    });
+                }
+                Err(e) => log!("[WARN] Live stream: accept failed: {}", e),
+            }
+        }
+    });
+}
+
+/// Value of `name` in the request's query string.
+fn query_param(request: &Request, name: &str) -> Option<String> {
+    let query = request.uri().query()?;
+    url::form_urlencoded::parse(query.as_bytes())
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.into_owned())
+}
+
+async fn serve(stream: TcpStream, token: &str, mut rx: broadcast::Receiver<Value>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut account: Option<String> = None;
+    // The callback signature is fixed by tungstenite
+    #[allow(clippy::result_large_err)]
+    let authenticate = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+        let bearer = request.headers().get("authorization")
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "))
+            .map(|t| t.trim().to_string());
+        if bearer.or_else(|| query_param(request, "token")).as_deref() != Some(token) {
+            let mut denied = ErrorResponse::new(Some("Invalid or missing token".to_string()));
+            *denied.status_mut() = StatusCode::UNAUTHORIZED;
+            return Err(denied);
+        }
+        account = query_param(request, "account");
+        Ok(response)
+    };
+    let mut ws = tokio_tungstenite::accept_hdr_async(stream, authenticate).await?;
+
+    loop {
+        tokio::select! {
+            event = rx.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => json!({ "type": "lagged", "missed": missed }),
+                    Err(RecvError::Closed) => return Ok(()),
+                };
+                if let (Some(wanted), Some(name)) = (&account, event.get("account").and_then(|a| a.as_str())) {
+                    if wanted != name {
+                        continue;
+                    }
+                }
+                ws.send(Message::Text(redact(&event.to_string()))).await?;
+            }
+            incoming = ws.next() => match incoming {
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                Some(Ok(Message::Ping(payload))) => ws.send(Message::Pong(payload)).await?,
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+            }
+        }
+    }
+}
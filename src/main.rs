@@ -1,361 +1,2657 @@
+#[macro_use]
+mod redact;
 mod protocol;
 mod db;
+mod cli;
+mod queue;
+mod progress_message;
+mod backoff;
+mod db_watcher;
+mod audit;
+mod history;
+mod scheduler;
+mod permissions;
+mod dispatcher;
+mod settings;
+mod reporting;
+mod run_id;
+mod diagnose;
+mod events;
+mod config;
+mod rate_limit;
+mod secrets;
+mod live_stream;
+mod backup;
+mod cooldown;
+mod alerts;
+mod legacy;
+mod snapshot;
+mod templates;
+mod i18n;
+mod settings_transfer;
+mod transcript_search;
+mod notifier;
+mod capacity;
+mod embeds;
+mod presence;
+mod timestamp;
+mod preflight;
 
-use protocol::socket::EvertextClient;
-use db::{Database, Account};
+use protocol::endpoint::{profile_names, resolve_base_url, DEFAULT_BASE_URL};
+use protocol::client::GameClient;
+use protocol::SessionClient;
+use db::{Database, Account, AccountFilter, RunDetail, READ_ONLY_AFTER_FAILURES, STATUS_INVALID_CODE, STATUS_NO_COOKIE, STATUS_RUNNING, STATUS_WORKER_CRASHED};
+use protocol::flow::Flow;
+use protocol::socket::RunOutcome;
+use protocol::tls;
+use queue::{batch_order, is_transient_error, min_retry_delay, BatchQueue, InFlightRegistry, PendingRun, PendingRuns, QueueStart, QueueState, RetryDecision, RetryTracker, WorkerProgress};
+use progress_message::{finish_progress, format_elapsed, RunProgressMessage};
+use backoff::Backoff;
+use rate_limit::RunLimiter;
+use capacity::ServerCapacity;
+use cooldown::Cooldowns;
+use alerts::Alert;
+use notifier::{ChannelNotifier, Level, LogLevel, DirectNotifier, Fanout, LogNotifier, Notifier, Severity, Sink, WebhookNotifier};
+use dispatcher::MessageDispatcher;
+use events::{QueueEvent, QueueEvents, SessionCounts};
+use presence::Presence;
+use redact::redact;
+use permissions::{required_tier, Tier, ASSIGNABLE_TIERS};
+use history::{weekly_digest, STALE_AFTER_DAYS};
+use settings::DIGEST_DAY_CHOICES;
+use scheduler::{Scheduler, SystemClock, Trigger, DIGEST_TIME};
+use i18n::Locale;
+use settings_transfer::{SettingsExport, MIN_PASSPHRASE_LEN};
+use diagnose::ShardStatus;
+use config::ShardCount;
 
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use serenity::all::*;
 use serenity::async_trait;
-use chrono::{Utc, Timelike};
-use chrono_tz::Asia::Jakarta;
+use chrono::{Datelike, Utc};
 
+// Select menu value meaning "no target server, let the terminal auto-select"
+const SERVER_AUTO_VALUE: &str = "__auto__";
+
+fn server_select_menu(account: &str, known_servers: &[String]) -> CreateActionRow {
+    let mut options = vec![
+        CreateSelectMenuOption::new("All servers", "All"),
+        CreateSelectMenuOption::new("Auto (single-server account)", SERVER_AUTO_VALUE),
+    ];
+    // Discord allows at most 25 options per menu
+    options.extend(known_servers.iter().take(23).map(|s| CreateSelectMenuOption::new(s, s)));
+    CreateActionRow::SelectMenu(
+        CreateSelectMenu::new(format!("set_server:{}", account), CreateSelectMenuKind::String { options })
+            .placeholder("Choose the target server")
+    )
+}
+
+// Commands that start runs; refused while maintenance mode is on
+const RUN_COMMANDS: &[&str] = &["force_run", "force_run_all", "run_menu", "debug_run"];
+// Refused while the database is read-only: new accounts would only live in memory, and the
+// queue stays paused until saving works again
+const READ_ONLY_COMMANDS: &[&str] = &["add_account", "setup", "restore_account", "resume_queue"];
+// How often a save that failed is tried again
+const SAVE_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+// Accounts per page of /run_menu; Discord allows at most 25 options per menu
+const RUN_MENU_PAGE: usize = 25;
+
+/// One page of the /run_menu picker over `accounts`: the prompt and its components.
+fn run_menu(accounts: &[Account], page: usize) -> (String, Vec<CreateActionRow>) {
+    let pages = accounts.len().div_ceil(RUN_MENU_PAGE).max(1);
+    let page = page.min(pages - 1);
+    let options: Vec<CreateSelectMenuOption> = accounts.iter()
+        .skip(page * RUN_MENU_PAGE)
+        .take(RUN_MENU_PAGE)
+        .map(|a| CreateSelectMenuOption::new(&a.name, &a.name).description(a.status.chars().take(100).collect::<String>()))
+        .collect();
+    let count = options.len() as u8;
+    let mut rows = vec![CreateActionRow::SelectMenu(
+        CreateSelectMenu::new(format!("run_menu:{}", page), CreateSelectMenuKind::String { options })
+            .placeholder("Choose the accounts to run")
+            .min_values(1)
+            .max_values(count)
+    )];
+    let mut prompt = "Pick the accounts to run; they are queued in the order listed.".to_string();
+    if pages > 1 {
+        prompt.push_str(&format!(" Page {}/{} ({} accounts); only this page's picks are queued.", page + 1, pages, accounts.len()));
+        rows.push(CreateActionRow::Buttons(vec![
+            CreateButton::new(format!("run_menu_page:{}", page.saturating_sub(1))).label("Previous").style(ButtonStyle::Secondary).disabled(page == 0),
+            CreateButton::new(format!("run_menu_page:{}", page + 1)).label("Next").style(ButtonStyle::Secondary).disabled(page + 1 >= pages),
+        ]));
+    }
+    (prompt, rows)
+}
+
+// Days /search_transcript may look back (transcripts are pruned long before that anyway)
+const SEARCH_MAX_DAYS: i64 = 30;
+// Room for the /search_transcript summary within Discord's 2000 characters
+const SEARCH_REPLY_LIMIT: usize = 1900;
+// Room for the /audit_log lines (and the note about omitted ones) within Discord's 2000 characters
+const AUDIT_REPLY_LIMIT: usize = 1900;
+// Options shown per /audit_log line; a command's options can be arbitrarily long
+const AUDIT_ARGS_LIMIT: usize = 150;
+// Settings exports are a few kilobytes; anything far larger is the wrong file
+const SETTINGS_IMPORT_MAX_BYTES: u32 = 1024 * 1024;
+// Discord's upload limit for bots without boosts; larger traces stay on disk
+const TRACE_ATTACH_MAX_BYTES: u64 = 25 * 1024 * 1024;
+// User context menu command showing a member's accounts to admins
+const VIEW_ACCOUNTS_MENU: &str = "View accounts";
+// Longest account name the /setup wizard accepts; the name is carried in component ids
+const SETUP_NAME_MAX: u16 = 32;
+
+/// First DM of the /setup wizard.
+fn setup_intro() -> CreateMessage {
+    CreateMessage::new()
+        .content("👋 Let's add your Evertext account.\n\
+            **Step 1 of 2:** press **Start** and enter a name for the account, its restore code (from the game's restore screen) and, if it plays on a specific server, that server.\n\
+            **Step 2 of 2:** choose whether I should ping you about its runs.\n\
+            Your restore code is only sent in the form and is stored encrypted.")
+        .components(vec![setup_start_button("Start")])
+}
+
+fn setup_start_button(label: &str) -> CreateActionRow {
+    CreateActionRow::Buttons(vec![CreateButton::new("setup_start").label(label).style(ButtonStyle::Primary)])
+}
+
+/// Step 1 of the /setup wizard: the account form.
+fn setup_modal(known_servers: &[String]) -> CreateModal {
+    let server_hint = if known_servers.is_empty() {
+        "e.g. E-15 or All; leave empty if unsure".to_string()
+    } else {
+        format!("e.g. {} or All; leave empty if unsure", known_servers.iter().take(3).cloned().collect::<Vec<_>>().join(", "))
+    };
+    CreateModal::new("setup_account", "Add an Evertext account")
+        .components(vec![
+            CreateActionRow::InputText(CreateInputText::new(InputTextStyle::Short, "Account name", "name")
+                .placeholder("A name to recognise it by, e.g. MyAlt")
+                .max_length(SETUP_NAME_MAX)),
+            CreateActionRow::InputText(CreateInputText::new(InputTextStyle::Short, "Restore code", "code")
+                .placeholder("From the game's restore screen")),
+            CreateActionRow::InputText(CreateInputText::new(InputTextStyle::Short, "Server (optional)", "server")
+                .placeholder(server_hint.chars().take(100).collect::<String>())
+                .required(false)),
+        ])
+}
+
+/// Step 2 of the /setup wizard: the ping preference for `account`.
+fn setup_ping_buttons(account: &str) -> CreateActionRow {
+    CreateActionRow::Buttons(vec![
+        CreateButton::new(format!("setup_ping:on:{}", account)).label("Yes, ping me").style(ButtonStyle::Success),
+        CreateButton::new(format!("setup_ping:off:{}", account)).label("No pings").style(ButtonStyle::Secondary),
+    ])
+}
+
+/// Who can do what right now, for /reset_admin_role. Roles missing from `guild` are flagged,
+/// since they grant nothing.
+fn effective_permissions(guild: &PartialGuild, settings: &settings::Settings) -> String {
+    let role = |id: &RoleId| if guild.roles.contains_key(id) && id.get() != guild.id.get() {
+        format!("<@&{}>", id)
+    } else {
+        format!("`{}` (deleted or @everyone, grants nothing)", id)
+    };
+    let mut lines = vec![format!("- **owner**: <@{}> (every command)", guild.owner_id)];
+    for tier in ASSIGNABLE_TIERS.iter().rev() {
+        let mut roles: Vec<String> = settings.role_tiers.iter().filter(|(_, t)| *t == tier).map(|(id, _)| role(id)).collect();
+        if *tier == Tier::Admin {
+            if let Some(id) = &settings.admin_role_id {
+                roles.insert(0, format!("{} (admin role)", role(id)));
+            }
+        }
+        let holders = if roles.is_empty() { "nobody but the owner".to_string() } else { roles.join(", ") };
+        lines.push(format!("- **{}**: {}", tier.label(), holders));
+    }
+    lines.push("- everyone else: their own accounts only".to_string());
+    lines.join("\n")
+}
+
+/// Reply line for a run request that could not start right away.
+fn queue_wait_notice(start: QueueStart, locale: Locale) -> Option<String> {
+    match start {
+        QueueStart::Started => None,
+        QueueStart::Queued(position) => Some(templates::render(locale, &templates::QUEUE_WAITING, &[("position", &position.to_string())])),
+        QueueStart::Full => Some(templates::render(locale, &templates::QUEUE_FULL, &[("limit", &config::get().max_pending_runs.to_string())])),
+        QueueStart::Maintenance => Some(templates::render(locale, &templates::MAINTENANCE, &[])),
+        QueueStart::ReadOnly => Some(templates::render(locale, &templates::READ_ONLY, &[])),
+    }
+}
+
+/// Status an account returns to after a dry run: the one it had, unless that was a stale "running".
+fn status_after_dry_run(acc: &Account) -> &str {
+    if acc.status == STATUS_RUNNING { "pending" } else { &acc.status }
+}
+
+/// Outcome line of a dry run: whether the restore code got past the login, and the servers listed.
+fn dry_run_report(account: &str, servers: &[String], result: &Result<RunOutcome, String>, hint: Option<&str>) -> String {
+    match result {
+        Ok(outcome) => {
+            let servers = if servers.is_empty() { String::new() } else { format!(" Servers: {}.", servers.join(", ")) };
+            let warnings = if outcome.warnings.is_empty() { String::new() } else { format!(" ⚠️ {}", outcome.warnings.join("; ")) };
+            format!("[DRY RUN] **{}**: restore code accepted.{} Stopped before the flow; nothing was played.{}", account, servers, warnings)
+        },
+        Err(e) if e == RUN_CANCELLED => format!("[DRY RUN] **{}** was cancelled.", account),
+        Err(e) => {
+            let error = if e == RUN_TIMEOUT { "run timeout" } else { e.as_str() };
+            let hint = hint.map(|h| format!(" ({})", h)).unwrap_or_default();
+            format!("[DRY RUN] **{}** failed: {}{}", account, error, hint)
+        },
+    }
+}
+
+/// Remaining run cooldown, e.g. "1h 05m" or "12m".
+fn format_cooldown(remaining: std::time::Duration) -> String {
+    let mins = remaining.as_secs().div_ceil(60);
+    if mins < 60 { format!("{}m", mins) } else { format!("{}h {:02}m", mins / 60, mins % 60) }
+}
+
+/// Why `account` can't be run yet: its run cooldown lasts until `until`.
+fn cooldown_notice(account: &str, until: chrono::DateTime<Utc>, locale: Locale) -> String {
+    let remaining = (until - Utc::now()).to_std().unwrap_or_default();
+    templates::render(locale, &templates::RUN_COOLDOWN, &[
+        ("account", account),
+        ("remaining", &format_cooldown(remaining)),
+        ("until", &until.timestamp().to_string()),
+    ])
+}
+
+/// Until when /force_run refuses `account` because it completed a run shortly before
+/// (`queue.run_cooldown_mins`); `None` once that has passed or if the cooldown is off.
+fn run_cooldown_until(db: &Database, account: &str, now: chrono::DateTime<Utc>) -> Option<chrono::DateTime<Utc>> {
+    let cooldown = chrono::Duration::from_std(config::get().run_cooldown).ok().filter(|c| !c.is_zero())?;
+    let until = history::last_success_end(&db.data.run_history, account)? + cooldown;
+    (until > now).then_some(until)
+}
+
+/// Tail of a backup message: rotation and upload results.
+fn backup_summary(report: &backup::BackupReport) -> String {
+    let mut summary = String::new();
+    if report.removed > 0 {
+        summary.push_str(&format!(" Deleted {} old backup(s).", report.removed));
+    }
+    match &report.upload {
+        Some(Ok(())) => summary.push_str(" Uploaded to S3."),
+        Some(Err(e)) => summary.push_str(&format!(" Upload failed: {}", e)),
+        None => {}
+    }
+    summary
+}
+
+// Error string used when a session is aborted through its cancellation token
+const RUN_CANCELLED: &str = "CANCELLED";
+// Error string used when a session outlives `queue.run_timeout_mins`
+const RUN_TIMEOUT: &str = "RUN_TIMEOUT";
+
+fn cancel_button(account: &str) -> CreateActionRow {
+    CreateActionRow::Buttons(vec![
+        CreateButton::new(format!("cancel_run:{}", account)).label("Cancel").style(ButtonStyle::Danger)
+    ])
+}
+
+#[derive(Clone)]
 struct Handler {
     db: Arc<Mutex<Database>>,
-    is_processing: Arc<Mutex<bool>>,
+    queue_state: Arc<Mutex<QueueState>>,
+    // Run requests waiting for the current batch to finish
+    pending_runs: Arc<Mutex<PendingRuns>>,
+    // Run order of the batch in progress
+    batch_queue: Arc<Mutex<BatchQueue>>,
+    in_flight: InFlightRegistry,
+    // Automatic channel messages (queue progress, log channel) go through here
+    dispatcher: MessageDispatcher,
+    started: std::time::Instant,
+    // Last minute tick of the scheduler loop, for /diagnose
+    scheduler_tick: Arc<Mutex<Option<std::time::Instant>>>,
+    // Run lifecycle events; subscribe instead of hooking into process_queue
+    queue_events: QueueEvents,
+    session_counts: Arc<std::sync::Mutex<SessionCounts>>,
+    // Queue progress in the bot's activity, on every shard
+    presence: Presence,
+    // Hourly session budget of the queue manager, kept across batches
+    run_limiter: Arc<Mutex<Option<RunLimiter>>>,
+    // SERVER_FULL history of the target servers, kept across batches
+    server_capacity: Arc<std::sync::Mutex<ServerCapacity>>,
+    cooldowns: Arc<std::sync::Mutex<Cooldowns>>,
+    // What the queue worker is doing, for crash recovery and /debug_snapshot
+    worker_progress: Arc<std::sync::Mutex<WorkerProgress>>,
+    // Every shard shares this one handler. The first to become ready starts the dispatcher and the
+    // scheduler, so there is one queue and one scheduler however many shards connect.
+    background_started: Arc<AtomicBool>,
+    shards: Arc<std::sync::Mutex<ShardStatus>>,
+}
+
+fn format_uptime(elapsed: std::time::Duration) -> String {
+    let minutes = elapsed.as_secs() / 60;
+    let (days, hours, minutes) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else {
+        format!("{}h {}m", hours, minutes)
+    }
 }
 
 impl Handler {
     async fn is_admin(&self, ctx: &Context, interaction: &CommandInteraction) -> bool {
-        let db = self.db.lock().await;
-        if let Some(role_id_str) = &db.data.settings.admin_role_id {
-            if let Ok(role_id) = role_id_str.parse::<u64>() {
-                if let Some(member) = &interaction.member {
-                    return member.roles.contains(&RoleId::new(role_id));
-                }
-            }
+        self.is_admin_member(ctx, interaction.guild_id, interaction.user.id, interaction.member.as_deref()).await
+    }
+
+    async fn is_admin_member(&self, ctx: &Context, guild_id: Option<GuildId>, user_id: UserId, member: Option<&Member>) -> bool {
+        self.has_tier(ctx, guild_id, user_id, member, Tier::Admin).await
+    }
+
+    /// Whether the user holds `required` or a higher tier: the admin role and the
+    /// configured role tiers first, then the server owner (needs an API call).
+    async fn has_tier(&self, ctx: &Context, guild_id: Option<GuildId>, user_id: UserId, member: Option<&Member>, required: Tier) -> bool {
+        let role_tier = {
+            let db = self.db.lock().await;
+            member.and_then(|m| db.tier_for_roles(m.roles.iter().copied()))
+        };
+        if role_tier.map(|t| t >= required).unwrap_or(false) {
+            return true;
         }
-        // Fallback to guild owner if no role set or failed to check
-        if let Some(guild_id) = interaction.guild_id {
+        if let Some(guild_id) = guild_id {
             if let Ok(guild) = guild_id.to_partial_guild(&ctx.http).await {
-                return interaction.user.id == guild.owner_id;
+                self.warn_stale_admin_role(&guild).await;
+                return user_id == guild.owner_id;
             }
         }
         false
     }
 
-    async fn log_message(db: Arc<Mutex<Database>>, http: Arc<Http>, message: String, skip_channel: Option<ChannelId>) {
-        let db = db.lock().await;
-        if let Some(true) = db.data.settings.mute_bot_messages {
+    /// Logs (once per role) that the stored admin role is gone from the server or is @everyone.
+    /// Nobody can hold such a role, so it counts as unset until it is set again.
+    async fn warn_stale_admin_role(&self, guild: &PartialGuild) {
+        static WARNED: AtomicU64 = AtomicU64::new(0);
+        let Some(role) = self.db.lock().await.data.settings.admin_role_id else { return };
+        if guild.roles.contains_key(&role) && role.get() != guild.id.get() {
             return;
         }
-        if let Some(channel_id_str) = &db.data.settings.log_channel_id {
-            if let Ok(channel_id) = channel_id_str.parse::<u64>() {
-                let channel = ChannelId::new(channel_id);
-                if Some(channel) == skip_channel {
-                    return;
-                }
-                let _ = channel.say(&http, message).await;
+        if WARNED.swap(role.get(), Ordering::Relaxed) != role.get() {
+            log!("[WARN] The admin role {} does not exist in {} (or is @everyone); treating it as unset. The server owner can run /reset_admin_role or /set_admin_role.", role, guild.name);
+        }
+    }
+
+    /// Adds a finished run to the history. A failure that completes a streak of
+    /// `notifications.failure_streak_days` failed days is sent to the owner by DM.
+    #[allow(clippy::too_many_arguments)]
+    async fn record_run(db: &Arc<Mutex<Database>>, dispatcher: &MessageDispatcher, account: &str, started_at: chrono::DateTime<Utc>, duration: std::time::Duration, error: Option<String>, attempts: u32, detail: RunDetail) {
+        let mut db = db.lock().await;
+        let failed = error.clone();
+        if let Err(e) = db.record_run(account, started_at, duration, error, attempts, detail) {
+            log!("[WARN] Failed to record run history for {}: {}", account, e);
+        }
+        let (Some(error), alert_after) = (failed, config::get().failure_streak_days) else { return };
+        if alert_after == 0 {
+            return;
+        }
+        let now = Utc::now().with_timezone(&db.data.settings.timezone());
+        let streak = history::failure_streak(&db.data.run_history, account, &now);
+        // Only the day's first failure completes a streak, so retries and later schedules don't repeat the DM
+        let failures_today = db.data.run_history.iter()
+            .filter(|r| r.account == account && !r.success)
+            .filter(|r| r.started_at.with_timezone(&now.timezone()).date_naive() == now.date_naive())
+            .count();
+        if streak == 0 || streak % alert_after as usize != 0 || failures_today != 1 {
+            return;
+        }
+        let Some(owner) = db.find_account(account).and_then(|a| a.user_id.as_deref()).and_then(|id| id.parse::<u64>().ok()) else { return };
+        log!("[INFO] {} has failed {} days in a row; notifying its owner.", account, streak);
+        dispatcher.send_dm(UserId::new(owner), format!(
+            "⚠️ Your account **{}** has failed its runs {} days in a row.\nLast error: {}\nSuggested fix: {}",
+            account, streak, error, history::suggested_fix(&error)
+        ));
+    }
+
+    async fn remember_servers(db: &Arc<Mutex<Database>>, servers: &[String]) {
+        if servers.is_empty() {
+            return;
+        }
+        let mut db = db.lock().await;
+        if let Ok(true) = db.record_known_servers(servers) {
+            log!("[INFO] Known server list updated: {:?}", db.data.settings.known_servers);
+        }
+    }
+
+    /// After a successful run that had to fall back to server 1, makes that server the account's
+    /// target so later runs land on it deliberately, and tells the owner by DM.
+    async fn adopt_fallback_server(db: &Arc<Mutex<Database>>, dispatcher: &MessageDispatcher, account: &Account, outcome: &RunOutcome) {
+        let (Some(missing), Some(server)) = (account.target_server.as_deref(), outcome.fallback_server.as_deref()) else { return };
+        let mut db = db.lock().await;
+        match db.adopt_fallback_server(&account.name, missing, server) {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => {
+                log!("[WARN] Failed to update the target server of {}: {}", account.name, e);
+                return;
             }
         }
+        log!("[INFO] {}: target server '{}' was not listed; it now targets '{}', where the run ended up.", account.name, missing, server);
+        let Some(owner) = db.find_account(&account.name).and_then(|a| a.user_id.as_deref()).and_then(|id| id.parse::<u64>().ok()) else { return };
+        dispatcher.send_dm(UserId::new(owner), format!(
+            "ℹ️ The game no longer lists server **{}** for your account **{}**, so the run used **{}**. That server is now the account's target. Pick another one with /set_server if that's wrong.",
+            missing, account.name, server
+        ));
     }
 
-    async fn process_queue(&self, ctx: Context, user_id_filter: Option<String>, source_channel: Option<ChannelId>) {
-        let db_clone = Arc::clone(&self.db);
-        let processing_clone = Arc::clone(&self.is_processing);
-        let http_clone = ctx.http.clone();
+    /// Whether `name` already finished successfully since the last daily reset.
+    async fn completed_today(&self, name: &str) -> bool {
+        let db = self.db.lock().await;
+        let now = Utc::now().with_timezone(&db.data.settings.timezone());
+        db.find_account(name).map(|a| scheduler::completed_since_reset(a, &now)).unwrap_or(false)
+    }
 
+    /// Runs one account outside the queue, reporting to `channel_id` (/force_run, /debug_run). With
+    /// `debug_for`, every step of the session is relayed to a private thread for that admin.
+    fn spawn_single_run(&self, name: &str, channel_id: ChannelId, dry_run: bool, debug_for: Option<UserId>, locale: Locale) {
+        let db_clone = Arc::clone(&self.db);
+        let in_flight = self.in_flight.clone();
+        let dispatcher = self.dispatcher.clone();
+        let n_owned = name.to_string();
         tokio::spawn(async move {
-            let already_running = {
-                let mut is_proc = processing_clone.lock().await;
-                if *is_proc {
-                    true
-                } else {
-                    *is_proc = true;
-                    false
+            let in_flight_guard = match in_flight.try_acquire(&n_owned) {
+                Some(guard) => guard,
+                None => {
+                    dispatcher.send(channel_id, format!("[WARN] **{}** is already running (queue or another force_run). Wait for it to finish.", n_owned));
+                    return;
                 }
             };
 
-            if already_running {
-                if let Some(chan) = source_channel {
-                    let _ = chan.say(&http_clone, "[WARN] Queue Manager: Already in progress.").await;
+            let (cookie, base_url, thread_parent, acc) = {
+                let db = db_clone.lock().await;
+                let acc = db.find_account(&n_owned).cloned();
+                (acc.as_ref().map(|a| a.session_cookie(db.data.settings.cookies.as_deref())).unwrap_or_default(),
+                 resolve_base_url(db.data.settings.base_url.as_deref()),
+                 db.data.settings.run_thread_parent(),
+                 acc)
+            };
+
+            if let Some(acc) = acc {
+                if let Err(reason) = Account::validate_code(&acc.decrypt_code()) {
+                    {
+                        let mut db = db_clone.lock().await;
+                        let _ = db.update_status(&acc.name, STATUS_INVALID_CODE);
+                    }
+                    let owner = acc.owner_ping().map(|m| format!(" {}", m)).unwrap_or_default();
+                    dispatcher.send(channel_id, format!("[ERROR] **{}** not started: {} Re-add it with /add_account.{}", acc.name, reason, owner));
+                } else if cookie.is_empty() {
+                    dispatcher.send(channel_id, "[ERROR] No cookies set.");
+                } else {
+                    let run_id = run_id::generate();
+                    let progress = match debug_for {
+                        Some(admin) => RunProgressMessage::start_debug(&dispatcher, channel_id, admin, acc.name.clone(), run_id.clone(), vec![cancel_button(&acc.name)]),
+                        None => RunProgressMessage::start_with_components(&dispatcher, Some(channel_id), thread_parent, acc.name.clone(), run_id.clone(), vec![cancel_button(&acc.name)]),
+                    };
+                    let started_at = Utc::now();
+                    let started = std::time::Instant::now();
+                    let _trace = protocol::trace::start(&acc.name, &run_id);
+                    let _ = db_clone.lock().await.mark_running(&acc.name, started_at);
+                    run_id::scope(run_id, async {
+                        log!("[INFO] Starting forced {} {} for {}", acc.flow, if dry_run { "dry run" } else { "run" }, acc.name);
+                        match SessionClient::connect(&cookie, &base_url).await {
+                            Ok(mut client) => {
+                                let decrypted_code = acc.decrypt_code();
+                                let cancel = in_flight_guard.cancellation();
+                                let deadline = tokio::time::Instant::from_std(started) + config::get().run_timeout;
+                                let run_result = tokio::select! {
+                                    result = client.run_loop(&acc, &decrypted_code, acc.flow, Some(progress.event_sender()), dry_run) => result,
+                                    _ = cancel.cancelled() => Err(RUN_CANCELLED.into()),
+                                    _ = tokio::time::sleep_until(deadline) => Err(RUN_TIMEOUT.into()),
+                                };
+                                Self::remember_servers(&db_clone, client.discovered_servers()).await;
+                                if dry_run {
+                                    let result = run_result.map_err(|e| e.to_string());
+                                    {
+                                        let mut db = db_clone.lock().await;
+                                        let _ = db.update_status(&acc.name, status_after_dry_run(&acc));
+                                    }
+                                    progress.finish(dry_run_report(&acc.name, client.discovered_servers(), &result, client.failure_hint()));
+                                    return;
+                                }
+                                let error = run_result.as_ref().err().map(|e| match e.to_string() {
+                                    e if e == RUN_TIMEOUT => "run timeout".to_string(),
+                                    e => e,
+                                });
+                                if error.as_deref() != Some(RUN_CANCELLED) {
+                                    Self::record_run(&db_clone, &dispatcher, &acc.name, started_at, started.elapsed(), error, 1, client.run_detail()).await;
+                                }
+                                match run_result {
+                                    Ok(summary) => {
+                                        {
+                                            let mut db = db_clone.lock().await;
+                                            let _ = db.update_status(&acc.name, "done");
+                                        }
+                                        Self::adopt_fallback_server(&db_clone, &dispatcher, &acc, &summary).await;
+                                        let servers = summary.server_summary().map(|s| format!(" {}", s)).unwrap_or_default();
+                                        progress.finish(templates::render(locale, &templates::RUN_SUCCESS, &[
+                                            ("account", &acc.name),
+                                            ("summary", &summary.short_summary()),
+                                            ("servers", &servers),
+                                            ("stats", &summary.stats_summary()),
+                                            ("duration", &format_elapsed(summary.duration)),
+                                        ]));
+                                    },
+                                    Err(e) if e.to_string() == RUN_CANCELLED => {
+                                        {
+                                            let mut db = db_clone.lock().await;
+                                            let _ = db.update_status(&acc.name, "pending");
+                                        }
+                                        progress.finish(templates::render(locale, &templates::RUN_CANCELLED, &[("account", &acc.name)]));
+                                    },
+                                    Err(e) if e.to_string() == RUN_TIMEOUT => {
+                                        {
+                                            let mut db = db_clone.lock().await;
+                                            let _ = db.update_status(&acc.name, "error: run timeout");
+                                        }
+                                        progress.finish(templates::render(locale, &templates::RUN_TIMED_OUT, &[("account", &acc.name), ("limit", &format_elapsed(config::get().run_timeout))]));
+                                    },
+                                    Err(e) => {
+                                        {
+                                            let mut db = db_clone.lock().await;
+                                            let status = if e.to_string().contains("INVALID_RESTORE_CODE") { STATUS_INVALID_CODE.to_string() } else { format!("error: {}", e) };
+                                            let _ = db.update_status(&acc.name, &status);
+                                        }
+                                        let hint = client.failure_hint().map(|h| format!(" ({})", h)).unwrap_or_default();
+                                        progress.finish(templates::render(locale, &templates::RUN_FAILED, &[
+                                            ("account", &acc.name),
+                                            ("error", &e.to_string()),
+                                            ("hint", &hint),
+                                            ("duration", &format_elapsed(started.elapsed())),
+                                        ]));
+                                        if let Some(block) = client.last_output_block() {
+                                            dispatcher.send(channel_id, format!("Last terminal output of **{}**:\n{}", acc.name, block));
+                                        }
+                                    }
+                                }
+                            },
+                            Err(e) => {
+                                {
+                                    let mut db = db_clone.lock().await;
+                                    let status = if dry_run { status_after_dry_run(&acc).to_string() } else { format!("error: connection failed: {}", e) };
+                                    let _ = db.update_status(&acc.name, &status);
+                                }
+                                progress.finish(format!("[ERROR] Connection failed for **{}**: {}", acc.name, e));
+                            }
+                        }
+                    }).await;
+                    let _ = db_clone.lock().await.release_running(&acc.name);
                 }
+            } else {
+                dispatcher.send(channel_id, format!("[ERROR] Account **{}** not found.", n_owned));
+            }
+        });
+    }
+
+    /// Until when /force_run refuses `name`, see `run_cooldown_until`.
+    async fn run_cooldown(&self, name: &str) -> Option<chrono::DateTime<Utc>> {
+        let db = self.db.lock().await;
+        db.find_account(name).and_then(|a| run_cooldown_until(&db, &a.name, Utc::now()))
+    }
+
+    /// Language to reply to `user_id` in: their own choice, else the server's, else the one their
+    /// Discord client uses (`discord_locale`), if the bot has it.
+    async fn locale_for(&self, user_id: &str, discord_locale: &str) -> Locale {
+        let db = self.db.lock().await;
+        db.data.user_preferences.get(user_id).and_then(|p| p.locale)
+            .or(db.data.settings.locale)
+            .or_else(|| Locale::from_discord(discord_locale))
+            .unwrap_or_default()
+    }
+
+    /// Whether `user_id` owns the account or holds Operator, which may run anyone's.
+    /// An unknown account passes; the run itself reports that it doesn't exist.
+    async fn owns_or_operates(&self, ctx: &Context, command: &CommandInteraction, name: &str, user_id: &str) -> bool {
+        let owner = self.db.lock().await.find_account(name).map(|a| a.user_id.clone());
+        match owner {
+            Some(owner) if owner.as_deref() != Some(user_id) => {
+                self.has_tier(ctx, command.guild_id, command.user.id, command.member.as_deref(), Tier::Operator).await
+            }
+            _ => true,
+        }
+    }
+
+    /// The user's accounts /run_menu offers: everything not done yet.
+    async fn run_menu_accounts(&self, user_id: &str) -> Vec<Account> {
+        let db = self.db.lock().await;
+        db.get_user_accounts(user_id).into_iter().filter(|a| a.status != "done").collect()
+    }
+
+    /// Posts `message` to the log channel, unless the bot is muted or /set_log_level drops `level`.
+    async fn log_message(db: Arc<Mutex<Database>>, dispatcher: &MessageDispatcher, level: Level, message: String, skip_channel: Option<ChannelId>) {
+        let db = db.lock().await;
+        if db.data.settings.is_muted() || !db.data.settings.log_level().allows(level) {
+            return;
+        }
+        if let Some(channel) = db.data.settings.log_channel_id {
+            if Some(channel) == skip_channel {
                 return;
             }
+            dispatcher.send(channel, message);
+        }
+    }
+
+    /// Reports an automation outcome to the sinks /set_notify chose for its severity. The `dm`
+    /// sink goes to `account`'s owner; the `channel` sink behaves like `log_message`.
+    async fn notify(db: &Arc<Mutex<Database>>, dispatcher: &MessageDispatcher, severity: Severity, account: Option<&str>, message: String, skip_channel: Option<ChannelId>) {
+        Self::notify_at(db, dispatcher, severity, severity.level(), account, message, skip_channel).await
+    }
 
-            if let Some(chan) = source_channel {
-                    let _ = chan.say(&http_clone, "[INFO] Queue Manager: Starting automation sequence...").await;
+    /// `notify` with the `level` the log channel sees, for outcomes less (or more) serious than their severity.
+    async fn notify_at(db: &Arc<Mutex<Database>>, dispatcher: &MessageDispatcher, severity: Severity, level: Level, account: Option<&str>, message: String, skip_channel: Option<ChannelId>) {
+        let db = db.lock().await;
+        let mut fanout = Fanout::default();
+        for sink in db.data.settings.notify_sinks(severity) {
+            match sink {
+                Sink::Channel => {
+                    if let Some(channel) = db.data.settings.log_channel_id.filter(|c| !db.data.settings.is_muted() && db.data.settings.log_level().allows(level) && Some(*c) != skip_channel) {
+                        fanout.push(ChannelNotifier { dispatcher, channel });
+                    }
+                }
+                Sink::Dm => {
+                    let owner = account.and_then(|name| db.find_account(name)).and_then(|a| a.user_id.as_deref()).and_then(|id| id.parse::<u64>().ok());
+                    if let Some(owner) = owner {
+                        fanout.push(DirectNotifier { dispatcher, user: UserId::new(owner) });
+                    }
+                }
+                Sink::Webhook => fanout.push(WebhookNotifier),
+                Sink::Log => fanout.push(LogNotifier),
             }
+        }
+        fanout.notify(severity, &message);
+    }
 
-            loop {
-                // Check if we were told to stop
-                {
-                    let is_proc = processing_clone.lock().await;
-                    if !*is_proc { break; }
+    /// Startup check for accounts a crash left mid-run or mid-retry. They are logged, reset to
+    /// pending unless disabled in the settings, and reported to the log channel once connected.
+    async fn reconcile_interrupted_runs(&self) {
+        let (found, reset) = {
+            let mut db = self.db.lock().await;
+            let reset = db.data.settings.reset_interrupted_on_startup();
+            match db.reconcile_interrupted(reset) {
+                Ok(found) => (found, reset),
+                Err(e) => {
+                    log!("[ERROR] Startup: Failed to reset interrupted accounts: {}", e);
+                    return;
                 }
+            }
+        };
+        if found.is_empty() {
+            return;
+        }
 
-                let next_account = {
-                    let db = db_clone.lock().await;
-                    let mut accs: Vec<Account> = db.data.accounts.iter()
-                        .filter(|a| a.status != "done")
-                        .cloned()
-                        .collect();
-                    
-                    if let Some(uid) = &user_id_filter {
-                        accs.retain(|a| a.user_id.as_deref() == Some(uid));
+        let list = found.iter().map(|(name, status)| format!("**{}** ({})", name, status)).collect::<Vec<_>>().join(", ");
+        log!("[WARN] Startup: {} account(s) were interrupted by the last shutdown: {}", found.len(), list);
+        let action = if reset { "They were reset to pending." } else { "Their status was left unchanged." };
+        Self::log_message(Arc::clone(&self.db), &self.dispatcher, Level::Warning, format!("[WARN] Startup: interrupted runs found for {}. {}", list, action), None).await;
+    }
+
+    /// Retries failed saves in the background. When the database goes read-only, a running queue
+    /// is paused and a critical notification sent; once a save goes through again, the queue is
+    /// resumed (if it was paused here) and the recovery reported.
+    fn watch_storage(&self) {
+        let handler = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SAVE_RETRY_INTERVAL);
+            let mut read_only = false;
+            let mut paused_queue = false;
+            loop {
+                interval.tick().await;
+                let (now_read_only, path) = {
+                    let mut db = handler.db.lock().await;
+                    if db.is_dirty() {
+                        let _ = db.save();
                     }
-                    
-                    // Explicitly prioritize:
-                    // 1. Pending accounts (in insertion order)
-                    // 2. Error/Retrying accounts (in insertion order)
-                    let (mut pending, errors): (Vec<Account>, Vec<Account>) = accs.into_iter()
-                        .partition(|a| !a.status.starts_with("error"));
-                    
-                    pending.extend(errors);
-                    pending.into_iter().next()
+                    (db.is_read_only(), db.path().unwrap_or(&config::get().database_path).to_string())
                 };
+                if now_read_only == read_only {
+                    continue;
+                }
+                read_only = now_read_only;
+                if read_only {
+                    {
+                        let mut state = handler.queue_state.lock().await;
+                        if *state == QueueState::Running {
+                            *state = QueueState::Paused;
+                            paused_queue = true;
+                        }
+                    }
+                    log!("[ERROR] Database: {} saves in a row failed. Read-only until a save succeeds; retrying every {}s.", READ_ONLY_AFTER_FAILURES, SAVE_RETRY_INTERVAL.as_secs());
+                    Self::notify(&handler.db, &handler.dispatcher, Severity::Critical, None, format!("⚠️ **[CRITICAL] Database: saving to `{}` keeps failing; changes are only in memory.** The bot is read-only: {}no runs are started and no accounts added until saving works again. Check the disk's free space and permissions.", path, if paused_queue { "the queue is paused, " } else { "" }), None).await;
+                } else {
+                    let resumed = paused_queue && {
+                        let mut state = handler.queue_state.lock().await;
+                        let paused = *state == QueueState::Paused;
+                        if paused {
+                            *state = QueueState::Running;
+                        }
+                        paused
+                    };
+                    paused_queue = false;
+                    log!("[INFO] Database: Saved to {} again. Read-only mode ended.", path);
+                    Self::notify(&handler.db, &handler.dispatcher, Severity::Success, None, format!("[SUCCESS] Database: saving to `{}` works again and the in-memory changes were written.{}", path, if resumed { " The queue was resumed." } else { "" }), None).await;
+                }
+            }
+        });
+    }
 
-                let acc = match next_account {
-                    Some(a) => a,
-                    None => break,
-                };
-                
-                let cookie = {
-                    let db = db_clone.lock().await;
-                    db.data.settings.cookies.clone().unwrap_or_default()
-                };
+    async fn run_diagnostics(&self, ctx: &Context) -> Vec<diagnose::Check> {
+        let (storage, running, cookie, base_url, log_channel) = {
+            let db = self.db.lock().await;
+            (diagnose::check_database(&db),
+             diagnose::check_running(&db),
+             db.data.settings.cookies.clone().unwrap_or_default(),
+             resolve_base_url(db.data.settings.base_url.as_deref()),
+             db.data.settings.log_channel_id)
+        };
+        let bot_id = ctx.cache.current_user().id;
+        vec![
+            storage,
+            diagnose::check_endpoint(&cookie, &base_url).await,
+            diagnose::check_log_channel(&ctx.http, log_channel, bot_id).await,
+            diagnose::check_scheduler(*self.scheduler_tick.lock().await),
+            diagnose::check_shards(&self.shards.lock().unwrap_or_else(|e| e.into_inner())),
+            running,
+        ]
+    }
 
-                if cookie.is_empty() {
-                     break;
-                }
+    /// Pre-flight check of the nightly batch, posted to the log channel. False if the batch
+    /// should not start: the shared cookie failed and /preflight_halt is on.
+    async fn preflight(&self) -> bool {
+        let (checks, halt, channel) = {
+            let db = self.db.lock().await;
+            let settings = &db.data.settings;
+            let checks = preflight::run(&db, Utc::now());
+            let level = match checks.iter().map(|c| c.status).max() {
+                Some(diagnose::Status::Fail) => Level::Error,
+                Some(diagnose::Status::Warn) => Level::Warning,
+                _ => Level::Info,
+            };
+            let channel = settings.log_channel_id.filter(|_| !settings.is_muted() && settings.log_level().allows(level));
+            (checks, settings.preflight_halt(), channel)
+        };
+        for check in checks.iter().filter(|c| c.status != diagnose::Status::Pass) {
+            log!("[WARN] Pre-flight: {}: {}", check.name, check.detail);
+        }
+        if let Some(channel) = channel {
+            self.dispatcher.send_embed(channel, diagnose::render("Nightly Pre-flight", &checks));
+        }
+        if halt && preflight::cookie_failed(&checks) {
+            log!("[ERROR] Scheduler: Nightly batch halted, the shared session cookie failed the pre-flight check.");
+            Self::log_message(Arc::clone(&self.db), &self.dispatcher, Level::Error, "[ERROR] The nightly batch was not started: the shared session cookie was rejected by the game. Set a fresh one with /set_cookies, then start the batch with /force_run_all.".to_string(), None).await;
+            return false;
+        }
+        true
+    }
+
+    /// Posts the weekly summary embed to the log channel (skipped when muted or unset).
+    async fn post_weekly_digest(db: Arc<Mutex<Database>>, dispatcher: &MessageDispatcher) {
+        let (channel, digest) = {
+            let db = db.lock().await;
+            if db.data.settings.is_muted() {
+                return;
+            }
+            let channel = match db.data.settings.log_channel_id {
+                Some(channel) => channel,
+                None => return,
+            };
+            let names: Vec<String> = db.active_accounts().map(|a| a.name.clone()).collect();
+            let now = Utc::now().with_timezone(&db.data.settings.timezone());
+            (channel, weekly_digest(&db.data.run_history, &names, &now))
+        };
+
+        let now = Utc::now().with_timezone(&db.lock().await.data.settings.timezone());
+        let mut embed = CreateEmbed::new()
+            .title("Weekly Summary")
+            .description(format!("{} – {}", (now - chrono::Duration::days(7)).format("%b %d"), now.format("%b %d")))
+            .colour(Colour::DARK_GREEN);
+        // Discord allows 25 fields per embed; one is kept for the stale list
+        for week in digest.accounts.iter().take(23) {
+            let mut value = format!("Completed: {} day(s)\nFailed: {} day(s)", week.days_completed, week.days_failed);
+            if let Some(error) = &week.most_common_error {
+                value.push_str(&format!("\nMost common error: {}", redact(error).chars().take(200).collect::<String>()));
+            }
+            embed = embed.field(&week.account, value, true);
+        }
+        if digest.accounts.len() > 23 {
+            embed = embed.field("…", format!("{} more accounts not shown", digest.accounts.len() - 23), false);
+        }
+        let stale = if digest.stale.is_empty() {
+            "None".to_string()
+        } else {
+            digest.stale.join(", ")
+        };
+        embed = embed.field(format!("No success in {}+ days", STALE_AFTER_DAYS), stale, false);
+
+        dispatcher.send_embed(channel, embed);
+    }
+
+    /// Cleans up after a queue worker panicked: marks the account it was running as errored, alerts
+    /// the log channel and webhook, and returns the request to restart (without that account) or
+    /// None once the queue was stopped.
+    async fn recover_crashed_worker(&self, panic: Box<dyn std::any::Any + Send>, progress: &std::sync::Mutex<WorkerProgress>, crashed: &mut Vec<String>, can_restart: bool) -> Option<PendingRun> {
+        let message = panic.downcast_ref::<&str>().map(|m| m.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        let (request, account) = match progress.lock() {
+            Ok(progress) => (progress.request.clone(), progress.account.clone()),
+            Err(poisoned) => (poisoned.get_ref().request.clone(), poisoned.get_ref().account.clone()),
+        };
+        let source_channel = request.as_ref().and_then(|r| r.channel);
+        let during = account.as_ref().map(|a| format!(" while running **{}**", a)).unwrap_or_default();
+        log!("[CRITICAL] Queue Manager: The worker crashed{}: {}", during.replace("**", ""), message);
+
+        self.batch_queue.lock().await.clear();
+        if let Some(name) = &account {
+            let _ = self.db.lock().await.update_status(name, STATUS_WORKER_CRASHED);
+            crashed.push(name.clone());
+        }
+
+        // A /force_stop_all meanwhile wins over the restart
+        let (restart, dropped) = {
+            let mut state = self.queue_state.lock().await;
+            if can_restart && state.is_active() && request.is_some() {
+                (request, Vec::new())
+            } else {
+                *state = QueueState::Stopped;
+                (None, self.pending_runs.lock().await.clear())
+            }
+        };
+        let outcome = if restart.is_some() {
+            "The queue was restarted without it."
+        } else {
+            "The queue was stopped; start it again with /force_run_all."
+        };
+        let text = format!("[CRITICAL] Queue Manager: The worker crashed{} ({}). {}", during, message, outcome);
+        if let Some(chan) = source_channel {
+            self.dispatcher.send(chan, text.clone());
+        }
+        Self::notify(&self.db, &self.dispatcher, Severity::Critical, account.as_deref(), text, source_channel).await;
+        alerts::send(Alert::QueueCrashed, "The queue worker crashed.", &[
+            ("panic", message),
+            ("account", account.unwrap_or_default()),
+            ("restarted", restart.is_some().to_string()),
+        ]);
+        for request in dropped {
+            if let Some(chan) = request.channel.filter(|c| Some(*c) != source_channel) {
+                self.dispatcher.send(chan, "[WARN] Queue Manager: The queue was stopped, so your waiting run request was dropped. Run it again when ready.");
+            }
+        }
+        restart
+    }
+
+    /// Starts a batch for `filter`, or queues the request behind the batch already running.
+    /// Accounts run their own flow unless `flow` overrides it.
+    async fn process_queue(&self, filter: AccountFilter, flow: Option<Flow>, dry_run: bool, source_channel: Option<ChannelId>) -> QueueStart {
+        {
+            let db = self.db.lock().await;
+            if db.data.settings.is_maintenance() {
+                return QueueStart::Maintenance;
+            }
+            if db.is_read_only() {
+                log!("[WARN] Queue Manager: Run request refused, the database is read-only.");
+                return QueueStart::ReadOnly;
+            }
+        }
+        {
+            let mut state = self.queue_state.lock().await;
+            if state.is_active() {
+                return self.pending_runs.lock().await.push(filter, flow, dry_run, source_channel);
+            }
+            *state = QueueState::Running;
+        }
+
+        let progress = Arc::clone(&self.worker_progress);
+        if let Ok(mut progress) = progress.lock() {
+            *progress = WorkerProgress::default();
+        }
+        // Spawns a worker for a request; `crashed` accounts are left out (see recover_crashed_worker)
+        let spawn_worker = {
+            let handler = self.clone();
+            let progress = Arc::clone(&progress);
+            move |filter: AccountFilter, flow: Option<Flow>, dry_run: bool, source_channel: Option<ChannelId>, crashed: Vec<String>| {
+                let db_clone = Arc::clone(&handler.db);
+                let state_clone = Arc::clone(&handler.queue_state);
+                let pending_runs = Arc::clone(&handler.pending_runs);
+                let batch_queue = Arc::clone(&handler.batch_queue);
+                let in_flight = handler.in_flight.clone();
+                let dispatcher = handler.dispatcher.clone();
+                let events = handler.queue_events.clone();
+                let run_limiter = Arc::clone(&handler.run_limiter);
+                let server_capacity = Arc::clone(&handler.server_capacity);
+
+                let progress = Arc::clone(&progress);
+                tokio::spawn(async move {
+                    let mut filter = filter;
+                    let mut flow = flow;
+                    let mut dry_run = dry_run;
+                    let mut source_channel = source_channel;
+                    loop {
+                        // Channel messages are in the server's language
+                        let locale = db_clone.lock().await.data.settings.locale();
+                        if let Ok(mut progress) = progress.lock() {
+                            progress.request = Some(PendingRun { filter: filter.clone(), flow, dry_run, channel: source_channel });
+                            progress.account = None;
+                            progress.crashed = crashed.clone();
+                        }
+                        if let Some(chan) = source_channel {
+                            dispatcher.send(chan, templates::render(locale, &templates::QUEUE_STARTED, &[]));
+                        }
 
-                match EvertextClient::connect(&cookie).await {
-                    Ok(mut client) => {
-                        let decrypted_code = acc.decrypt_code();
-                        match client.run_loop(&acc, &decrypted_code).await {
-                             Ok(_) => {
+                        // Accounts skipped for the rest of this batch: another path was already running them,
+                        // they were cancelled, or they had no session cookie
+                        let mut busy_skipped: Vec<String> = Vec::new();
+                        // Shared by every transient-failure retry in this batch; reset once a session succeeds
+                        let mut connect_backoff = Backoff::default();
+                        let mut retries = RetryTracker::default();
+                        // The hourly limit was reported for the wait in progress
+                        let mut announced_limit = false;
+                        // Full servers whose accounts were already reported as deferred this batch
+                        let mut announced_full: Vec<String> = Vec::new();
+                        // Waiting for a full server was reported for the wait in progress
+                        let mut announced_full_wait = false;
+                        batch_queue.lock().await.clear();
+
+                        'queue: loop {
+                            // Check if we were told to stop, and hold here while paused
+                            let mut announced_pause = false;
+                            loop {
+                                let state = *state_clone.lock().await;
+                                match state {
+                                    QueueState::Running => break,
+                                    QueueState::Stopped => break 'queue,
+                                    QueueState::Paused => {
+                                        if !announced_pause {
+                                            if let Some(chan) = source_channel {
+                                                dispatcher.send(chan, templates::render(locale, &templates::QUEUE_PAUSED, &[]));
+                                            }
+                                            announced_pause = true;
+                                        }
+                                        tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+                                    }
+                                }
+                            }
+
+                            if let Ok(mut progress) = progress.lock() {
+                                progress.account = None;
+                                progress.retries = retries.snapshot();
+                            }
+                            let next_account = {
+                                let db = db_clone.lock().await;
+                                let runnable = |a: &Account| a.status != "done" && a.status != STATUS_INVALID_CODE
+                                    && !busy_skipped.contains(&a.name) && !retries.is_exhausted(&a.name)
+                                    && !crashed.contains(&a.name) && filter.matches(a);
+                                let mut order = batch_queue.lock().await;
+                                if order.is_empty() {
+                                    // First pick of the batch, or everything queued so far is through; accounts
+                                    // that became runnable meanwhile (e.g. newly added) join at the back
+                                    let last_completed = db.data.settings.catch_up_first()
+                                        .then(|| history::last_completed_days(&db.data.run_history, &db.data.settings.timezone()));
+                                    order.extend(batch_order(db.active_accounts().filter(|a| runnable(a)), &filter, last_completed.as_ref()));
+                                }
+                                // Skip accounts that were finished, removed or changed since they were queued
+                                let mut next = None;
+                                while let Some(name) = order.pop_front() {
+                                    if let Some(acc) = db.active_accounts().find(|a| a.name == name && runnable(a)) {
+                                        next = Some(acc.clone());
+                                        break;
+                                    }
+                                }
+                                next
+                            };
+
+                            let acc = match next_account {
+                                Some(a) => a,
+                                None => break,
+                            };
+                            if let Ok(mut progress) = progress.lock() {
+                                progress.account = Some(acc.name.clone());
+                            }
+
+                            // Don't spend a session on a code the game is bound to reject
+                            if let Err(reason) = Account::validate_code(&acc.decrypt_code()) {
                                 {
                                     let mut db = db_clone.lock().await;
-                                    let _ = db.update_status(&acc.name, "done");
+                                    let _ = db.update_status(&acc.name, STATUS_INVALID_CODE);
                                 }
+                                log!("[ERROR] {} skipped without connecting: {}", acc.name, reason);
+                                let owner = acc.owner_ping().map(|m| format!(" {}", m)).unwrap_or_default();
                                 if let Some(chan) = source_channel {
-                                    let _ = chan.say(&http_clone, format!("[SUCCESS] **{}** completed.", acc.name)).await;
+                                    dispatcher.send(chan, format!("[ERROR] **{}** skipped: {} Re-add it with /add_account to run it again.{}", acc.name, reason, owner));
                                 }
-                                Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[SUCCESS] Automation: **{}** completed successfully.", acc.name), source_channel).await;
-                            },
-                            Err(e) => {
-                                let err_str = e.to_string();
-                                
-                                if err_str.contains("SESSION_COMPLETE") {
-                                    {
-                                        let mut db = db_clone.lock().await;
-                                        let _ = db.update_status(&acc.name, "done");
+                                Self::notify(&db_clone, &dispatcher, Severity::Failure, Some(&acc.name), format!("[ERROR] Automation: **{}** skipped, invalid restore code: {}{}", acc.name, reason, owner), source_channel).await;
+                                continue;
+                            }
+
+                            let (cookie, base_url, thread_parent) = {
+                                let db = db_clone.lock().await;
+                                (acc.session_cookie(db.data.settings.cookies.as_deref()),
+                                 resolve_base_url(db.data.settings.base_url.as_deref()),
+                                 db.data.settings.run_thread_parent())
+                            };
+                            let own_cookie = acc.cookie_override.is_some();
+
+                            // Without a shared cookie, only the accounts that have their own can run
+                            if cookie.is_empty() {
+                                {
+                                    let mut db = db_clone.lock().await;
+                                    let _ = db.update_status(&acc.name, STATUS_NO_COOKIE);
+                                }
+                                busy_skipped.push(acc.name.clone());
+                                log!("[ERROR] {} skipped: no session cookie", acc.name);
+                                let owner = acc.owner_ping().map(|m| format!(" {}", m)).unwrap_or_default();
+                                if let Some(chan) = source_channel {
+                                    dispatcher.send(chan, templates::render(locale, &templates::RUN_NO_COOKIE, &[("account", &acc.name), ("owner", &owner)]));
+                                }
+                                Self::notify(&db_clone, &dispatcher, Severity::Failure, Some(&acc.name), format!("[ERROR] Automation: **{}** skipped, no session cookie is set.{}", acc.name, owner), source_channel).await;
+                                continue;
+                            }
+
+                            // A server that just turned a session away likely still has no room; run other servers first
+                            let cooldown = config::get().server_full_cooldown;
+                            let full_until = acc.target_server.as_deref()
+                                .and_then(|server| server_capacity.lock().ok()?.full_until(server, Utc::now(), cooldown));
+                            if let (Some(server), Some(until)) = (acc.target_server.clone(), full_until) {
+                                let waiting = batch_queue.lock().await.names();
+                                let others_have_room = {
+                                    let db = db_clone.lock().await;
+                                    let capacity = server_capacity.lock().ok();
+                                    waiting.iter()
+                                        .filter_map(|name| db.active_accounts().find(|a| &a.name == name))
+                                        .any(|a| match (&a.target_server, &capacity) {
+                                            (Some(other), Some(capacity)) => capacity.full_until(other, Utc::now(), cooldown).is_none(),
+                                            _ => true,
+                                        })
+                                };
+                                if others_have_room {
+                                    log!("[INFO] Queue Manager: {} targets {}, full until {}; running other servers first.", acc.name, server, until.format("%H:%M:%S UTC"));
+                                    if !announced_full.contains(&server) {
+                                        if let Some(chan) = source_channel {
+                                            dispatcher.send(chan, format!("[INFO] Queue Manager: **{}** was full recently. Accounts targeting it run after the others (from <t:{}:R>).", server, until.timestamp()));
+                                        }
+                                        announced_full.push(server);
                                     }
-                                    if let Some(chan) = source_channel {
-                                        let _ = chan.say(&http_clone, format!("[SUCCESS] **{}** completed.", acc.name)).await;
+                                    batch_queue.lock().await.push_back(&acc.name);
+                                } else {
+                                    let wait = (until - Utc::now()).to_std().unwrap_or_default();
+                                    if !announced_full_wait {
+                                        log!("[INFO] Queue Manager: Only accounts on full servers are left. {} ({}) starts in {}.", acc.name, server, format_elapsed(wait));
+                                        if let Some(chan) = source_channel {
+                                            dispatcher.send(chan, format!("[INFO] Queue Manager: Only accounts on full servers are left. **{}** ({}) starts <t:{}:R>.", acc.name, server, until.timestamp()));
+                                        }
+                                        announced_full_wait = true;
                                     }
-                                    Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[SUCCESS] Automation: **{}** completed through prompt flow.", acc.name), source_channel).await;
+                                    // Wake up regularly so stop and pause still apply while waiting
+                                    batch_queue.lock().await.push_front(&acc.name);
+                                    tokio::time::sleep(wait.clamp(std::time::Duration::from_secs(1), std::time::Duration::from_secs(30))).await;
+                                }
+                                continue;
+                            }
+                            announced_full_wait = false;
 
-                                } else if err_str.contains("INVALID_COMMAND_RESTART") {
+                            let in_flight_guard = match in_flight.try_acquire(&acc.name) {
+                                Some(guard) => guard,
+                                None => {
                                     if let Some(chan) = source_channel {
-                                         let _ = chan.say(&http_clone, format!("[WARN] Invalid Command on **{}**. Restarting session immediately.", acc.name)).await;
+                                        dispatcher.send(chan, templates::render(locale, &templates::RUN_BUSY, &[("account", &acc.name)]));
                                     }
-                                    tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+                                    busy_skipped.push(acc.name.clone());
+                                    continue;
+                                }
+                            };
 
-                                } else if err_str.contains("ZIGZA_DETECTED") {
+                            let wait = run_limiter.lock().await.as_mut().and_then(|limiter| limiter.try_take().err().map(|wait| (wait, limiter.limit())));
+                            if let Some((wait, limit)) = wait {
+                                if !announced_limit {
+                                    log!("[INFO] Queue Manager: Hourly run limit ({}) reached. Next run ({}) in {}.", limit, acc.name, format_elapsed(wait));
                                     if let Some(chan) = source_channel {
-                                        let _ = chan.say(&http_clone, format!("[WARN] Zigza error on **{}**. Waiting 10 mins before retry.", acc.name)).await;
+                                        dispatcher.send(chan, format!("[INFO] Queue Manager: Hourly limit of {} runs reached. **{}** starts in about {}.", limit, acc.name, format_elapsed(wait)));
                                     }
-                                    Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[WARN] Automation: Zigza detected on **{}**. Retrying in 10m.", acc.name), source_channel).await;
+                                    announced_limit = true;
+                                }
+                                // Wake up regularly so stop and pause still apply while waiting
+                                drop(in_flight_guard);
+                                batch_queue.lock().await.push_front(&acc.name);
+                                tokio::time::sleep(wait.min(std::time::Duration::from_secs(30))).await;
+                                continue;
+                            }
+                            announced_limit = false;
+
+                            let attempt = retries.start_attempt(&acc.name);
+                            let run_flow = flow.unwrap_or(acc.flow);
+                            let run_id = run_id::generate();
+                            let progress = RunProgressMessage::start(&dispatcher, source_channel, thread_parent, acc.name.clone(), run_id.clone());
+                            let started_at = Utc::now();
+                            let started = std::time::Instant::now();
+                            let _ = db_clone.lock().await.mark_running(&acc.name, started_at);
+
+                            let (position, total) = batch_queue.lock().await.progress();
+                            events.emit(QueueEvent::RunStarted { account: acc.name.clone(), run_id: run_id.clone(), position, total });
+                            let mut last_output: Option<String> = None;
+                            let mut run_detail = RunDetail::default();
+                            let mut failure_hint: Option<&str> = None;
+                            let mut listed_servers: Vec<String> = Vec::new();
+                            let _trace = protocol::trace::start(&acc.name, &run_id);
+                            let run_result = run_id::scope(run_id.clone(), async {
+                                log!("[INFO] Starting {} {} for {} (attempt {})", run_flow, if dry_run { "dry run" } else { "run" }, acc.name, attempt);
+                                match SessionClient::connect(&cookie, &base_url).await {
+                                    Ok(mut client) => {
+                                        let decrypted_code = acc.decrypt_code();
+                                        let cancel = in_flight_guard.cancellation();
+                                        // Counted from before the connect, so a slow handshake eats into the limit too
+                                        let deadline = tokio::time::Instant::from_std(started) + config::get().run_timeout;
+                                        let result = tokio::select! {
+                                            result = client.run_loop(&acc, &decrypted_code, run_flow, progress.as_ref().map(|p| p.event_sender()), dry_run) => result,
+                                            _ = cancel.cancelled() => Err(RUN_CANCELLED.into()),
+                                            _ = tokio::time::sleep_until(deadline) => Err(RUN_TIMEOUT.into()),
+                                        };
+                                        Self::remember_servers(&db_clone, client.discovered_servers()).await;
+                                        last_output = client.last_output_block();
+                                        run_detail = client.run_detail();
+                                        failure_hint = client.failure_hint();
+                                        listed_servers = client.discovered_servers().to_vec();
+                                        result.map_err(|e| e.to_string())
+                                    },
+                                    Err(e) => {
+                                        log!("[WARN] Connection failed for {} (attempt {}): {}", acc.name, attempt, e);
+                                        Err(format!("Connection failed: {}", e))
+                                    }
+                                }
+                            }).await;
+
+                            events.emit(match &run_result {
+                                Ok(_) => QueueEvent::RunFinished { account: acc.name.clone(), run_id: run_id.clone(), duration: started.elapsed() },
+                                Err(error) => QueueEvent::RunFailed { account: acc.name.clone(), run_id: run_id.clone(), error: error.clone() },
+                            });
+                            // A dry run is not recorded and leaves the status as it was. An expired shared
+                            // cookie or a certificate mismatch still stops the queue below.
+                            let stops_queue = run_result.as_ref().err()
+                                .map(|e| e.contains(tls::PIN_MISMATCH) || (e.contains("LOGIN_REQUIRED") && !own_cookie))
+                                .unwrap_or(false);
+                            if dry_run && !stops_queue {
+                                retries.finish(&acc.name);
+                                if run_result.is_ok() {
+                                    connect_backoff.reset();
+                                }
+                                {
+                                    let mut db = db_clone.lock().await;
+                                    let _ = db.update_status(&acc.name, status_after_dry_run(&acc));
+                                }
+                                finish_progress(progress, dry_run_report(&acc.name, &listed_servers, &run_result, failure_hint));
+                                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+                                continue;
+                            }
+                            match run_result {
+                                Ok(summary) => {
+                                    connect_backoff.reset();
+                                    retries.finish(&acc.name);
+                                    Self::record_run(&db_clone, &dispatcher, &acc.name, started_at, started.elapsed(), None, attempt, summary.detail()).await;
                                     {
                                         let mut db = db_clone.lock().await;
-                                        let _ = db.update_status(&acc.name, "error: Zigza Retrying");
+                                        let _ = db.update_status(&acc.name, "done");
                                     }
-                                    tokio::time::sleep(tokio::time::Duration::from_secs(600)).await;
+                                    Self::adopt_fallback_server(&db_clone, &dispatcher, &acc, &summary).await;
+                                    let servers = summary.server_summary().map(|s| format!(" {}", s)).unwrap_or_default();
+                                    finish_progress(progress, templates::render(locale, &templates::RUN_SUCCESS, &[
+                                        ("account", &acc.name),
+                                        ("summary", &summary.short_summary()),
+                                        ("servers", &servers),
+                                        ("stats", &summary.stats_summary()),
+                                        ("duration", &format_elapsed(summary.duration)),
+                                    ]));
+                                    Self::notify(&db_clone, &dispatcher, Severity::Success, Some(&acc.name), format!("[SUCCESS] Automation: **{}** completed successfully. {}{} {} (run `{}`)", acc.name, summary.short_summary(), servers, summary.stats_summary(), run_id), source_channel).await;
+                                },
+                                Err(err_str) => {
+                                    if err_str == RUN_CANCELLED {
+                                        retries.finish(&acc.name);
+                                        {
+                                            let mut db = db_clone.lock().await;
+                                            let _ = db.update_status(&acc.name, "pending");
+                                        }
+                                        // Don't pick it straight back up in this batch
+                                        busy_skipped.push(acc.name.clone());
+                                        finish_progress(progress, templates::render(locale, &templates::RUN_CANCELLED, &[("account", &acc.name)]));
 
-                                } else if err_str.contains("SERVER_FULL") {
-                                    if let Some(chan) = source_channel {
-                                        let _ = chan.say(&http_clone, format!("[WARN] Server Full. Retrying **{}** in 5 mins.", acc.name)).await;
-                                    }
-                                    Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[WARN] Automation: Server full. Retrying **{}** in 5m.", acc.name), source_channel).await;
-                                    tokio::time::sleep(tokio::time::Duration::from_secs(300)).await;
+                                    } else if is_transient_error(&err_str) {
+                                        let reason = if err_str.contains("ZIGZA_DETECTED") {
+                                            "Zigza error".to_string()
+                                        } else if err_str.contains("INVALID_COMMAND_RESTART") {
+                                            "Invalid command".to_string()
+                                        } else if err_str.contains("SERVER_FULL") {
+                                            if let (Some(server), Ok(mut capacity)) = (&acc.target_server, server_capacity.lock()) {
+                                                capacity.record_full(server, Utc::now());
+                                            }
+                                            "Server full".to_string()
+                                        } else {
+                                            format!("Connection issue ({})", err_str)
+                                        };
+                                        match retries.on_transient_failure(&acc.name) {
+                                            RetryDecision::RetryInline => {
+                                                let delay = connect_backoff.next_delay().max(min_retry_delay(&err_str));
+                                                finish_progress(progress, templates::render(locale, &templates::RUN_RETRYING, &[
+                                                    ("account", &acc.name),
+                                                    ("reason", &reason),
+                                                    ("delay", &format_elapsed(delay)),
+                                                    ("attempt", &attempt.to_string()),
+                                                ]));
+                                                batch_queue.lock().await.push_front(&acc.name);
+                                                tokio::time::sleep(delay).await;
+                                            },
+                                            RetryDecision::Requeue => {
+                                                batch_queue.lock().await.push_back(&acc.name);
+                                                {
+                                                    let mut db = db_clone.lock().await;
+                                                    let _ = db.update_status(&acc.name, &format!("error: {} (requeued after {} attempts)", reason, attempt));
+                                                }
+                                                finish_progress(progress, templates::render(locale, &templates::RUN_REQUEUED, &[("account", &acc.name), ("reason", &reason), ("attempt", &attempt.to_string())]));
+                                                Self::notify_at(&db_clone, &dispatcher, Severity::Failure, Level::Warning, Some(&acc.name), format!("[WARN] Automation: **{}** requeued after {} attempts ({}). (run `{}`)", acc.name, attempt, reason, run_id), source_channel).await;
+                                            },
+                                            RetryDecision::GiveUp => {
+                                                Self::record_run(&db_clone, &dispatcher, &acc.name, started_at, started.elapsed(), Some(err_str.clone()), attempt, run_detail.clone()).await;
+                                                {
+                                                    let mut db = db_clone.lock().await;
+                                                    let _ = db.update_status(&acc.name, &format!("error: {} (gave up after {} attempts)", reason, attempt));
+                                                }
+                                                finish_progress(progress, templates::render(locale, &templates::RUN_GAVE_UP, &[("account", &acc.name), ("reason", &reason), ("attempt", &attempt.to_string())]));
+                                                Self::notify(&db_clone, &dispatcher, Severity::Failure, Some(&acc.name), format!("[ERROR] Automation: **{}** gave up after {} attempts ({}). (run `{}`)", acc.name, attempt, reason, run_id), source_channel).await;
+                                            },
+                                        }
+
+                                    } else if err_str.contains("INVALID_RESTORE_CODE") {
+                                        retries.finish(&acc.name);
+                                        Self::record_run(&db_clone, &dispatcher, &acc.name, started_at, started.elapsed(), Some(err_str.clone()), attempt, run_detail.clone()).await;
+                                        {
+                                            let mut db = db_clone.lock().await;
+                                            let _ = db.update_status(&acc.name, STATUS_INVALID_CODE);
+                                        }
+                                        finish_progress(progress, templates::render(locale, &templates::RUN_INVALID_CODE, &[("account", &acc.name)]));
+                                        Self::notify(&db_clone, &dispatcher, Severity::Failure, Some(&acc.name), format!("[ERROR] Automation: **{}** skipped, restore code is malformed. (run `{}`)", acc.name, run_id), source_channel).await;
+
+                                    } else if err_str.contains("LOGIN_REQUIRED") && own_cookie {
+                                        // Only this member's own session expired; the rest of the queue is fine
+                                        retries.finish(&acc.name);
+                                        Self::record_run(&db_clone, &dispatcher, &acc.name, started_at, started.elapsed(), Some(err_str.clone()), attempt, run_detail.clone()).await;
+                                        {
+                                            let mut db = db_clone.lock().await;
+                                            let _ = db.update_status(&acc.name, "error: own session cookie expired");
+                                        }
+                                        finish_progress(progress, templates::render(locale, &templates::RUN_OWN_COOKIE_EXPIRED, &[("account", &acc.name)]));
+                                        Self::notify(&db_clone, &dispatcher, Severity::Failure, Some(&acc.name), format!("[ERROR] Automation: **{}** skipped, its own session cookie expired. (run `{}`)", acc.name, run_id), source_channel).await;
 
-                                } else if err_str.contains("LOGIN_REQUIRED") {
-                                    if let Some(chan) = source_channel {
-                                        let _ = chan.say(&http_clone, "⚠️ **CRITICAL: Session cookie expired!** Stopping queue.").await;
-                                    }
-                                    Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), "⚠️ **[CRITICAL] Automation: Session cookie expired!** Stopping queue.".to_string(), source_channel).await;
-                                    break;
+                                    } else if err_str.contains(tls::PIN_MISMATCH) {
+                                        // Every account connects to the same server, so don't try the rest
+                                        retries.finish(&acc.name);
+                                        let _ = db_clone.lock().await.release_running(&acc.name);
+                                        finish_progress(progress, format!("⚠️ **CRITICAL: TLS pin mismatch!** Stopping queue. {}", err_str));
+                                        Self::notify(&db_clone, &dispatcher, Severity::Critical, Some(&acc.name), format!("⚠️ **[CRITICAL] Automation: The game server's certificate does not match tls.spki_pins.** Stopping queue. {} (run `{}`)", err_str, run_id), source_channel).await;
+                                        *state_clone.lock().await = QueueState::Stopped;
+                                        break;
 
-                                } else if err_str.contains("IDLE_TIMEOUT") || err_str.contains("CONNECTION_FAILED") || err_str.contains("SERVER_DISCONNECT") || err_str.contains("Connection handshake timed out") || err_str.contains("Failed to handshake") || err_str.contains("Stream closed") {
-                                    if let Some(chan) = source_channel {
-                                        let _ = chan.say(&http_clone, format!("[WARN] Connection issue on **{}** (Reason: {}). Retrying in 5s...", acc.name, err_str)).await;
-                                    }
-                                    tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+                                    } else if err_str.contains("LOGIN_REQUIRED") {
+                                        Self::record_run(&db_clone, &dispatcher, &acc.name, started_at, started.elapsed(), Some(err_str.clone()), attempt, run_detail.clone()).await;
+                                        let _ = db_clone.lock().await.release_running(&acc.name);
+                                        finish_progress(progress, templates::render(locale, &templates::RUN_COOKIE_EXPIRED, &[("account", &acc.name)]));
+                                        Self::notify(&db_clone, &dispatcher, Severity::Critical, Some(&acc.name), format!("⚠️ **[CRITICAL] Automation: Session cookie expired!** Stopping queue. (run `{}`)", run_id), source_channel).await;
+                                        alerts::send(Alert::CookieExpired, "The shared session cookie has expired; the queue was stopped.", &[
+                                            ("account", acc.name.clone()),
+                                            ("run_id", run_id.to_string()),
+                                        ]);
+                                        *state_clone.lock().await = QueueState::Stopped;
+                                        break;
 
-                                } else {
-                                    {
-                                        let mut db = db_clone.lock().await;
-                                        let _ = db.update_status(&acc.name, &format!("error: {}", err_str));
-                                    }
-                                    if let Some(chan) = source_channel {
-                                        let _ = chan.say(&http_clone, format!("[ERROR] **{}** failed: {}", acc.name, err_str)).await;
+                                    } else if err_str == RUN_TIMEOUT {
+                                        // The session is dropped with the select above; don't retry what just hung
+                                        retries.finish(&acc.name);
+                                        Self::record_run(&db_clone, &dispatcher, &acc.name, started_at, started.elapsed(), Some("run timeout".to_string()), attempt, run_detail.clone()).await;
+                                        {
+                                            let mut db = db_clone.lock().await;
+                                            let _ = db.update_status(&acc.name, "error: run timeout");
+                                        }
+                                        let limit = format_elapsed(config::get().run_timeout);
+                                        finish_progress(progress, templates::render(locale, &templates::RUN_TIMED_OUT, &[("account", &acc.name), ("limit", &limit)]));
+                                        Self::notify(&db_clone, &dispatcher, Severity::Failure, Some(&acc.name), format!("[ERROR] Automation: **{}** aborted after running longer than {} (run timeout). (run `{}`)", acc.name, limit, run_id), source_channel).await;
+                                        if let Some(block) = last_output.take() {
+                                            let text = format!("Last terminal output of **{}**:\n{}", acc.name, block);
+                                            match source_channel {
+                                                Some(chan) => dispatcher.send(chan, text),
+                                                None => Self::log_message(Arc::clone(&db_clone), &dispatcher, Level::Error, text, None).await,
+                                            }
+                                        }
+
+                                    } else {
+                                        retries.finish(&acc.name);
+                                        Self::record_run(&db_clone, &dispatcher, &acc.name, started_at, started.elapsed(), Some(err_str.clone()), attempt, run_detail.clone()).await;
+                                        {
+                                            let mut db = db_clone.lock().await;
+                                            let _ = db.update_status(&acc.name, &format!("error: {}", err_str));
+                                        }
+                                        let hint = failure_hint.map(|h| format!(" ({})", h)).unwrap_or_default();
+                                        finish_progress(progress, templates::render(locale, &templates::RUN_FAILED, &[
+                                            ("account", &acc.name),
+                                            ("error", &err_str),
+                                            ("hint", &hint),
+                                            ("duration", &format_elapsed(started.elapsed())),
+                                        ]));
+                                        Self::notify(&db_clone, &dispatcher, Severity::Failure, Some(&acc.name), format!("[ERROR] Automation: **{}** failed. Reason: {}{} (run `{}`)", acc.name, err_str, hint, run_id), source_channel).await;
+                                        if let Some(block) = last_output.take() {
+                                            let text = format!("Last terminal output of **{}**:\n{}", acc.name, block);
+                                            match source_channel {
+                                                Some(chan) => dispatcher.send(chan, text),
+                                                None => Self::log_message(Arc::clone(&db_clone), &dispatcher, Level::Error, text, None).await,
+                                            }
+                                        }
                                     }
-                                    Self::log_message(Arc::clone(&db_clone), Arc::clone(&http_clone), format!("[ERROR] Automation: **{}** failed. Reason: {}", acc.name, err_str), source_channel).await;
                                 }
                             }
+                            let _ = db_clone.lock().await.release_running(&acc.name);
+                            // Small delay to prevent tight loops in edge cases
+                            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
                         }
-                    },
-                    Err(e) => {
+
+                        // A stop leaves accounts behind; they aren't waiting anymore
+                        batch_queue.lock().await.clear();
                         if let Some(chan) = source_channel {
-                            let _ = chan.say(&http_clone, format!("[ERROR] Connection failed for **{}**: {}", acc.name, e)).await;
+                            dispatcher.send(chan, templates::render(locale, &templates::QUEUE_FINISHED, &[]));
+                        }
+
+                        // Hand over to the oldest waiting request; a stopped queue drops them all
+                        let (next, dropped) = {
+                            let mut state = state_clone.lock().await;
+                            let mut pending = pending_runs.lock().await;
+                            let dropped = if *state == QueueState::Stopped { pending.clear() } else { Vec::new() };
+                            let next = pending.pop();
+                            if next.is_none() {
+                                *state = QueueState::Stopped;
+                            }
+                            (next, dropped)
+                        };
+                        for request in dropped {
+                            if let Some(chan) = request.channel {
+                                dispatcher.send(chan, "[WARN] Queue Manager: The queue was stopped, so your waiting run request was dropped. Run it again when ready.");
+                            }
+                        }
+                        match next {
+                            Some(request) => {
+                                filter = request.filter;
+                                flow = request.flow;
+                                dry_run = request.dry_run;
+                                source_channel = request.channel;
+                            }
+                            None => break,
                         }
-                        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
                     }
-                }
-                // Small delay to prevent tight loops in edge cases
-                tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+                })
             }
+        };
 
-            {
-                let mut is_proc = processing_clone.lock().await;
-                *is_proc = false;
+        // Supervises the worker, so a panic in it doesn't leave the queue marked as running forever
+        let handler = self.clone();
+        tokio::spawn(async move {
+            let mut worker = spawn_worker(filter, flow, dry_run, source_channel, Vec::new());
+            let mut crashed: Vec<String> = Vec::new();
+            let mut restarts = 0;
+            loop {
+                match worker.await {
+                    Err(e) if e.is_panic() => {
+                        let can_restart = restarts < config::get().crash_restarts;
+                        match handler.recover_crashed_worker(e.into_panic(), &progress, &mut crashed, can_restart).await {
+                            Some(request) => {
+                                restarts += 1;
+                                worker = spawn_worker(request.filter, request.flow, request.dry_run, request.channel, crashed.clone());
+                            }
+                            None => break,
+                        }
+                    }
+                    _ => break,
+                }
             }
-            if let Some(chan) = source_channel {
-                let _ = chan.say(&http_clone, "[INFO] Queue Manager: Processing finished.").await;
+            if let Ok(mut progress) = progress.lock() {
+                *progress = WorkerProgress::default();
             }
+            handler.queue_events.emit(QueueEvent::QueueDrained);
         });
+        QueueStart::Started
+    }
+}
+
+fn language_option() -> CreateCommandOption {
+    Locale::ALL.iter().fold(
+        CreateCommandOption::new(CommandOptionType::String, "language", "Language").required(true),
+        |option, locale| option.add_string_choice(locale.label(), locale.code()),
+    )
+}
+
+/// Every slash command the bot registers. /help is generated from this list too.
+fn command_definitions() -> Vec<CreateCommand> {
+    vec![
+        CreateCommand::new("help")
+            .description("List the bot's commands and who can use them"),
+        CreateCommand::new("add_account")
+            .description("Add a new game account")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true))
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "code", "Restore Code").required(true))
+            .add_option(CreateCommandOption::new(CommandOptionType::Boolean, "toggle_server_selection", "Enable server selection?").required(true))
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "server", "Target server (e.g., E-15, All)").required(false))
+            .add_option(CreateCommandOption::new(CommandOptionType::Boolean, "allow_duplicate", "[ADMIN] Add even if another account uses the same code").required(false)),
+        CreateCommand::new("setup")
+            .description("Add an account step by step in a DM"),
+        CreateCommand::new("remove_account")
+            .description("Remove a game account")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true)),
+        CreateCommand::new("restore_account")
+            .description("Restore a previously removed account")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true)),
+        CreateCommand::new("transfer_account")
+            .description("Give one of your accounts to another member (owner or admin)")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true))
+            .add_option(CreateCommandOption::new(CommandOptionType::User, "user", "New owner").required(true)),
+        CreateCommand::new("claim_account")
+            .description("Take ownership of an account that has no owner")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true)),
+        CreateCommand::new("detach_account")
+            .description("Give up ownership of an account without removing it (owner or admin)")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true)),
+        CreateCommand::new("purge_deleted")
+            .description("[ADMIN] Permanently delete all removed accounts"),
+        CreateCommand::new("purge_done")
+            .description("[ADMIN] Reset account statuses to pending so they run again")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "target", "'all', 'errors', 'tag:<name>' or 'user:<id>' (default: all)").required(false))
+            .add_option(CreateCommandOption::new(CommandOptionType::Boolean, "run", "Start the queue for them right away").required(false)),
+        CreateCommand::new("backup_now")
+            .description("[ADMIN] Back up the database now"),
+        CreateCommand::new("export_db")
+            .description("[ADMIN] Download the database as JSON (restore codes and cookies left out)")
+            .add_option(CreateCommandOption::new(CommandOptionType::Boolean, "dm", "Send it to your DMs instead").required(false)),
+        CreateCommand::new("export_settings")
+            .description("[ADMIN] Download the bot settings to move them to another host")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "passphrase", "Include the session cookie, encrypted with this passphrase").required(false)
+                .min_length(MIN_PASSPHRASE_LEN as u16)),
+        CreateCommand::new("import_settings")
+            .description("[ADMIN] Replace the bot settings with a file from /export_settings (the database is backed up first)")
+            .add_option(CreateCommandOption::new(CommandOptionType::Attachment, "file", "Settings file").required(true))
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "passphrase", "Passphrase the session cookie was exported with").required(false)),
+        CreateCommand::new("restore_backup")
+            .description("[ADMIN] Replace the database with a backup (the current state is backed up first)")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "id", "Backup id, e.g. 20260101-235500").required(true)),
+        CreateCommand::new("set_server")
+            .description("Change the target server of one of your accounts")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true))
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "server", "Server (e.g. E-15, All). Leave empty to pick from a list").required(false)),
+        CreateCommand::new("set_tags")
+            .description("Set tags on one of your accounts (used by schedules)")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true))
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "tags", "Comma-separated tags, empty to clear").required(false)),
+        CreateCommand::new("set_note")
+            .description("Add a note to one of your accounts, e.g. when to stop running it")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true))
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "note", "Note text, empty to clear").required(false)),
+        CreateCommand::new("set_flow")
+            .description("Choose what one of your accounts does when it runs")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true))
+            .add_option({
+                let mut opt = CreateCommandOption::new(CommandOptionType::String, "flow", "Automation flow").required(true);
+                for flow in Flow::ALL {
+                    opt = opt.add_string_choice(flow.name(), flow.name());
+                }
+                opt
+            }),
+        CreateCommand::new("add_schedule")
+            .description("[ADMIN] Add a recurring run at a fixed time")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "time", "Time of day, HH:MM (bot timezone, see /set_timezone)").required(true))
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "target", "'all', 'tag:<name>', 'user:<id>' or 'names:<a>,<b>'").required(true))
+            .add_option({
+                let mut opt = CreateCommandOption::new(CommandOptionType::String, "flow", "Automation flow (default: each account's own flow)").required(false);
+                for flow in Flow::ALL {
+                    opt = opt.add_string_choice(flow.name(), flow.name());
+                }
+                opt
+            }),
+        CreateCommand::new("list_schedules")
+            .description("List recurring runs"),
+        CreateCommand::new("remove_schedule")
+            .description("[ADMIN] Remove a recurring run")
+            .add_option(CreateCommandOption::new(CommandOptionType::Integer, "id", "Schedule ID (see /list_schedules)").required(true)),
+        CreateCommand::new("search_transcript")
+            .description("[VIEWER] Search an account's recent run transcripts for a pattern")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true))
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "pattern", "Regular expression, case-insensitive (e.g. maximum limit)").required(true))
+            .add_option(CreateCommandOption::new(CommandOptionType::Integer, "days", "How many days back to search (default 7)").required(false)
+                .min_int_value(1).max_int_value(SEARCH_MAX_DAYS as u64)),
+        CreateCommand::new("account_info")
+            .description("Show details about an account")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true)),
+        CreateCommand::new("stats")
+            .description("Show bot-wide statistics"),
+        CreateCommand::new("settings")
+            .description("Show the bot's current configuration"),
+        CreateCommand::new("diagnose")
+            .description("[ADMIN] Check the database, game endpoint, log channel and scheduler"),
+        CreateCommand::new("list_accounts")
+            .description("[VIEWER] List all configured accounts"),
+        CreateCommand::new("queue_status")
+            .description("[VIEWER] Show what the queue is running and what is waiting"),
+        CreateCommand::new("run_history")
+            .description("[VIEWER] Show the most recent runs")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Only runs of this account").required(false))
+            .add_option(CreateCommandOption::new(CommandOptionType::Integer, "count", "Number of runs (default 10, max 25)").required(false)),
+        CreateCommand::new("list_my_accounts")
+            .description("List only your accounts"),
+        CreateCommand::new("toggle_ping")
+            .description("Toggle ping notifications for your accounts"),
+        CreateCommand::new("next_run")
+            .description("Show when your accounts are next scheduled to run, in your timezone"),
+        CreateCommand::new("set_my_timezone")
+            .description("Set the timezone /next_run shows times in")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "timezone", "IANA name, e.g. Europe/Berlin, or 'default' for the bot's timezone").required(true)),
+        CreateCommand::new("set_my_language")
+            .description("Choose the language the bot replies to you in")
+            .add_option(language_option().add_string_choice("Server default", "default")),
+        CreateCommand::new("force_run")
+            .description("Force run automation. Accepts a name, 'all', a comma-separated list or a pattern like 'guild-*'.")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account name, 'all', 'alt1,alt2' or 'guild-*'").required(false))
+            .add_option(CreateCommandOption::new(CommandOptionType::Boolean, "force", "Run even if already completed since the daily reset").required(false))
+            .add_option(CreateCommandOption::new(CommandOptionType::Boolean, "dry_run", "Only log in and check the restore code; stops before anything is played").required(false)),
+        CreateCommand::new("run_menu")
+            .description("Pick which of your accounts to run from a menu"),
+        CreateCommand::new("debug_run")
+            .description("[ADMIN] Run one account and follow every step of it in a private thread")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account name").required(true)),
+        CreateCommand::new("force_run_all")
+            .description("[OPERATOR] Run all accounts in the system")
+            .add_option(CreateCommandOption::new(CommandOptionType::Boolean, "dry_run", "Only log in and check each restore code; stops before anything is played").required(false)),
+        CreateCommand::new("force_stop_all")
+            .description("[OPERATOR] Stop all running processes"),
+        CreateCommand::new("pause_queue")
+            .description("[OPERATOR] Pause the queue after the current account finishes"),
+        CreateCommand::new("resume_queue")
+            .description("[OPERATOR] Resume a paused queue"),
+        CreateCommand::new("queue_promote")
+            .description("[ADMIN] Run a waiting account next in the current batch")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true)),
+        CreateCommand::new("queue_demote")
+            .description("[ADMIN] Move a waiting account to the end of the current batch")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true)),
+        CreateCommand::new("maintenance")
+            .description("[ADMIN] Turn maintenance mode on or off (no runs start while it is on)")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "state", "on or off").required(true)
+                .add_string_choice("on", "on")
+                .add_string_choice("off", "off")),
+        CreateCommand::new("catch_up_first")
+            .description("[ADMIN] Run accounts that missed the most days first")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "state", "on or off").required(true)
+                .add_string_choice("on", "on")
+                .add_string_choice("off", "off")),
+        CreateCommand::new("preflight_halt")
+            .description("[ADMIN] Skip the nightly batch when the pre-flight check finds the cookie rejected")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "state", "on or off").required(true)
+                .add_string_choice("on", "on")
+                .add_string_choice("off", "off")),
+        CreateCommand::new("run_threads")
+            .description("[ADMIN] Give each run its own thread in the log channel")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "state", "on or off").required(true)
+                .add_string_choice("on", "on")
+                .add_string_choice("off", "off")),
+        CreateCommand::new("set_notify")
+            .description("[ADMIN] Choose where run successes, failures or critical errors are reported")
+            .add_option({
+                let mut opt = CreateCommandOption::new(CommandOptionType::String, "severity", "Which outcomes").required(true);
+                for severity in Severity::ALL {
+                    opt = opt.add_string_choice(severity.label(), severity.label());
+                }
+                opt
+            })
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "sinks", "Comma-separated: channel, dm, webhook, log; or none").required(true)),
+        CreateCommand::new("set_log_level")
+            .description("[ADMIN] Choose which messages the log channel gets")
+            .add_option({
+                let mut opt = CreateCommandOption::new(CommandOptionType::String, "level", "Which messages").required(true);
+                for level in LogLevel::ALL {
+                    opt = opt.add_string_choice(format!("{} ({})", level.label(), level.description()), level.label());
+                }
+                opt
+            }),
+        CreateCommand::new("trace")
+            .description("[ADMIN] Record the raw socket.io frames of the next runs, or download the latest trace")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "state", "on, off, or download").required(true)
+                .add_string_choice("on", "on")
+                .add_string_choice("off", "off")
+                .add_string_choice("download", "download"))
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "account", "Only this account (default: every run)").required(false)),
+        CreateCommand::new("mute_bot")
+            .description("[ADMIN] Mute automatic bot messages"),
+        CreateCommand::new("unmute_bot")
+            .description("[ADMIN] Unmute automatic bot messages"),
+        CreateCommand::new("set_log_channel")
+            .description("[ADMIN] Set channel for automatic messages")
+            .add_option(CreateCommandOption::new(CommandOptionType::Channel, "channel", "Log Channel").required(true)),
+        CreateCommand::new("set_admin_role")
+            .description("[OWNER] Set admin role for bot management")
+            .add_option(CreateCommandOption::new(CommandOptionType::Role, "role", "Admin Role").required(true)),
+        CreateCommand::new("reset_admin_role")
+            .description("[OWNER] Clear the admin role (e.g. after it was deleted) and show who can do what"),
+        CreateCommand::new("set_role_tier")
+            .description("[OWNER] Give a role a bot tier (viewer, operator, admin)")
+            .add_option(CreateCommandOption::new(CommandOptionType::Role, "role", "Role").required(true))
+            .add_option({
+                let mut opt = CreateCommandOption::new(CommandOptionType::String, "tier", "Tier, or 'none' to remove").required(true);
+                for tier in ASSIGNABLE_TIERS {
+                    opt = opt.add_string_choice(tier.label(), tier.label());
+                }
+                opt.add_string_choice("none", "none")
+            }),
+        CreateCommand::new("set_base_url")
+            .description("[ADMIN] Set the game websocket endpoint (URL, host, profile name, or 'default')")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "url", "e.g. wss://host/socket.io/, a profile name, or 'default'").required(true)),
+        CreateCommand::new("set_digest_day")
+            .description("[ADMIN] Choose the weekday the weekly summary is posted to the log channel")
+            .add_option({
+                let mut opt = CreateCommandOption::new(CommandOptionType::String, "day", "Weekday, or 'off' to disable").required(true);
+                for day in DIGEST_DAY_CHOICES {
+                    opt = opt.add_string_choice(*day, *day);
+                }
+                opt
+            }),
+        CreateCommand::new("set_timezone")
+            .description("[ADMIN] Set the timezone for the daily reset, schedules and weekly summary")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "timezone", "IANA name, e.g. Asia/Jakarta, Europe/Berlin, UTC").required(true)),
+        CreateCommand::new("set_language")
+            .description("[ADMIN] Set the language of channel messages and the default for replies")
+            .add_option(language_option()),
+        CreateCommand::new("allow_channel")
+            .description("[ADMIN] Allow bot commands in a channel (once set, other channels are rejected)")
+            .add_option(CreateCommandOption::new(CommandOptionType::Channel, "channel", "Channel").required(true)),
+        CreateCommand::new("deny_channel")
+            .description("[ADMIN] Remove a channel from the allowed command channels")
+            .add_option(CreateCommandOption::new(CommandOptionType::Channel, "channel", "Channel").required(true)),
+        CreateCommand::new("debug_snapshot")
+            .description("[ADMIN] Download the queue's order, retry counters and worker state as JSON"),
+        CreateCommand::new("audit_log")
+            .description("[VIEWER] Show the most recent admin actions")
+            .add_option(CreateCommandOption::new(CommandOptionType::Integer, "count", "Number of entries (default 10, max 25)").required(false)),
+        CreateCommand::new("set_my_cookie")
+            .description("Use your own site session cookie for one of your accounts")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true)),
+        CreateCommand::new("set_cookies")
+            .description("[ADMIN] Set session cookie to bypass login")
+            .add_option(CreateCommandOption::new(CommandOptionType::String, "cookie", "The 'session' cookie value").required(true)),
+        // Right-click a member > Apps
+        CreateCommand::new(VIEW_ACCOUNTS_MENU).kind(CommandType::User),
+    ]
+}
+
+/// /help: every registered command, grouped by the tier it needs.
+fn help_embed() -> CreateEmbed {
+    let groups: [(Option<Tier>, &str); 5] = [
+        (None, "Everyone"),
+        (Some(Tier::Viewer), "Viewer"),
+        (Some(Tier::Operator), "Operator"),
+        (Some(Tier::Admin), "Admin"),
+        (Some(Tier::Owner), "Server owner"),
+    ];
+    let commands: Vec<(String, String)> = command_definitions().iter()
+        .filter_map(|command| serde_json::to_value(command).ok())
+        // Context menu entries have no description and aren't slash commands
+        .filter_map(|value| Some((value["name"].as_str()?.to_string(), value["description"].as_str()?.to_string())))
+        .collect();
+
+    let mut embed = CreateEmbed::new()
+        .title("Commands")
+        .description("Each group needs that tier or higher (see /set_role_tier). Everyone can add and manage their own accounts.")
+        .colour(Colour::BLUE);
+    for (tier, heading) in groups {
+        let lines: Vec<String> = commands.iter()
+            .filter(|(name, _)| required_tier(name) == tier)
+            .map(|(name, description)| {
+                // Tags like "[ADMIN]" repeat the group heading
+                let description = description.strip_prefix('[').and_then(|d| d.split_once("] ")).map(|(_, rest)| rest).unwrap_or(description);
+                format!("`/{}` {}", name, description)
+            })
+            .collect();
+        // Embed field values are limited to 1024 characters
+        let mut value = String::new();
+        let mut continued = false;
+        for line in lines {
+            if !value.is_empty() && value.len() + 1 + line.len() > 1024 {
+                embed = embed.field(if continued { format!("{} (cont.)", heading) } else { heading.to_string() }, std::mem::take(&mut value), false);
+                continued = true;
+            }
+            if !value.is_empty() {
+                value.push('\n');
+            }
+            value.push_str(&line);
+        }
+        if !value.is_empty() {
+            embed = embed.field(if continued { format!("{} (cont.)", heading) } else { heading.to_string() }, value, false);
+        }
     }
+    embed
 }
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn ready(&self, ctx: Context, ready: Ready) {
-        println!("[INFO] Discord: Bot successfully logged in as {}", ready.user.name);
-
-        let _ = Command::set_global_commands(&ctx.http, vec![
-            CreateCommand::new("add_account")
-                .description("Add a new game account")
-                .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true))
-                .add_option(CreateCommandOption::new(CommandOptionType::String, "code", "Restore Code").required(true))
-                .add_option(CreateCommandOption::new(CommandOptionType::Boolean, "toggle_server_selection", "Enable server selection?").required(true))
-                .add_option(CreateCommandOption::new(CommandOptionType::String, "server", "Target server (e.g., E-15, All)").required(false)),
-            CreateCommand::new("remove_account")
-                .description("Remove a game account")
-                .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name").required(true)),
-            CreateCommand::new("list_accounts")
-                .description("List all configured accounts"),
-            CreateCommand::new("list_my_accounts")
-                .description("List only your accounts"),
-            CreateCommand::new("toggle_ping")
-                .description("Toggle ping notifications for your accounts"),
-            CreateCommand::new("force_run")
-                .description("Force run automation. Use 'all' to run all your accounts.")
-                .add_option(CreateCommandOption::new(CommandOptionType::String, "name", "Account Name or 'all'").required(false)),
-            CreateCommand::new("force_run_all")
-                .description("[ADMIN] Run all accounts in the system"),
-            CreateCommand::new("force_stop_all")
-                .description("[ADMIN] Stop all running processes"),
-            CreateCommand::new("mute_bot")
-                .description("[ADMIN] Mute automatic bot messages"),
-            CreateCommand::new("unmute_bot")
-                .description("[ADMIN] Unmute automatic bot messages"),
-            CreateCommand::new("set_log_channel")
-                .description("[ADMIN] Set channel for automatic messages")
-                .add_option(CreateCommandOption::new(CommandOptionType::Channel, "channel", "Log Channel").required(true)),
-            CreateCommand::new("set_admin_role")
-                .description("[ADMIN] Set admin role for bot management")
-                .add_option(CreateCommandOption::new(CommandOptionType::Role, "role", "Admin Role").required(true)),
-            CreateCommand::new("set_cookies")
-                .description("[ADMIN] Set session cookie to bypass login")
-                .add_option(CreateCommandOption::new(CommandOptionType::String, "cookie", "The 'session' cookie value").required(true)),
-        ]).await;
-
-        println!("[INFO] Discord: Slash commands registered successfully");
+        let (shard_id, shard_total) = ready.shard.map(|s| (s.id.0, s.total)).unwrap_or((0, 1));
+        log!("[INFO] Discord: Bot successfully logged in as {} (shard {}/{}, {} server(s))", ready.user.name, shard_id + 1, shard_total, ready.guilds.len());
+        if let Ok(mut shards) = self.shards.lock() {
+            shards.total = shard_total;
+            shards.ready.insert(shard_id, ready.guilds.len());
+        }
+        self.presence.register(shard_id, ctx.shard.clone());
+        // Later shards, and a shard starting a new session after a reconnect, only report in
+        if self.background_started.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.dispatcher.start(ctx.http.clone()).await;
+        self.watch_storage();
+
+        let _ = Command::set_global_commands(&ctx.http, command_definitions()).await;
+
+        log!("[INFO] Discord: Slash commands registered successfully");
 
         // Start Scheduler
         let db_clone = Arc::clone(&self.db);
-        let ctx_clone = ctx.clone();
-        let is_processing_clone = Arc::clone(&self.is_processing);
+        let handler = self.clone();
         
         tokio::spawn(async move {
-            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
+            let mut scheduler = Scheduler::new(SystemClock, &db_clone.lock().await.data.scheduler_handled);
             loop {
-                interval.tick().await;
-                let now = Utc::now().with_timezone(&Jakarta);
-                if now.hour() == 0 && now.minute() == 0 {
-                    println!("[INFO] Scheduler: Daily reset triggered at {}", now);
-                    {
-                        let mut db = db_clone.lock().await;
-                        let _ = db.reset_all_statuses();
+                *handler.scheduler_tick.lock().await = Some(std::time::Instant::now());
+                let (timezone, timetable) = {
+                    let db = db_clone.lock().await;
+                    (db.data.settings.timezone(), scheduler::timetable(&db.data.settings.schedules))
+                };
+                let due = scheduler.due(&timetable, &timezone);
+                // Saved before the triggers run, so a crash while handling one doesn't repeat it
+                if let Some(handled) = scheduler.take_handled() {
+                    if let Err(e) = db_clone.lock().await.set_scheduler_handled(handled) {
+                        log!("[WARN] Scheduler: Could not save the handled triggers: {}", e);
+                    }
+                }
+                for (trigger, at) in due {
+                    match trigger {
+                        Trigger::DailyReset => {
+                            log!("[INFO] Scheduler: Daily reset triggered at {}", at);
+                            {
+                                let mut db = db_clone.lock().await;
+                                let _ = db.reset_all_statuses();
+                            }
+
+                            // Statuses are still reset, so /force_run_all can catch up once maintenance ends
+                            if db_clone.lock().await.data.settings.is_maintenance() {
+                                log!("[INFO] Scheduler: Maintenance mode is on, the daily run was not started.");
+                                continue;
+                            }
+
+                            if !handler.preflight().await {
+                                continue;
+                            }
+
+                            // Trigger queue for all accounts
+                            let h = handler.clone();
+                            tokio::spawn(async move {
+                                h.process_queue(AccountFilter::All, None, false, None).await;
+                            });
+                        },
+                        Trigger::Backup => {
+                            let db = Arc::clone(&db_clone);
+                            let dispatcher = handler.dispatcher.clone();
+                            tokio::spawn(async move {
+                                match backup::create(&db).await {
+                                    Ok(report) => {
+                                        log!("[INFO] Backup: nightly backup {} created.{}", report.id, backup_summary(&report));
+                                        if let Some(Err(e)) = &report.upload {
+                                            Handler::log_message(db, &dispatcher, Level::Warning, format!("[WARN] Nightly backup `{}` was saved locally but the upload failed: {}", report.id, e), None).await;
+                                        }
+                                    },
+                                    Err(e) => {
+                                        log!("[ERROR] Backup: nightly backup failed: {}", e);
+                                        Handler::log_message(db, &dispatcher, Level::Error, format!("[ERROR] Nightly backup failed: {}", e), None).await;
+                                    }
+                                }
+                            });
+                        },
+                        Trigger::Digest => {
+                            let digest_day = {
+                                let db = db_clone.lock().await;
+                                db.data.settings.digest_weekday()
+                            };
+                            if digest_day == Some(at.weekday()) {
+                                log!("[INFO] Scheduler: Posting weekly digest");
+                                Handler::post_weekly_digest(Arc::clone(&db_clone), &handler.dispatcher).await;
+                            }
+                        },
+                        // Custom schedules (e.g. evening events) run in addition to the daily reset
+                        Trigger::Schedule(id) => {
+                            let schedule = {
+                                let db = db_clone.lock().await;
+                                db.data.settings.schedules.iter().find(|s| s.id == id).cloned()
+                            };
+                            let Some(schedule) = schedule else { continue };
+                            if db_clone.lock().await.data.settings.is_maintenance() {
+                                log!("[INFO] Scheduler: Schedule #{} skipped, maintenance mode is on.", schedule.id);
+                                continue;
+                            }
+                            log!("[INFO] Scheduler: Schedule #{} ({} for '{}', flow {}) triggered at {}", schedule.id, schedule.time, schedule.filter, schedule.flow_label(), at);
+                            {
+                                let mut db = db_clone.lock().await;
+                                let _ = db.reset_statuses_matching(&schedule.filter);
+                            }
+                            if handler.process_queue(schedule.filter.clone(), schedule.flow, false, None).await == QueueStart::Full {
+                                log!("[WARN] Scheduler: Schedule #{} skipped, too many run requests are waiting.", schedule.id);
+                            }
+                        },
                     }
-                    
-                    // Trigger queue for all accounts
-                     let db_c = Arc::clone(&db_clone);
-                     let proc_c = Arc::clone(&is_processing_clone);
-                     let ctx_c = ctx_clone.clone();
-
-                     tokio::spawn(async move {
-                         let h = Handler { db: db_c, is_processing: proc_c };
-                         h.process_queue(ctx_c, None, None).await;
-                     });
                 }
+                tokio::time::sleep(scheduler.until_next(&timetable, &timezone)).await;
             }
         });
     }
 
+    async fn ratelimit(&self, data: RatelimitInfo) {
+        log!("[WARN] Discord rate limit on {} ({:?}). Holding queued messages.", data.path, data.timeout);
+        self.dispatcher.note_rate_limit(data.timeout);
+    }
+
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
         if let Interaction::Command(command) = interaction {
             let user_id = command.user.id.to_string();
+            let locale = self.locale_for(&user_id, &command.locale).await;
             let mut content = "Processing...".to_string();
+            let mut components: Vec<CreateActionRow> = Vec::new();
+            let mut embeds: Vec<CreateEmbed> = Vec::new();
+            let mut attachments: Vec<CreateAttachment> = Vec::new();
+            let mut ephemeral = false;
+
+            // Channel allowlist. The commands that manage it stay usable everywhere so admins can't lock themselves out.
+            let manages_channels = matches!(command.data.name.as_str(), "allow_channel" | "deny_channel" | "set_admin_role");
+            if !manages_channels {
+                let rejection = {
+                    let db = self.db.lock().await;
+                    if db.is_channel_allowed(command.channel_id) {
+                        None
+                    } else {
+                        Some(db.data.settings.allowed_channels.iter().map(|c| format!("<#{}>", c)).collect::<Vec<_>>().join(", "))
+                    }
+                };
+                if let Some(allowed_list) = rejection {
+                    let _ = command.create_response(&ctx.http, CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new()
+                            .content(templates::render(locale, &templates::CHANNEL_NOT_ALLOWED, &[("channels", &allowed_list)]))
+                            .ephemeral(true)
+                    )).await;
+                    return;
+                }
+            }
+
+            let required = required_tier(&command.data.name);
+            let permitted = match required {
+                Some(tier) => self.has_tier(&ctx, command.guild_id, command.user.id, command.member.as_deref(), tier).await,
+                None => true,
+            };
+
+            if permitted {
+                let wait = self.cooldowns.lock().map(|mut c| c.try_use(command.user.id, &command.data.name)).unwrap_or(Ok(()));
+                if let Err(wait) = wait {
+                    let _ = command.create_response(&ctx.http, CreateInteractionResponse::Message(
+                        CreateInteractionResponseMessage::new()
+                            .content(templates::render(locale, &templates::COOLDOWN, &[("command", &command.data.name), ("seconds", &wait.as_secs().max(1).to_string())]))
+                            .ephemeral(true)
+                    )).await;
+                    return;
+                }
+            }
+
+            let (maintenance, read_only) = {
+                let db = self.db.lock().await;
+                (db.data.settings.is_maintenance(), db.is_read_only())
+            };
 
             match command.data.name.as_str() {
+                _ if !permitted => {
+                    content = templates::render(locale, &templates::TIER_REQUIRED, &[("tier", required.map(|t| t.label()).unwrap_or_default())]);
+                },
+                name if maintenance && RUN_COMMANDS.contains(&name) => {
+                    content = templates::render(locale, &templates::MAINTENANCE, &[]);
+                    ephemeral = true;
+                },
+                name if read_only && (RUN_COMMANDS.contains(&name) || READ_ONLY_COMMANDS.contains(&name)) => {
+                    content = templates::render(locale, &templates::READ_ONLY, &[]);
+                    ephemeral = true;
+                },
+                "help" => {
+                    content = String::new();
+                    embeds.push(help_embed());
+                    ephemeral = true;
+                },
                 "list_accounts" => {
+                    // Notes are the owners' remarks; only admins see them in the full list
+                    let show_notes = self.is_admin(&ctx, &command).await;
                     let db = self.db.lock().await;
-                    content = if db.data.accounts.is_empty() {
-                        "No accounts registered.".to_string()
+                    if db.active_accounts().next().is_none() {
+                        content = "No accounts registered.".to_string();
+                    } else {
+                        content = String::new();
+                        embeds.push(embeds::account_list("Accounts", db.active_accounts(), Utc::now(), show_notes));
+                    }
+                },
+                "queue_status" => {
+                    let state = *self.queue_state.lock().await;
+                    let waiting = self.batch_queue.lock().await.names();
+                    let requests = self.pending_runs.lock().await.len();
+                    let running = self.in_flight.running();
+                    let maintenance_line = if maintenance { "\n🛠️ Maintenance mode is on; no new runs are started." } else { "" };
+                    content = format!("**Queue:** {:?}{}", state, maintenance_line);
+                    content.push_str(&format!("\n**Running:** {}", if running.is_empty() { "nothing".to_string() } else { running.join(", ") }));
+                    if !waiting.is_empty() {
+                        // Keeps the reply well under Discord's message limit for big batches
+                        let shown: Vec<String> = waiting.iter().take(20).enumerate().map(|(i, n)| format!("{}. {}", i + 1, n)).collect();
+                        content.push_str(&format!("\n**Up next ({}):**\n{}", waiting.len(), shown.join("\n")));
+                        if waiting.len() > shown.len() {
+                            content.push_str(&format!("\n…and {} more", waiting.len() - shown.len()));
+                        }
+                    }
+                    if requests > 0 {
+                        content.push_str(&format!("\n**Waiting run requests:** {}", requests));
+                    }
+                },
+                "run_history" => {
+                    let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).map(|s| s.to_string());
+                    let count = command.data.options.iter().find(|o| o.name == "count").and_then(|o| o.value.as_i64()).unwrap_or(10).clamp(1, 25) as usize;
+                    let db = self.db.lock().await;
+                    let runs: Vec<&db::RunRecord> = db.data.run_history.iter().rev()
+                        .filter(|r| name.as_deref().map(|n| r.account == n).unwrap_or(true))
+                        .take(count)
+                        .collect();
+                    content = if runs.is_empty() {
+                        match &name {
+                            Some(name) => format!("No runs recorded for **{}**.", name),
+                            None => "No runs recorded yet.".to_string(),
+                        }
+                    } else {
+                        runs.iter()
+                            .map(|r| {
+                                let when = format!("<t:{}:f>", r.started_at.timestamp());
+                                let result = match &r.error {
+                                    _ if r.success => "✅".to_string(),
+                                    Some(e) => format!("❌ {}", redact(e).chars().take(80).collect::<String>()),
+                                    None => "❌".to_string(),
+                                };
+                                let attempts = if r.attempts > 1 { format!(", {} attempts", r.attempts) } else { String::new() };
+                                format!("- {} **{}** {} ({}{})", when, r.account, result, format_elapsed(std::time::Duration::from_secs(r.duration_secs)), attempts)
+                            })
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                    };
+                },
+                "search_transcript" => {
+                    let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).unwrap_or("").to_string();
+                    let pattern = command.data.options.iter().find(|o| o.name == "pattern").and_then(|o| o.value.as_str()).unwrap_or("").to_string();
+                    let days = command.data.options.iter().find(|o| o.name == "days").and_then(|o| o.value.as_i64()).unwrap_or(7).clamp(1, SEARCH_MAX_DAYS);
+                    let regex = match transcript_search::compile(&pattern) {
+                        Ok(regex) => regex,
+                        Err(e) => {
+                            let _ = command.create_response(&ctx.http, CreateInteractionResponse::Message(
+                                CreateInteractionResponseMessage::new().content(e).ephemeral(true)
+                            )).await;
+                            return;
+                        }
+                    };
+                    let runs = {
+                        let db = self.db.lock().await;
+                        if db.find_account(&name).is_none() && !db.data.run_history.iter().any(|r| r.account == name) {
+                            None
+                        } else {
+                            let since = Utc::now() - chrono::Duration::days(days);
+                            Some(db.data.run_history.iter()
+                                .filter(|r| r.account == name)
+                                .filter(|r| r.started_at >= since)
+                                .cloned()
+                                .collect::<Vec<_>>())
+                        }
+                    };
+                    let Some(runs) = runs else {
+                        let _ = command.create_response(&ctx.http, CreateInteractionResponse::Message(
+                            CreateInteractionResponseMessage::new().content(templates::render(locale, &templates::ACCOUNT_NOT_FOUND, &[("account", &name)])).ephemeral(true)
+                        )).await;
+                        return;
+                    };
+                    // Reading the transcript files can take longer than Discord's 3 second reply window
+                    let _ = command.defer(&ctx.http).await;
+                    let result = tokio::task::spawn_blocking(move || transcript_search::search(&runs, &regex)).await.unwrap_or_default();
+                    let total = result.matches.len() + result.more;
+                    let mut text = format!("**{}**: {} match(es) for `{}` in {} run(s) of the last {} day(s).", name, total, pattern.replace('`', "'"), result.runs_searched, days);
+                    if result.missing > 0 {
+                        text.push_str(&format!(" {} run(s) have no saved transcript; only their error was searched.", result.missing));
+                    }
+                    let lines: Vec<String> = result.matches.iter().map(|m| {
+                        let run = m.run_id.as_deref().map(|id| format!(" `{}`", id)).unwrap_or_default();
+                        let at = if m.line_number > 0 { format!(" line {}", m.line_number) } else { " error".to_string() };
+                        format!("<t:{}:f>{}{}: `{}`", m.run_started.timestamp(), run, at, m.line.replace('`', "'"))
+                    }).collect();
+                    let mut shown = 0;
+                    for line in &lines {
+                        if text.len() + line.len() + 1 > SEARCH_REPLY_LIMIT {
+                            break;
+                        }
+                        text.push('\n');
+                        text.push_str(line);
+                        shown += 1;
+                    }
+                    let mut response = EditInteractionResponse::new();
+                    if shown < total {
+                        // The rest goes in a file, with plain timestamps
+                        let full = result.matches.iter().map(|m| format!("{} {} line {}: {}", m.run_started.to_rfc3339(), m.run_id.as_deref().unwrap_or("-"), m.line_number, m.line)).collect::<Vec<_>>().join("\n");
+                        let note = if result.more > 0 { format!("\n(first {} matches; {} more not listed)", result.matches.len(), result.more) } else { String::new() };
+                        text.push_str(&format!("\n… {} more in the attached file.", total - shown));
+                        response = response.new_attachment(CreateAttachment::bytes(format!("{}{}", full, note).into_bytes(), format!("search-{}.txt", name)));
+                    }
+                    let _ = command.edit_response(&ctx.http, response.content(text)).await;
+                    return;
+                },
+                "account_info" => {
+                    let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).unwrap_or("").to_string();
+                    let is_admin = self.is_admin(&ctx, &command).await;
+                    let viewer = self.has_tier(&ctx, command.guild_id, command.user.id, command.member.as_deref(), Tier::Viewer).await;
+                    let db = self.db.lock().await;
+                    match db.find_account(&name) {
+                        None => content = templates::render(locale, &templates::ACCOUNT_NOT_FOUND, &[("account", &name)]),
+                        // Other members' accounts are as private as /list_accounts
+                        Some(acc) if !viewer && acc.user_id.as_deref() != Some(user_id.as_str()) => {
+                            content = templates::render(locale, &templates::TIER_REQUIRED, &[("tier", Tier::Viewer.label())]);
+                            ephemeral = true;
+                        }
+                        Some(acc) => {
+                            let stats = history::account_stats(&db.data.run_history, &acc.name);
+                            let now = Utc::now().with_timezone(&db.data.settings.timezone());
+                            let next_run = scheduler::next_run_for(acc, &db.data.settings.schedules, &now)
+                                .map(|t| format!("<t:{}:f> (<t:{}:R>)", t.timestamp(), t.timestamp()))
+                                .unwrap_or_else(|| "Unknown".to_string());
+                            let last_run = acc.last_run
+                                .map(|t| format!("<t:{}:R>", t.timestamp()))
+                                .unwrap_or_else(|| "Never".to_string());
+
+                            let mut embed = embeds::account_detail(acc, Utc::now())
+                                .field("Owner", acc.user_id.as_ref().map(|u| format!("<@{}>", u)).unwrap_or_else(|| "Unclaimed".to_string()), true)
+                                .field("Target Server", acc.target_server.clone().unwrap_or_else(|| "Auto".to_string()), true)
+                                .field("Flow", acc.flow.name(), true)
+                                .field("Status", acc.status_label(Utc::now()), true)
+                                .field("Last Run", last_run, true)
+                                .field("Success Streak", stats.success_streak.to_string(), true)
+                                .field("Average Duration", stats.average_duration.map(format_elapsed).unwrap_or_else(|| "n/a".to_string()), true)
+                                .field("Next Scheduled Run", next_run, true)
+                                .field("Pings", if acc.ping_enabled { "Enabled" } else { "Disabled" }, true)
+                                .field("Runs Recorded", stats.runs.to_string(), true)
+                                .field("Servers (last \"All\" run)", protocol::servers::describe(&stats.last_servers).unwrap_or_else(|| "n/a".to_string()), false)
+                                .field("Last Error", stats.last_error.map(|e| redact(&e).chars().take(1000).collect()).unwrap_or_else(|| "None".to_string()), false);
+                            if !acc.tags.is_empty() {
+                                embed = embed.field("Tags", acc.tags.join(", "), false);
+                            }
+                            // Like /list_accounts: notes are for the owner and admins
+                            if let Some(note) = acc.notes.as_ref().filter(|_| is_admin || acc.user_id.as_deref() == Some(user_id.as_str())) {
+                                embed = embed.field("Notes", note.clone(), false);
+                            }
+                            content = String::new();
+                            embeds.push(embed);
+                        }
+                    }
+                },
+                "stats" => {
+                    let queue_state = *self.queue_state.lock().await;
+                    let waiting_requests = self.pending_runs.lock().await.len();
+                    let counts = self.session_counts.lock().map(|c| *c).unwrap_or_default();
+                    let capacity = self.server_capacity.lock()
+                        .map(|c| c.states(Utc::now(), config::get().server_full_cooldown))
+                        .unwrap_or_default();
+                    let capacity = if capacity.is_empty() {
+                        "No server reported full in the last 24h".to_string()
                     } else {
-                        db.data.accounts.iter()
-                            .map(|a| format!("- **{}**: {} (Last Run: {})", a.name, a.status, a.last_run.as_deref().unwrap_or("Never")))
+                        capacity.iter()
+                            .map(|s| {
+                                let state = match s.cooling_until {
+                                    Some(until) => format!("deferred until <t:{}:t>", until.timestamp()),
+                                    None => "has room again".to_string(),
+                                };
+                                format!("{}: full {}x in 24h, last <t:{}:R>, {}", s.server, s.recent, s.last_full.timestamp(), state)
+                            })
                             .collect::<Vec<_>>()
                             .join("\n")
                     };
+                    let db = self.db.lock().await;
+                    let now = Utc::now().with_timezone(&db.data.settings.timezone());
+                    let overall = history::overall_stats(&db.data.run_history, &now);
+
+                    let mut by_status = embeds::status_counts(db.active_accounts(), Utc::now());
+                    let invalid_codes = db.active_accounts().filter(|a| a.status == STATUS_INVALID_CODE).count();
+                    if invalid_codes > 0 {
+                        by_status.push(format!("(of the errors, {} invalid code)", invalid_codes));
+                    }
+                    let accounts = if by_status.is_empty() {
+                        "No accounts registered.".to_string()
+                    } else {
+                        by_status.join("\n")
+                    };
+                    let done_since_reset = db.active_accounts().filter(|a| scheduler::completed_since_reset(a, &now)).count();
+                    // Accounts the queue would still pick up
+                    let queue_depth = db.active_accounts().filter(|a| a.status != "done" && a.status != STATUS_INVALID_CODE).count();
+                    let queue_label = match queue_state {
+                        QueueState::Running => "running",
+                        QueueState::Paused => "paused",
+                        QueueState::Stopped => "idle",
+                    };
+                    let cookie_health = if db.data.settings.cookies.as_deref().unwrap_or("").is_empty() {
+                        "Not set".to_string()
+                    } else if overall.cookie_rejected {
+                        "Expired (last run needed a login)".to_string()
+                    } else {
+                        match overall.last_success {
+                            Some(t) => format!("OK (last success <t:{}:R>)", t.timestamp()),
+                            None => "Unverified (no successful run yet)".to_string(),
+                        }
+                    };
+
+                    let embed = CreateEmbed::new()
+                        .title("Bot Statistics")
+                        .colour(embeds::worst_colour(db.active_accounts(), Utc::now()))
+                        .footer(embeds::legend_footer())
+                        .field("Accounts", accounts, true)
+                        .field("Queue", format!("{} waiting ({}, {} run request(s) queued)", queue_depth, queue_label, waiting_requests), true)
+                        .field("Runs Today", format!("{} ({} account(s) done since the daily reset)", overall.runs_today, done_since_reset), true)
+                        .field("Success Rate (7d)", overall.success_rate().map(|r| format!("{:.0}% of {} runs", r, overall.week_runs)).unwrap_or_else(|| "No runs".to_string()), true)
+                        .field("Average Duration (7d)", overall.average_duration.map(format_elapsed).unwrap_or_else(|| "n/a".to_string()), true)
+                        .field("Uptime", format_uptime(self.started.elapsed()), true)
+                        .field("Queue Sessions (since start)", format!("{} started, {} succeeded, {} failed", counts.started, counts.succeeded, counts.failed), true)
+                        .field("Cookie", cookie_health, false)
+                        .field("Server Capacity", capacity, false);
+                    content = String::new();
+                    embeds.push(embed);
+                },
+                "settings" => {
+                    let db = self.db.lock().await;
+                    let lines = db.data.settings.overview();
+                    let unset: Vec<&str> = lines.iter().filter(|l| l.value.is_none()).map(|l| l.label).collect();
+                    let mut embed = CreateEmbed::new()
+                        .title("Bot Settings")
+                        .description(if unset.is_empty() {
+                            "Everything is configured.".to_string()
+                        } else {
+                            format!("Not set: {}", unset.join(", "))
+                        });
+                    for line in lines {
+                        let value = match (line.value, line.default) {
+                            (Some(value), _) => value,
+                            (None, Some(default)) => format!("*Not set* (default: {})", default),
+                            (None, None) => "*Not set*".to_string(),
+                        };
+                        // Embed field values are limited to 1024 characters
+                        let value = if value.chars().count() > 1024 { format!("{}…", value.chars().take(1023).collect::<String>()) } else { value };
+                        embed = embed.field(line.label, value, false);
+                    }
+                    content = String::new();
+                    embeds.push(embed);
+                },
+                "diagnose" => {
+                    // The endpoint check can take longer than Discord's 3 second reply window
+                    let _ = command.defer(&ctx.http).await;
+                    let checks = self.run_diagnostics(&ctx).await;
+                    let _ = command.edit_response(&ctx.http, EditInteractionResponse::new().embed(diagnose::render("Diagnostics", &checks))).await;
+                    return;
+                },
+                VIEW_ACCOUNTS_MENU => {
+                    ephemeral = true;
+                    match command.data.target() {
+                        Some(ResolvedTarget::User(user, _)) => {
+                            let db = self.db.lock().await;
+                            let accs = db.get_user_accounts(&user.id.to_string());
+                            if accs.is_empty() {
+                                content = format!("<@{}> has no accounts registered.", user.id);
+                            } else {
+                                content = format!("Accounts of <@{}>:", user.id);
+                                embeds.push(embeds::account_list(format!("Accounts of {}", user.name), &accs, Utc::now(), true));
+                            }
+                        },
+                        _ => content = "Use this on a member.".to_string(),
+                    }
                 },
                 "list_my_accounts" => {
+                    let db = self.db.lock().await;
+                    let my_accs = db.get_user_accounts(&user_id);
+                    if my_accs.is_empty() {
+                        content = "You have no accounts registered.".to_string();
+                    } else {
+                        content = String::new();
+                        embeds.push(embeds::account_list("Your Accounts", &my_accs, Utc::now(), false));
+                    }
+                },
+                "next_run" => {
                     let db = self.db.lock().await;
                     let my_accs = db.get_user_accounts(&user_id);
                     content = if my_accs.is_empty() {
                         "You have no accounts registered.".to_string()
                     } else {
-                        my_accs.iter()
-                            .map(|a| format!("- **{}**: {} (Last Run: {})", a.name, a.status, a.last_run.as_deref().unwrap_or("Never")))
+                        // Schedules are wall-clock times in the bot's timezone; only the display is converted
+                        let tz = db.user_timezone(&user_id);
+                        let now = Utc::now().with_timezone(&db.data.settings.timezone());
+                        let lines = my_accs.iter()
+                            .map(|a| match scheduler::next_run_for(a, &db.data.settings.schedules, &now) {
+                                Some(t) => format!("- **{}**: {} (<t:{}:R>)", a.name, t.with_timezone(&tz).format("%a %d %b, %H:%M"), t.timestamp()),
+                                None => format!("- **{}**: unknown", a.name),
+                            })
                             .collect::<Vec<_>>()
-                            .join("\n")
+                            .join("\n");
+                        let hint = if db.data.user_preferences.get(&user_id).and_then(|p| p.timezone()).is_none() {
+                            " Set your own with /set_my_timezone."
+                        } else {
+                            ""
+                        };
+                        format!("Next scheduled runs, in **{}**:{}\n{}", tz.name(), hint, lines)
+                    };
+                },
+                "set_my_timezone" => {
+                    let name = command.data.options.iter().find(|o| o.name == "timezone").and_then(|o| o.value.as_str()).unwrap_or("").trim();
+                    let name = Some(name).filter(|n| !n.eq_ignore_ascii_case("default"));
+                    let mut db = self.db.lock().await;
+                    content = match db.set_user_timezone(&user_id, name) {
+                        Ok(()) => {
+                            let tz = db.user_timezone(&user_id);
+                            match name {
+                                Some(_) => format!("Your times are now shown in **{}** (now {}).", tz.name(), Utc::now().with_timezone(&tz).format("%H:%M")),
+                                None => format!("Your timezone was cleared; times are shown in the bot's timezone (**{}**).", tz.name()),
+                            }
+                        },
+                        Err(e) => e.to_string(),
+                    };
+                },
+                "set_my_language" => {
+                    let choice = command.data.options.iter().find(|o| o.name == "language").and_then(|o| o.value.as_str()).unwrap_or("");
+                    let chosen = if choice == "default" { Ok(None) } else { Locale::parse(choice).map(Some) };
+                    content = match chosen {
+                        Err(e) => e,
+                        Ok(chosen) => {
+                            let saved = self.db.lock().await.set_user_locale(&user_id, chosen);
+                            match saved {
+                                Err(e) => e.to_string(),
+                                Ok(()) => {
+                                    let locale = self.locale_for(&user_id, &command.locale).await;
+                                    templates::render(locale, &templates::LANGUAGE_SET, &[("language", locale.label())])
+                                }
+                            }
+                        }
                     };
                 },
                 "add_account" => {
                     let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).unwrap_or("").to_string();
                     let code = command.data.options.iter().find(|o| o.name == "code").and_then(|o| o.value.as_str()).unwrap_or("").to_string();
                     let server = command.data.options.iter().find(|o| o.name == "server").and_then(|o| o.value.as_str()).map(|s| s.to_string());
-                    
-                    {
-                        let mut db = self.db.lock().await;
-                        let encrypted_code = Account::encrypt_code_str(&code); // Encrypt!
-                        let new_acc = Account {
-                            name: name.clone(),
-                            code: encrypted_code,
-                            target_server: server,
-                            user_id: Some(user_id.clone()),
-                            username: Some(command.user.name.clone()),
-                            discord_nickname: command.member.as_ref().and_then(|m| m.nick.clone()),
-                            ping_enabled: false,
-                            status: "pending".to_string(),
-                            last_run: None,
+                    let server_selection = command.data.options.iter().find(|o| o.name == "toggle_server_selection").and_then(|o| o.value.as_bool()).unwrap_or(false);
+                    let allow_duplicate = command.data.options.iter().find(|o| o.name == "allow_duplicate").and_then(|o| o.value.as_bool()).unwrap_or(false);
+                    let (server, known_servers, duplicate, deleted_name, existing) = {
+                        let db = self.db.lock().await;
+                        (
+                            server.map(|s| db.validate_server(&s)).transpose(),
+                            db.data.settings.known_servers.clone(),
+                            db.find_duplicate_code(&code, &name).map(|a| a.name.clone()),
+                            db.is_deleted_name(&name),
+                            db.find_account(&name).cloned(),
+                        )
+                    };
+                    let other_owner = existing.as_ref().is_some_and(|a| a.user_id.as_deref() != Some(user_id.as_str()));
+
+                    if deleted_name {
+                        content = templates::render(locale, &templates::ACCOUNT_NAME_DELETED, &[("account", &name)]);
+                    } else if other_owner && !self.is_admin(&ctx, &command).await {
+                        // Re-adding replaces the account, so only its owner or an admin may
+                        content = templates::render(locale, &templates::NOT_OWNER, &[("account", &name)]);
+                    } else if let Err(reason) = Account::validate_code(&code) {
+                        content = templates::render(locale, &templates::INVALID_CODE, &[("account", &name), ("reason", &reason)]);
+                    } else if let Err(reason) = &server {
+                        content = format!("Invalid server for **{}**: {}", name, reason);
+                    } else if duplicate.is_some() && !(allow_duplicate && self.is_admin(&ctx, &command).await) {
+                        // Running the same game account twice in a row triggers Zigza
+                        content = templates::render(locale, &templates::DUPLICATE_CODE, &[("existing", duplicate.as_deref().unwrap_or_default())]);
+                    } else {
+                        let server = server.unwrap_or(None);
+                        let offer_menu = server_selection && server.is_none() && !known_servers.is_empty();
+                        let code = code.trim().to_string();
+                        let saved = {
+                            let mut db = self.db.lock().await;
+                            let encrypted_code = Account::encrypt_code_str(&code); // Encrypt!
+                            // An admin re-adding a member's account leaves it with that member
+                            let (owner_id, owner_name, owner_nick) = match existing.as_ref().filter(|_| other_owner) {
+                                Some(acc) => (acc.user_id.clone(), acc.username.clone(), acc.discord_nickname.clone()),
+                                None => (Some(user_id.clone()), Some(command.user.name.clone()), command.member.as_ref().and_then(|m| m.nick.clone())),
+                            };
+                            let new_acc = Account {
+                                name: name.clone(),
+                                code: encrypted_code,
+                                target_server: server,
+                                user_id: owner_id,
+                                username: owner_name,
+                                discord_nickname: owner_nick,
+                                ping_enabled: false,
+                                status: "pending".to_string(),
+                                last_run: None,
+                                deleted: false,
+                                deleted_at: None,
+                                tags: Vec::new(),
+                                cookie_override: None,
+                                flow: Flow::default(),
+                                notes: None,
+                                running_since: None,
+                            };
+                            // Re-adding an existing account gives it the new code
+                            if existing.is_some() { db.replace_account(new_acc).map(|_| ()) } else { db.add_account(new_acc) }
                         };
-                        let _ = db.add_account(new_acc);
+                        if let Err(e) = saved {
+                            content = format!("Error: {}", e);
+                        } else {
+                            content = templates::render(locale, &templates::ACCOUNT_ADDED, &[("account", &name)]);
+                            if let Some(other) = &duplicate {
+                                content.push_str(&format!("\n[WARN] It uses the same restore code as **{}**.", other));
+                            }
+                            if offer_menu {
+                                content.push_str("\nPick its target server below (or later with /set_server).");
+                                components.push(server_select_menu(&name, &known_servers));
+                            }
+                            if let Some(notice) = queue_wait_notice(self.process_queue(AccountFilter::User(user_id), None, false, Some(command.channel_id)).await, locale) {
+                                content.push_str(&format!("\n{}", notice));
+                            }
+                        }
+                    }
+                },
+                "setup" => {
+                    ephemeral = true;
+                    let sent = match command.user.create_dm_channel(&ctx.http).await {
+                        Ok(dm) => dm.send_message(&ctx.http, setup_intro()).await.map(|_| ()),
+                        Err(e) => Err(e),
+                    };
+                    content = match sent {
+                        Ok(_) => "I sent you a DM to set up your account.".to_string(),
+                        Err(e) => {
+                            log!("[WARN] /setup: cannot DM {}: {}", command.user.name, e);
+                            "I couldn't DM you. Allow direct messages from server members (Privacy Settings) and try again, or use /add_account.".to_string()
+                        }
+                    };
+                },
+                "remove_account" => {
+                    let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).unwrap_or("").to_string();
+                    let owner = {
+                        let db = self.db.lock().await;
+                        db.find_account(&name).map(|a| a.user_id.clone())
+                    };
+                    match owner {
+                        None => content = templates::render(locale, &templates::ACCOUNT_NOT_FOUND, &[("account", &name)]),
+                        Some(owner) => {
+                            if owner.as_deref() != Some(user_id.as_str()) && !self.is_admin(&ctx, &command).await {
+                                content = templates::render(locale, &templates::NOT_OWNER, &[("account", &name)]);
+                            } else {
+                                let mut db = self.db.lock().await;
+                                match db.remove_account(&name) {
+                                    Ok(true) => content = templates::render(locale, &templates::ACCOUNT_REMOVED, &[("account", &name)]),
+                                    _ => content = templates::render(locale, &templates::ACCOUNT_NOT_FOUND, &[("account", &name)]),
+                                }
+                            }
+                        }
+                    }
+                },
+                "restore_account" => {
+                    let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).unwrap_or("").to_string();
+                    let owner = {
+                        let db = self.db.lock().await;
+                        db.deleted_accounts().into_iter().find(|a| a.name == name).map(|a| a.user_id)
+                    };
+                    match owner {
+                        None => content = templates::render(locale, &templates::DELETED_NOT_FOUND, &[("account", &name)]),
+                        Some(owner) => {
+                            if owner.as_deref() != Some(user_id.as_str()) && !self.is_admin(&ctx, &command).await {
+                                content = templates::render(locale, &templates::NOT_OWNER, &[("account", &name)]);
+                            } else {
+                                let mut db = self.db.lock().await;
+                                match db.restore_account(&name) {
+                                    Ok(true) => content = templates::render(locale, &templates::ACCOUNT_RESTORED, &[("account", &name)]),
+                                    Ok(false) => content = templates::render(locale, &templates::DELETED_NOT_FOUND, &[("account", &name)]),
+                                    Err(e) => content = format!("Error: {}", e),
+                                }
+                            }
+                        }
+                    }
+                },
+                "transfer_account" => {
+                    let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).unwrap_or("").to_string();
+                    let target = command.data.options.iter().find(|o| o.name == "user").and_then(|o| o.value.as_user_id());
+                    let owner = {
+                        let db = self.db.lock().await;
+                        db.find_account(&name).map(|a| a.user_id.clone())
+                    };
+                    match (owner, target) {
+                        (None, _) => content = templates::render(locale, &templates::ACCOUNT_NOT_FOUND, &[("account", &name)]),
+                        (Some(_), None) => content = "Pick the member to transfer the account to.".to_string(),
+                        (Some(owner), Some(target)) => {
+                            let target_user = command.data.resolved.users.get(&target);
+                            if owner.as_deref() != Some(user_id.as_str()) && !self.is_admin(&ctx, &command).await {
+                                content = templates::render(locale, &templates::NOT_OWNER, &[("account", &name)]);
+                            } else if target_user.map(|u| u.bot).unwrap_or(false) {
+                                content = "Accounts can't be given to bots.".to_string();
+                            } else if owner.as_deref() == Some(target.to_string().as_str()) {
+                                content = format!("<@{}> already owns **{}**.", target, name);
+                            } else {
+                                let username = target_user.map(|u| u.name.clone()).unwrap_or_default();
+                                let nickname = command.data.resolved.members.get(&target).and_then(|m| m.nick.clone());
+                                let result = self.db.lock().await.set_owner(&name, &target.to_string(), &username, nickname);
+                                content = match result {
+                                    Ok(true) => {
+                                        let previous = owner.map(|o| format!("<@{}>", o)).unwrap_or_else(|| "nobody".to_string());
+                                        Self::log_message(Arc::clone(&self.db), &self.dispatcher, Level::Info, format!("[INFO] **{}** was transferred from {} to <@{}> by <@{}>.", name, previous, target, user_id), Some(command.channel_id)).await;
+                                        format!("**{}** now belongs to <@{}> (previously {}).", name, target, previous)
+                                    },
+                                    Ok(false) => templates::render(locale, &templates::ACCOUNT_NOT_FOUND, &[("account", &name)]),
+                                    Err(e) => format!("Error: {}", e),
+                                };
+                            }
+                        }
+                    }
+                },
+                "claim_account" => {
+                    let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).unwrap_or("").to_string();
+                    let nickname = command.member.as_ref().and_then(|m| m.nick.clone());
+                    let result = {
+                        let mut db = self.db.lock().await;
+                        match db.find_account(&name).map(|a| a.user_id.clone()) {
+                            None => Err(templates::render(locale, &templates::ACCOUNT_NOT_FOUND, &[("account", &name)])),
+                            Some(Some(owner)) => Err(format!("**{}** already belongs to <@{}>. Ask them (or an admin) to /transfer_account it.", name, owner)),
+                            Some(None) => db.claim_account(&name, &user_id, &command.user.name, nickname).map_err(|e| format!("Error: {}", e)),
+                        }
+                    };
+                    content = match result {
+                        Ok(true) => {
+                            Self::log_message(Arc::clone(&self.db), &self.dispatcher, Level::Info, format!("[INFO] Unowned account **{}** was claimed by <@{}>.", name, user_id), Some(command.channel_id)).await;
+                            format!("You now own **{}**.", name)
+                        },
+                        Ok(false) => format!("**{}** can't be claimed anymore.", name),
+                        Err(message) => message,
+                    };
+                },
+                "detach_account" => {
+                    let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).unwrap_or("").to_string();
+                    let owner = {
+                        let db = self.db.lock().await;
+                        db.find_account(&name).map(|a| a.user_id.clone())
+                    };
+                    match owner {
+                        None => content = templates::render(locale, &templates::ACCOUNT_NOT_FOUND, &[("account", &name)]),
+                        Some(None) => content = format!("**{}** has no owner.", name),
+                        Some(Some(owner)) => {
+                            if owner != user_id && !self.is_admin(&ctx, &command).await {
+                                content = templates::render(locale, &templates::NOT_OWNER, &[("account", &name)]);
+                            } else {
+                                let result = self.db.lock().await.detach_account(&name);
+                                content = match result {
+                                    Ok(true) => {
+                                        Self::log_message(Arc::clone(&self.db), &self.dispatcher, Level::Info, format!("[INFO] **{}** was detached from <@{}> by <@{}>.", name, owner, user_id), Some(command.channel_id)).await;
+                                        format!("**{}** no longer has an owner. It stays in the queue and can be taken over with /claim_account.", name)
+                                    },
+                                    Ok(false) => templates::render(locale, &templates::ACCOUNT_NOT_FOUND, &[("account", &name)]),
+                                    Err(e) => format!("Error: {}", e),
+                                };
+                            }
+                        }
+                    }
+                },
+                "purge_deleted" => {
+                    let mut db = self.db.lock().await;
+                    match db.purge_deleted() {
+                        Ok(0) => content = "There are no deleted accounts to purge.".to_string(),
+                        Ok(n) => content = format!("Permanently purged **{}** deleted account(s).", n),
+                        Err(e) => content = format!("Error: {}", e),
+                    }
+                },
+                "purge_done" => {
+                    let target = command.data.options.iter().find(|o| o.name == "target").and_then(|o| o.value.as_str()).unwrap_or("all").trim().to_string();
+                    let run = command.data.options.iter().find(|o| o.name == "run").and_then(|o| o.value.as_bool()).unwrap_or(false);
+                    // "errors" resets failed accounts only; anything else is an account filter
+                    let selection = if target.eq_ignore_ascii_case("errors") {
+                        Ok((AccountFilter::All, true))
+                    } else {
+                        AccountFilter::parse(&target).map(|filter| (filter, false))
+                    };
+                    match selection {
+                        Err(e) => content = e,
+                        Ok((filter, errors_only)) => {
+                            let result = self.db.lock().await.reset_statuses(&filter, errors_only);
+                            match result {
+                                Err(e) => content = format!("Error: {}", e),
+                                Ok(reset) if reset.is_empty() => content = format!("No accounts to reset for `{}`.", target),
+                                Ok(reset) => {
+                                    let mut names = reset.iter().take(20).map(|n| format!("**{}**", n)).collect::<Vec<_>>().join(", ");
+                                    if reset.len() > 20 {
+                                        names.push_str(&format!(" and {} more", reset.len() - 20));
+                                    }
+                                    content = format!("Reset **{}** account(s) to pending: {}", reset.len(), names);
+                                    if run {
+                                        // Only the reset accounts (plus any already pending) are left to pick up
+                                        let start = self.process_queue(filter, None, false, Some(command.channel_id)).await;
+                                        content.push_str(&format!("\n{}", queue_wait_notice(start, locale).unwrap_or_else(|| "Starting the queue for them now.".to_string())));
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+                "backup_now" => {
+                    content = match backup::create(&self.db).await {
+                        Ok(report) => format!("Backup `{}` created.{}", report.id, backup_summary(&report)),
+                        Err(e) => format!("Backup failed: {}", e),
+                    };
+                },
+                "export_db" => {
+                    let dm = command.data.options.iter().find(|o| o.name == "dm").and_then(|o| o.value.as_bool()).unwrap_or(false);
+                    let snapshot = {
+                        let db = self.db.lock().await;
+                        // Codes and cookies are not serialized (they live in secrets.json); redact catches any copy elsewhere
+                        serde_json::to_string_pretty(&db.data).map(|json| (redact(&json), db.active_accounts().count()))
+                    };
+                    ephemeral = true;
+                    match snapshot {
+                        Err(e) => content = format!("Export failed: {}", e),
+                        Ok((json, accounts)) => {
+                            let file_name = format!("db-{}.json", Utc::now().format("%Y%m%d-%H%M%S"));
+                            let summary = format!("Database snapshot `{}` ({} accounts). Restore codes and cookies are not included.", file_name, accounts);
+                            let file = CreateAttachment::bytes(json.into_bytes(), file_name);
+                            if dm {
+                                content = match command.user.direct_message(&ctx.http, CreateMessage::new().content(&summary).add_file(file)).await {
+                                    Ok(_) => "Sent the database snapshot to your DMs.".to_string(),
+                                    Err(e) => format!("Could not send you a DM ({}). Run /export_db without dm to get it here.", e),
+                                };
+                            } else {
+                                content = summary;
+                                attachments.push(file);
+                            }
+                        }
+                    }
+                },
+                "restore_backup" => {
+                    let id = command.data.options.iter().find(|o| o.name == "id").and_then(|o| o.value.as_str()).unwrap_or("").trim().to_string();
+                    if *self.queue_state.lock().await != QueueState::Stopped {
+                        content = "The queue is running. Stop it with /force_stop_all before restoring a backup.".to_string();
+                    } else {
+                        match backup::load(&id) {
+                            Err(e) => {
+                                let recent = backup::list().into_iter().take(10).map(|b| format!("`{}`", b)).collect::<Vec<_>>();
+                                content = if recent.is_empty() { format!("{} There are no backups yet.", e) } else { format!("{} Recent backups: {}", e, recent.join(", ")) };
+                            },
+                            // Back up what is being replaced, so a restore can itself be undone
+                            Ok(data) => match backup::create(&self.db).await {
+                                Err(e) => content = format!("Not restored: backing up the current database failed: {}", e),
+                                Ok(safety) => {
+                                    let result = self.db.lock().await.replace_data(data);
+                                    content = match result {
+                                        Ok(()) => {
+                                            Self::log_message(Arc::clone(&self.db), &self.dispatcher, Level::Warning, format!("[WARN] The database was restored from backup `{}` by <@{}>.", id, user_id), Some(command.channel_id)).await;
+                                            format!("Restored backup `{}`. The previous state was saved as backup `{}`.", id, safety.id)
+                                        },
+                                        Err(e) => format!("Restore failed: {}", e),
+                                    };
+                                }
+                            },
+                        }
                     }
-                    content = format!("Successfully added account **{}**.", name);
-                    self.process_queue(ctx.clone(), Some(user_id), Some(command.channel_id)).await;
                 },
-                "remove_account" => {
-                    let mut db = self.db.lock().await;
-                    let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).unwrap_or("");
-                    match db.remove_account(name) {
-                        Ok(true) => content = format!("Successfully removed account **{}**.", name),
-                        _ => content = format!("Account **{}** not found.", name),
+                "export_settings" => {
+                    let passphrase = command.data.options.iter().find(|o| o.name == "passphrase").and_then(|o| o.value.as_str());
+                    ephemeral = true;
+                    let export = {
+                        let db = self.db.lock().await;
+                        SettingsExport::new(&db.data.settings, passphrase)
+                    };
+                    match export.and_then(|e| serde_json::to_string_pretty(&e).map_err(|e| e.to_string())) {
+                        Err(e) => content = format!("Export failed: {}", e),
+                        Ok(json) => {
+                            let file_name = format!("settings-{}.json", Utc::now().format("%Y%m%d-%H%M%S"));
+                            content = if passphrase.is_some() {
+                                format!("Settings `{}`, with the session cookie encrypted with your passphrase. Load it on the new host with /import_settings and the same passphrase.", file_name)
+                            } else {
+                                format!("Settings `{}`. The session cookie is not included; pass a passphrase to include it.", file_name)
+                            };
+                            attachments.push(CreateAttachment::bytes(redact(&json).into_bytes(), file_name));
+                        }
                     }
                 },
+                "import_settings" => {
+                    let passphrase = command.data.options.iter().find(|o| o.name == "passphrase").and_then(|o| o.value.as_str());
+                    let attachment = command.data.options.iter().find(|o| o.name == "file")
+                        .and_then(|o| o.value.as_attachment_id())
+                        .and_then(|id| command.data.resolved.attachments.get(&id));
+                    ephemeral = true;
+                    let export = match attachment {
+                        None => Err("Attach the file from /export_settings.".to_string()),
+                        Some(file) if file.size > SETTINGS_IMPORT_MAX_BYTES => Err(format!("`{}` is too large to be a settings export.", file.filename)),
+                        Some(file) => match file.download().await {
+                            Ok(bytes) => SettingsExport::parse(&bytes),
+                            Err(e) => Err(format!("Could not download `{}`: {}", file.filename, e)),
+                        },
+                    };
+                    let cookie_note = match &export {
+                        Ok(export) if export.has_secrets() && passphrase.is_some() => " The session cookie was imported too.",
+                        Ok(export) if export.has_secrets() => " The file has a session cookie, but no passphrase was given; the current cookie was kept.",
+                        _ => " The current session cookie was kept.",
+                    };
+                    content = match export.and_then(|e| e.into_settings(passphrase)) {
+                        Err(e) => format!("Not imported: {}", e),
+                        // Back up what is being replaced, like /restore_backup
+                        Ok(settings) => match backup::create(&self.db).await {
+                            Err(e) => format!("Not imported: backing up the current database failed: {}", e),
+                            Ok(safety) => {
+                                let summary = format!("{} schedule(s), {} role tier(s), {} command channel(s)", settings.schedules.len(), settings.role_tiers.len(), settings.allowed_channels.len());
+                                let result = self.db.lock().await.replace_settings(settings);
+                                match result {
+                                    Ok(()) => {
+                                        Self::log_message(Arc::clone(&self.db), &self.dispatcher, Level::Warning, format!("[WARN] The bot settings were replaced from an import by <@{}>.", user_id), Some(command.channel_id)).await;
+                                        format!("Settings imported: {}.{} The previous state was saved as backup `{}`.", summary, cookie_note, safety.id)
+                                    },
+                                    Err(e) => format!("Not imported: {}", e),
+                                }
+                            },
+                        },
+                    };
+                },
                 "toggle_ping" => {
                     let mut db = self.db.lock().await;
                     match db.toggle_ping(&user_id) {
@@ -367,195 +2663,1028 @@ impl EventHandler for Handler {
                     let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str());
                     
                     let target_name = name.unwrap_or("all");
+                    // Accounts that already finished today are only run again when forced
+                    let force = command.data.options.iter().find(|o| o.name == "force").and_then(|o| o.value.as_bool()).unwrap_or(false);
+                    // Checks the login only; the account's status and run history stay as they are
+                    let dry_run = command.data.options.iter().find(|o| o.name == "dry_run").and_then(|o| o.value.as_bool()).unwrap_or(false);
                     
                     if target_name.to_lowercase() == "all" {
                         // Run all for THIS user
-                        self.process_queue(ctx.clone(), Some(user_id), Some(command.channel_id)).await;
-                        content = "Queued all your accounts for execution.".to_string();
+                        if force && !dry_run {
+                            // Accounts that just completed a run stay done until their cooldown is over
+                            let mut db = self.db.lock().await;
+                            let now = Utc::now();
+                            let ready: Vec<String> = db.get_user_accounts(&user_id).into_iter()
+                                .filter(|a| run_cooldown_until(&db, &a.name, now).is_none())
+                                .map(|a| a.name)
+                                .collect();
+                            let _ = db.reset_statuses_matching(&AccountFilter::Names(ready));
+                        }
+                        let start = self.process_queue(AccountFilter::User(user_id), None, dry_run, Some(command.channel_id)).await;
+                        content = queue_wait_notice(start, locale).unwrap_or_else(|| {
+                            templates::render(locale, if dry_run { &templates::FORCE_RUN_MINE_DRY } else { &templates::FORCE_RUN_MINE }, &[])
+                        });
+                    } else if target_name.contains(',') || target_name.contains('*') || target_name.contains('?') {
+                        // Several accounts ("alt1,alt2" or "guild-*") go through the queue, in the given order
+                        // Below Operator, a list or pattern only reaches the caller's own accounts
+                        let operator = self.has_tier(&ctx, command.guild_id, command.user.id, command.member.as_deref(), Tier::Operator).await;
+                        let (names, unmatched, done_today, cooling) = {
+                            let db = self.db.lock().await;
+                            let (names, unmatched) = db.resolve_account_list(target_name, if operator { None } else { Some(user_id.as_str()) });
+                            let now = Utc::now().with_timezone(&db.data.settings.timezone());
+                            let (done_today, names): (Vec<String>, Vec<String>) = names.into_iter()
+                                .partition(|n| (!force || dry_run) && db.find_account(n).map(|a| scheduler::completed_since_reset(a, &now)).unwrap_or(false));
+                            let (cooling, names): (Vec<String>, Vec<String>) = names.into_iter()
+                                .partition(|n| !dry_run && run_cooldown_until(&db, n, now.with_timezone(&Utc)).is_some());
+                            (names, unmatched, done_today, cooling)
+                        };
+                        if names.is_empty() && !cooling.is_empty() {
+                            content = templates::render(locale, &templates::FORCE_RUN_ALL_COOLING, &[("accounts", &cooling.join(", "))]);
+                        } else if names.is_empty() && !done_today.is_empty() {
+                            content = templates::render(locale, &templates::FORCE_RUN_ALL_DONE, &[("accounts", &done_today.join(", "))]);
+                        } else if names.is_empty() {
+                            content = templates::render(locale, &templates::FORCE_RUN_NO_MATCH, &[("pattern", target_name)]);
+                        } else {
+                            let filter = AccountFilter::Names(names.clone());
+                            // A dry run must not undo today's results; it keeps every status
+                            if !dry_run {
+                                let mut db = self.db.lock().await;
+                                let _ = db.reset_statuses_matching(&filter);
+                            }
+                            let start = self.process_queue(filter, None, dry_run, Some(command.channel_id)).await;
+                            let order = names.iter().map(|n| format!("**{}**", n)).collect::<Vec<_>>().join(", ");
+                            content = templates::render(locale, if dry_run { &templates::FORCE_RUN_QUEUED_DRY } else { &templates::FORCE_RUN_QUEUED }, &[
+                                ("count", &names.len().to_string()),
+                                ("accounts", &order),
+                            ]);
+                            if let Some(notice) = queue_wait_notice(start, locale) {
+                                content.push_str(&format!("\n{}", notice));
+                            }
+                            if !unmatched.is_empty() {
+                                content.push_str(&format!("\n{}", templates::render(locale, &templates::FORCE_RUN_UNMATCHED, &[("entries", &unmatched.join(", "))])));
+                            }
+                            if !done_today.is_empty() {
+                                content.push_str(&format!("\n{}", templates::render(locale, &templates::FORCE_RUN_SKIPPED_DONE, &[("accounts", &done_today.join(", "))])));
+                            }
+                            if !cooling.is_empty() {
+                                content.push_str(&format!("\n{}", templates::render(locale, &templates::FORCE_RUN_SKIPPED_COOLING, &[("accounts", &cooling.join(", "))])));
+                            }
+                        }
+                    } else if !self.owns_or_operates(&ctx, &command, target_name, &user_id).await {
+                        // A dry run logs in with the restore code too, which can end the owner's own session
+                        content = templates::render(locale, &templates::FORCE_RUN_NOT_OWNER, &[("account", target_name)]);
+                    } else if let Some(until) = if dry_run { None } else { self.run_cooldown(target_name).await } {
+                        content = cooldown_notice(target_name, until, locale);
+                    } else if !force && !dry_run && self.completed_today(target_name).await {
+                        content = templates::render(locale, &templates::DONE_TODAY, &[("account", target_name)]);
                     } else {
                         // Start single
-                        let db_clone = Arc::clone(&self.db);
-                        let processing_clone = Arc::clone(&self.is_processing);
-                        let http_clone = ctx.http.clone();
-                        let channel_id = command.channel_id;
-                        let n_owned = target_name.to_string();
-                        
-                         tokio::spawn(async move {
-                            let (cookie, acc) = {
-                                let mut is_proc = processing_clone.lock().await;
-                                if *is_proc {
-                                    let _ = channel_id.say(&http_clone, "[WARN] Already in progress.").await;
-                                    return;
-                                }
-                                *is_proc = true;
-                                
-                                let db = db_clone.lock().await;
-                                (db.data.settings.cookies.clone().unwrap_or_default(), 
-                                 db.data.accounts.iter().find(|a| a.name == n_owned).cloned())
-                            };
-                            
-                            if let Some(acc) = acc {
-                                if cookie.is_empty() {
-                                    let _ = channel_id.say(&http_clone, "[ERROR] No cookies set.").await;
-                                } else {
-                                    let _ = channel_id.say(&http_clone, format!("[INFO] Force running **{}**...", acc.name)).await;
-                                    match EvertextClient::connect(&cookie).await {
-                                        Ok(mut client) => {
-                                            let decrypted_code = acc.decrypt_code();
-                                            match client.run_loop(&acc, &decrypted_code).await {
-                                                Ok(_) => {
-                                                    let mut db = db_clone.lock().await;
-                                                    let _ = db.update_status(&acc.name, "done");
-                                                    let _ = channel_id.say(&http_clone, format!("[SUCCESS] **{}** finished.", acc.name)).await;
-                                                },
-                                                Err(e) => {
-                                                    let err_str = e.to_string();
-                                                    if err_str.contains("SESSION_COMPLETE") {
-                                                        let mut db = db_clone.lock().await;
-                                                        let _ = db.update_status(&acc.name, "done");
-                                                        let _ = channel_id.say(&http_clone, format!("[SUCCESS] **{}** finished.", acc.name)).await;
-                                                    } else {
-                                                        let _ = channel_id.say(&http_clone, format!("[ERROR] **{}** failed: {}", acc.name, err_str)).await;
-                                                    }
-                                                }
-                                            }
-                                        },
-                                        Err(e) => {
-                                            let _ = channel_id.say(&http_clone, format!("[ERROR] Connection failed: {}", e)).await;
-                                        }
-                                    }
-                                }
-                            } else {
-                                let _ = channel_id.say(&http_clone, format!("[ERROR] Account **{}** not found.", n_owned)).await;
-                            }
-                            
-                            let mut is_proc = processing_clone.lock().await;
-                            *is_proc = false;
-                        });
-                        content = format!("Force run initiated for **{}**.", target_name);
+                        self.spawn_single_run(target_name, command.channel_id, dry_run, None, locale);
+                        content = if dry_run {
+                            templates::render(locale, &templates::DRY_RUN_STARTED, &[("account", target_name)])
+                        } else {
+                            templates::render(locale, &templates::FORCE_RUN_STARTED, &[("account", target_name)])
+                        };
                     }
                 },
-                "force_run_all" => {
-                    if !self.is_admin(&ctx, &command).await {
-                        content = "Admin permissions required.".to_string();
+                "debug_run" => {
+                    let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).unwrap_or("");
+                    ephemeral = true;
+                    let found = self.db.lock().await.find_account(name).is_some();
+                    content = if !found {
+                        templates::render(locale, &templates::ACCOUNT_NOT_FOUND, &[("account", name)])
+                    } else if let Some(until) = self.run_cooldown(name).await {
+                        cooldown_notice(name, until, locale)
+                    } else {
+                        self.spawn_single_run(name, command.channel_id, false, Some(command.user.id), locale);
+                        format!("Debug run started for **{}**. Every step and all terminal output go to a private thread in this channel, with secrets masked.", name)
+                    };
+                },
+                "run_menu" => {
+                    let accounts = self.run_menu_accounts(&user_id).await;
+                    ephemeral = true;
+                    if accounts.is_empty() {
+                        content = templates::render(locale, &templates::RUN_MENU_EMPTY, &[]);
                     } else {
-                        self.process_queue(ctx.clone(), None, Some(command.channel_id)).await;
-                        content = "Starting ALL pending accounts...".to_string();
+                        let (prompt, rows) = run_menu(&accounts, 0);
+                        content = prompt;
+                        components = rows;
                     }
                 },
+                "force_run_all" => {
+                    let dry_run = command.data.options.iter().find(|o| o.name == "dry_run").and_then(|o| o.value.as_bool()).unwrap_or(false);
+                    let start = self.process_queue(AccountFilter::All, None, dry_run, Some(command.channel_id)).await;
+                    content = queue_wait_notice(start, locale).unwrap_or_else(|| if dry_run {
+                        "Starting a dry run of ALL pending accounts...".to_string()
+                    } else {
+                        "Starting ALL pending accounts...".to_string()
+                    });
+                },
                 "force_stop_all" => {
-                    if !self.is_admin(&ctx, &command).await {
-                        content = "Admin permissions required.".to_string();
+                    let mut state = self.queue_state.lock().await;
+                    *state = QueueState::Stopped;
+                    let waiting = self.pending_runs.lock().await.len();
+                    content = "Queue processing halted.".to_string();
+                    if waiting > 0 {
+                        content.push_str(&format!(" {} waiting run request(s) will be dropped.", waiting));
+                    }
+                },
+                "pause_queue" => {
+                    let mut state = self.queue_state.lock().await;
+                    content = match *state {
+                        QueueState::Running => {
+                            *state = QueueState::Paused;
+                            "Queue paused. The current account will finish, the rest are held.".to_string()
+                        },
+                        QueueState::Paused => "Queue is already paused.".to_string(),
+                        QueueState::Stopped => "Queue is not running.".to_string(),
+                    };
+                },
+                "resume_queue" => {
+                    let mut state = self.queue_state.lock().await;
+                    content = match *state {
+                        QueueState::Paused => {
+                            *state = QueueState::Running;
+                            "Queue resumed.".to_string()
+                        },
+                        QueueState::Running => "Queue is already running.".to_string(),
+                        QueueState::Stopped => "Queue is not running. Use /force_run_all to start it.".to_string(),
+                    };
+                },
+                "queue_promote" | "queue_demote" => {
+                    let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).unwrap_or("").to_string();
+                    let promote = command.data.name == "queue_promote";
+                    if !self.queue_state.lock().await.is_active() {
+                        content = "Queue is not running.".to_string();
                     } else {
-                        let mut is_proc = self.is_processing.lock().await;
-                        *is_proc = false;
-                        content = "Queue processing halted.".to_string();
+                        let mut order = self.batch_queue.lock().await;
+                        let moved = if promote { order.promote(&name) } else { order.demote(&name) };
+                        content = match moved {
+                            Some(was) if promote => format!("**{}** runs next (was #{} of {}).", name, was, order.len()),
+                            Some(was) => format!("**{}** moved to the end of the batch (was #{} of {}).", name, was, order.len()),
+                            None if self.in_flight.is_running(&name) => format!("**{}** is already running.", name),
+                            None => format!("**{}** is not waiting in the current batch.", name),
+                        };
                     }
                 },
+                "maintenance" => {
+                    let on = command.data.options.iter().find(|o| o.name == "state").and_then(|o| o.value.as_str()) == Some("on");
+                    let set = {
+                        let mut db = self.db.lock().await;
+                        db.set_maintenance(on)
+                    };
+                    content = match set {
+                        Err(e) => format!("Error: {}", e),
+                        Ok(()) if on => {
+                            // Every run would fail during the maintenance window; don't start the next one
+                            let stopped = {
+                                let mut state = self.queue_state.lock().await;
+                                let active = state.is_active();
+                                *state = QueueState::Stopped;
+                                active
+                            };
+                            log!("[INFO] Maintenance mode turned on by {}", command.user.name);
+                            let mut text = "🛠️ Maintenance mode **on**. Runs are refused and the scheduler skips its runs; account commands still work.".to_string();
+                            if stopped {
+                                text.push_str(" The running batch was stopped (an account already in progress finishes first).");
+                            }
+                            text
+                        },
+                        Ok(()) => {
+                            log!("[INFO] Maintenance mode turned off by {}", command.user.name);
+                            "Maintenance mode **off**. Runs are allowed again; use /force_run_all to catch up on runs skipped meanwhile.".to_string()
+                        },
+                    };
+                },
+                "catch_up_first" => {
+                    let on = command.data.options.iter().find(|o| o.name == "state").and_then(|o| o.value.as_str()) == Some("on");
+                    let mut db = self.db.lock().await;
+                    content = match db.set_catch_up_first(on) {
+                        Err(e) => format!("Error: {}", e),
+                        Ok(()) if on => "Missed accounts first **on**. New batches start with the accounts whose last completed day is oldest (never completed first); named lists keep their order.".to_string(),
+                        Ok(()) => "Missed accounts first **off**. Batches run pending accounts in the order they were added.".to_string(),
+                    };
+                },
+                "preflight_halt" => {
+                    let on = command.data.options.iter().find(|o| o.name == "state").and_then(|o| o.value.as_str()) == Some("on");
+                    let mut db = self.db.lock().await;
+                    content = match db.set_preflight_halt(on) {
+                        Err(e) => format!("Error: {}", e),
+                        Ok(()) if on => "Pre-flight halt **on**. If the shared cookie was rejected and hasn't been replaced, the nightly batch is skipped and the log channel told why; /set_cookies and /force_run_all start it once fixed.".to_string(),
+                        Ok(()) => "Pre-flight halt **off**. The nightly batch always starts; the pre-flight report is still posted.".to_string(),
+                    };
+                },
+                "run_threads" => {
+                    let on = command.data.options.iter().find(|o| o.name == "state").and_then(|o| o.value.as_str()) == Some("on");
+                    let mut db = self.db.lock().await;
+                    content = match db.set_run_threads(on) {
+                        Err(e) => format!("Error: {}", e),
+                        Ok(()) if on && db.data.settings.log_channel_id.is_none() => "Run threads **on**, but no log channel is set yet. Set one with /set_log_channel; the bot needs the Create Public Threads permission there.".to_string(),
+                        Ok(()) if on => "Run threads **on**. Each run gets a thread in the log channel with its progress, archived when the run ends with the outcome in its title.".to_string(),
+                        Ok(()) => "Run threads **off**.".to_string(),
+                    };
+                },
+                "set_notify" => {
+                    let severity = command.data.options.iter().find(|o| o.name == "severity").and_then(|o| o.value.as_str()).and_then(Severity::parse);
+                    let sinks = command.data.options.iter().find(|o| o.name == "sinks").and_then(|o| o.value.as_str()).unwrap_or("");
+                    content = match (severity, Sink::parse_list(sinks)) {
+                        (None, _) => "Unknown severity. Use success, failure or critical.".to_string(),
+                        (_, Err(e)) => e,
+                        (Some(severity), Ok(sinks)) => {
+                            let mut db = self.db.lock().await;
+                            let labels = sinks.iter().map(|s| s.label()).collect::<Vec<_>>().join(", ");
+                            let webhook_unset = sinks.contains(&Sink::Webhook) && std::env::var("ALERT_WEBHOOK_URL").map(|u| u.trim().is_empty()).unwrap_or(true);
+                            match db.set_notify_sinks(severity, sinks) {
+                                Err(e) => format!("Error: {}", e),
+                                Ok(()) if labels.is_empty() => format!("Run outcomes of severity **{}** are no longer reported anywhere.", severity.label()),
+                                Ok(()) if webhook_unset => format!("**{}** outcomes now go to: {}. Note that ALERT_WEBHOOK_URL is not set, so the webhook sink sends nothing.", severity.label(), labels),
+                                Ok(()) => format!("**{}** outcomes now go to: {}.", severity.label(), labels),
+                            }
+                        }
+                    };
+                },
+                "set_log_level" => {
+                    let level = command.data.options.iter().find(|o| o.name == "level").and_then(|o| o.value.as_str()).and_then(LogLevel::parse);
+                    content = match level {
+                        None => "Unknown level. Use errors, warnings or verbose.".to_string(),
+                        Some(level) => {
+                            let mut db = self.db.lock().await;
+                            match db.set_log_level(level) {
+                                Ok(()) => format!("The log channel now gets **{}** ({}).", level.label(), level.description()),
+                                Err(e) => format!("Error: {}", e),
+                            }
+                        }
+                    };
+                },
+                "trace" => {
+                    let state = command.data.options.iter().find(|o| o.name == "state").and_then(|o| o.value.as_str()).unwrap_or("");
+                    let account = command.data.options.iter().find(|o| o.name == "account").and_then(|o| o.value.as_str());
+                    let known = match account {
+                        Some(name) => self.db.lock().await.find_account(name).is_some(),
+                        None => true,
+                    };
+                    ephemeral = true;
+                    content = match (state, account) {
+                        ("on", Some(name)) if !known => templates::render(locale, &templates::ACCOUNT_NOT_FOUND, &[("account", name)]),
+                        ("on", Some(name)) => {
+                            protocol::trace::enable(Some(name));
+                            format!("Tracing **on** for **{}**. Its next runs record every socket.io frame (secrets masked); get the file with `/trace download`.", name)
+                        }
+                        ("on", None) => {
+                            protocol::trace::enable(None);
+                            "Tracing **on** for every run. Each run records its socket.io frames (secrets masked) until `/trace off`; get the latest with `/trace download`.".to_string()
+                        }
+                        ("off", _) => {
+                            protocol::trace::disable(account);
+                            match protocol::trace::target() {
+                                None => "Tracing **off**. Runs in progress finish their trace.".to_string(),
+                                Some(protocol::trace::TraceTarget::All) => "Tracing stays on for every run; turn it off without an account first.".to_string(),
+                                Some(protocol::trace::TraceTarget::Accounts(names)) => format!("Tracing still on for: {}.", names.into_iter().collect::<Vec<_>>().join(", ")),
+                            }
+                        }
+                        _ => match protocol::trace::latest(account) {
+                            None => "No trace recorded yet. Turn tracing on with `/trace on` and run the account.".to_string(),
+                            Some(path) => {
+                                let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                                match std::fs::metadata(&path) {
+                                    Ok(meta) if meta.len() > TRACE_ATTACH_MAX_BYTES => format!("The latest trace is too large to upload ({} MB); it is at `{}` on the host.", meta.len() / (1024 * 1024), path.display()),
+                                    _ => match CreateAttachment::path(&path).await {
+                                        Ok(file) => {
+                                            attachments.push(file);
+                                            format!("Trace `{}`.", file_name)
+                                        }
+                                        Err(e) => format!("Could not read `{}`: {}", path.display(), e),
+                                    },
+                                }
+                            }
+                        },
+                    };
+                },
                 "mute_bot" => {
-                    if !self.is_admin(&ctx, &command).await {
-                        content = "Admin permissions required.".to_string();
-                    } else {
+                    let mut db = self.db.lock().await;
+                    let _ = db.set_mute(true);
+                    content = "Bot messages muted.".to_string();
+                },
+                "unmute_bot" => {
+                    let mut db = self.db.lock().await;
+                    let _ = db.set_mute(false);
+                    content = "Bot messages unmuted.".to_string();
+                },
+                "set_log_channel" => {
+                    let channel = command.data.options.iter().find(|o| o.name == "channel").and_then(|o| o.value.as_channel_id());
+                    if let Some(chan) = channel {
                         let mut db = self.db.lock().await;
-                        let _ = db.set_mute(true);
-                        content = "Bot messages muted.".to_string();
+                        let _ = db.set_log_channel(chan);
+                        content = format!("Log channel set to <#{}>.", chan);
                     }
                 },
-                "unmute_bot" => {
-                    if !self.is_admin(&ctx, &command).await {
-                        content = "Admin permissions required.".to_string();
-                    } else {
+                "set_admin_role" => {
+                    let role = command.data.options.iter().find(|o| o.name == "role").and_then(|o| o.value.as_role_id());
+                    if let Some(r) = role {
                         let mut db = self.db.lock().await;
-                        let _ = db.set_mute(false);
-                        content = "Bot messages unmuted.".to_string();
+                        let _ = db.set_admin_role(Some(r));
+                        content = format!("Admin role set to <@&{}>.", r);
                     }
                 },
-                "set_log_channel" => {
-                    if !self.is_admin(&ctx, &command).await {
-                        content = "Admin permissions required.".to_string();
-                    } else {
-                        let channel = command.data.options.iter().find(|o| o.name == "channel").and_then(|o| o.value.as_channel_id());
-                        if let Some(chan) = channel {
+                "reset_admin_role" => {
+                    ephemeral = true;
+                    let guild = match command.guild_id {
+                        Some(guild_id) => guild_id.to_partial_guild(&ctx.http).await.map_err(|e| e.to_string()),
+                        None => Err("Use this command in the server.".to_string()),
+                    };
+                    content = match guild {
+                        Err(e) => format!("Could not load the server: {}", e),
+                        Ok(guild) => {
                             let mut db = self.db.lock().await;
-                            let _ = db.set_log_channel(chan.to_string());
-                            content = format!("Log channel set to <#{}>.", chan);
+                            let previous = db.data.settings.admin_role_id;
+                            match db.set_admin_role(None) {
+                                Err(e) => format!("Error: {}", e),
+                                Ok(()) => {
+                                    let cleared = match previous {
+                                        Some(role) => format!("Admin role cleared (was `{}`).", role),
+                                        None => "No admin role was set.".to_string(),
+                                    };
+                                    format!("{} Set a new one with /set_admin_role.\n**Effective permissions:**\n{}", cleared, effective_permissions(&guild, &db.data.settings))
+                                }
+                            }
+                        }
+                    };
+                },
+                "set_role_tier" => {
+                    let role = command.data.options.iter().find(|o| o.name == "role").and_then(|o| o.value.as_role_id());
+                    let tier = command.data.options.iter().find(|o| o.name == "tier").and_then(|o| o.value.as_str()).unwrap_or("");
+                    if let Some(r) = role {
+                        let mut db = self.db.lock().await;
+                        if tier.eq_ignore_ascii_case("none") {
+                            let _ = db.set_role_tier(r, None);
+                            content = format!("<@&{}> no longer has a bot tier.", r);
+                        } else if let Some(tier) = Tier::parse(tier).filter(|t| ASSIGNABLE_TIERS.contains(t)) {
+                            let _ = db.set_role_tier(r, Some(tier));
+                            content = format!("<@&{}> now has the **{}** tier.", r, tier.label());
+                        } else {
+                            content = format!("Unknown tier '{}'. Use one of: {}, none.", tier, ASSIGNABLE_TIERS.iter().map(|t| t.label()).collect::<Vec<_>>().join(", "));
                         }
                     }
                 },
-                "set_admin_role" => {
-                    // Check if owner
-                    let is_owner = if let Some(guild_id) = command.guild_id {
-                        if let Ok(guild) = guild_id.to_partial_guild(&ctx.http).await {
-                            command.user.id == guild.owner_id
-                        } else { false }
-                    } else { false };
-
-                    if !is_owner {
-                        content = "Only the server owner can set the admin role.".to_string();
+                "set_base_url" => {
+                    let input = command.data.options.iter().find(|o| o.name == "url").and_then(|o| o.value.as_str()).unwrap_or("").trim().to_string();
+                    if input.eq_ignore_ascii_case("default") || input.eq_ignore_ascii_case("reset") {
+                        let mut db = self.db.lock().await;
+                        let _ = db.set_base_url(None);
+                        content = format!("Websocket endpoint reset. Now using: `{}`", resolve_base_url(None));
                     } else {
-                        let role = command.data.options.iter().find(|o| o.name == "role").and_then(|o| o.value.as_role_id());
-                        if let Some(r) = role {
+                        let mut db = self.db.lock().await;
+                        content = match db.set_base_url(Some(&input)) {
+                            Ok(()) => format!("Websocket endpoint set to `{}`.", db.data.settings.base_url.as_deref().unwrap_or_default()),
+                            Err(e) => format!("Invalid endpoint: {}\nKnown profiles: {}. Default: `{}`", e, profile_names().join(", "), DEFAULT_BASE_URL),
+                        };
+                    }
+                },
+                "set_digest_day" => {
+                    let day = command.data.options.iter().find(|o| o.name == "day").and_then(|o| o.value.as_str()).unwrap_or("");
+                    let mut db = self.db.lock().await;
+                    content = match db.set_digest_day(day) {
+                        Ok(()) => match db.data.settings.digest_weekday() {
+                            Some(weekday) => format!("Weekly summary will be posted every {:?} at {} ({}).", weekday, DIGEST_TIME, db.data.settings.timezone().name()),
+                            None => "Weekly summary disabled.".to_string(),
+                        },
+                        Err(e) => e.to_string(),
+                    };
+                },
+                "set_language" => {
+                    let choice = command.data.options.iter().find(|o| o.name == "language").and_then(|o| o.value.as_str()).unwrap_or("");
+                    content = match Locale::parse(choice) {
+                        Err(e) => e,
+                        Ok(chosen) => match self.db.lock().await.set_locale(chosen) {
+                            Ok(()) => format!("Channel messages are now in **{}**; members who haven't picked a language with /set_my_language get replies in it too.", chosen.label()),
+                            Err(e) => e.to_string(),
+                        },
+                    };
+                },
+                "set_timezone" => {
+                    let name = command.data.options.iter().find(|o| o.name == "timezone").and_then(|o| o.value.as_str()).unwrap_or("");
+                    let mut db = self.db.lock().await;
+                    content = match db.set_timezone(name) {
+                        Ok(()) => {
+                            let tz = db.data.settings.timezone();
+                            format!("Timezone set to **{}** (now {}). The daily reset runs at 00:00 there.", tz.name(), Utc::now().with_timezone(&tz).format("%H:%M"))
+                        },
+                        Err(e) => e.to_string(),
+                    };
+                },
+                "set_server" => {
+                    let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).unwrap_or("").to_string();
+                    let server = command.data.options.iter().find(|o| o.name == "server").and_then(|o| o.value.as_str()).map(|s| s.to_string());
+                    let (owner, known_servers) = {
+                        let db = self.db.lock().await;
+                        (db.find_account(&name).map(|a| a.user_id.clone()), db.data.settings.known_servers.clone())
+                    };
+                    match owner {
+                        None => content = templates::render(locale, &templates::ACCOUNT_NOT_FOUND, &[("account", &name)]),
+                        Some(owner) => {
+                            if owner.as_deref() != Some(user_id.as_str()) && !self.is_admin(&ctx, &command).await {
+                                content = templates::render(locale, &templates::NOT_OWNER, &[("account", &name)]);
+                            } else if let Some(server) = server {
+                                let mut db = self.db.lock().await;
+                                content = match db.validate_server(&server) {
+                                    Ok(server) => match db.set_target_server(&name, Some(server.clone())) {
+                                        Ok(_) => format!("Target server for **{}** set to **{}**.", name, server),
+                                        Err(e) => format!("Error: {}", e),
+                                    },
+                                    Err(reason) => reason,
+                                };
+                            } else if known_servers.is_empty() {
+                                content = "No server list has been seen yet, so there is nothing to pick from. Pass the server name directly, e.g. `server: E-15`.".to_string();
+                            } else {
+                                content = format!("Choose the target server for **{}**:", name);
+                                components.push(server_select_menu(&name, &known_servers));
+                            }
+                        }
+                    }
+                },
+                "set_tags" => {
+                    let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).unwrap_or("").to_string();
+                    let tags: Vec<String> = command.data.options.iter().find(|o| o.name == "tags").and_then(|o| o.value.as_str()).unwrap_or("")
+                        .split(',')
+                        .map(|t| t.trim().to_lowercase())
+                        .filter(|t| !t.is_empty())
+                        .collect();
+                    let owner = {
+                        let db = self.db.lock().await;
+                        db.find_account(&name).map(|a| a.user_id.clone())
+                    };
+                    match owner {
+                        None => content = templates::render(locale, &templates::ACCOUNT_NOT_FOUND, &[("account", &name)]),
+                        Some(owner) => {
+                            if owner.as_deref() != Some(user_id.as_str()) && !self.is_admin(&ctx, &command).await {
+                                content = templates::render(locale, &templates::NOT_OWNER, &[("account", &name)]);
+                            } else {
+                                let mut db = self.db.lock().await;
+                                content = match db.set_tags(&name, tags.clone()) {
+                                    Ok(_) if tags.is_empty() => format!("Tags cleared for **{}**.", name),
+                                    Ok(_) => format!("Tags for **{}**: {}", name, tags.join(", ")),
+                                    Err(e) => format!("Error: {}", e),
+                                };
+                            }
+                        }
+                    }
+                },
+                "set_note" => {
+                    let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).unwrap_or("").to_string();
+                    let note = command.data.options.iter().find(|o| o.name == "note").and_then(|o| o.value.as_str()).map(|n| n.to_string());
+                    let owner = {
+                        let db = self.db.lock().await;
+                        db.find_account(&name).map(|a| a.user_id.clone())
+                    };
+                    match owner {
+                        None => content = templates::render(locale, &templates::ACCOUNT_NOT_FOUND, &[("account", &name)]),
+                        Some(owner) => {
+                            if owner.as_deref() != Some(user_id.as_str()) && !self.is_admin(&ctx, &command).await {
+                                content = templates::render(locale, &templates::NOT_OWNER, &[("account", &name)]);
+                            } else {
+                                let mut db = self.db.lock().await;
+                                content = match db.set_note(&name, note) {
+                                    Ok(_) => match db.find_account(&name).and_then(|a| a.notes.as_deref()) {
+                                        Some(note) => format!("Note for **{}**: {}", name, note),
+                                        None => format!("Note cleared for **{}**.", name),
+                                    },
+                                    Err(e) => format!("Error: {}", e),
+                                };
+                            }
+                        }
+                    }
+                },
+                "set_flow" => {
+                    let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).unwrap_or("").to_string();
+                    let flow = command.data.options.iter().find(|o| o.name == "flow").and_then(|o| o.value.as_str()).unwrap_or("");
+                    let owner = {
+                        let db = self.db.lock().await;
+                        db.find_account(&name).map(|a| a.user_id.clone())
+                    };
+                    match (owner, Flow::parse(flow)) {
+                        (None, _) => content = templates::render(locale, &templates::ACCOUNT_NOT_FOUND, &[("account", &name)]),
+                        (_, Err(e)) => content = e,
+                        (Some(owner), Ok(flow)) => {
+                            if owner.as_deref() != Some(user_id.as_str()) && !self.is_admin(&ctx, &command).await {
+                                content = templates::render(locale, &templates::NOT_OWNER, &[("account", &name)]);
+                            } else {
+                                let mut db = self.db.lock().await;
+                                content = match db.set_flow(&name, flow) {
+                                    Ok(_) => format!("**{}** will now run **{}**.", name, flow),
+                                    Err(e) => format!("Error: {}", e),
+                                };
+                            }
+                        }
+                    }
+                },
+                "add_schedule" => {
+                    let time = command.data.options.iter().find(|o| o.name == "time").and_then(|o| o.value.as_str()).unwrap_or("").trim().to_string();
+                    let target = command.data.options.iter().find(|o| o.name == "target").and_then(|o| o.value.as_str()).unwrap_or("");
+                    let flow = command.data.options.iter().find(|o| o.name == "flow").and_then(|o| o.value.as_str()).map(Flow::parse).transpose();
+                    match (AccountFilter::parse(target), flow) {
+                        (Err(e), _) | (_, Err(e)) => content = e,
+                        (Ok(filter), Ok(flow)) => {
                             let mut db = self.db.lock().await;
-                            let _ = db.set_admin_role(r.to_string());
-                            content = format!("Admin role set to <@&{}>.", r);
+                            content = match db.add_schedule(time.clone(), filter.clone(), flow) {
+                                Ok(id) => format!("Schedule **#{}** added: {} daily at {} ({}) for `{}`.", id, flow.map(|f| f.name()).unwrap_or("each account's own flow"), time, db.data.settings.timezone().name(), filter),
+                                Err(e) => format!("Error: {}", e),
+                            };
+                        }
+                    }
+                },
+                "list_schedules" => {
+                    let db = self.db.lock().await;
+                    let mut lines = vec![format!("- Daily reset: 00:00 ({}), all accounts", db.data.settings.timezone().name())];
+                    lines.extend(db.data.settings.schedules.iter()
+                        .map(|s| format!("- **#{}** {} · `{}` · {}", s.id, s.time, s.filter, s.flow_label())));
+                    content = lines.join("\n");
+                },
+                "remove_schedule" => {
+                    let id = command.data.options.iter().find(|o| o.name == "id").and_then(|o| o.value.as_i64()).unwrap_or(0);
+                    let mut db = self.db.lock().await;
+                    content = match db.remove_schedule(id.max(0) as u32) {
+                        Ok(true) => format!("Schedule **#{}** removed.", id),
+                        Ok(false) => format!("Schedule **#{}** not found.", id),
+                        Err(e) => format!("Error: {}", e),
+                    };
+                },
+                "allow_channel" => {
+                    if let Some(chan) = command.data.options.iter().find(|o| o.name == "channel").and_then(|o| o.value.as_channel_id()) {
+                        let mut db = self.db.lock().await;
+                        content = match db.allow_channel(chan) {
+                            Ok(true) => format!("Commands are now allowed in <#{}>. Other channels not on the list will be rejected.", chan),
+                            Ok(false) => format!("<#{}> is already allowed.", chan),
+                            Err(e) => format!("Error: {}", e),
+                        };
+                    }
+                },
+                "deny_channel" => {
+                    if let Some(chan) = command.data.options.iter().find(|o| o.name == "channel").and_then(|o| o.value.as_channel_id()) {
+                        let mut db = self.db.lock().await;
+                        content = match db.deny_channel(chan) {
+                            Ok(true) if db.data.settings.allowed_channels.is_empty() => format!("<#{}> removed. The allowlist is now empty, so commands are accepted in every channel.", chan),
+                            Ok(true) => format!("Commands are no longer allowed in <#{}>.", chan),
+                            Ok(false) => format!("<#{}> was not on the allowlist.", chan),
+                            Err(e) => format!("Error: {}", e),
+                        };
+                    }
+                },
+                "set_my_cookie" => {
+                    let name = command.data.options.iter().find(|o| o.name == "name").and_then(|o| o.value.as_str()).unwrap_or("").to_string();
+                    let owner = {
+                        let db = self.db.lock().await;
+                        db.find_account(&name).map(|a| a.user_id.clone())
+                    };
+                    match owner {
+                        None => content = templates::render(locale, &templates::ACCOUNT_NOT_FOUND, &[("account", &name)]),
+                        Some(owner) if owner.as_deref() != Some(user_id.as_str()) => content = templates::render(locale, &templates::OWNER_ONLY, &[("account", &name)]),
+                        Some(_) => {
+                            // The cookie is entered in a modal so it never shows up as a visible command option
+                            let input = CreateInputText::new(InputTextStyle::Paragraph, "Session cookie (empty = use the shared one)", "cookie")
+                                .required(false)
+                                .placeholder("Value of the 'session' cookie");
+                            let modal = CreateModal::new(format!("set_my_cookie:{}", name), format!("Cookie for {}", name).chars().take(45).collect::<String>())
+                                .components(vec![CreateActionRow::InputText(input)]);
+                            let _ = command.create_response(&ctx.http, CreateInteractionResponse::Modal(modal)).await;
+                            return;
                         }
                     }
                 },
                 "set_cookies" => {
-                    if !self.is_admin(&ctx, &command).await {
-                        content = "Admin permissions required.".to_string();
+                    let mut db = self.db.lock().await;
+                    if let Some(option) = command.data.options.iter().find(|o| o.name == "cookie") {
+                        if let Some(cookie) = option.value.as_str() {
+                            db.data.settings.cookies = Some(cookie.to_string());
+                            db.data.settings.cookies_set_at = Some(Utc::now());
+                            let _ = db.save();
+                            content = "Session cookies updated.".to_string();
+                        }
+                    }
+                },
+                "debug_snapshot" => {
+                    ephemeral = true;
+                    let state = *self.queue_state.lock().await;
+                    let order = self.batch_queue.lock().await.names();
+                    let pending = self.pending_runs.lock().await.requests().iter().map(snapshot::SnapshotRequest::from).collect();
+                    let (request, current_account, retries, crashed) = match self.worker_progress.lock() {
+                        Ok(p) => (p.request.as_ref().map(snapshot::SnapshotRequest::from), p.account.clone(), p.retries.clone(), p.crashed.clone()),
+                        Err(_) => (None, None, Default::default(), Vec::new()),
+                    };
+                    let db = self.db.lock().await;
+                    let taken = Utc::now();
+                    let snapshot = snapshot::QueueSnapshot {
+                        taken_at: taken.to_rfc3339(),
+                        version: env!("CARGO_PKG_VERSION").to_string(),
+                        state: format!("{:?}", state),
+                        maintenance,
+                        request,
+                        current_account,
+                        order,
+                        pending,
+                        in_flight: self.in_flight.running(),
+                        retries,
+                        crashed,
+                        accounts: db.data.accounts.clone(),
+                        catch_up_first: db.data.settings.catch_up_first(),
+                        last_completed: history::last_completed_days(&db.data.run_history, &db.data.settings.timezone())
+                            .into_iter().map(|(name, day)| (name, day.to_string())).collect(),
+                    };
+                    match serde_json::to_string_pretty(&snapshot) {
+                        Err(e) => content = format!("Snapshot failed: {}", e),
+                        Ok(json) => {
+                            let file_name = format!("queue-snapshot-{}.json", taken.format("%Y%m%d-%H%M%S"));
+                            content = format!("Queue snapshot `{}`. Replay it with `evertext_bot_rust --replay-snapshot {}`; restore codes and cookies are not included.", file_name, file_name);
+                            attachments.push(CreateAttachment::bytes(redact(&json).into_bytes(), file_name));
+                        }
+                    }
+                },
+                "audit_log" => {
+                    let count = command.data.options.iter().find(|o| o.name == "count").and_then(|o| o.value.as_i64()).unwrap_or(10).clamp(1, 25) as usize;
+                    let db = self.db.lock().await;
+                    let entries = db.recent_audit(count);
+                    content = if entries.is_empty() {
+                        "The audit log is empty.".to_string()
                     } else {
-                        let mut db = self.db.lock().await;
-                        if let Some(option) = command.data.options.iter().find(|o| o.name == "cookie") {
-                            if let Some(cookie) = option.value.as_str() {
-                                db.data.settings.cookies = Some(cookie.to_string());
-                                let _ = db.save();
-                                content = "Session cookies updated.".to_string();
+                        // Newest first; older entries that don't fit are counted instead
+                        let mut lines: Vec<String> = Vec::new();
+                        let mut length = 0;
+                        for e in &entries {
+                            let when = format!("<t:{}:f>", e.timestamp.timestamp());
+                            let args = match e.args.replace('`', "'") {
+                                a if a.is_empty() => String::new(),
+                                a if a.chars().count() > AUDIT_ARGS_LIMIT => format!(" `{}…`", a.chars().take(AUDIT_ARGS_LIMIT).collect::<String>()),
+                                a => format!(" `{}`", a),
+                            };
+                            let line = format!("- {} **{}** (<@{}>) /{}{} → {}", when, e.username, e.user_id, e.command, args, e.outcome);
+                            if length + line.len() + 1 > AUDIT_REPLY_LIMIT {
+                                break;
                             }
+                            length += line.len() + 1;
+                            lines.push(line);
                         }
-                    }
+                        let omitted = entries.len() - lines.len();
+                        if omitted > 0 {
+                            lines.push(format!("…and {} older entr{} not shown (Discord's message length limit).", omitted, if omitted == 1 { "y" } else { "ies" }));
+                        }
+                        lines.join("\n")
+                    };
                 },
                 _ => content = "Unknown command.".to_string(),
             }
 
-            let _ = command.create_response(&ctx.http, CreateInteractionResponse::Message(
-                CreateInteractionResponseMessage::new().content(content)
-            )).await;
+            if audit::is_audited(&command.data.name) {
+                let outcome: String = content.lines().next().unwrap_or("").chars().take(120).collect();
+                let mut db = self.db.lock().await;
+                let _ = db.record_audit(&command.user.id.to_string(), &command.user.name, &command.data.name, audit::describe_options(&command.data.options), outcome);
+            }
+
+            let mut response = CreateInteractionResponseMessage::new().content(redact(&content));
+            if !components.is_empty() {
+                response = response.components(components);
+            }
+            if !embeds.is_empty() {
+                response = response.embeds(embeds);
+            }
+            if !attachments.is_empty() {
+                response = response.add_files(attachments);
+            }
+            if ephemeral {
+                response = response.ephemeral(true);
+            }
+            let _ = command.create_response(&ctx.http, CreateInteractionResponse::Message(response)).await;
+        } else if let Interaction::Component(component) = interaction {
+            let locale = self.locale_for(&component.user.id.to_string(), &component.locale).await;
+            if let Some(account) = component.data.custom_id.strip_prefix("cancel_run:") {
+                let account = account.to_string();
+                let owner = {
+                    let db = self.db.lock().await;
+                    db.find_account(&account).and_then(|a| a.user_id.clone())
+                };
+                let allowed = owner.as_deref() == Some(component.user.id.to_string().as_str())
+                    || self.is_admin_member(&ctx, component.guild_id, component.user.id, component.member.as_ref()).await;
+                let reply = if !allowed {
+                    Some("Only the account owner or an admin can cancel this run.".to_string())
+                } else if !self.in_flight.cancel(&account) {
+                    Some(format!("**{}** is not running anymore.", account))
+                } else {
+                    log!("[INFO] Run of {} cancelled by {}", account, component.user.name);
+                    None
+                };
+                let response = match reply {
+                    Some(text) => CreateInteractionResponse::Message(CreateInteractionResponseMessage::new().content(text).ephemeral(true)),
+                    // The progress message itself is edited once the session has stopped
+                    None => CreateInteractionResponse::Acknowledge,
+                };
+                let _ = component.create_response(&ctx.http, response).await;
+            } else if component.data.custom_id == "setup_start" {
+                let known_servers = self.db.lock().await.data.settings.known_servers.clone();
+                let _ = component.create_response(&ctx.http, CreateInteractionResponse::Modal(setup_modal(&known_servers))).await;
+            } else if let Some(rest) = component.data.custom_id.strip_prefix("setup_ping:") {
+                let (choice, account) = rest.split_once(':').unwrap_or((rest, ""));
+                let enabled = choice == "on";
+                let user_id = component.user.id.to_string();
+                let result = {
+                    let mut db = self.db.lock().await;
+                    match db.find_account(account).map(|a| a.user_id.clone()) {
+                        None => Err(format!("Account **{}** no longer exists.", account)),
+                        Some(owner) if owner.as_deref() != Some(user_id.as_str()) => Err(templates::render(locale, &templates::OWNER_ONLY, &[("account", account)])),
+                        Some(_) => db.set_ping(account, enabled).map_err(|e| format!("Error: {}", e)),
+                    }
+                };
+                let content = match result {
+                    Err(e) => e,
+                    Ok(_) => {
+                        let mut content = format!(
+                            "✅ **{}** is set up{}. It runs every day after the daily reset; /account_info name:{} shows how it went and /set_server changes its server.",
+                            account,
+                            if enabled { " and you'll be pinged about its runs" } else { "" },
+                            account,
+                        );
+                        let start = self.process_queue(AccountFilter::Names(vec![account.to_string()]), None, false, Some(component.channel_id)).await;
+                        match queue_wait_notice(start, locale) {
+                            Some(notice) => content.push_str(&format!("\n{}", notice)),
+                            None => content.push_str("\nIts first run starts now."),
+                        }
+                        content
+                    }
+                };
+                let _ = component.create_response(&ctx.http, CreateInteractionResponse::UpdateMessage(
+                    CreateInteractionResponseMessage::new().content(content).components(vec![])
+                )).await;
+            } else if let Some(page) = component.data.custom_id.strip_prefix("run_menu_page:") {
+                let page = page.parse().unwrap_or(0);
+                let accounts = self.run_menu_accounts(&component.user.id.to_string()).await;
+                let message = if accounts.is_empty() {
+                    CreateInteractionResponseMessage::new().content("None of your accounts are waiting to run anymore.").components(vec![])
+                } else {
+                    let (prompt, rows) = run_menu(&accounts, page);
+                    CreateInteractionResponseMessage::new().content(prompt).components(rows)
+                };
+                let _ = component.create_response(&ctx.http, CreateInteractionResponse::UpdateMessage(message)).await;
+            } else if component.data.custom_id.starts_with("run_menu:") {
+                if self.db.lock().await.data.settings.is_maintenance() {
+                    let _ = component.create_response(&ctx.http, CreateInteractionResponse::UpdateMessage(
+                        CreateInteractionResponseMessage::new().content(templates::render(locale, &templates::MAINTENANCE, &[])).components(vec![])
+                    )).await;
+                    return;
+                }
+                let selected = match &component.data.kind {
+                    ComponentInteractionDataKind::StringSelect { values } => values.clone(),
+                    _ => Vec::new(),
+                };
+                // The menu may be stale; only queue what is still the caller's and not done
+                let waiting: Vec<String> = self.run_menu_accounts(&component.user.id.to_string()).await.into_iter().map(|a| a.name).collect();
+                let (names, gone): (Vec<String>, Vec<String>) = selected.into_iter().partition(|n| waiting.contains(n));
+                let content = if names.is_empty() {
+                    "None of the chosen accounts are waiting to run anymore.".to_string()
+                } else {
+                    let filter = AccountFilter::Names(names.clone());
+                    {
+                        let mut db = self.db.lock().await;
+                        let _ = db.reset_statuses_matching(&filter);
+                    }
+                    let start = self.process_queue(filter, None, false, Some(component.channel_id)).await;
+                    let mut content = format!("Queued {} account(s) in this order: {}", names.len(), names.iter().map(|n| format!("**{}**", n)).collect::<Vec<_>>().join(", "));
+                    if let Some(notice) = queue_wait_notice(start, locale) {
+                        content.push_str(&format!("\n{}", notice));
+                    }
+                    if !gone.is_empty() {
+                        content.push_str(&format!("\nSkipped, no longer waiting to run: {}", gone.join(", ")));
+                    }
+                    content
+                };
+                let _ = component.create_response(&ctx.http, CreateInteractionResponse::UpdateMessage(
+                    CreateInteractionResponseMessage::new().content(content).components(vec![])
+                )).await;
+            } else if let Some(account) = component.data.custom_id.strip_prefix("set_server:") {
+                let account = account.to_string();
+                let selected = match &component.data.kind {
+                    ComponentInteractionDataKind::StringSelect { values } => values.first().cloned(),
+                    _ => None,
+                };
+                let owner = {
+                    let db = self.db.lock().await;
+                    db.find_account(&account).map(|a| a.user_id.clone())
+                };
+                let content = match (owner, selected) {
+                    (None, _) => format!("Account **{}** no longer exists.", account),
+                    (_, None) => "No server selected.".to_string(),
+                    (Some(owner), Some(value)) => {
+                        if owner.as_deref() != Some(component.user.id.to_string().as_str())
+                            && !self.is_admin_member(&ctx, component.guild_id, component.user.id, component.member.as_ref()).await {
+                            let _ = component.create_response(&ctx.http, CreateInteractionResponse::Message(
+                                CreateInteractionResponseMessage::new().content(templates::render(locale, &templates::NOT_OWNER, &[("account", &account)])).ephemeral(true)
+                            )).await;
+                            return;
+                        }
+                        let target = if value == SERVER_AUTO_VALUE { None } else { Some(value) };
+                        let mut db = self.db.lock().await;
+                        match db.set_target_server(&account, target.clone()) {
+                            Ok(_) => format!("Target server for **{}** set to **{}**.", account, target.as_deref().unwrap_or("auto")),
+                            Err(e) => format!("Error: {}", e),
+                        }
+                    }
+                };
+                let _ = component.create_response(&ctx.http, CreateInteractionResponse::UpdateMessage(
+                    CreateInteractionResponseMessage::new().content(content).components(vec![])
+                )).await;
+            }
+        } else if let Interaction::Modal(modal) = interaction {
+            if modal.data.custom_id == "setup_account" {
+                let field = |id: &str| modal.data.components.iter()
+                    .flat_map(|row| row.components.iter())
+                    .find_map(|c| match c {
+                        ActionRowComponent::InputText(input) if input.custom_id == id => input.value.clone(),
+                        _ => None,
+                    })
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string();
+                let (name, code, server) = (field("name"), field("code"), field("server"));
+                let user_id = modal.user.id.to_string();
+                let locale = self.locale_for(&user_id, &modal.locale).await;
+
+                // Nothing is stored until every field checks out; the form is simply offered again
+                let checked = {
+                    let mut db = self.db.lock().await;
+                    let server = if server.is_empty() { Ok(None) } else { db.validate_server(&server).map(Some) };
+                    if db.is_read_only() {
+                        Err(templates::render(locale, &templates::READ_ONLY, &[]))
+                    } else if name.is_empty() {
+                        Err("The account needs a name.".to_string())
+                    } else if name.contains(':') {
+                        Err("The account name can't contain ':'.".to_string())
+                    } else if db.data.accounts.iter().any(|a| a.name == name) {
+                        Err(format!("There is already an account named **{}**. Pick another name.", name))
+                    } else if let Err(reason) = Account::validate_code(&code) {
+                        Err(format!("Invalid restore code: {}\nDouble-check the code from the game's restore screen.", reason))
+                    } else if let Some(other) = db.find_duplicate_code(&code, &name) {
+                        Err(format!("This restore code is already registered as **{}**. Running the same game account twice gets it flagged by Zigza; ask an admin if this is intended.", other.name))
+                    } else {
+                        match server {
+                            Err(reason) => Err(format!("Invalid server: {}", reason)),
+                            Ok(server) => {
+                                let account = Account {
+                                    name: name.clone(),
+                                    code: Account::encrypt_code_str(&code),
+                                    target_server: server,
+                                    user_id: Some(user_id.clone()),
+                                    username: Some(modal.user.name.clone()),
+                                    discord_nickname: None,
+                                    ping_enabled: false,
+                                    status: "pending".to_string(),
+                                    last_run: None,
+                                    deleted: false,
+                                    deleted_at: None,
+                                    tags: Vec::new(),
+                                    cookie_override: None,
+                                    flow: Flow::default(),
+                                    notes: None,
+                                    running_since: None,
+                                };
+                                db.add_account(account).map_err(|e| format!("Error: {}", e))
+                            }
+                        }
+                    }
+                };
+                let message = match checked {
+                    Ok(_) => {
+                        log!("[INFO] /setup: {} added account {}", modal.user.name, name);
+                        CreateInteractionResponseMessage::new()
+                            .content(format!("Account **{}** added.\n**Step 2 of 2:** should I ping you when its runs finish or fail?", name))
+                            .components(vec![setup_ping_buttons(&name)])
+                    }
+                    Err(reason) => CreateInteractionResponseMessage::new()
+                        .content(format!("{}\nNothing was saved yet.", reason))
+                        .components(vec![setup_start_button("Try again")]),
+                };
+                let _ = modal.create_response(&ctx.http, CreateInteractionResponse::Message(message)).await;
+            } else if let Some(account) = modal.data.custom_id.strip_prefix("set_my_cookie:") {
+                let locale = self.locale_for(&modal.user.id.to_string(), &modal.locale).await;
+                let cookie = modal.data.components.iter()
+                    .flat_map(|row| row.components.iter())
+                    .find_map(|c| match c {
+                        ActionRowComponent::InputText(input) if input.custom_id == "cookie" => input.value.clone(),
+                        _ => None,
+                    })
+                    .unwrap_or_default();
+                let cookie = cookie.trim();
+                let content = {
+                    let mut db = self.db.lock().await;
+                    let owner = db.find_account(account).map(|a| a.user_id.clone());
+                    if owner.is_none() {
+                        templates::render(locale, &templates::ACCOUNT_NOT_FOUND, &[("account", account)])
+                    } else if owner.flatten().as_deref() != Some(modal.user.id.to_string().as_str()) {
+                        templates::render(locale, &templates::OWNER_ONLY, &[("account", account)])
+                    } else {
+                        let value = if cookie.is_empty() { None } else { Some(cookie) };
+                        match db.set_cookie_override(account, value) {
+                            Ok(_) if value.is_some() => format!("**{}** will now run with your own session cookie.", account),
+                            Ok(_) => format!("**{}** will use the shared session cookie again.", account),
+                            Err(e) => format!("Error: {}", e),
+                        }
+                    }
+                };
+                let _ = modal.create_response(&ctx.http, CreateInteractionResponse::Message(
+                    CreateInteractionResponseMessage::new().content(content).ephemeral(true)
+                )).await;
+            }
         }
     }
 }
 
 #[tokio::main]
 async fn main() {
+    // Parsed first, so --help and usage errors don't depend on the configuration
+    let cli_command = cli::parse_args();
     dotenv::dotenv().ok();
     env_logger::init();
-    
+    if let Err(e) = config::init() {
+        eprintln!("[ERROR] Invalid configuration: {}", e);
+        std::process::exit(cli::EXIT_USAGE);
+    }
+    if let Err(e) = protocol::patterns::init(config::get().failure_patterns_file.as_deref()) {
+        eprintln!("[ERROR] Invalid failure patterns: {}", e);
+        std::process::exit(cli::EXIT_USAGE);
+    }
+    if config::get().templates.customized() > 0 {
+        log!("[INFO] {} message template(s) customized.", config::get().templates.customized());
+    }
+    reporting::init();
+    alerts::init();
+
+    // One-shot CLI mode (e.g. `--run all` from cron) runs without Discord.
+    if let Some(command) = cli_command {
+        let code = cli::execute(command).await;
+        reporting::flush().await;
+        alerts::flush().await;
+        std::process::exit(code);
+    }
+
     let token = std::env::var("DISCORD_TOKEN").expect("Expected a DISCORD_TOKEN in the environment");
     let database_res = Database::load();
     let database = match database_res {
         Ok(db) => Arc::new(Mutex::new(db)),
         Err(e) => {
-            println!("[CRITICAL] Failed to load database: {}. Bot may not function correctly.", e);
+            log!("[CRITICAL] Failed to load database: {}. Bot may not function correctly.", e);
             // We still need a database object to continue, so we'll try to create a dummy one if possible
             // or just exit gracefully instead of panicking.
             return; 
         }
     };
     
+    db_watcher::spawn(Arc::clone(&database));
+
+    let queue_events = QueueEvents::new();
+    let live_port = config::get().live_port;
+    if live_port != 0 {
+        match std::env::var("LIVE_STREAM_TOKEN").ok().filter(|t| !t.trim().is_empty()) {
+            Some(live_token) => live_stream::start(live_port, live_token.trim().to_string(), &queue_events).await,
+            None => log!("[WARN] dashboard.live_port is set but LIVE_STREAM_TOKEN is not; live stream disabled."),
+        }
+    }
     let handler = Handler {
         db: database,
-        is_processing: Arc::new(Mutex::new(false)),
+        queue_state: Arc::new(Mutex::new(QueueState::Stopped)),
+        pending_runs: Arc::new(Mutex::new(PendingRuns::default())),
+        batch_queue: Arc::new(Mutex::new(BatchQueue::default())),
+        in_flight: InFlightRegistry::default(),
+        dispatcher: MessageDispatcher::new(),
+        started: std::time::Instant::now(),
+        scheduler_tick: Arc::new(Mutex::new(None)),
+        session_counts: events::count_sessions(&queue_events),
+        presence: presence::track(&queue_events),
+        run_limiter: Arc::new(Mutex::new(RunLimiter::per_hour(config::get().max_runs_per_hour))),
+        server_capacity: Arc::new(std::sync::Mutex::new(ServerCapacity::default())),
+        cooldowns: Arc::new(std::sync::Mutex::new(Cooldowns::default())),
+        worker_progress: Arc::new(std::sync::Mutex::new(WorkerProgress::default())),
+        background_started: Arc::new(AtomicBool::new(false)),
+        shards: Arc::new(std::sync::Mutex::new(ShardStatus::default())),
+        queue_events,
     };
+    handler.reconcile_interrupted_runs().await;
+    alerts::watch_scheduler(Arc::clone(&handler.scheduler_tick));
 
     let intents = GatewayIntents::GUILD_MESSAGES | GatewayIntents::DIRECT_MESSAGES;
 
-    println!("[INFO] Starting EverText Rust Bot...");
+    log!("[INFO] Starting EverText Rust Bot...");
     let mut client = Client::builder(&token, intents)
         .event_handler(handler)
         .await
         .expect("Err creating client");
 
-    if let Err(why) = client.start().await {
-        println!("Client error: {:?}", why);
+    let started = match config::get().shards {
+        ShardCount::Fixed(1) => client.start().await,
+        ShardCount::Fixed(count) => {
+            log!("[INFO] Discord: Starting {} shards", count);
+            client.start_shards(count).await
+        },
+        ShardCount::Auto => {
+            log!("[INFO] Discord: Starting the recommended number of shards");
+            client.start_autosharded().await
+        },
+    };
+    if let Err(why) = started {
+        log!("Client error: {:?}", why);
     }
 }
@@ -0,0 +1,253 @@
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, UserId};
+
+use crate::alerts;
+use crate::dispatcher::MessageDispatcher;
+
+/// How an automation event turned out. Each severity has its own sinks (/set_notify).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Success,
+    Failure,
+    /// Stops the whole queue, e.g. an expired shared cookie
+    Critical,
+}
+
+impl Severity {
+    pub const ALL: [Severity; 3] = [Severity::Success, Severity::Failure, Severity::Critical];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Severity::Success => "success",
+            Severity::Failure => "failure",
+            Severity::Critical => "critical",
+        }
+    }
+
+    pub fn parse(input: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.label().eq_ignore_ascii_case(input.trim()))
+    }
+
+    /// Level of an outcome of this severity in the log channel, unless the sender says otherwise.
+    pub fn level(&self) -> Level {
+        match self {
+            Severity::Success => Level::Info,
+            Severity::Failure | Severity::Critical => Level::Error,
+        }
+    }
+}
+
+/// How much a log channel message matters, lowest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
+/// Which messages the log channel gets (/set_log_level). Everything below is dropped, e.g. the
+/// requeue warnings of a night's batch with `Errors`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Errors,
+    Warnings,
+    #[default]
+    Verbose,
+}
+
+impl LogLevel {
+    pub const ALL: [LogLevel; 3] = [LogLevel::Errors, LogLevel::Warnings, LogLevel::Verbose];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            LogLevel::Errors => "errors",
+            LogLevel::Warnings => "warnings",
+            LogLevel::Verbose => "verbose",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            LogLevel::Errors => "errors only",
+            LogLevel::Warnings => "warnings and errors",
+            LogLevel::Verbose => "everything",
+        }
+    }
+
+    pub fn parse(input: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|l| l.label().eq_ignore_ascii_case(input.trim()))
+    }
+
+    pub fn allows(&self, level: Level) -> bool {
+        let minimum = match self {
+            LogLevel::Errors => Level::Error,
+            LogLevel::Warnings => Level::Warning,
+            LogLevel::Verbose => Level::Info,
+        };
+        level >= minimum
+    }
+}
+
+/// Where notifications of a severity are sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sink {
+    /// The log channel (unless muted)
+    Channel,
+    /// The owner of the account the event is about
+    Dm,
+    /// `ALERT_WEBHOOK_URL`
+    Webhook,
+    /// Only the bot's console log
+    Log,
+}
+
+/// Sinks of a severity nobody has configured: the log channel, as before sinks existed.
+pub const DEFAULT_SINKS: &[Sink] = &[Sink::Channel];
+
+impl Sink {
+    pub const ALL: [Sink; 4] = [Sink::Channel, Sink::Dm, Sink::Webhook, Sink::Log];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Sink::Channel => "channel",
+            Sink::Dm => "dm",
+            Sink::Webhook => "webhook",
+            Sink::Log => "log",
+        }
+    }
+
+    /// Parses a comma-separated list such as "channel,dm"; "none" is the empty list.
+    pub fn parse_list(input: &str) -> Result<Vec<Sink>, String> {
+        if input.trim().eq_ignore_ascii_case("none") {
+            return Ok(Vec::new());
+        }
+        let mut sinks = Vec::new();
+        for part in input.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let sink = Self::ALL.into_iter().find(|s| s.label().eq_ignore_ascii_case(part))
+                .ok_or_else(|| format!("Unknown sink '{}'. Use any of: {}, or none.", part, Self::ALL.map(|s| s.label()).join(", ")))?;
+            if !sinks.contains(&sink) {
+                sinks.push(sink);
+            }
+        }
+        if sinks.is_empty() {
+            return Err("Name at least one sink, or none.".to_string());
+        }
+        Ok(sinks)
+    }
+}
+
+/// Something automation outcomes can be reported to. Sending never waits: every implementation
+/// queues the message (or just logs it).
+pub trait Notifier {
+    fn notify_success(&self, text: &str);
+    fn notify_failure(&self, text: &str);
+    fn notify_critical(&self, text: &str);
+
+    fn notify(&self, severity: Severity, text: &str) {
+        match severity {
+            Severity::Success => self.notify_success(text),
+            Severity::Failure => self.notify_failure(text),
+            Severity::Critical => self.notify_critical(text),
+        }
+    }
+}
+
+pub struct ChannelNotifier<'a> {
+    pub dispatcher: &'a MessageDispatcher,
+    pub channel: ChannelId,
+}
+
+impl Notifier for ChannelNotifier<'_> {
+    fn notify_success(&self, text: &str) {
+        self.dispatcher.send(self.channel, text);
+    }
+
+    fn notify_failure(&self, text: &str) {
+        self.dispatcher.send(self.channel, text);
+    }
+
+    fn notify_critical(&self, text: &str) {
+        self.dispatcher.send(self.channel, text);
+    }
+}
+
+pub struct DirectNotifier<'a> {
+    pub dispatcher: &'a MessageDispatcher,
+    pub user: UserId,
+}
+
+impl Notifier for DirectNotifier<'_> {
+    fn notify_success(&self, text: &str) {
+        self.dispatcher.send_dm(self.user, text);
+    }
+
+    fn notify_failure(&self, text: &str) {
+        self.dispatcher.send_dm(self.user, text);
+    }
+
+    fn notify_critical(&self, text: &str) {
+        self.dispatcher.send_dm(self.user, text);
+    }
+}
+
+/// Posts to `ALERT_WEBHOOK_URL`; does nothing when it isn't set.
+pub struct WebhookNotifier;
+
+impl Notifier for WebhookNotifier {
+    fn notify_success(&self, text: &str) {
+        alerts::notify(Severity::Success, text);
+    }
+
+    fn notify_failure(&self, text: &str) {
+        alerts::notify(Severity::Failure, text);
+    }
+
+    fn notify_critical(&self, text: &str) {
+        alerts::notify(Severity::Critical, text);
+    }
+}
+
+/// The console log only. Messages carry their own [SUCCESS]/[ERROR] tag; Discord markup is dropped.
+pub struct LogNotifier;
+
+impl Notifier for LogNotifier {
+    fn notify_success(&self, text: &str) {
+        log!("{}", text.replace("**", ""));
+    }
+
+    fn notify_failure(&self, text: &str) {
+        log!("{}", text.replace("**", ""));
+    }
+
+    fn notify_critical(&self, text: &str) {
+        log!("{}", text.replace("**", ""));
+    }
+}
+
+/// Several notifiers at once: every sink configured for a severity.
+#[derive(Default)]
+pub struct Fanout<'a> {
+    notifiers: Vec<Box<dyn Notifier + Send + Sync + 'a>>,
+}
+
+impl<'a> Fanout<'a> {
+    pub fn push(&mut self, notifier: impl Notifier + Send + Sync + 'a) {
+        self.notifiers.push(Box::new(notifier));
+    }
+}
+
+impl Notifier for Fanout<'_> {
+    fn notify_success(&self, text: &str) {
+        self.notifiers.iter().for_each(|n| n.notify_success(text));
+    }
+
+    fn notify_failure(&self, text: &str) {
+        self.notifiers.iter().for_each(|n| n.notify_failure(text));
+    }
+
+    fn notify_critical(&self, text: &str) {
+        self.notifiers.iter().for_each(|n| n.notify_critical(text));
+    }
+}
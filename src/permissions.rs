@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+
+/// Bot permission tiers, lowest first. Users without any tier can still add and
+/// manage their own accounts; tiers only gate shared commands.
+/// `Owner` is the Discord server owner and is never assigned to a role.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tier {
+    Viewer,
+    Operator,
+    Admin,
+    Owner,
+}
+
+/// Tiers that can be given to a role with /set_role_tier.
+pub const ASSIGNABLE_TIERS: &[Tier] = &[Tier::Viewer, Tier::Operator, Tier::Admin];
+
+impl Tier {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Tier::Viewer => "viewer",
+            Tier::Operator => "operator",
+            Tier::Admin => "admin",
+            Tier::Owner => "owner",
+        }
+    }
+
+    pub fn parse(input: &str) -> Option<Tier> {
+        let input = input.trim();
+        [Tier::Viewer, Tier::Operator, Tier::Admin, Tier::Owner].into_iter().find(|t| t.label().eq_ignore_ascii_case(input))
+    }
+}
+
+/// Minimum tier per command. Commands not listed are open to every user.
+const COMMAND_TIERS: &[(&str, Tier)] = &[
+    ("audit_log", Tier::Viewer),
+    ("settings", Tier::Viewer),
+    ("list_accounts", Tier::Viewer),
+    ("queue_status", Tier::Viewer),
+    ("run_history", Tier::Viewer),
+    ("search_transcript", Tier::Viewer),
+    ("force_run_all", Tier::Operator),
+    ("force_stop_all", Tier::Operator),
+    ("pause_queue", Tier::Operator),
+    ("resume_queue", Tier::Operator),
+    ("queue_promote", Tier::Admin),
+    ("queue_demote", Tier::Admin),
+    ("set_cookies", Tier::Admin),
+    ("set_log_channel", Tier::Admin),
+    ("set_base_url", Tier::Admin),
+    ("set_digest_day", Tier::Admin),
+    ("set_timezone", Tier::Admin),
+    ("set_language", Tier::Admin),
+    ("maintenance", Tier::Admin),
+    ("catch_up_first", Tier::Admin),
+    ("preflight_halt", Tier::Admin),
+    ("run_threads", Tier::Admin),
+    ("set_notify", Tier::Admin),
+    ("set_log_level", Tier::Admin),
+    ("debug_run", Tier::Admin),
+    ("trace", Tier::Admin),
+    ("mute_bot", Tier::Admin),
+    ("unmute_bot", Tier::Admin),
+    ("allow_channel", Tier::Admin),
+    ("deny_channel", Tier::Admin),
+    ("add_schedule", Tier::Admin),
+    ("remove_schedule", Tier::Admin),
+    ("purge_deleted", Tier::Admin),
+    ("purge_done", Tier::Admin),
+    ("backup_now", Tier::Admin),
+    ("restore_backup", Tier::Admin),
+    ("export_db", Tier::Admin),
+    ("export_settings", Tier::Admin),
+    ("import_settings", Tier::Admin),
+    ("diagnose", Tier::Admin),
+    ("debug_snapshot", Tier::Admin),
+    ("View accounts", Tier::Admin),
+    ("set_admin_role", Tier::Owner),
+    ("reset_admin_role", Tier::Owner),
+    ("set_role_tier", Tier::Owner),
+];
+
+pub fn required_tier(command: &str) -> Option<Tier> {
+    COMMAND_TIERS.iter().find(|(name, _)| *name == command).map(|(_, tier)| *tier)
+}
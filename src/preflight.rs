@@ -0,0 +1,117 @@
+use std::collections::{BTreeMap, HashSet};
+
+use chrono::{DateTime, Utc};
+
+use crate::db::{Account, Database, STATUS_INVALID_CODE};
+use crate::diagnose::{Check, Status};
+
+// A shared cookie without a successful run for this long is reported as unverified
+const COOKIE_FRESH_HOURS: i64 = 48;
+// Names listed per check before the rest are only counted
+const MAX_LISTED: usize = 10;
+
+pub const COOKIE_CHECK: &str = "Session cookie";
+
+/// Checks run before the nightly batch starts, in report order.
+pub fn run(db: &Database, now: DateTime<Utc>) -> Vec<Check> {
+    vec![
+        check_cookie(db, now),
+        check_codes(db),
+        check_disabled(db),
+        check_duplicates(db),
+    ]
+}
+
+/// The shared cookie failed its check, so every account using it would fail with LOGIN_REQUIRED.
+pub fn cookie_failed(checks: &[Check]) -> bool {
+    checks.iter().any(|c| c.name == COOKIE_CHECK && c.status == Status::Fail)
+}
+
+/// The shared cookie is set, and was accepted by a run more recently than the game last asked
+/// for a login. A cookie replaced with /set_cookies since that rejection counts as untried.
+fn check_cookie(db: &Database, now: DateTime<Utc>) -> Check {
+    let settings = &db.data.settings;
+    let shared: HashSet<&str> = db.active_accounts().filter(|a| a.cookie_override.is_none()).map(|a| a.name.as_str()).collect();
+    if shared.is_empty() {
+        return Check::new(COOKIE_CHECK, Status::Pass, "Every account uses its own cookie");
+    }
+    if settings.cookies.as_deref().unwrap_or("").trim().is_empty() {
+        return Check::new(COOKIE_CHECK, Status::Fail, format!("Not set (/set_cookies); {} account(s) need it", shared.len()));
+    }
+
+    let runs = db.data.run_history.iter().filter(|r| shared.contains(r.account.as_str()));
+    let mut last_success: Option<DateTime<Utc>> = None;
+    let mut last_rejected: Option<DateTime<Utc>> = None;
+    for run in runs {
+        if run.success {
+            last_success = last_success.max(Some(run.started_at));
+        } else if run.error.as_deref().map(|e| e.contains("LOGIN_REQUIRED")).unwrap_or(false) {
+            last_rejected = last_rejected.max(Some(run.started_at));
+        }
+    }
+
+    let replaced = settings.cookies_set_at;
+    match last_rejected {
+        Some(rejected) if Some(rejected) > last_success.max(replaced) => Check::new(COOKIE_CHECK, Status::Fail,
+            format!("The game asked for a login <t:{}:R> and the cookie hasn't worked or been replaced since. Set a fresh one with /set_cookies.", rejected.timestamp())),
+        _ => match last_success {
+            Some(success) if replaced.map(|r| r <= success).unwrap_or(true) && now - success < chrono::Duration::hours(COOKIE_FRESH_HOURS) => {
+                Check::new(COOKIE_CHECK, Status::Pass, format!("Accepted <t:{}:R>", success.timestamp()))
+            }
+            Some(success) if replaced.map(|r| r <= success).unwrap_or(true) => Check::new(COOKIE_CHECK, Status::Warn,
+                format!("Last accepted <t:{}:R>; it may have expired since", success.timestamp())),
+            _ => Check::new(COOKIE_CHECK, Status::Warn, "Not verified yet: no run has succeeded with the current cookie"),
+        },
+    }
+}
+
+/// Accounts whose restore code is missing or malformed; each would fail at the restore prompt.
+fn check_codes(db: &Database) -> Check {
+    const NAME: &str = "Restore codes";
+    let invalid: Vec<&str> = db.active_accounts()
+        .filter(|a| a.status != STATUS_INVALID_CODE && Account::validate_code(&a.decrypt_code()).is_err())
+        .map(|a| a.name.as_str())
+        .collect();
+    if invalid.is_empty() {
+        Check::new(NAME, Status::Pass, "Every account has a restore code")
+    } else {
+        Check::new(NAME, Status::Warn, format!("Empty or malformed, will be skipped: {}. Add them again with /add_account and the right code.", list(&invalid)))
+    }
+}
+
+/// Accounts the queue leaves out until someone fixes them.
+fn check_disabled(db: &Database) -> Check {
+    const NAME: &str = "Disabled accounts";
+    let disabled: Vec<&str> = db.active_accounts().filter(|a| a.status == STATUS_INVALID_CODE).map(|a| a.name.as_str()).collect();
+    if disabled.is_empty() {
+        Check::new(NAME, Status::Pass, "None")
+    } else {
+        Check::new(NAME, Status::Warn, format!("Not run until their restore code is replaced: {}", list(&disabled)))
+    }
+}
+
+/// Restore codes shared by several accounts; running the same game account twice risks a Zigza flag.
+fn check_duplicates(db: &Database) -> Check {
+    const NAME: &str = "Duplicate codes";
+    let mut by_code: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for account in db.active_accounts() {
+        let code = Account::normalize_code(&account.decrypt_code());
+        if !code.is_empty() {
+            by_code.entry(code).or_default().push(account.name.as_str());
+        }
+    }
+    let groups: Vec<String> = by_code.into_values().filter(|names| names.len() > 1).map(|names| names.join(" = ")).collect();
+    if groups.is_empty() {
+        Check::new(NAME, Status::Pass, "None")
+    } else {
+        Check::new(NAME, Status::Warn, format!("Same game account added more than once: {}. Remove the extra copies.", groups.join("; ")))
+    }
+}
+
+fn list(names: &[&str]) -> String {
+    let shown = names.iter().take(MAX_LISTED).copied().collect::<Vec<_>>().join(", ");
+    match names.len().saturating_sub(MAX_LISTED) {
+        0 => shown,
+        more => format!("{} and {} more", shown, more),
+    }
+}
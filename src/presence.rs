@@ -0,0 +1,66 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex as StdMutex};
+
+use serenity::all::{ActivityData, OnlineStatus, ShardMessenger};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::events::{QueueEvent, QueueEvents};
+
+/// The bot's activity on every shard: "Running 12/80 accounts…" while the queue works through a
+/// batch, nothing once it has drained.
+#[derive(Clone, Default)]
+pub struct Presence {
+    shards: Arc<StdMutex<BTreeMap<u32, ShardMessenger>>>,
+    activity: Arc<StdMutex<Option<String>>>,
+}
+
+impl Presence {
+    /// Adds a shard that became ready (again) and shows it the current activity, since a new
+    /// session starts without one.
+    pub fn register(&self, shard_id: u32, shard: ShardMessenger) {
+        let activity = self.activity.lock().ok().and_then(|a| a.clone());
+        shard.set_presence(activity.map(ActivityData::custom), OnlineStatus::Online);
+        if let Ok(mut shards) = self.shards.lock() {
+            shards.insert(shard_id, shard);
+        }
+    }
+
+    fn show(&self, activity: Option<String>) {
+        if let Ok(mut current) = self.activity.lock() {
+            if *current == activity {
+                return;
+            }
+            current.clone_from(&activity);
+        }
+        if let Ok(shards) = self.shards.lock() {
+            for shard in shards.values() {
+                shard.set_presence(activity.clone().map(ActivityData::custom), OnlineStatus::Online);
+            }
+        }
+    }
+}
+
+/// Subscribes a task that keeps the bot's activity in step with the queue.
+pub fn track(events: &QueueEvents) -> Presence {
+    let presence = Presence::default();
+    let mut rx = events.subscribe();
+    let shared = presence.clone();
+    tokio::spawn(async move {
+        loop {
+            let event = match rx.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    log!("[WARN] Presence missed {} queue events.", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+            match event {
+                QueueEvent::RunStarted { position, total, .. } => shared.show(Some(format!("Running {}/{} accounts…", position, total))),
+                QueueEvent::QueueDrained => shared.show(None),
+                QueueEvent::RunFinished { .. } | QueueEvent::RunFailed { .. } => {}
+            }
+        }
+    });
+    presence
+}
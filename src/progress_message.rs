@@ -0,0 +1,136 @@
+use std::time::Duration;
+
+use serenity::all::{ChannelId, CreateActionRow, UserId};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::dispatcher::{MessageDispatcher, Notification};
+use crate::protocol::progress::{ProgressEvent, ProgressSender, RunStage};
+
+/// One Discord message per account run, edited in place as the session advances
+/// (Connecting → Logging in → Server selected → Dailies → Done) instead of posting
+/// a new message for every step. The message itself is owned by the dispatcher's task,
+/// so updating it never waits on Discord.
+/// With run threads on, the run also gets a thread under the log channel that follows the
+/// session in more detail (stages, prompts, commands, errors) and is archived when it ends.
+pub struct RunProgressMessage {
+    dispatcher: MessageDispatcher,
+    run_id: String,
+    event_tx: ProgressSender,
+    message: bool,
+    thread: bool,
+}
+
+pub fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else {
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    }
+}
+
+pub fn render_progress(account: &str, run_id: &str, reached: &[RunStage], elapsed: Duration) -> String {
+    let path = reached.iter().map(|s| s.label()).collect::<Vec<_>>().join(" → ");
+    format!("[RUN] **{}** (run `{}`) · {} · {}", account, run_id, path, format_elapsed(elapsed))
+}
+
+impl RunProgressMessage {
+    /// Queues the initial "Connecting" message in `channel` and opens the run's thread under
+    /// `thread_parent`, then forwards the session's progress to both. `None` if there is neither.
+    pub fn start(dispatcher: &MessageDispatcher, channel: Option<ChannelId>, thread_parent: Option<ChannelId>, account: String, run_id: String) -> Option<Self> {
+        if channel.is_none() && thread_parent.is_none() {
+            return None;
+        }
+        Some(Self::start_with_components(dispatcher, channel, thread_parent, account, run_id, Vec::new()))
+    }
+
+    /// Like `start`, with components (e.g. a Cancel button) attached to the message until the run finishes.
+    pub fn start_with_components(dispatcher: &MessageDispatcher, channel: Option<ChannelId>, thread_parent: Option<ChannelId>, account: String, run_id: String, components: Vec<CreateActionRow>) -> Self {
+        Self::open(dispatcher, channel, thread_parent.map(|parent| (parent, None)), account, run_id, components, thread_line)
+    }
+
+    /// A run under /debug_run: the message in `channel`, plus a private thread there for `admin`
+    /// that relays every event of the session, output included. The mute setting doesn't apply.
+    pub fn start_debug(dispatcher: &MessageDispatcher, channel: ChannelId, admin: UserId, account: String, run_id: String, components: Vec<CreateActionRow>) -> Self {
+        Self::open(dispatcher, Some(channel), Some((channel, Some(admin))), account, run_id, components, debug_line)
+    }
+
+    // `thread` is the parent of the run's thread and, for a private one, who it is for
+    fn open(dispatcher: &MessageDispatcher, channel: Option<ChannelId>, thread: Option<(ChannelId, Option<UserId>)>, account: String, run_id: String, components: Vec<CreateActionRow>, line: fn(&ProgressEvent) -> Option<String>) -> Self {
+        if let Some(channel) = channel {
+            dispatcher.notify(Notification::RunStarted { run_id: run_id.clone(), channel, account: account.clone(), started: Instant::now(), components });
+        }
+        if let Some((parent, private_for)) = thread {
+            dispatcher.notify(Notification::ThreadStarted { run_id: run_id.clone(), parent, account, private_for });
+        }
+        let (message, thread) = (channel.is_some(), thread.is_some());
+
+        let (event_tx, mut event_rx) = mpsc::unbounded_channel::<ProgressEvent>();
+        let forward = dispatcher.clone();
+        let id = run_id.clone();
+        tokio::spawn(async move {
+            while let Some(event) = event_rx.recv().await {
+                // The message only shows milestones
+                if let (true, ProgressEvent::Stage(stage)) = (message, &event) {
+                    forward.notify(Notification::RunStage { run_id: id.clone(), stage: *stage });
+                }
+                if let Some(text) = thread.then(|| line(&event)).flatten() {
+                    forward.notify(Notification::ThreadLine { run_id: id.clone(), text });
+                }
+            }
+        });
+
+        Self { dispatcher: dispatcher.clone(), run_id, event_tx, message, thread }
+    }
+
+    /// Sender to pass to `run_loop`.
+    pub fn event_sender(&self) -> ProgressSender {
+        self.event_tx.clone()
+    }
+
+    /// Replaces the message with the final outcome and closes the thread with it.
+    pub fn finish(self, outcome: String) {
+        if self.thread {
+            self.dispatcher.notify(Notification::ThreadFinished { run_id: self.run_id.clone(), outcome: outcome.clone() });
+        }
+        if self.message {
+            self.dispatcher.notify(Notification::RunFinished { run_id: self.run_id, outcome });
+        }
+    }
+}
+
+/// What a run's thread shows of `event`: milestones, the prompts answered, the commands sent and
+/// output lines that report a problem. Other output stays in the transcript.
+fn thread_line(event: &ProgressEvent) -> Option<String> {
+    match event {
+        ProgressEvent::Stage(stage) => Some(format!("**{}**", stage.label())),
+        ProgressEvent::PromptDetected(prompt) => Some(format!("> {}", prompt)),
+        ProgressEvent::CommandSent(command) => Some(format!("→ `{}`", command)),
+        ProgressEvent::WaitStarted { secs } => Some(format!("Waiting {}s", secs)),
+        ProgressEvent::OutputChunk(text) => {
+            let lines: Vec<&str> = text.lines().map(str::trim)
+                .filter(|l| { let lower = l.to_lowercase(); lower.contains("error") || lower.contains("warning") || lower.contains("failed") })
+                .collect();
+            (!lines.is_empty()).then(|| format!("```\n{}\n```", lines.join("\n")))
+        }
+        ProgressEvent::StateChanged(_) => None,
+    }
+}
+
+/// What a /debug_run thread shows of `event`: everything, the output in full.
+fn debug_line(event: &ProgressEvent) -> Option<String> {
+    match event {
+        ProgressEvent::StateChanged(state) => Some(format!("State: {:?}", state)),
+        ProgressEvent::OutputChunk(text) if text.trim().is_empty() => None,
+        ProgressEvent::OutputChunk(text) => Some(format!("```\n{}\n```", text.trim_end().replace("```", "'''"))),
+        other => thread_line(other),
+    }
+}
+
+/// Finishes the progress message if this run has one.
+pub fn finish_progress(progress: Option<RunProgressMessage>, outcome: String) {
+    if let Some(progress) = progress {
+        progress.finish(outcome);
+    }
+}
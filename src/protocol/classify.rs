@@ -0,0 +1,63 @@
+use regex::Regex;
+use serde::Deserialize;
+
+/// Built-in failure patterns, checked after any from `failures.patterns_file`. Matched
+/// case-insensitively against the end of the transcript.
+const DEFAULT_PATTERNS: &[(&str, &[&str])] = &[
+    ("looks like maintenance", &[r"maintenance", r"server is (currently )?(down|offline|updating)", r"under construction", r"be back soon"]),
+    ("looks like rate limiting", &[r"rate.?limit", r"too many (requests|attempts|logins)", r"\b429\b", r"slow down", r"try again in \d+"]),
+    ("looks like the game server is unreachable", &[r"\b50[234]\b", r"bad gateway", r"service unavailable", r"failed to connect to (the )?game", r"connection (refused|reset)"]),
+    ("looks like the terminal script crashed", &[r"traceback \(most recent call last\)", r"unhandled exception", r"segmentation fault", r"panicked at"]),
+    ("looks like the game logged the account out", &[r"session (has )?expired", r"logged in (from|on) another device", r"please log ?in again"]),
+];
+
+/// One entry of the pattern library: the suggestion shown when any of its patterns matches.
+#[derive(Debug, Clone)]
+pub struct FailurePattern {
+    pub label: String,
+    pub patterns: Vec<Regex>,
+}
+
+/// File format of `failures.patterns_file`: `[{"label": "...", "patterns": ["regex", ...]}]`.
+#[derive(Deserialize)]
+struct PatternEntry {
+    label: String,
+    patterns: Vec<String>,
+}
+
+fn compile(label: &str, patterns: &[impl AsRef<str>]) -> Result<FailurePattern, String> {
+    let patterns = patterns.iter()
+        .map(|p| Regex::new(&format!("(?i){}", p.as_ref())).map_err(|e| format!("pattern '{}' of \"{}\": {}", p.as_ref(), label, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(FailurePattern { label: label.to_string(), patterns })
+}
+
+fn default_patterns() -> Vec<FailurePattern> {
+    DEFAULT_PATTERNS.iter()
+        .map(|(label, patterns)| compile(label, patterns).expect("built-in failure pattern"))
+        .collect()
+}
+
+/// The pattern library: the entries of `path` (checked first) followed by the built-in ones.
+pub fn load_patterns(path: Option<&str>) -> Result<Vec<FailurePattern>, String> {
+    let mut library = Vec::new();
+    if let Some(path) = path {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+        let entries: Vec<PatternEntry> = serde_json::from_str(&text).map_err(|e| format!("{} is not a valid pattern list: {}", path, e))?;
+        for entry in entries {
+            if entry.label.trim().is_empty() || entry.patterns.is_empty() {
+                return Err(format!("{}: every entry needs a label and at least one pattern", path));
+            }
+            library.push(compile(entry.label.trim(), &entry.patterns).map_err(|e| format!("{}: {}", path, e))?);
+        }
+    }
+    library.extend(default_patterns());
+    Ok(library)
+}
+
+/// Suggests what a failure looks like from the end of its transcript; first matching entry wins.
+pub fn classify(output: &str) -> Option<&'static str> {
+    crate::protocol::patterns::get().failures.iter()
+        .find(|entry| entry.patterns.iter().any(|p| p.is_match(output)))
+        .map(|entry| entry.label.as_str())
+}
@@ -0,0 +1,34 @@
+use std::future::Future;
+
+use crate::db::{Account, RunDetail};
+use super::flow::Flow;
+use super::progress::ProgressSender;
+use super::socket::RunOutcome;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// What the queue, the one-shot mode and /diagnose need from a game session. Implemented by the
+/// real `EvertextClient` and, in `--features sim` builds, by the network-free `SimClient` and the
+/// `sim::SessionClient` choosing between the two.
+pub trait GameClient: Sized + Send {
+    /// Opens a connection and closes it again after the handshake; a one-line description of it.
+    fn probe(cookie: &str, base_url: &str) -> impl Future<Output = Result<String, BoxError>> + Send;
+
+    fn connect(cookie: &str, base_url: &str) -> impl Future<Output = Result<Self, BoxError>> + Send;
+
+    /// Logs `account` in and plays `flow` (or stops at the server list for a dry run).
+    fn run_loop(&mut self, account: &Account, decrypted_code: &str, flow: Flow, progress: Option<ProgressSender>, dry_run: bool)
+        -> impl Future<Output = Result<RunOutcome, BoxError>> + Send;
+
+    /// The last lines of the game terminal as a code block, for failure reports.
+    fn last_output_block(&self) -> Option<String>;
+
+    /// A likely cause of the failure, recognised from the terminal output.
+    fn failure_hint(&self) -> Option<&'static str>;
+
+    /// Server names seen in the session's server list.
+    fn discovered_servers(&self) -> &[String];
+
+    /// Details of the session for the run history.
+    fn run_detail(&self) -> RunDetail;
+}
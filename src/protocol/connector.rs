@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderMap;
+use tokio_tungstenite::tungstenite::Error as WsError;
+use tokio_tungstenite::{client_async, MaybeTlsStream, WebSocketStream};
+use url::Url;
+
+use super::tls;
+use super::transport::USER_AGENT;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+pub type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// How long a resolved address is reused. The endpoint is on dynamic DNS, so keep this short;
+// a failed connect drops the entry early anyway.
+const DNS_TTL: Duration = Duration::from_secs(300);
+
+static SHARED: OnceLock<Connector> = OnceLock::new();
+
+/// Connection setup shared by every session: one TLS configuration (root store loaded once),
+/// one DNS cache, and one HTTP client whose pool keeps polling connections alive between runs.
+pub struct Connector {
+    tls: tokio_native_tls::TlsConnector,
+    http: reqwest::Client,
+    dns: DnsCache,
+}
+
+/// Where the time of a websocket connection went.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectTimings {
+    /// The resolved address that was used
+    pub address: IpAddr,
+    pub dns: Duration,
+    pub dns_cached: bool,
+    pub tcp: Duration,
+    /// `None` for plain `ws://`
+    pub tls: Option<Duration>,
+}
+
+impl ConnectTimings {
+    pub fn total(&self) -> Duration {
+        self.dns + self.tcp + self.tls.unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for ConnectTimings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ms via {} (dns {} ms{}, tcp {} ms", self.total().as_millis(), self.address, self.dns.as_millis(),
+            if self.dns_cached { " cached" } else { "" }, self.tcp.as_millis())?;
+        match self.tls {
+            Some(tls) => write!(f, ", tls {} ms)", tls.as_millis()),
+            None => write!(f, ")"),
+        }
+    }
+}
+
+/// The process-wide connector, built on first use.
+pub fn shared() -> Result<&'static Connector, BoxError> {
+    if let Some(connector) = SHARED.get() {
+        return Ok(connector);
+    }
+    let connector = Connector::new()?;
+    // Another session may have built one meanwhile; either is fine
+    let _ = SHARED.set(connector);
+    Ok(SHARED.get().expect("connector was just set"))
+}
+
+impl Connector {
+    fn new() -> Result<Self, BoxError> {
+        let dns = DnsCache::default();
+        let http = tls::http_client_builder()?
+            .user_agent(USER_AGENT)
+            .dns_resolver(Arc::new(dns.clone()))
+            .pool_idle_timeout(Duration::from_secs(90))
+            .build()?;
+        Ok(Self { tls: tokio_native_tls::TlsConnector::from(tls::native_connector()?), http, dns })
+    }
+
+    /// HTTP client for the polling transport. Cheap to clone; clones share the pool.
+    pub fn http(&self) -> &reqwest::Client {
+        &self.http
+    }
+
+    /// Opens a websocket to `url`: TCP, TLS for `wss`, then the upgrade request with `headers`.
+    /// Each resolved address is tried in `game.ip_family` order until one gets through; the pin
+    /// is checked before the upgrade request, so the session cookie never reaches a server that
+    /// fails it. If every address fails, the error lists each attempt and the phase it failed in.
+    pub async fn connect_websocket(&self, url: &Url, headers: &HeaderMap) -> Result<(WsStream, ConnectTimings), BoxError> {
+        let host = url.host_str().ok_or("URL has no host")?;
+        let port = url.port_or_known_default().ok_or("URL has no port")?;
+
+        let started = Instant::now();
+        let (ips, dns_cached) = self.dns.lookup(host).await
+            .map_err(|e| ConnectError { host: host.to_string(), port, attempts: vec![Attempt { ip: None, phase: Phase::Dns, error: e.to_string() }] })?;
+        let dns = started.elapsed();
+
+        let mut attempts = Vec::new();
+        let mut connected = self.try_addresses(url, headers, host, &ips, port, &mut attempts).await?;
+        // The dynamic DNS name may have moved; resolve again rather than wait out the TTL
+        if connected.is_none() && dns_cached {
+            self.dns.forget(host);
+            match self.dns.lookup(host).await {
+                Ok((fresh, _)) if fresh != ips => connected = self.try_addresses(url, headers, host, &fresh, port, &mut attempts).await?,
+                Ok(_) => {}
+                Err(e) => attempts.push(Attempt { ip: None, phase: Phase::Dns, error: e.to_string() }),
+            }
+        }
+        let (ws_stream, mut timings) = connected.ok_or_else(|| ConnectError { host: host.to_string(), port, attempts: std::mem::take(&mut attempts) })?;
+        if !attempts.is_empty() {
+            log!("[WARN] Reached {} at {} after {} failed attempt(s): {}", host, timings.address, attempts.len(), describe_attempts(&attempts));
+        }
+        timings.dns = dns;
+        timings.dns_cached = dns_cached;
+        Ok((ws_stream, timings))
+    }
+
+    /// Tries `ips` in order, recording each failure in `attempts`. `Ok(None)` if none worked; an
+    /// error only for failures another address would not fix (a pin mismatch, or the server
+    /// answering the upgrade with an HTTP error).
+    async fn try_addresses(&self, url: &Url, headers: &HeaderMap, host: &str, ips: &[IpAddr], port: u16, attempts: &mut Vec<Attempt>) -> Result<Option<(WsStream, ConnectTimings)>, BoxError> {
+        let timeout = crate::config::get().handshake_timeout;
+        for ip in ips {
+            let failed = |phase: Phase, error: String| Attempt { ip: Some(*ip), phase, error };
+
+            let started = Instant::now();
+            let tcp = match tokio::time::timeout(timeout, TcpStream::connect(SocketAddr::new(*ip, port))).await {
+                Ok(Ok(tcp)) => tcp,
+                Ok(Err(e)) => { attempts.push(failed(Phase::Tcp, e.to_string())); continue; }
+                Err(_) => { attempts.push(failed(Phase::Tcp, format!("no answer within {} s", timeout.as_secs()))); continue; }
+            };
+            let tcp_time = started.elapsed();
+
+            let (stream, tls_time) = if matches!(url.scheme(), "ws" | "http") {
+                (MaybeTlsStream::Plain(tcp), None)
+            } else {
+                let started = Instant::now();
+                let stream = match tokio::time::timeout(timeout, self.tls.connect(host, tcp)).await {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(e)) => { attempts.push(failed(Phase::Tls, e.to_string())); continue; }
+                    Err(_) => { attempts.push(failed(Phase::Tls, format!("no answer within {} s", timeout.as_secs()))); continue; }
+                };
+                let certificate = stream.get_ref().peer_certificate()?.map(|c| c.to_der()).transpose()?;
+                tls::verify_pin(host, certificate.as_deref())?;
+                (MaybeTlsStream::NativeTls(stream), Some(started.elapsed()))
+            };
+
+            let mut request = url.as_str().into_client_request()?;
+            request.headers_mut().extend(headers.clone());
+            match client_async(request, stream).await {
+                Ok((ws_stream, _)) => {
+                    let timings = ConnectTimings { address: *ip, dns: Duration::ZERO, dns_cached: false, tcp: tcp_time, tls: tls_time };
+                    return Ok(Some((ws_stream, timings)));
+                }
+                // The server itself turned the request down; another address reaches the same one
+                Err(WsError::Http(response)) => {
+                    attempts.push(failed(Phase::Upgrade, format!("HTTP {}", response.status())));
+                    return Err(ConnectError { host: host.to_string(), port, attempts: std::mem::take(attempts) }.into());
+                }
+                Err(e) => attempts.push(failed(Phase::Upgrade, e.to_string())),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Which IP versions the game connection uses and which it tries first. The default tries IPv4
+/// first because a stale AAAA record on the dynamic DNS name otherwise fails every connect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IpFamily {
+    #[default]
+    PreferIpv4,
+    PreferIpv6,
+    Ipv4Only,
+    Ipv6Only,
+}
+
+impl IpFamily {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.trim().to_ascii_lowercase().as_str() {
+            "prefer-ipv4" => Ok(IpFamily::PreferIpv4),
+            "prefer-ipv6" => Ok(IpFamily::PreferIpv6),
+            "ipv4" => Ok(IpFamily::Ipv4Only),
+            "ipv6" => Ok(IpFamily::Ipv6Only),
+            other => Err(format!("'{}' is not an IP family; use prefer-ipv4, prefer-ipv6, ipv4 or ipv6", other)),
+        }
+    }
+
+    /// Drops the addresses of an excluded version and moves the preferred one to the front,
+    /// keeping the resolver's order within each version.
+    fn arrange(self, ips: Vec<IpAddr>) -> Vec<IpAddr> {
+        let (v4, v6): (Vec<IpAddr>, Vec<IpAddr>) = ips.into_iter().partition(|ip| ip.is_ipv4());
+        match self {
+            IpFamily::PreferIpv4 => v4.into_iter().chain(v6).collect(),
+            IpFamily::PreferIpv6 => v6.into_iter().chain(v4).collect(),
+            IpFamily::Ipv4Only => v4,
+            IpFamily::Ipv6Only => v6,
+        }
+    }
+}
+
+/// Step of connection setup an attempt failed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Dns,
+    Tcp,
+    Tls,
+    Upgrade,
+}
+
+impl std::fmt::Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Phase::Dns => "DNS",
+            Phase::Tcp => "TCP",
+            Phase::Tls => "TLS",
+            Phase::Upgrade => "upgrade",
+        })
+    }
+}
+
+/// One failed step of a connect: the address it used (`None` for a lookup) and why.
+#[derive(Debug)]
+pub struct Attempt {
+    pub ip: Option<IpAddr>,
+    pub phase: Phase,
+    pub error: String,
+}
+
+fn describe_attempts(attempts: &[Attempt]) -> String {
+    attempts.iter()
+        .map(|a| match a.ip {
+            Some(ip) => format!("{} {} failed: {}", ip, a.phase, a.error),
+            None => format!("{} failed: {}", a.phase, a.error),
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// A websocket connect where nothing got through, with every attempt in order.
+#[derive(Debug)]
+pub struct ConnectError {
+    pub host: String,
+    pub port: u16,
+    pub attempts: Vec<Attempt>,
+}
+
+impl std::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "could not connect to {}:{} ({})", self.host, self.port, describe_attempts(&self.attempts))
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+/// Resolved addresses by host name, shared by the websocket connector and the HTTP client.
+#[derive(Clone, Default)]
+struct DnsCache {
+    entries: Arc<Mutex<HashMap<String, DnsEntry>>>,
+}
+
+/// Addresses of a host and when they were resolved.
+type DnsEntry = (Vec<IpAddr>, Instant);
+
+impl DnsCache {
+    /// Addresses of `host` and whether they came from the cache.
+    async fn lookup(&self, host: &str) -> Result<(Vec<IpAddr>, bool), BoxError> {
+        if let Ok(ip) = host.trim_matches(['[', ']']).parse::<IpAddr>() {
+            return Ok((vec![ip], false));
+        }
+        if let Some((ips, at)) = self.entries.lock().unwrap().get(host) {
+            if at.elapsed() < DNS_TTL {
+                return Ok((ips.clone(), true));
+            }
+        }
+        let ips: Vec<IpAddr> = tokio::net::lookup_host((host, 0)).await
+            .map_err(|e| format!("DNS lookup of {} failed: {}", host, e))?
+            .map(|addr| addr.ip())
+            .collect();
+        if ips.is_empty() {
+            return Err(format!("DNS lookup of {} returned no addresses", host).into());
+        }
+        let family = crate::config::get().ip_family;
+        let ips = family.arrange(ips);
+        if ips.is_empty() {
+            return Err(format!("{} has no address allowed by game.ip_family ({:?})", host, family).into());
+        }
+        self.entries.lock().unwrap().insert(host.to_string(), (ips.clone(), Instant::now()));
+        Ok((ips, false))
+    }
+
+    fn forget(&self, host: &str) {
+        self.entries.lock().unwrap().remove(host);
+    }
+}
+
+impl reqwest::dns::Resolve for DnsCache {
+    fn resolve(&self, name: hyper::client::connect::dns::Name) -> reqwest::dns::Resolving {
+        let cache = self.clone();
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let (ips, _) = cache.lookup(&host).await?;
+            let addrs: reqwest::dns::Addrs = Box::new(ips.into_iter().map(|ip| SocketAddr::new(ip, 0)));
+            Ok(addrs)
+        })
+    }
+}
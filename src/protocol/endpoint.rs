@@ -0,0 +1,76 @@
+use url::Url;
+
+pub const DEFAULT_BASE_URL: &str = "wss://evertext.sytes.net/socket.io/?EIO=4&transport=websocket";
+
+// Named endpoints that can be passed to /set_base_url or EVERTEXT_WS_URL instead of a full URL.
+const PROFILES: &[(&str, &str)] = &[
+    ("production", DEFAULT_BASE_URL),
+    ("local", "ws://127.0.0.1:5000/socket.io/?EIO=4&transport=websocket"),
+];
+
+pub fn profile_names() -> Vec<&'static str> {
+    PROFILES.iter().map(|(name, _)| *name).collect()
+}
+
+/// Turns user input (profile name, bare host, http(s)/ws(s) URL) into a full socket.io
+/// websocket URL, or explains why it can't be used.
+pub fn normalize_base_url(input: &str) -> Result<String, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("URL is empty.".to_string());
+    }
+    if let Some((_, url)) = PROFILES.iter().find(|(name, _)| name.eq_ignore_ascii_case(input)) {
+        return Ok(url.to_string());
+    }
+
+    let with_scheme = if input.contains("://") { input.to_string() } else { format!("wss://{}", input) };
+    let mut url = Url::parse(&with_scheme).map_err(|e| format!("Invalid URL '{}': {}", input, e))?;
+
+    let scheme = match url.scheme() {
+        "ws" | "http" => "ws",
+        "wss" | "https" => "wss",
+        other => return Err(format!("Unsupported scheme '{}'. Use ws://, wss://, http:// or https://.", other)),
+    };
+    url.set_scheme(scheme).map_err(|_| format!("Could not use scheme '{}' for '{}'.", scheme, input))?;
+
+    if url.host_str().map(|h| h.is_empty()).unwrap_or(true) {
+        return Err(format!("URL '{}' has no host.", input));
+    }
+
+    if url.path().is_empty() || url.path() == "/" {
+        url.set_path("/socket.io/");
+    }
+
+    // The client speaks Engine.IO v4 over a raw websocket; make sure the query says so.
+    let has_eio = url.query_pairs().any(|(k, _)| k == "EIO");
+    let has_transport = url.query_pairs().any(|(k, _)| k == "transport");
+    {
+        let mut pairs = url.query_pairs_mut();
+        if !has_eio {
+            pairs.append_pair("EIO", "4");
+        }
+        if !has_transport {
+            pairs.append_pair("transport", "websocket");
+        }
+    }
+
+    Ok(url.to_string())
+}
+
+/// Effective endpoint: the admin setting wins, then the configured URL (EVERTEXT_WS_URL or
+/// config.toml), then the default. Invalid values are logged and skipped so a typo never stops the queue.
+pub fn resolve_base_url(setting: Option<&str>) -> String {
+    let candidates = [
+        ("settings", setting.map(|s| s.to_string())),
+        ("config", crate::config::get().websocket_url.clone()),
+    ];
+    for (source, value) in candidates {
+        if let Some(value) = value.filter(|v| !v.trim().is_empty()) {
+            match normalize_base_url(&value) {
+                Ok(url) => return url,
+                Err(e) => log!("[WARN] Ignoring websocket URL from {}: {}", source, e),
+            }
+        }
+    }
+    DEFAULT_BASE_URL.to_string()
+}
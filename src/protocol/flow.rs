@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+
+use super::progress::RunStage;
+
+/// What a session does once logged in. Picked at the terminal's first prompt; the login
+/// and server selection that follow are the same for every flow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Flow {
+    #[default]
+    Dailies,
+    Arena,
+}
+
+impl Flow {
+    pub const ALL: &'static [Flow] = &[Flow::Dailies, Flow::Arena];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Flow::Dailies => "dailies",
+            Flow::Arena => "arena",
+        }
+    }
+
+    pub fn parse(input: &str) -> Result<Flow, String> {
+        let input = input.trim();
+        Self::ALL.iter().copied().find(|f| f.name().eq_ignore_ascii_case(input))
+            .ok_or_else(|| format!("Unknown flow '{}'. Known flows: {}", input, Self::ALL.iter().map(|f| f.name()).collect::<Vec<_>>().join(", ")))
+    }
+
+    /// Answer to the terminal's "Enter Command to use" prompt.
+    pub fn command(&self) -> &'static str {
+        match self {
+            Flow::Dailies => "d",
+            Flow::Arena => "ar",
+        }
+    }
+
+    /// Output that shows the flow's main procedure has begun, after login.
+    pub fn started_marker(&self) -> &'static str {
+        match self {
+            Flow::Dailies => "Performing Dailies",
+            Flow::Arena => "Performing Arena",
+        }
+    }
+
+    pub fn stage(&self) -> RunStage {
+        match self {
+            Flow::Dailies => RunStage::Dailies,
+            Flow::Arena => RunStage::Arena,
+        }
+    }
+}
+
+impl std::fmt::Display for Flow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
@@ -1 +1,22 @@
+pub mod classify;
+pub mod client;
+pub mod connector;
+pub mod endpoint;
+pub mod flow;
+pub mod patterns;
+pub mod progress;
+pub mod servers;
+#[cfg(feature = "sim")]
+pub mod sim;
 pub mod socket;
+pub mod tls;
+pub mod trace;
+pub mod transcript;
+pub mod transport;
+
+/// The client sessions run with. `--features sim` builds use `sim::SessionClient`, which
+/// simulates sessions unless told to use the game server.
+#[cfg(not(feature = "sim"))]
+pub type SessionClient = socket::EvertextClient;
+#[cfg(feature = "sim")]
+pub use sim::SessionClient;
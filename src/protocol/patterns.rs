@@ -0,0 +1,50 @@
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use crate::protocol::classify::{self, FailurePattern};
+
+// Entry of the server list: "3--> Name (Server)"
+const SERVER_ENTRY: &str = r"(\d+)-->.*?\((.*?)\)";
+// Terminal line reporting claimed rewards
+const REWARD_LINE: &str = r"(?i)\b(claimed|collected|received|obtained|rewards?|got)\b";
+
+/// Every regex the session matches terminal output with, compiled once.
+pub struct Patterns {
+    /// Captures the index and the server name of a server list entry
+    pub server_entry: Regex,
+    pub reward_line: Regex,
+    /// Suggestions attached to unrecognised failures: `failures.patterns_file`, then the built-ins
+    pub failures: Vec<FailurePattern>,
+}
+
+impl Patterns {
+    /// Compiles the built-in patterns and those of `failures_file`. Any invalid one is an error
+    /// naming it.
+    pub fn compile(failures_file: Option<&str>) -> Result<Self, String> {
+        Ok(Self {
+            server_entry: builtin("server_entry", SERVER_ENTRY)?,
+            reward_line: builtin("reward_line", REWARD_LINE)?,
+            failures: classify::load_patterns(failures_file)?,
+        })
+    }
+}
+
+fn builtin(name: &str, pattern: &str) -> Result<Regex, String> {
+    Regex::new(pattern).map_err(|e| format!("built-in pattern {}: {}", name, e))
+}
+
+static PATTERNS: OnceLock<Patterns> = OnceLock::new();
+
+/// Compiles the patterns; call once at startup, after `config::init`, so a bad pattern file stops
+/// the bot there instead of failing a run.
+pub fn init(failures_file: Option<&str>) -> Result<(), String> {
+    let patterns = Patterns::compile(failures_file)?;
+    let _ = PATTERNS.set(patterns);
+    Ok(())
+}
+
+/// The compiled patterns (only the built-in ones if `init` was never called).
+pub fn get() -> &'static Patterns {
+    PATTERNS.get_or_init(|| Patterns::compile(None).expect("built-in patterns"))
+}
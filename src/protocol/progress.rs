@@ -0,0 +1,47 @@
+use tokio::sync::mpsc::UnboundedSender;
+
+use super::socket::GameState;
+
+/// Coarse milestones of a session, emitted by the client as the terminal flow advances.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStage {
+    Connecting,
+    LoggingIn,
+    ServerSelected,
+    Dailies,
+    Arena,
+    Done,
+}
+
+impl RunStage {
+    pub fn label(&self) -> &'static str {
+        match self {
+            RunStage::Connecting => "Connecting",
+            RunStage::LoggingIn => "Logging in",
+            RunStage::ServerSelected => "Server selected",
+            RunStage::Dailies => "Dailies",
+            RunStage::Arena => "Arena",
+            RunStage::Done => "Done",
+        }
+    }
+}
+
+/// What the client reports while `run_loop` runs. Consumers pick the events they care about:
+/// the Discord progress message only follows stages, the live stream forwards everything.
+#[derive(Debug, Clone)]
+pub enum ProgressEvent {
+    /// The session reached a new milestone.
+    Stage(RunStage),
+    /// The prompt state machine moved on.
+    StateChanged(GameState),
+    /// A terminal prompt the client knows how to answer showed up.
+    PromptDetected(String),
+    /// Input sent to the terminal. Restore codes are never included.
+    CommandSent(String),
+    /// The client is deliberately waiting before it acts.
+    WaitStarted { secs: u64 },
+    /// Terminal output, ANSI-stripped.
+    OutputChunk(String),
+}
+
+pub type ProgressSender = UnboundedSender<ProgressEvent>;
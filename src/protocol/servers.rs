@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+
+/// How one server fared in a run with target server "All".
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerResult {
+    pub name: String,
+    pub completed: bool,
+}
+
+/// Formats results for Discord, e.g. "E-1 ✅, E-2 ✅, E-3 ❌ (2/3 servers)".
+pub fn describe(results: &[ServerResult]) -> Option<String> {
+    if results.is_empty() {
+        return None;
+    }
+    let done = results.iter().filter(|r| r.completed).count();
+    let list: Vec<String> = results.iter()
+        .map(|r| format!("{} {}", r.name, if r.completed { "✅" } else { "❌" }))
+        .collect();
+    Some(format!("{} ({}/{} servers)", list.join(", "), done, results.len()))
+}
+
+/// Follows an "All of them" session through its servers. The terminal runs the flow once per
+/// server, printing the flow's start marker each time, usually after a line naming the server.
+/// A server counts as completed once the next one starts or the session ends cleanly.
+#[derive(Debug, Default)]
+pub struct ServerTracker {
+    results: Vec<ServerResult>,
+    // Index into `results` of the server being played
+    current: Option<usize>,
+    // Server named in the output since the last start marker
+    announced: Option<usize>,
+}
+
+impl ServerTracker {
+    /// Starts tracking with the servers from the selection list, in list order.
+    pub fn new(servers: &[String]) -> Self {
+        Self {
+            results: servers.iter().map(|name| ServerResult { name: name.clone(), completed: false }).collect(),
+            ..Self::default()
+        }
+    }
+
+    /// Reads one chunk of (ANSI-stripped) output.
+    pub fn observe(&mut self, output: &str, started_marker: &str) {
+        for line in output.lines() {
+            if let Some(index) = self.named_server(line) {
+                if Some(index) != self.current {
+                    self.announced = Some(index);
+                }
+            }
+            if line.contains(started_marker) {
+                self.start_next();
+            }
+        }
+    }
+
+    /// The session ended cleanly, so the server in progress finished too.
+    pub fn finish(&mut self) {
+        if let Some(current) = self.current.take() {
+            self.results[current].completed = true;
+        }
+    }
+
+    pub fn results(&self) -> &[ServerResult] {
+        &self.results
+    }
+
+    fn start_next(&mut self) {
+        self.finish();
+        let next = self.announced.take()
+            .filter(|&i| !self.results[i].completed)
+            .or_else(|| self.results.iter().position(|r| !r.completed));
+        self.current = Some(match next {
+            Some(index) => index,
+            None => {
+                // More rounds than listed servers; keep counting rather than lose them
+                self.results.push(ServerResult { name: format!("Server {}", self.results.len() + 1), completed: false });
+                self.results.len() - 1
+            }
+        });
+    }
+
+    /// The listed server `line` mentions, preferring the longest name so "E-1" doesn't match "E-10".
+    fn named_server(&self, line: &str) -> Option<usize> {
+        self.results.iter().enumerate()
+            .filter(|(_, r)| mentions(line, &r.name))
+            .max_by_key(|(_, r)| r.name.len())
+            .map(|(i, _)| i)
+    }
+}
+
+/// Whether `name` appears in `line` as a whole word.
+fn mentions(line: &str, name: &str) -> bool {
+    if name.is_empty() {
+        return false;
+    }
+    line.match_indices(name).any(|(start, _)| {
+        let before = line[..start].chars().next_back();
+        let after = line[start + name.len()..].chars().next();
+        !before.map(|c| c.is_alphanumeric()).unwrap_or(false) && !after.map(|c| c.is_alphanumeric()).unwrap_or(false)
+    })
+}
@@ -0,0 +1,229 @@
+//! A stand-in for `EvertextClient` that never touches the network, built with `--features sim`.
+//! Sessions succeed after a short delay unless `EVERTEXT_SIM_FAILURES` says otherwise, so the
+//! queue's retry, backoff, requeue and crash handling can be run end to end (e.g. in CI with
+//! `--run all`) against a throwaway database.
+//!
+//! `EVERTEXT_SIM_FAILURES` is a comma-separated list of `<account>=<error>[*<times>]`:
+//! - `<account>` is an account name, `*` for every account, or `@connect` for the connection
+//!   itself (which happens before the account is known).
+//! - `<error>` is what the session fails with, e.g. `ZIGZA_DETECTED`, `SERVER_FULL`,
+//!   `LOGIN_REQUIRED`, `CONNECTION_TIMEOUT` or any other text; `PANIC` panics instead, for
+//!   the worker's crash recovery.
+//! - `*<times>` fails only that many sessions, after which they succeed; without it every
+//!   session fails.
+//!
+//! Example: `EVERTEXT_SIM_FAILURES="alt1=ZIGZA_DETECTED*2,alt2=LOGIN_REQUIRED,@connect=refused*1"`.
+//! `EVERTEXT_SIM_DELAY_MS` sets how long a session takes (default 200). `EVERTEXT_SIM=off` makes
+//! the build use the real game server instead, e.g. to compare against a simulated run.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::db::{Account, RunDetail};
+use super::client::GameClient;
+use super::flow::Flow;
+use super::progress::{ProgressEvent, ProgressSender, RunStage};
+use super::socket::{EvertextClient, RunOutcome};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+const CONNECT_KEY: &str = "@connect";
+const ANY_ACCOUNT: &str = "*";
+const DEFAULT_DELAY: Duration = Duration::from_millis(200);
+
+struct Failure {
+    error: String,
+    // `None` = every session
+    times: Option<u32>,
+}
+
+struct Plan {
+    failures: HashMap<String, Failure>,
+    delay: Duration,
+    // Sessions started so far per key, to stop failing after `times`
+    started: Mutex<HashMap<String, u32>>,
+}
+
+fn parse(spec: &str) -> Result<HashMap<String, Failure>, String> {
+    let mut failures = HashMap::new();
+    for entry in spec.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()) {
+        let (key, rule) = entry.split_once('=').ok_or_else(|| format!("'{}' is not <account>=<error>", entry))?;
+        let (error, times) = match rule.rsplit_once('*') {
+            Some((error, times)) => (error, Some(times.trim().parse::<u32>().map_err(|_| format!("'{}': '{}' is not a number", entry, times))?)),
+            None => (rule, None),
+        };
+        if error.trim().is_empty() {
+            return Err(format!("'{}' has no error", entry));
+        }
+        failures.insert(key.trim().to_string(), Failure { error: error.trim().to_string(), times });
+    }
+    Ok(failures)
+}
+
+fn plan() -> &'static Plan {
+    static PLAN: OnceLock<Plan> = OnceLock::new();
+    PLAN.get_or_init(|| {
+        let failures = match std::env::var("EVERTEXT_SIM_FAILURES") {
+            Ok(spec) => parse(&spec).unwrap_or_else(|e| {
+                log!("[WARN] Simulation: ignoring EVERTEXT_SIM_FAILURES: {}", e);
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+        let delay = std::env::var("EVERTEXT_SIM_DELAY_MS").ok()
+            .and_then(|ms| ms.trim().parse().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_DELAY);
+        log!("[WARN] Simulation build: no game server is contacted. {} failure rule(s) loaded.", failures.len());
+        Plan { failures, delay, started: Mutex::new(HashMap::new()) }
+    })
+}
+
+impl Plan {
+    /// The error the next session under `key` should end with, if any. Counts the session.
+    fn next_failure(&self, key: &str) -> Option<String> {
+        let (key, failure) = self.failures.get_key_value(key)
+            .or_else(|| (key != CONNECT_KEY).then(|| self.failures.get_key_value(ANY_ACCOUNT)).flatten())?;
+        let mut started = self.started.lock().ok()?;
+        let count = started.entry(key.clone()).or_insert(0);
+        *count += 1;
+        match failure.times {
+            Some(times) if *count > times => None,
+            _ => Some(failure.error.clone()),
+        }
+    }
+}
+
+/// Stands in for `EvertextClient` behind `GameClient`, so the queue and the one-shot mode use it unchanged.
+pub struct SimClient {
+    commands_sent: u32,
+    last_error: Option<String>,
+}
+
+impl GameClient for SimClient {
+    async fn probe(_cookie: &str, base_url: &str) -> Result<String, BoxError> {
+        plan();
+        Ok(format!("Simulated handshake (nothing sent to {})", base_url))
+    }
+
+    async fn connect(_cookie: &str, _base_url: &str) -> Result<Self, BoxError> {
+        if let Some(error) = plan().next_failure(CONNECT_KEY) {
+            return Err(error.into());
+        }
+        Ok(Self { commands_sent: 0, last_error: None })
+    }
+
+    async fn run_loop(&mut self, account: &Account, _decrypted_code: &str, flow: Flow, progress: Option<ProgressSender>, dry_run: bool) -> Result<RunOutcome, BoxError> {
+        let started = Instant::now();
+        let emit = |event| if let Some(tx) = &progress {
+            let _ = tx.send(event);
+        };
+        emit(ProgressEvent::Stage(RunStage::LoggingIn));
+        self.commands_sent += 2;
+        tokio::time::sleep(plan().delay / 2).await;
+        if !dry_run {
+            emit(ProgressEvent::Stage(flow.stage()));
+            tokio::time::sleep(plan().delay / 2).await;
+        }
+
+        if let Some(error) = plan().next_failure(&account.name) {
+            log!("[INFO] Simulation: {} fails with {}", account.name, error);
+            if error == "PANIC" {
+                panic!("simulated crash while running {}", account.name);
+            }
+            self.last_error = Some(error.clone());
+            return Err(error.into());
+        }
+        emit(ProgressEvent::Stage(RunStage::Done));
+        Ok(RunOutcome {
+            duration: started.elapsed(),
+            rewards: if dry_run { Vec::new() } else { vec!["Simulated reward".to_string()] },
+            commands_sent: self.commands_sent,
+            ..RunOutcome::default()
+        })
+    }
+
+    fn last_output_block(&self) -> Option<String> {
+        self.last_error.as_ref().map(|e| format!("```\n(simulated session) {}\n```", e))
+    }
+
+    fn failure_hint(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn discovered_servers(&self) -> &[String] {
+        &[]
+    }
+
+    fn run_detail(&self) -> RunDetail {
+        RunDetail { commands_sent: self.commands_sent, ..RunDetail::default() }
+    }
+}
+
+/// Whether sessions are simulated: always, unless `EVERTEXT_SIM=off`.
+fn simulated() -> bool {
+    static SIMULATED: OnceLock<bool> = OnceLock::new();
+    *SIMULATED.get_or_init(|| {
+        let simulated = !std::env::var("EVERTEXT_SIM").map(|v| v.trim().eq_ignore_ascii_case("off")).unwrap_or(false);
+        if !simulated {
+            log!("[WARN] Simulation build with EVERTEXT_SIM=off: sessions go to the real game server.");
+        }
+        simulated
+    })
+}
+
+/// The session client of a simulation build: `SimClient`, or the real client with `EVERTEXT_SIM=off`.
+pub enum SessionClient {
+    Live(Box<EvertextClient>),
+    Simulated(SimClient),
+}
+
+impl GameClient for SessionClient {
+    async fn probe(cookie: &str, base_url: &str) -> Result<String, BoxError> {
+        if simulated() { SimClient::probe(cookie, base_url).await } else { EvertextClient::probe(cookie, base_url).await }
+    }
+
+    async fn connect(cookie: &str, base_url: &str) -> Result<Self, BoxError> {
+        Ok(if simulated() {
+            Self::Simulated(SimClient::connect(cookie, base_url).await?)
+        } else {
+            Self::Live(Box::new(EvertextClient::connect(cookie, base_url).await?))
+        })
+    }
+
+    async fn run_loop(&mut self, account: &Account, decrypted_code: &str, flow: Flow, progress: Option<ProgressSender>, dry_run: bool) -> Result<RunOutcome, BoxError> {
+        match self {
+            Self::Live(client) => client.run_loop(account, decrypted_code, flow, progress, dry_run).await,
+            Self::Simulated(client) => client.run_loop(account, decrypted_code, flow, progress, dry_run).await,
+        }
+    }
+
+    fn last_output_block(&self) -> Option<String> {
+        match self {
+            Self::Live(client) => client.last_output_block(),
+            Self::Simulated(client) => client.last_output_block(),
+        }
+    }
+
+    fn failure_hint(&self) -> Option<&'static str> {
+        match self {
+            Self::Live(client) => client.failure_hint(),
+            Self::Simulated(client) => client.failure_hint(),
+        }
+    }
+
+    fn discovered_servers(&self) -> &[String] {
+        match self {
+            Self::Live(client) => client.discovered_servers(),
+            Self::Simulated(client) => client.discovered_servers(),
+        }
+    }
+
+    fn run_detail(&self) -> RunDetail {
+        match self {
+            Self::Live(client) => client.run_detail(),
+            Self::Simulated(client) => client.run_detail(),
+        }
+    }
+}
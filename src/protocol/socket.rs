@@ -1,28 +1,207 @@
-use futures_util::{SinkExt, StreamExt, stream::{SplitSink, SplitStream}};
 use serde_json::json;
 use std::time::{Duration, Instant};
-use tokio::net::TcpStream;
-use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
-use tokio_tungstenite::tungstenite::client::IntoClientRequest;
-use tokio_tungstenite::tungstenite::http::HeaderValue;
-use tokio_tungstenite::tungstenite::Message;
-use regex::Regex;
 
-use crate::db::Account; // Import Account struct
+use crate::db::{Account, RunDetail}; // Import Account struct
+use crate::live_stream;
+use crate::progress_message::format_elapsed;
+use crate::queue::is_transient_error;
+use crate::reporting;
+use crate::run_id;
+use super::classify;
+use super::client::GameClient;
+use super::patterns;
+use super::flow::Flow;
+use super::progress::{ProgressEvent, ProgressSender, RunStage};
+use super::servers::{self, ServerResult, ServerTracker};
+use super::tls;
+use super::transcript::{ansi_code_block, strip_ansi, Transcript, TranscriptFile};
+use super::transport::{Handshake, Transport};
 
-const BASE_URL: &str = "wss://evertext.sytes.net/socket.io/?EIO=4&transport=websocket";
+// How far back (bytes) the server-list parser looks, and how much earlier output
+// error detection considers in addition to the current chunk.
+const SERVER_LIST_WINDOW: usize = 4096;
+const ERROR_OVERLAP: usize = 256;
+// While on the polling fallback, how often a websocket upgrade is retried
+const UPGRADE_RETRY_INTERVAL: Duration = Duration::from_secs(120);
+// After joining, how long to listen for output from a session a previous run left running
+const ATTACH_WINDOW: Duration = Duration::from_secs(3);
+// Output that only appears once a dailies session is past login
+const MID_DAILIES_MARKERS: &[&str] = &[
+    "Performing Dailies",
+    "Press y to spend mana on event stages",
+    "next: Go to the next event",
+    "DO U WANT TO REFILL MANA",
+    "Enter 1, 2 or 3 to select potion to refill",
+    "number of stam100 potions to refill",
+    "Press y to do more events",
+];
+// Commands of the event phase in the order the terminal asks for them. Each is sent when its
+// prompt appears; only if the terminal goes silent are the remaining ones sent blind, as the
+// bot originally did, RAPID_FIRE_GAP apart.
+const RAPID_FIRE_SEQUENCE: &[&str] = &["y", "auto", "exit", "exit", "exit", "exit"];
+const RAPID_FIRE_GAP: Duration = Duration::from_millis(500);
+// Output attached to error reports
+const REPORT_OUTPUT_BYTES: usize = 1500;
+// Output the failure classifier looks at
+const CLASSIFY_BYTES: usize = 2048;
+// Output shown in Discord after a failed run; keeps the message under Discord's limit
+const DISCORD_OUTPUT_BYTES: usize = 1200;
+// Session endings that describe the account, not a bug; these are not sent to error reporting
+const EXPECTED_OUTCOMES: &[&str] = &["INVALID_RESTORE_CODE", "LOGIN_REQUIRED", "INVALID_COMMAND_RESTART", "AUTH_REJECTED"];
+// Words in a refusal message that point at the session's credentials
+const AUTH_MARKERS: &[&str] = &["auth", "forbidden", "login", "logged in", "token", "cookie", "credential"];
+
+/// The server refused to let the session join, via a socket.io "44" packet or a
+/// `connect_error` event. Carries the server's message.
+#[derive(Debug)]
+pub enum ConnectError {
+    /// The session's credentials were refused (e.g. an expired cookie).
+    AuthRejected(String),
+    /// The namespace could not be joined for another reason.
+    NamespaceError(String),
+}
+
+impl ConnectError {
+    /// Reads an error payload: `{"message": ...}` (socket.io v3+) or a plain string (v2).
+    fn from_payload(payload: Option<&serde_json::Value>) -> Self {
+        let message = match payload {
+            Some(serde_json::Value::String(text)) => text.clone(),
+            Some(value) => value.get("message").and_then(|m| m.as_str()).map(|m| m.to_string()).unwrap_or_else(|| value.to_string()),
+            None => "no message".to_string(),
+        };
+        let lower = message.to_lowercase();
+        if AUTH_MARKERS.iter().any(|m| lower.contains(m)) {
+            ConnectError::AuthRejected(message)
+        } else {
+            ConnectError::NamespaceError(message)
+        }
+    }
+}
+
+impl std::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectError::AuthRejected(message) => write!(f, "AUTH_REJECTED: {}", message),
+            ConnectError::NamespaceError(message) => write!(f, "NAMESPACE_ERROR: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {}
 
 #[allow(dead_code)]
 pub struct EvertextClient {
-    write: SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>,
-    read: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
+    transport: Transport,
+    // Kept for websocket upgrades of a polling session
+    cookie: String,
+    base_url: String,
     ping_interval: u64,
-    history: String,
+    transcript: Transcript,
+    // The flow's main procedure is running (rewards are collected from here on)
+    procedure_started: bool,
+    rewards: Vec<String>,
+    progress: Option<ProgressSender>,
+    // Account of the running session, for the live stream
+    account_name: String,
+    last_stage: Option<RunStage>,
+    discovered_servers: Vec<String>,
+    // Per-server progress when target server "All" was selected
+    servers: Option<ServerTracker>,
+    // Terminal inputs sent, restore code included
+    commands_sent: u32,
+    // Problems that didn't end the session, for the run record
+    warnings: Vec<String>,
+    // Server picked by defaulting to entry 1 because the target wasn't listed
+    fallback_server: Option<String>,
+    transcript_file: Option<TranscriptFile>,
+    // Event-phase commands of RAPID_FIRE_SEQUENCE sent so far
+    rapid_fire_sent: usize,
+    // When the terminal counts as stalled in the event phase; pushed back by every output chunk
+    step_deadline: Option<tokio::time::Instant>,
+    // Stop once the restore code is accepted, before the flow plays anything
+    dry_run: bool,
+}
+
+/// What a finished session reported. Returned by `run_loop` on success.
+#[derive(Debug, Default, Clone)]
+pub struct RunOutcome {
+    /// From the start of the prompt flow to the end of the session (the connect is not included)
+    pub duration: Duration,
+    /// Reward / claim lines printed after "Performing Dailies", in order, de-duplicated.
+    pub rewards: Vec<String>,
+    /// Per-server results when the account targets "All"; empty otherwise.
+    pub servers: Vec<ServerResult>,
+    /// Terminal inputs sent, restore code included
+    pub commands_sent: u32,
+    /// Problems that didn't fail the run, e.g. a target server missing from the list
+    pub warnings: Vec<String>,
+    /// Where the session's transcript was saved, if transcripts are on
+    pub transcript_path: Option<String>,
+    /// The server the session ended up on when the target server was not listed and entry 1 was
+    /// picked instead
+    pub fallback_server: Option<String>,
+}
+
+const MAX_REWARD_LINES: usize = 25;
+
+impl RunOutcome {
+    /// Short one-line description suitable for a Discord message.
+    pub fn short_summary(&self) -> String {
+        if self.rewards.is_empty() {
+            return "No rewards reported.".to_string();
+        }
+        let shown: Vec<String> = self.rewards.iter()
+            .take(3)
+            .map(|r| if r.chars().count() > 60 { format!("{}…", r.chars().take(60).collect::<String>()) } else { r.clone() })
+            .collect();
+        let more = self.rewards.len().saturating_sub(shown.len());
+        if more > 0 {
+            format!("Rewards: {} (+{} more)", shown.join("; "), more)
+        } else {
+            format!("Rewards: {}", shown.join("; "))
+        }
+    }
+
+    /// Which servers finished, for runs on "All"; `None` for single-server runs.
+    pub fn server_summary(&self) -> Option<String> {
+        servers::describe(&self.servers).map(|s| format!("Servers: {}", s))
+    }
+
+    /// Duration and command count, followed by any warnings.
+    pub fn stats_summary(&self) -> String {
+        let mut summary = format!("Took {}, {} commands sent.", format_elapsed(self.duration), self.commands_sent);
+        if !self.warnings.is_empty() {
+            summary.push_str(&format!(" ⚠️ {}", self.warnings.join("; ")));
+        }
+        summary
+    }
+
+    /// The part kept in the run history.
+    pub fn detail(&self) -> RunDetail {
+        RunDetail {
+            servers: self.servers.clone(),
+            commands_sent: self.commands_sent,
+            rewards: self.rewards.clone(),
+            warnings: self.warnings.clone(),
+            transcript_path: self.transcript_path.clone(),
+        }
+    }
 }
 
+/// Picks the lines of a terminal chunk that look like claimed rewards.
+fn extract_reward_lines(output: &str) -> Vec<String> {
+    let re = &patterns::get().reward_line;
+    output.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && re.is_match(l))
+        .map(|l| l.to_string())
+        .collect()
+}
+
+/// Where the session is in the prompt flow.
 #[allow(dead_code)]
-#[derive(Debug, PartialEq)]
-enum GameState {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameState {
     Connected,
     WaitingForCommandPrompt,
     SentD,
@@ -35,98 +214,162 @@ enum GameState {
     Finished,
 }
 
+/// Terminal text of an "output" event, ANSI-stripped; `None` for other packets.
+fn output_text(packet: &str) -> Option<String> {
+    let event: serde_json::Value = serde_json::from_str(packet.get(2..)?).ok()?;
+    if event.get(0)?.as_str()? != "output" {
+        return None;
+    }
+    Some(strip_ansi(event.get(1)?["data"].as_str()?))
+}
+
 impl EvertextClient {
-    pub async fn connect(cookie: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
-        let mut request = BASE_URL.into_client_request()?;
-        let headers = request.headers_mut();
-        let cookie_header = format!("session={}", cookie);
-        headers.insert("Cookie", HeaderValue::from_str(&cookie_header)?);
-        headers.insert("User-Agent", HeaderValue::from_static("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36"));
-
-        println!("[INFO] Connecting to EverText WebSocket...");
-        let (mut ws_stream, _) = connect_async(request).await?;
-
-        // 1. Wait for "Open" packet (Type 0) with a timeout
-        let msg = tokio::time::timeout(Duration::from_secs(10), ws_stream.next())
-            .await
-            .map_err(|_| "Connection handshake timed out")?
-            .ok_or("Stream closed")??;
-
-        let msg_str = msg.to_string();
-        
-        if msg_str.starts_with('0') {
-            let json_part = &msg_str[1..];
-            let data: serde_json::Value = serde_json::from_str(json_part)?;
-            
-            let sid = data["sid"].as_str().ok_or("No SID found")?.to_string();
-            let ping = data["pingInterval"].as_u64().unwrap_or(25000);
-            
-            println!("[INFO] Connected! Session ID: {}", sid);
-            
-            // 2. Send "40" to upgrade namespace
-            ws_stream.send(Message::Text("40".into())).await?;
-            
-            let (write, read) = ws_stream.split();
-
-            return Ok(Self {
-                write,
-                read,
-                ping_interval: ping,
-                history: String::new(),
-            });
+    async fn open_transport(cookie: &str, base_url: &str) -> Result<(Transport, Handshake), Box<dyn std::error::Error + Send + Sync>> {
+        match Transport::open_websocket(cookie, base_url).await {
+            Ok(opened) => Ok(opened),
+            // Polling would reach the same server with the same certificate
+            Err(ws_err) if ws_err.to_string().starts_with(tls::PIN_MISMATCH) => Err(ws_err),
+            Err(ws_err) => {
+                // Some networks block websocket upgrades; socket.io also speaks plain HTTP
+                log!("[WARN] WebSocket connection failed ({}). Falling back to HTTP long-polling...", ws_err);
+                Transport::open_polling(cookie, base_url).await
+                    .map_err(|poll_err| format!("Connection failed: websocket: {}; polling: {}", ws_err, poll_err).into())
+            }
+        }
+    }
+
+    /// On the polling fallback, tries to move the session onto a websocket.
+    async fn try_upgrade(&mut self) {
+        if !self.transport.is_polling() {
+            return;
+        }
+        match self.transport.try_upgrade(&self.cookie, &self.base_url).await {
+            Ok(()) => log!("[INFO] Upgraded session from HTTP long-polling to websocket."),
+            Err(e) => log!("[INFO] Staying on HTTP long-polling: {}", e),
+        }
+    }
+
+    /// Per-server results so far for a session on "All"; empty for single-server sessions.
+    pub fn server_results(&self) -> Vec<ServerResult> {
+        self.servers.as_ref().map(|t| t.results().to_vec()).unwrap_or_default()
+    }
+
+    /// Adds a raw output chunk to the transcript (and its file); returns the cleaned text.
+    fn record_output(&mut self, raw: &str) -> String {
+        let clean = self.transcript.push(raw);
+        if let Some(file) = &mut self.transcript_file {
+            file.write(&clean);
         }
+        clean
+    }
 
-        Err("Failed to handshake".into())
+    /// Hands `event` to the caller's progress sender and the live stream.
+    fn emit(&self, event: ProgressEvent) {
+        live_stream::publish_progress(run_id::current(), &self.account_name, &event);
+        if let Some(tx) = &self.progress {
+            // The receiver going away must never break the session
+            let _ = tx.send(event);
+        }
     }
 
-    pub async fn run_loop(&mut self, account: &Account, decrypted_code: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    fn report_stage(&mut self, stage: RunStage) {
+        if self.last_stage == Some(stage) {
+            return;
+        }
+        self.last_stage = Some(stage);
+        self.emit(ProgressEvent::Stage(stage));
+    }
+
+    fn change_state(&self, state: &mut GameState, next: GameState) {
+        if *state != next {
+            *state = next;
+            self.emit(ProgressEvent::StateChanged(next));
+        }
+    }
+
+    async fn run_session(&mut self, account: &Account, decrypted_code: &str, flow: Flow, state: &mut GameState) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let mut last_ping = Instant::now();
-        let mut state = GameState::Connected;
         
         // Track whether 'auto' has been sent for this session (only once allowed)
         let mut auto_sent = false;
 
-        println!("[INFO][PID:{}] Starting session for account: {}", std::process::id(), account.name);
+        log!("[INFO][PID:{}] Starting {} session for account: {}", std::process::id(), flow, account.name);
 
         let mut heartbeat_check = tokio::time::interval(Duration::from_secs(5));
+        let mut last_upgrade_attempt = Instant::now();
+        // Set between joining the namespace and deciding whether to attach or start fresh
+        let mut attach_deadline: Option<tokio::time::Instant> = None;
 
         loop {
             tokio::select! {
+                _ = tokio::time::sleep_until(attach_deadline.unwrap_or_else(tokio::time::Instant::now)), if attach_deadline.is_some() => {
+                    attach_deadline = None;
+                    self.start_fresh().await?;
+                }
+                _ = tokio::time::sleep_until(self.step_deadline.unwrap_or_else(tokio::time::Instant::now)), if self.step_deadline.is_some() => {
+                    self.step_deadline = None;
+                    self.fire_remaining().await?;
+                }
                 _ = heartbeat_check.tick() => {
                      // Check if we haven't received a ping in a while (interval + 15s grace period)
                      if last_ping.elapsed().as_millis() as u64 > (self.ping_interval + 15000) {
-                         println!("[ERROR] Connection timed out (no heartbeat from server). Last ping: {} ms ago", last_ping.elapsed().as_millis());
+                         log!("[ERROR] Connection timed out (no heartbeat from server). Last ping: {} ms ago", last_ping.elapsed().as_millis());
                          return Err("CONNECTION_TIMEOUT".into());
                      }
+                     if self.transport.is_polling() && last_upgrade_attempt.elapsed() >= UPGRADE_RETRY_INTERVAL {
+                         last_upgrade_attempt = Instant::now();
+                         self.try_upgrade().await;
+                     }
                 }
-                msg = self.read.next() => {
+                msg = self.transport.recv() => {
                     match msg {
-                        Some(Ok(m)) => {
-                            let text = m.to_string();
-                            // println!("[DEBUG] Received: {}", text); 
+                        Some(Ok(text)) => {
+                            // log!("[DEBUG] Received: {}", text); 
                             
                             if text == "2" {
-                                self.write.send(Message::Text("3".into())).await?;
+                                self.transport.send("3".to_string()).await?;
                                 last_ping = Instant::now();
+                            } else if let Some(payload) = text.strip_prefix("44") {
+                                // The namespace join (or a later reconnect) was refused
+                                let error = ConnectError::from_payload(serde_json::from_str(payload).ok().as_ref());
+                                log!("[ERROR] Server refused the session: {}", error);
+                                return Err(error.into());
                             } else if text.starts_with("40") {
-                                // Namespace join acknowledged
-                                println!("[INFO] Namespace joined. Initializing session...");
-                                
-                                // Send 'stop' first to ensure it's not already running
-                                let stop_payload = json!(["stop", {}]);
-                                self.write.send(Message::Text(format!("42{}", stop_payload.to_string()).into())).await?;
-                                
-                                tokio::time::sleep(Duration::from_millis(500)).await;
-
-                                // Send 'start'
-                                println!("[ACTION] Sending 'start' event...");
-                                let start_payload = json!(["start", {"args": ""}]);
-                                self.write.send(Message::Text(format!("42{}", start_payload.to_string()).into())).await?;
+                                // Namespace join acknowledged. A session left running by an earlier run
+                                // streams its output right away; give it a moment before restarting.
+                                log!("[INFO] Namespace joined. Checking for a session that is already running...");
+                                attach_deadline = Some(tokio::time::Instant::now() + ATTACH_WINDOW);
+                                self.emit(ProgressEvent::WaitStarted { secs: ATTACH_WINDOW.as_secs() });
                             } else if text.starts_with("42") {
-                                self.handle_event(&text, &mut state, account, decrypted_code, &mut auto_sent).await?;
+                                if attach_deadline.is_some() {
+                                    match output_text(&text) {
+                                        // Only a dailies run can pick up a dailies session; a dry run restarts it
+                                        Some(output) if flow == Flow::Dailies && !self.dry_run && MID_DAILIES_MARKERS.iter().any(|m| output.contains(m)) => {
+                                            log!("[INFO] A session is already running its dailies. Attaching instead of restarting...");
+                                            attach_deadline = None;
+                                            self.change_state(state, GameState::WaitingProcedure);
+                                            self.procedure_started = true;
+                                            self.report_stage(RunStage::Dailies);
+                                        }
+                                        // Anything else from before the restart is stale
+                                        Some(_) => continue,
+                                        None => {}
+                                    }
+                                }
+                                match self.handle_event(&text, state, account, decrypted_code, flow, &mut auto_sent).await {
+                                    // Clean end of the prompt flow
+                                    Err(e) if e.to_string() == "SESSION_COMPLETE" => {
+                                        self.report_stage(RunStage::Done);
+                                        if let Some(tracker) = &mut self.servers {
+                                            tracker.finish();
+                                        }
+                                        return Ok(());
+                                    }
+                                    other => other?,
+                                }
                             }
                         }
-                        Some(Err(e)) => return Err(e.into()),
+                        Some(Err(e)) => return Err(e),
                         None => return Err("Socket closed".into()),
                     }
                 }
@@ -134,14 +377,96 @@ impl EvertextClient {
         }
     }
 
+    /// Stops whatever the terminal is doing and starts a new session.
+    async fn start_fresh(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Send 'stop' first to ensure it's not already running
+        self.stop_terminal().await?;
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        // Send 'start'
+        log!("[ACTION] Sending 'start' event...");
+        let start_payload = json!(["start", {"args": ""}]);
+        self.transport.send(format!("42{}", start_payload)).await?;
+        Ok(())
+    }
+
+    async fn stop_terminal(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let stop_payload = json!(["stop", {}]);
+        self.transport.send(format!("42{}", stop_payload)).await
+    }
+
+    /// Index and name of each entry of the server list just printed. The servers are also
+    /// remembered for the server picker.
+    fn listed_servers(&mut self) -> Vec<(String, String)> {
+        let re = &patterns::get().server_entry;
+        let recent = self.transcript.tail(SERVER_LIST_WINDOW);
+        let servers: Vec<(String, String)> = re.captures_iter(&recent)
+            .map(|cap| (cap[1].to_string(), cap[2].trim().to_string()))
+            .collect();
+        for (_, server_name) in &servers {
+            if !server_name.contains("All of them") && !self.discovered_servers.contains(server_name) {
+                self.discovered_servers.push(server_name.clone());
+            }
+        }
+        servers
+    }
+
+    /// Ends a dry run: the terminal is stopped before the flow starts playing.
+    async fn finish_dry_run(&mut self, state: &mut GameState) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        log!("[INFO] Dry run: restore code accepted. Stopping the terminal before the flow starts.");
+        self.stop_terminal().await?;
+        self.change_state(state, GameState::Finished);
+        Err("SESSION_COMPLETE".into())
+    }
+
     async fn send_command(&mut self, cmd: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-         let payload = json!(["input", {"input": cmd}]); 
-         let packet = format!("42{}", payload.to_string());
-         self.write.send(Message::Text(packet.into())).await?;
-         Ok(())
+        self.emit(ProgressEvent::CommandSent(cmd.to_string()));
+        self.send_input(cmd).await
+    }
+
+    async fn send_input(&mut self, input: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let payload = json!(["input", {"input": input}]);
+        let packet = format!("42{}", payload);
+        self.transport.send(packet).await?;
+        self.commands_sent += 1;
+        Ok(())
+    }
+
+    /// Starts (or restarts) the event-phase step timeout after an event-phase command.
+    /// `in_sequence` commands count toward what the blind fallback still has to send.
+    fn arm_step(&mut self, state: &mut GameState, in_sequence: bool) {
+        self.change_state(state, GameState::RapidFire);
+        if in_sequence {
+            self.rapid_fire_sent += 1;
+        }
+        self.step_deadline = (self.rapid_fire_sent < RAPID_FIRE_SEQUENCE.len())
+            .then(|| tokio::time::Instant::now() + crate::config::get().prompt_step_timeout);
+    }
+
+    /// The terminal printed nothing for a whole step timeout in the event phase: sends the rest
+    /// of the sequence without waiting for prompts. Prompts that show up later are still answered.
+    async fn fire_remaining(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let remaining = &RAPID_FIRE_SEQUENCE[self.rapid_fire_sent.min(RAPID_FIRE_SEQUENCE.len())..];
+        let timeout = crate::config::get().prompt_step_timeout;
+        log!("[WARN] No output for {}s in the event phase. Sending the rest of the sequence without prompts: {}", timeout.as_secs(), remaining.join(", "));
+        self.warnings.push(format!("event prompts stalled, {} command(s) sent without waiting", remaining.len()));
+        for cmd in remaining {
+            self.send_command(cmd).await?;
+            tokio::time::sleep(RAPID_FIRE_GAP).await;
+        }
+        self.rapid_fire_sent = RAPID_FIRE_SEQUENCE.len();
+        Ok(())
     }
 
-    async fn handle_event(&mut self, text: &str, state: &mut GameState, account: &Account, code: &str, auto_sent: &mut bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    /// Answers a recognised prompt: reports it, logs the action and sends `cmd`.
+    async fn answer(&mut self, prompt: &str, cmd: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        log!("[ACTION] Prompt: '{}'. Sending '{}'...", prompt, cmd);
+        self.emit(ProgressEvent::PromptDetected(prompt.to_string()));
+        self.send_command(cmd).await
+    }
+
+    async fn handle_event(&mut self, text: &str, state: &mut GameState, account: &Account, code: &str, flow: Flow, auto_sent: &mut bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let json_part = &text[2..];
         // Parse the event. If it fails, just ignore it (sometimes random packets come in)
         let event: serde_json::Value = match serde_json::from_str(json_part) {
@@ -150,67 +475,120 @@ impl EvertextClient {
         };
         
         if let Some(event_array) = event.as_array() {
-            let event_name = event_array.get(0).and_then(|v| v.as_str()).unwrap_or("");
+            let event_name = event_array.first().and_then(|v| v.as_str()).unwrap_or("");
             let event_data = event_array.get(1);
 
             if event_name == "output" {
                  if let Some(data) = event_data {
-                     if let Some(output_text) = data["data"].as_str() {
+                     if let Some(raw_output) = data["data"].as_str() {
+                         // Record in the transcript; everything below matches on the ANSI-stripped text
+                         let output_text = self.record_output(raw_output);
+                         self.emit(ProgressEvent::OutputChunk(output_text.clone()));
+                         let output_text = output_text.as_str();
+                         let error_window = output_text.len() + ERROR_OVERLAP;
+                         // Any output means the terminal is still working on the last event-phase command
+                         if let Some(deadline) = &mut self.step_deadline {
+                             *deadline = tokio::time::Instant::now() + crate::config::get().prompt_step_timeout;
+                         }
+
                          // Print terminal output (clean up newlines for log readability)
                          let clean_log = output_text.replace("\n", " ");
                          // Log only significant chunks to avoid spam
                          if clean_log.len() > 5 {
-                             println!("[TERMINAL] {}", clean_log.chars().take(150).collect::<String>());
+                             log!("[TERMINAL] {}", clean_log.chars().take(150).collect::<String>());
+                         }
+
+                         // Collect the reward summary printed once the flow's procedure is running
+                         if self.dry_run && output_text.contains(flow.started_marker()) {
+                             // No server list came first (single-server account), so stop right away
+                             self.warnings.push(format!("no server list was shown; stopped as '{}' began", flow.started_marker()));
+                             return self.finish_dry_run(state).await;
+                         }
+                         if output_text.contains(flow.started_marker()) {
+                             self.procedure_started = true;
+                             self.report_stage(flow.stage());
+                         }
+                         if let Some(tracker) = &mut self.servers {
+                             tracker.observe(output_text, flow.started_marker());
+                         }
+                         if self.procedure_started {
+                             for line in extract_reward_lines(output_text) {
+                                 if self.rewards.len() < MAX_REWARD_LINES && !self.rewards.contains(&line) {
+                                     log!("[INFO] Reward: {}", line);
+                                     self.rewards.push(line);
+                                 }
+                             }
                          }
-                         
-                        // Update history for multi-line parsing
-                        self.history.push_str(output_text);
-                        if self.history.len() > 10000 {
-                            let mut drain_len = self.history.len() - 10000;
-                            while !self.history.is_char_boundary(drain_len) && drain_len > 0 {
-                                drain_len -= 1;
-                            }
-                            self.history.replace_range(..drain_len, "");
-                        }
 
                          // --- 1. Initial / Login Flow ---
                          if output_text.contains("Enter Command to use") {
-                             println!("[ACTION] Prompt: 'Enter Command'. Sending 'd'...");
-                             *state = GameState::SentD;
-                             self.send_command("d").await?;
+                             self.change_state(state, GameState::SentD);
+                             self.answer("Enter Command", flow.command()).await?;
                          }
                          
                          if output_text.contains("Enter Restore code") {
-                             println!("[ACTION] Prompt: 'Enter Restore code'. Sending Code...");
-                             *state = GameState::SentCode;
-                             self.send_command(code).await?;
+                             if let Err(reason) = Account::validate_code(code) {
+                                 // Don't burn the session (and risk a Zigza flag) on a code that can't be right.
+                                 log!("[ERROR] Restore code for {} is malformed: {}", account.name, reason);
+                                 return Err("INVALID_RESTORE_CODE".into());
+                             }
+                             log!("[ACTION] Prompt: 'Enter Restore code'. Sending Code...");
+                             self.emit(ProgressEvent::PromptDetected("Enter Restore code".to_string()));
+                             self.report_stage(RunStage::LoggingIn);
+                             self.change_state(state, GameState::SentCode);
+                             // The code itself stays out of progress events
+                             self.emit(ProgressEvent::CommandSent("<restore code>".to_string()));
+                             self.send_input(code.trim()).await?;
                          }
 
                          // Server Selection
                          if output_text.contains("Which acc u want to Login") {
                              if let Some(target) = &account.target_server {
-                                 println!("[ACTION] Prompt: 'Server Selection'. Parsing for '{}'...", target);
+                                 log!("[ACTION] Prompt: 'Server Selection'. Parsing for '{}'...", target);
+                                 self.emit(ProgressEvent::PromptDetected("Server Selection".to_string()));
                                  let mut selected_index = "1".to_string();
-                                 let re = Regex::new(r"(\d+)-->.*?\((.*?)\)").unwrap();
                                  let mut found = false;
-                                 
-                                 for cap in re.captures_iter(&self.history) {
-                                     let index = &cap[1];
-                                     let server_name = &cap[2];
-                                     if server_name.contains(target) || (target.to_lowercase() == "all" && server_name.contains("All of them")) {
-                                         println!("[INFO] Found target server '{}' at index {}", target, index);
-                                         selected_index = index.to_string();
+                                 let servers = self.listed_servers();
+
+                                 for (index, server_name) in &servers {
+                                     let all_of_them = target.to_lowercase() == "all" && server_name.contains("All of them");
+                                     if server_name.contains(target.as_str()) || all_of_them {
+                                         log!("[INFO] Found target server '{}' at index {}", target, index);
+                                         selected_index = index.clone();
                                          found = true;
+                                         if all_of_them && !self.dry_run {
+                                             let listed: Vec<String> = servers.iter().map(|(_, name)| name.clone()).filter(|name| !name.contains("All of them")).collect();
+                                             self.servers = Some(ServerTracker::new(&listed));
+                                         }
                                          break;
                                      }
                                  }
-                                 if !found { println!("[WARN] Target '{}' not found. Defaulting to '1'.", target); }
+                                 if !found {
+                                     let first = servers.iter().find(|(index, _)| index == "1").map(|(_, name)| name.clone()).filter(|name| !name.contains("All of them"));
+                                     log!("[WARN] Target '{}' not found. Defaulting to '1' ({}).", target, first.as_deref().unwrap_or("unnamed"));
+                                     self.warnings.push(match &first {
+                                         Some(name) => format!("target server '{}' was not listed, server 1 ({}) was used", target, name),
+                                         None => format!("target server '{}' was not listed, server 1 was used", target),
+                                     });
+                                     self.fallback_server = first;
+                                 }
                                  
-                                 println!("[ACTION] Sending server choice: {}", selected_index);
+                                 if self.dry_run {
+                                     log!("[INFO] Dry run: would send server choice {}", selected_index);
+                                     self.report_stage(RunStage::ServerSelected);
+                                     return self.finish_dry_run(state).await;
+                                 }
+                                 log!("[ACTION] Sending server choice: {}", selected_index);
                                  self.send_command(&selected_index).await?;
-                                 *state = GameState::ServerSelected;
+                                 self.change_state(state, GameState::ServerSelected);
+                                 self.report_stage(RunStage::ServerSelected);
+                             } else if self.dry_run {
+                                 self.listed_servers();
+                                 self.report_stage(RunStage::ServerSelected);
+                                 return self.finish_dry_run(state).await;
                              } else {
-                                 println!("[INFO] No targetServer specified. Assuming single server - waiting for terminal to auto-select.");
+                                 log!("[INFO] No targetServer specified. Assuming single server - waiting for terminal to auto-select.");
+                                 self.report_stage(RunStage::ServerSelected);
                                  // Do NOT send any command. Terminal handles it.
                              }
                          }
@@ -219,48 +597,45 @@ impl EvertextClient {
                          
                          // "Press y to spend mana on event stages :"
                          if output_text.contains("Press y to spend mana on event stages") {
-                             println!("[ACTION] Prompt: 'Spend mana'. Sending 'y'...");
-                             self.send_command("y").await?;
+                             self.answer("Spend mana", "y").await?;
+                             self.arm_step(state, true);
                          }
 
                          // "next: Go to the next event. [default option if nothing entered]"
                          if output_text.contains("next: Go to the next event") {
+                             // 'auto' only the first time, 'exit' after that
                              if !*auto_sent {
-                                 println!("[ACTION] Prompt: 'next event'. Sending 'auto' (First time)...");
-                                 self.send_command("auto").await?;
+                                 self.answer("next event", "auto").await?;
                                  *auto_sent = true;
                              } else {
-                                 println!("[ACTION] Prompt: 'next event'. Sending 'exit' (Already sent auto)...");
-                                 self.send_command("exit").await?;
+                                 self.answer("next event", "exit").await?;
                              }
+                             self.arm_step(state, true);
                          }
 
                          // --- 3. Mana Refill Logic (Situational) ---
                          // "DO U WANT TO REFILL MANA ? (press y to refill):"
                          // "DO U WANT TO REFILL MANA ? (press y to refill):"
                          if output_text.contains("DO U WANT TO REFILL MANA") {
-                             println!("[ACTION] Prompt: 'Refill Mana'. Sending 'y'...");
-                             self.send_command("y").await?;
+                             self.answer("Refill Mana", "y").await?;
                          }
 
                          // "Enter 1, 2 or 3 to select potion to refill:"
                          if output_text.contains("Enter 1, 2 or 3 to select potion to refill") {
-                             println!("[ACTION] Prompt: 'Select potion'. Sending '3'...");
-                             self.send_command("3").await?;
+                             self.answer("Select potion", "3").await?;
                          }
 
                          // "Enter the number of stam100 potions to refill"
                          if output_text.contains("number of stam100 potions to refill") {
-                             println!("[ACTION] Prompt: 'Potion quantity'. Sending '1'...");
-                             self.send_command("1").await?;
+                             self.answer("Potion quantity", "1").await?;
                          }
 
                          // --- 4. More Events Prompt ---
                          // "Press y to do more events:"
                          // User logic: "we will write 'y' and now the terminal will ask for 'next: ...' now we will write 'exit'"
                          if output_text.contains("Press y to do more events") {
-                             println!("[ACTION] Prompt: 'Do more events?'. Sending 'y' (waiting for 'next' prompt to exit)...");
-                             self.send_command("y").await?;
+                             self.answer("Do more events?", "y").await?;
+                             self.arm_step(state, false);
                              // We do NOT send 'exit' here. We wait for the "next: Go to the next event" prompt to appear again.
                              // Since 'auto_sent' is already true, the 'next' block above will handle sending 'exit'.
                          }
@@ -268,47 +643,168 @@ impl EvertextClient {
                          // --- 5. End of Loop ---
                          // "Press y to perform more commands:"
                          if output_text.contains("Press y to perform more commands") {
-                             println!("[INFO] Prompt: 'Perform more commands'. Run Complete.");
+                             log!("[INFO] Prompt: 'Perform more commands'. Run Complete.");
+                             self.change_state(state, GameState::Finished);
                              return Err("SESSION_COMPLETE".into()); // Trigger clean exit
                          }
 
                          // --- 6. Error Handling ---
                          
                          // "Invalid Command ... Exiting Now"
-                         if output_text.contains("Invalid Command") && output_text.contains("Exiting Now") {
-                             println!("[ERROR] Invalid Command Detected. Triggering Restart...");
+                         if self.transcript.contains_recent("Invalid Command", error_window) && self.transcript.contains_recent("Exiting Now", error_window) {
+                             log!("[ERROR] Invalid Command Detected. Triggering Restart...");
                              return Err("INVALID_COMMAND_RESTART".into());
                          }
 
-                         if output_text.contains("Either Zigza error or Incorrect Restore Code Entered") {
-                             println!("[ERROR] Zigza Error Detected!");
+                         if self.transcript.contains_recent("Either Zigza error or Incorrect Restore Code Entered", error_window) {
+                             log!("[ERROR] Zigza Error Detected!");
                              return Err("ZIGZA_DETECTED".into());
                          }
 
-                         if output_text.contains("Server reached maximum limit of restore accounts") {
-                             println!("[ERROR] Server Full Detected!");
+                         if self.transcript.contains_recent("Server reached maximum limit of restore accounts", error_window) {
+                             log!("[ERROR] Server Full Detected!");
                              return Err("SERVER_FULL".into());
                          }
 
-                         if output_text.contains("Access to start bot is restricted only for logged in users") {
-                             println!("[ERROR] Login Required / Cookie Expired!");
+                         if self.transcript.contains_recent("Access to start bot is restricted only for logged in users", error_window) {
+                             log!("[ERROR] Login Required / Cookie Expired!");
                              return Err("LOGIN_REQUIRED".into());
                          }
                      }
                  }
+            } else if event_name == "connect_error" {
+                let error = ConnectError::from_payload(event_data);
+                log!("[ERROR] Server sent 'connect_error': {}", error);
+                return Err(error.into());
             } else if event_name == "idle_timeout" {
-                println!("[ERROR] Server sent 'idle_timeout'. Disconnecting...");
+                log!("[ERROR] Server sent 'idle_timeout'. Disconnecting...");
                 return Err("IDLE_TIMEOUT".into());
             } else if event_name == "connection_failed" {
-                println!("[ERROR] Server sent 'connection_failed'. Disconnecting...");
+                log!("[ERROR] Server sent 'connection_failed'. Disconnecting...");
                 return Err("CONNECTION_FAILED".into());
             } else if event_name == "disconnect" {
-                println!("[ERROR] Server sent 'disconnect' event.");
+                log!("[ERROR] Server sent 'disconnect' event.");
                 return Err("SERVER_DISCONNECT".into());
             } else {
-                println!("[DEBUG] Unhandled Socket.io event: {} -> {:?}", event_name, event_data);
+                log!("[DEBUG] Unhandled Socket.io event: {} -> {:?}", event_name, event_data);
             }
         }
         Ok(())
     }
 }
+
+impl GameClient for EvertextClient {
+    /// Completes the Engine.IO handshake and disconnects without joining the namespace,
+    /// so no game session is started. Describes the connection on success.
+    async fn probe(cookie: &str, base_url: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+        let (transport, handshake) = Self::open_transport(cookie, base_url).await?;
+        Ok(format!("Handshake OK over {} (ping interval {} ms)", transport.name(), handshake.ping_interval))
+    }
+
+    async fn connect(cookie: &str, base_url: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        log!("[INFO] Connecting to EverText WebSocket at {}...", base_url);
+        let (mut transport, handshake) = Self::open_transport(cookie, base_url).await?;
+
+        log!("[INFO] Connected over {}! Session ID: {}", transport.name(), handshake.sid);
+
+        // Send "40" to join the default namespace
+        transport.send("40".to_string()).await?;
+
+        let mut client = Self {
+            transport,
+            cookie: cookie.to_string(),
+            base_url: base_url.to_string(),
+            ping_interval: handshake.ping_interval,
+            transcript: Transcript::default(),
+            procedure_started: false,
+            rewards: Vec::new(),
+            progress: None,
+            account_name: String::new(),
+            last_stage: None,
+            discovered_servers: Vec::new(),
+            servers: None,
+            commands_sent: 0,
+            warnings: Vec::new(),
+            fallback_server: None,
+            transcript_file: None,
+            rapid_fire_sent: 0,
+            step_deadline: None,
+            dry_run: false,
+        };
+        if let Some(id) = run_id::current() {
+            client.transcript.push(&format!("--- run {} ---\n", id));
+        }
+        client.try_upgrade().await;
+        if client.transport.is_polling() {
+            client.warnings.push("ran over HTTP long-polling, the websocket was unavailable".to_string());
+        }
+        Ok(client)
+    }
+
+    /// Runs `flow` for `account` until the terminal asks for more commands. Progress is sent
+    /// to `progress`, if given, as the session advances. A `dry_run` logs in and stops at the
+    /// server list, so the restore code is checked without playing the flow.
+    async fn run_loop(&mut self, account: &Account, decrypted_code: &str, flow: Flow, progress: Option<ProgressSender>, dry_run: bool) -> Result<RunOutcome, Box<dyn std::error::Error + Send + Sync>> {
+        self.progress = progress;
+        self.dry_run = dry_run;
+        self.account_name = account.name.clone();
+        self.transcript_file = TranscriptFile::create(&account.name, run_id::current().as_deref());
+        if let Some(file) = &mut self.transcript_file {
+            file.write(&self.transcript.text());
+        }
+        let started = Instant::now();
+        let mut state = GameState::Connected;
+        let result = reporting::with_account(&account.name, self.run_session(account, decrypted_code, flow, &mut state)).await;
+        if let Err(e) = &result {
+            let reason = e.to_string();
+            if !is_transient_error(&reason) && !EXPECTED_OUTCOMES.iter().any(|o| reason.starts_with(o)) {
+                reporting::report_error("protocol", &format!("Session failed: {}", reason), &[
+                    ("account", account.name.clone()),
+                    ("run_id", run_id::current().unwrap_or_default()),
+                    ("flow", flow.to_string()),
+                    ("state", format!("{:?}", state)),
+                    ("transport", self.transport.name().to_string()),
+                    ("last_output", self.transcript.tail(REPORT_OUTPUT_BYTES)),
+                ]);
+            }
+        }
+        result?;
+        let detail = self.run_detail();
+        Ok(RunOutcome {
+            duration: started.elapsed(),
+            rewards: detail.rewards,
+            servers: detail.servers,
+            commands_sent: detail.commands_sent,
+            warnings: detail.warnings,
+            transcript_path: detail.transcript_path,
+            fallback_server: self.fallback_server.clone(),
+        })
+    }
+
+    /// The end of the terminal output as a Discord ```ansi block, colours as the browser shows them.
+    fn last_output_block(&self) -> Option<String> {
+        ansi_code_block(&self.transcript.raw_tail(DISCORD_OUTPUT_BYTES))
+    }
+
+    /// What the end of the transcript suggests went wrong, e.g. "looks like maintenance".
+    fn failure_hint(&self) -> Option<&'static str> {
+        classify::classify(&self.transcript.tail(CLASSIFY_BYTES))
+    }
+
+    /// Server names parsed from the server-selection list during this session.
+    fn discovered_servers(&self) -> &[String] {
+        &self.discovered_servers
+    }
+
+    /// What the session reported so far, for the run record of a failed or aborted run.
+    fn run_detail(&self) -> RunDetail {
+        RunDetail {
+            servers: self.server_results(),
+            commands_sent: self.commands_sent,
+            rewards: self.rewards.clone(),
+            warnings: self.warnings.clone(),
+            transcript_path: self.transcript_file.as_ref().map(|f| f.path().to_string()),
+        }
+    }
+}
+
@@ -0,0 +1,210 @@
+use base64::Engine;
+use ring::digest;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// SHA-256 of a certificate's SubjectPublicKeyInfo.
+pub type SpkiPin = [u8; 32];
+
+/// Start of the error a pin mismatch produces; the queue stops on it rather than retrying.
+pub const PIN_MISMATCH: &str = "TLS_PIN_MISMATCH";
+
+const PIN_PREFIX: &str = "sha256/";
+
+/// Reads a pin written as `sha256/<base64>` (the usual HPKP / curl `--pinnedpubkey` form) or as
+/// 64 hex digits.
+pub fn parse_pin(text: &str) -> Result<SpkiPin, String> {
+    let text = text.trim();
+    let bytes = match text.strip_prefix(PIN_PREFIX) {
+        Some(b64) => base64::engine::general_purpose::STANDARD.decode(b64)
+            .map_err(|_| format!("'{}' is not valid base64", text))?,
+        None if text.len() == 64 && text.chars().all(|c| c.is_ascii_hexdigit()) => {
+            (0..64).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect()
+        }
+        None => return Err(format!("'{}' is not a pin; use sha256/<base64> or 64 hex digits", text)),
+    };
+    bytes.try_into().map_err(|_| format!("'{}' is not a SHA-256 hash (32 bytes)", text))
+}
+
+pub fn format_pin(pin: &SpkiPin) -> String {
+    format!("{}{}", PIN_PREFIX, base64::engine::general_purpose::STANDARD.encode(pin))
+}
+
+/// Splits a PEM file into its certificates, checking each one parses.
+pub fn read_ca_file(path: &str) -> Result<Vec<native_tls::Certificate>, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    const END: &str = "-----END CERTIFICATE-----";
+    let mut certificates = Vec::new();
+    for block in text.split_inclusive(END).filter(|b| b.contains("-----BEGIN CERTIFICATE-----")) {
+        let certificate = native_tls::Certificate::from_pem(block.trim().as_bytes())
+            .map_err(|e| format!("{} contains an invalid certificate: {}", path, e))?;
+        certificates.push(certificate);
+    }
+    if certificates.is_empty() {
+        return Err(format!("{} contains no PEM certificate", path));
+    }
+    Ok(certificates)
+}
+
+/// Pin of a DER certificate: the hash of its whole SubjectPublicKeyInfo element.
+pub fn spki_pin(cert_der: &[u8]) -> Result<SpkiPin, String> {
+    let invalid = || "certificate is not valid DER".to_string();
+    let (certificate, _) = der_element(cert_der, 0x30).ok_or_else(invalid)?;
+    let (mut tbs, _) = der_element(certificate, 0x30).ok_or_else(invalid)?;
+    // Optional [0] version, then serial number, signature algorithm, issuer, validity, subject
+    if tbs.first() == Some(&0xA0) {
+        tbs = der_element(tbs, 0xA0).ok_or_else(invalid)?.1;
+    }
+    for tag in [0x02, 0x30, 0x30, 0x30, 0x30] {
+        tbs = der_element(tbs, tag).ok_or_else(invalid)?.1;
+    }
+    let (_, rest) = der_element(tbs, 0x30).ok_or_else(invalid)?;
+    // Hash the element including its tag and length
+    let element = &tbs[..tbs.len() - rest.len()];
+    let hash = digest::digest(&digest::SHA256, element);
+    Ok(hash.as_ref().try_into().unwrap())
+}
+
+/// Splits off one DER element with the expected tag: returns (contents, remaining input).
+fn der_element(input: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    if *input.first()? != tag {
+        return None;
+    }
+    let first = *input.get(1)? as usize;
+    let (length, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let count = first & 0x7F;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let length = input.get(2..2 + count)?.iter().fold(0usize, |acc, b| acc << 8 | *b as usize);
+        (length, 2 + count)
+    };
+    let end = header.checked_add(length)?;
+    Some((input.get(header..end)?, &input[end..]))
+}
+
+/// Checks the server certificate against `tls.spki_pins`. Without pins any certificate the
+/// root store accepts is fine.
+pub fn verify_pin(host: &str, cert_der: Option<&[u8]>) -> Result<(), BoxError> {
+    let pins = &crate::config::get().tls_pins;
+    if pins.is_empty() {
+        return Ok(());
+    }
+    let cert_der = cert_der.ok_or_else(|| format!("{}: {} sent no certificate to check against tls.spki_pins", PIN_MISMATCH, host))?;
+    let pin = spki_pin(cert_der).map_err(|e| format!("{}: cannot read the certificate of {}: {}", PIN_MISMATCH, host, e))?;
+    if pins.contains(&pin) {
+        return Ok(());
+    }
+    Err(format!("{}: the certificate of {} has key {}, which matches none of tls.spki_pins. \
+        If the server's key changed on purpose, update the pin; otherwise the connection may be intercepted.",
+        PIN_MISMATCH, host, format_pin(&pin)).into())
+}
+
+pub fn native_connector() -> Result<native_tls::TlsConnector, BoxError> {
+    let mut builder = native_tls::TlsConnector::builder();
+    if let Some(path) = &crate::config::get().tls_ca_file {
+        for certificate in read_ca_file(path)? {
+            builder.add_root_certificate(certificate);
+        }
+    }
+    Ok(builder.build()?)
+}
+
+/// HTTP client settings for the polling transport: the extra root CA, and the peer
+/// certificate exposed so `verify_response` can check the pin.
+pub fn http_client_builder() -> Result<reqwest::ClientBuilder, BoxError> {
+    let mut builder = reqwest::Client::builder().tls_info(true);
+    if let Some(path) = &crate::config::get().tls_ca_file {
+        let text = std::fs::read(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+        for certificate in reqwest::Certificate::from_pem_bundle(&text)? {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+    Ok(builder)
+}
+
+/// Checks the pin of the connection a polling response came over. Plain HTTP has nothing to check.
+pub fn verify_response(response: &reqwest::Response) -> Result<(), BoxError> {
+    if response.url().scheme() != "https" {
+        return Ok(());
+    }
+    let certificate = response.extensions().get::<reqwest::tls::TlsInfo>().and_then(|info| info.peer_certificate());
+    verify_pin(response.url().host_str().unwrap_or_default(), certificate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Self-signed P-256 certificate for CN=pin.test; its pin comes from
+    // openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary | base64
+    const CERT_PEM: &str = "\
+        MIIBezCCASGgAwIBAgIUVNUb/7u8kvs5hKQ6byv+dZZXbWUwCgYIKoZIzj0EAwIw\
+        EzERMA8GA1UEAwwIcGluLnRlc3QwHhcNMjYxMDE1MTUzNjI3WhcNMzYxMDEyMTUz\
+        NjI3WjATMREwDwYDVQQDDAhwaW4udGVzdDBZMBMGByqGSM49AgEGCCqGSM49AwEH\
+        A0IABLMYEB24gZVMohFP0yJ+Nwd9c2Z9xrgxcSDN6aCycSrN4ULvvTbZI9PhHqYL\
+        7J27BO7vsVaQcpoePAyxz0nHCoOjUzBRMB0GA1UdDgQWBBSzutpb2ofGFj4s3JYl\
+        WN36WZFcszAfBgNVHSMEGDAWgBSzutpb2ofGFj4s3JYlWN36WZFcszAPBgNVHRMB\
+        Af8EBTADAQH/MAoGCCqGSM49BAMCA0gAMEUCIHQ3P5q+XcP7zPWD41Ue0FZdMUvN\
+        DUZ361JuQRZwF00wAiEA+zx0lQ+vewpbU2NRsgCmGoeciIofEwZ6VaZHvzje02s=";
+    const CERT_PIN: &str = "sha256/cctqKvnkki4+MmDXlKMLyMlN1GfPcBbm1DaksaZtKD0=";
+
+    fn cert_der() -> Vec<u8> {
+        base64::engine::general_purpose::STANDARD.decode(CERT_PEM).unwrap()
+    }
+
+    #[test]
+    fn spki_pin_matches_openssl() {
+        let pin = spki_pin(&cert_der()).unwrap();
+        assert_eq!(pin, parse_pin(CERT_PIN).unwrap());
+        assert_eq!(format_pin(&pin), CERT_PIN);
+    }
+
+    #[test]
+    fn spki_pin_rejects_truncated_certificate() {
+        let der = cert_der();
+        for len in [0, 1, 3, 4, 40, 150, der.len() - 1] {
+            assert!(spki_pin(&der[..len]).is_err(), "accepted {} of {} bytes", len, der.len());
+        }
+    }
+
+    #[test]
+    fn der_element_reads_short_and_long_form_lengths() {
+        assert_eq!(der_element(&[0x02, 0x01, 0x05, 0xFF], 0x02), Some((&[0x05][..], &[0xFF][..])));
+
+        let mut one_byte = vec![0x04, 0x81, 0x80];
+        one_byte.extend([0xAB; 0x80]);
+        one_byte.push(0x01);
+        let (contents, rest) = der_element(&one_byte, 0x04).unwrap();
+        assert_eq!((contents.len(), rest), (0x80, &[0x01][..]));
+
+        let mut two_bytes = vec![0x30, 0x82, 0x01, 0x00];
+        two_bytes.extend([0; 0x100]);
+        let (contents, rest) = der_element(&two_bytes, 0x30).unwrap();
+        assert_eq!((contents.len(), rest.len()), (0x100, 0));
+    }
+
+    #[test]
+    fn der_element_rejects_bad_input() {
+        // Wrong tag, missing length, indefinite and oversized length forms
+        assert_eq!(der_element(&[0x02, 0x01, 0x05], 0x30), None);
+        assert_eq!(der_element(&[0x30], 0x30), None);
+        assert_eq!(der_element(&[0x30, 0x80, 0x00], 0x30), None);
+        assert_eq!(der_element(&[0x30, 0x85, 0, 0, 0, 0, 1, 0], 0x30), None);
+        // Length longer than the input, in short and long form
+        assert_eq!(der_element(&[0x30, 0x03, 0x01, 0x02], 0x30), None);
+        assert_eq!(der_element(&[0x30, 0x82, 0x01], 0x30), None);
+        assert_eq!(der_element(&[0x30, 0x81, 0x02, 0x01], 0x30), None);
+    }
+
+    #[test]
+    fn parse_pin_accepts_base64_and_hex() {
+        let pin = parse_pin(CERT_PIN).unwrap();
+        let hex: String = pin.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(parse_pin(&hex).unwrap(), pin);
+        assert!(parse_pin("sha256/AAAA").is_err());
+        assert!(parse_pin("not a pin").is_err());
+    }
+}
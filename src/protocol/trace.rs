@@ -0,0 +1,167 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::redact::redact;
+
+// Kept under the transcript directory
+const TRACE_SUBDIR: &str = "traces";
+// Older trace files beyond this are deleted when a new trace starts
+const TRACE_KEEP: usize = 20;
+
+/// Which runs record a trace. Set with /trace and kept until the bot restarts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TraceTarget {
+    All,
+    Accounts(BTreeSet<String>),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+static TARGET: Mutex<Option<TraceTarget>> = Mutex::new(None);
+// Run ID -> trace file of runs in progress
+static ACTIVE: Mutex<Option<HashMap<String, TraceFile>>> = Mutex::new(None);
+
+struct TraceFile {
+    path: PathBuf,
+    file: File,
+}
+
+/// Traces every run (`None`) or also the runs of `account`.
+pub fn enable(account: Option<&str>) {
+    let Ok(mut target) = TARGET.lock() else { return };
+    *target = match (account, target.take()) {
+        (None, _) | (Some(_), Some(TraceTarget::All)) => Some(TraceTarget::All),
+        (Some(name), Some(TraceTarget::Accounts(mut names))) => {
+            names.insert(name.to_string());
+            Some(TraceTarget::Accounts(names))
+        }
+        (Some(name), None) => Some(TraceTarget::Accounts(BTreeSet::from([name.to_string()]))),
+    };
+}
+
+/// Stops tracing entirely (`None`) or only `account`. Runs in progress finish their trace.
+pub fn disable(account: Option<&str>) {
+    let Ok(mut target) = TARGET.lock() else { return };
+    *target = match (account, target.take()) {
+        (Some(name), Some(TraceTarget::Accounts(mut names))) => {
+            names.remove(name);
+            (!names.is_empty()).then_some(TraceTarget::Accounts(names))
+        }
+        (Some(_), Some(TraceTarget::All)) => Some(TraceTarget::All),
+        _ => None,
+    };
+}
+
+pub fn target() -> Option<TraceTarget> {
+    TARGET.lock().ok().and_then(|t| t.clone())
+}
+
+fn file_safe(account: &str) -> String {
+    account.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect()
+}
+
+fn directory() -> PathBuf {
+    Path::new(&crate::config::get().transcript_dir).join(TRACE_SUBDIR)
+}
+
+/// Ends the trace of a run when dropped.
+pub struct TraceGuard {
+    run_id: String,
+}
+
+impl Drop for TraceGuard {
+    fn drop(&mut self) {
+        let finished = ACTIVE.lock().ok().and_then(|mut active| active.as_mut()?.remove(&self.run_id));
+        if let Some(trace) = finished {
+            log!("[INFO] Trace saved to {}", trace.path.display());
+        }
+    }
+}
+
+/// Starts recording the frames of run `run_id` if tracing covers `account`. Frames are recorded
+/// from tasks running inside `run_id::scope(run_id, ..)` until the guard is dropped.
+pub fn start(account: &str, run_id: &str) -> Option<TraceGuard> {
+    let traced = match target()? {
+        TraceTarget::All => true,
+        TraceTarget::Accounts(names) => names.contains(account),
+    };
+    if !traced {
+        return None;
+    }
+    let dir = directory();
+    if let Err(e) = fs::create_dir_all(&dir) {
+        log!("[WARN] Trace: cannot create {}: {}", dir.display(), e);
+        return None;
+    }
+    prune(&dir, TRACE_KEEP - 1);
+    let path = dir.join(format!("{}_{}_{}.trace", chrono::Utc::now().format("%Y%m%dT%H%M%S"), file_safe(account), run_id));
+    let file = match File::create(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            log!("[WARN] Trace: cannot create {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    log!("[INFO] Tracing the frames of run {} ({})", run_id, account);
+    let Ok(mut active) = ACTIVE.lock() else { return None };
+    active.get_or_insert_with(HashMap::new).insert(run_id.to_string(), TraceFile { path, file });
+    Some(TraceGuard { run_id: run_id.to_string() })
+}
+
+/// Writes a raw frame of the current run's connection, secrets masked. Does nothing for runs
+/// without a trace.
+pub fn record(direction: Direction, transport: &str, frame: &str) {
+    let Some(run_id) = crate::run_id::current() else { return };
+    let Ok(mut active) = ACTIVE.lock() else { return };
+    let Some(trace) = active.as_mut().and_then(|a| a.get_mut(&run_id)) else { return };
+    let arrow = match direction {
+        Direction::Sent => ">>",
+        Direction::Received => "<<",
+    };
+    let line = format!("{} {} {} {}\n", chrono::Utc::now().format("%H:%M:%S%.3f"), transport, arrow, redact(frame));
+    if let Err(e) = trace.file.write_all(line.as_bytes()) {
+        log!("[WARN] Trace: writing {} failed: {}", trace.path.display(), e);
+        if let Some(active) = active.as_mut() {
+            active.remove(&run_id);
+        }
+    }
+}
+
+/// Most recent finished or running trace, of `account` if given.
+pub fn latest(account: Option<&str>) -> Option<PathBuf> {
+    let mut files: Vec<PathBuf> = fs::read_dir(directory()).ok()?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().map(|x| x == "trace").unwrap_or(false))
+        .collect();
+    files.sort();
+    let suffix = account.map(|name| format!("_{}_", file_safe(name)));
+    files.into_iter().rev().find(|p| match &suffix {
+        Some(suffix) => p.file_name().map(|n| n.to_string_lossy().contains(suffix.as_str())).unwrap_or(false),
+        None => true,
+    })
+}
+
+fn prune(dir: &Path, keep: usize) {
+    let mut files: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().map(|x| x == "trace").unwrap_or(false))
+            .collect(),
+        Err(_) => return,
+    };
+    if files.len() <= keep {
+        return;
+    }
+    files.sort();
+    for old in &files[..files.len() - keep] {
+        if let Err(e) = fs::remove_file(old) {
+            log!("[WARN] Trace: could not delete {}: {}", old.display(), e);
+        }
+    }
+}
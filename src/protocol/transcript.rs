@@ -0,0 +1,313 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+use crate::redact::redact;
+
+pub const DEFAULT_CAPACITY: usize = 10_000;
+// Output kept with its colour codes, for showing the end of a session in Discord
+const RAW_CAPACITY: usize = 4096;
+
+/// Removes ANSI escape sequences (CSI colour/cursor codes and OSC titles) and stray
+/// carriage returns from terminal output.
+pub fn strip_ansi(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\u{1b}' => match chars.peek() {
+                // CSI: ESC [ params... final byte in @..~
+                Some('[') => {
+                    chars.next();
+                    for n in chars.by_ref() {
+                        if ('@'..='~').contains(&n) {
+                            break;
+                        }
+                    }
+                }
+                // OSC: ESC ] ... terminated by BEL or ESC \
+                Some(']') => {
+                    chars.next();
+                    while let Some(n) = chars.next() {
+                        if n == '\u{7}' {
+                            break;
+                        }
+                        if n == '\u{1b}' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                    }
+                }
+                // Two-character escapes (ESC =, ESC >, ...)
+                Some(_) => {
+                    chars.next();
+                }
+                None => {}
+            },
+            '\r' => {}
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Rewrites terminal output for a Discord ```ansi block: SGR codes Discord understands (reset,
+/// bold, underline, the 8 basic colours) are kept, bright colours are mapped to the basic ones,
+/// and every other escape sequence is removed.
+pub fn ansi_for_discord(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\u{1b}' if chars.peek() == Some(&'[') => {
+                chars.next();
+                let mut sequence = String::new();
+                let mut last = None;
+                for n in chars.by_ref() {
+                    if ('@'..='~').contains(&n) {
+                        last = Some(n);
+                        break;
+                    }
+                    sequence.push(n);
+                }
+                if last != Some('m') {
+                    continue;
+                }
+                let mut params = sequence.split(';').map(|p| if p.is_empty() { Some(0) } else { p.parse::<u32>().ok() });
+                let mut codes: Vec<String> = Vec::new();
+                while let Some(param) = params.next() {
+                    let code = match param {
+                        Some(0 | 1 | 4) => param,
+                        Some(code @ (30..=37 | 40..=47)) => Some(code),
+                        // Discord has no separate "default colour"
+                        Some(39 | 49) => Some(0),
+                        Some(code @ (90..=97 | 100..=107)) => Some(code - 60),
+                        // 256-colour and RGB colours: skip their arguments too
+                        Some(38 | 48) => {
+                            let skip = match params.next() {
+                                Some(Some(5)) => 1,
+                                Some(Some(2)) => 3,
+                                _ => 0,
+                            };
+                            params.by_ref().take(skip).for_each(drop);
+                            None
+                        }
+                        _ => None,
+                    };
+                    if let Some(code) = code {
+                        codes.push(code.to_string());
+                    }
+                }
+                if !codes.is_empty() {
+                    out.push_str(&format!("\u{1b}[{}m", codes.join(";")));
+                }
+            }
+            // OSC titles and two-character escapes, dropped as in `strip_ansi`
+            '\u{1b}' if chars.peek() == Some(&']') => {
+                while let Some(n) = chars.next() {
+                    if n == '\u{7}' {
+                        break;
+                    }
+                    if n == '\u{1b}' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            '\u{1b}' => {
+                chars.next();
+            }
+            '\r' => {}
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// `text` as a Discord ```ansi code block, or `None` if there is nothing visible to show.
+pub fn ansi_code_block(text: &str) -> Option<String> {
+    let rendered = ansi_for_discord(text).replace("```", "'''");
+    if strip_ansi(&rendered).trim().is_empty() {
+        return None;
+    }
+    Some(format!("```ansi\n{}\n\u{1b}[0m```", rendered.trim_matches('\n')))
+}
+
+/// Bounded, line-oriented record of a session's terminal output.
+/// Text is ANSI-stripped on ingest; once `capacity` bytes are exceeded the oldest lines are dropped.
+pub struct Transcript {
+    lines: VecDeque<String>,
+    partial: String,
+    bytes: usize,
+    capacity: usize,
+    // Most recent output as received, colour codes included
+    raw: String,
+}
+
+impl Default for Transcript {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl Transcript {
+    pub fn new(capacity: usize) -> Self {
+        Self { lines: VecDeque::new(), partial: String::new(), bytes: 0, capacity, raw: String::new() }
+    }
+
+    /// Appends a raw output chunk and returns its cleaned form for prompt matching.
+    pub fn push(&mut self, raw: &str) -> String {
+        self.raw.push_str(raw);
+        if self.raw.len() > RAW_CAPACITY {
+            let cut = line_start_after(&self.raw, self.raw.len() - RAW_CAPACITY);
+            self.raw.replace_range(..cut, "");
+        }
+        let clean = strip_ansi(raw);
+        let mut rest = clean.as_str();
+        while let Some(pos) = rest.find('\n') {
+            self.partial.push_str(&rest[..pos]);
+            self.lines.push_back(std::mem::take(&mut self.partial));
+            rest = &rest[pos + 1..];
+        }
+        self.partial.push_str(rest);
+        self.bytes += clean.len();
+        self.evict();
+        clean
+    }
+
+    fn evict(&mut self) {
+        while self.bytes > self.capacity {
+            match self.lines.pop_front() {
+                Some(line) => self.bytes -= line.len() + 1,
+                None => {
+                    // A single unterminated line larger than the buffer: keep its tail
+                    let mut cut = self.partial.len().saturating_sub(self.capacity);
+                    while !self.partial.is_char_boundary(cut) {
+                        cut += 1;
+                    }
+                    self.partial.replace_range(..cut, "");
+                    self.bytes = self.partial.len();
+                }
+            }
+        }
+    }
+
+    /// The last `max_bytes` of the transcript (cut on a char boundary).
+    pub fn tail(&self, max_bytes: usize) -> String {
+        let full = self.text();
+        let mut start = full.len().saturating_sub(max_bytes);
+        while !full.is_char_boundary(start) {
+            start += 1;
+        }
+        full[start..].to_string()
+    }
+
+    /// About the last `max_bytes` of raw output, colour codes included. Starts at a line
+    /// boundary so no escape sequence is cut in half.
+    pub fn raw_tail(&self, max_bytes: usize) -> String {
+        let start = line_start_after(&self.raw, self.raw.len().saturating_sub(max_bytes));
+        self.raw[start..].to_string()
+    }
+
+    /// Whole retained transcript.
+    pub fn text(&self) -> String {
+        let mut out = String::with_capacity(self.bytes);
+        for line in &self.lines {
+            out.push_str(line);
+            out.push('\n');
+        }
+        out.push_str(&self.partial);
+        out
+    }
+
+    /// Searches only the most recent `window_bytes`, so messages split across
+    /// chunks are still found without matching stale output from long ago.
+    pub fn contains_recent(&self, needle: &str, window_bytes: usize) -> bool {
+        self.tail(window_bytes).contains(needle)
+    }
+}
+
+/// Index of the first line that starts at or after `from` (the end of `text` if none does).
+fn line_start_after(text: &str, from: usize) -> usize {
+    if from == 0 {
+        return 0;
+    }
+    let mut from = from;
+    while !text.is_char_boundary(from) {
+        from += 1;
+    }
+    // '\n' never occurs inside a multi-byte character, so the result is a char boundary
+    text.as_bytes()[from - 1..].iter().position(|b| *b == b'\n').map(|i| from + i).unwrap_or(text.len())
+}
+
+/// A session's cleaned output, written to `transcripts.directory` as it arrives (one file per
+/// run, secrets masked). Unlike `Transcript` it keeps the whole session.
+pub struct TranscriptFile {
+    path: String,
+    // Dropped after a failed write
+    file: Option<File>,
+}
+
+impl TranscriptFile {
+    /// Starts the file for a run, dropping the oldest ones beyond `transcripts.keep`. `None` when
+    /// transcripts are turned off or the directory can't be written.
+    pub fn create(account: &str, run_id: Option<&str>) -> Option<Self> {
+        let config = crate::config::get();
+        if config.transcript_keep == 0 {
+            return None;
+        }
+        let dir = Path::new(&config.transcript_dir);
+        if let Err(e) = fs::create_dir_all(dir) {
+            log!("[WARN] Transcripts: cannot create {}: {}", dir.display(), e);
+            return None;
+        }
+        prune(dir, config.transcript_keep - 1);
+
+        let account: String = account.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect();
+        // Timestamp first, so names sort oldest first
+        let name = format!("{}_{}_{}.log", chrono::Utc::now().format("%Y%m%dT%H%M%S"), account, run_id.unwrap_or("no-run-id"));
+        let path = dir.join(name);
+        match File::create(&path) {
+            Ok(file) => Some(Self { path: path.to_string_lossy().into_owned(), file: Some(file) }),
+            Err(e) => {
+                log!("[WARN] Transcripts: cannot create {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Appends cleaned output. A failed write is logged once and the file is given up on.
+    pub fn write(&mut self, text: &str) {
+        if let Some(file) = &mut self.file {
+            if let Err(e) = file.write_all(redact(text).as_bytes()) {
+                log!("[WARN] Transcripts: writing {} failed: {}", self.path, e);
+                self.file = None;
+            }
+        }
+    }
+}
+
+/// Deletes the oldest transcript files until at most `keep` are left.
+fn prune(dir: &Path, keep: usize) {
+    let mut files: Vec<_> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().map(|x| x == "log").unwrap_or(false))
+            .collect(),
+        Err(_) => return,
+    };
+    if files.len() <= keep {
+        return;
+    }
+    files.sort();
+    for old in &files[..files.len() - keep] {
+        if let Err(e) = fs::remove_file(old) {
+            log!("[WARN] Transcripts: could not delete {}: {}", old.display(), e);
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use futures_util::{SinkExt, StreamExt, stream::{SplitSink, SplitStream}};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::http::{HeaderMap, HeaderValue};
+use tokio_tungstenite::tungstenite::Message;
+use url::Url;
+
+use super::connector::{self, WsStream};
+use super::tls;
+use super::trace::{self, Direction};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub(super) const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36";
+// Engine.IO v4 separates packets in a polling payload with the record separator
+const PACKET_SEPARATOR: char = '\u{1e}';
+// Extra time a long-poll request may stay open beyond the ping interval
+const POLL_GRACE: Duration = Duration::from_secs(20);
+
+/// Contents of the Engine.IO "open" packet.
+#[derive(Debug, Clone)]
+pub struct Handshake {
+    pub sid: String,
+    pub ping_interval: u64,
+    pub upgrades: Vec<String>,
+}
+
+impl Handshake {
+    fn parse(packet: &str) -> Result<Self, BoxError> {
+        let json_part = packet.strip_prefix('0').ok_or("Failed to handshake")?;
+        let data: serde_json::Value = serde_json::from_str(json_part)?;
+        Ok(Self {
+            sid: data["sid"].as_str().ok_or("No SID found")?.to_string(),
+            ping_interval: data["pingInterval"].as_u64().unwrap_or(25000),
+            upgrades: data["upgrades"].as_array()
+                .map(|u| u.iter().filter_map(|v| v.as_str().map(|s| s.to_string())).collect())
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// The Engine.IO connection under the socket.io session: a raw websocket when the network
+/// allows it, otherwise HTTP long-polling (which can later be upgraded to a websocket).
+pub enum Transport {
+    WebSocket {
+        write: SplitSink<WsStream, Message>,
+        read: SplitStream<WsStream>,
+        // Packets the last poll delivered while upgrading; read before the websocket
+        pending: VecDeque<String>,
+    },
+    Polling(PollingTransport),
+}
+
+impl Transport {
+    pub async fn open_websocket(cookie: &str, base_url: &str) -> Result<(Self, Handshake), BoxError> {
+        let mut ws_stream = open_ws_stream(cookie, base_url).await?;
+        let msg = tokio::time::timeout(crate::config::get().handshake_timeout, ws_stream.next())
+            .await
+            .map_err(|_| "Connection handshake timed out")?
+            .ok_or("Stream closed")??;
+        trace::record(Direction::Received, "websocket", &msg.to_string());
+        let handshake = Handshake::parse(&msg.to_string())?;
+        let (write, read) = ws_stream.split();
+        Ok((Transport::WebSocket { write, read, pending: VecDeque::new() }, handshake))
+    }
+
+    pub async fn open_polling(cookie: &str, base_url: &str) -> Result<(Self, Handshake), BoxError> {
+        PollingTransport::open(cookie, base_url).await
+            .map(|(polling, handshake)| (Transport::Polling(polling), handshake))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Transport::WebSocket { .. } => "websocket",
+            Transport::Polling(_) => "polling",
+        }
+    }
+
+    pub fn is_polling(&self) -> bool {
+        matches!(self, Transport::Polling(_))
+    }
+
+    pub async fn send(&mut self, packet: String) -> Result<(), BoxError> {
+        trace::record(Direction::Sent, self.name(), &packet);
+        match self {
+            Transport::WebSocket { write, .. } => write.send(Message::Text(packet)).await.map_err(|e| e.into()),
+            Transport::Polling(polling) => polling.send(&packet).await,
+        }
+    }
+
+    /// Next Engine.IO packet. Cancel-safe, so it can sit in a `select!`.
+    pub async fn recv(&mut self) -> Option<Result<String, BoxError>> {
+        let name = self.name();
+        let packet = match self {
+            Transport::WebSocket { read, pending, .. } => match pending.pop_front() {
+                Some(packet) => Some(Ok(packet)),
+                None => read.next().await.map(|m| m.map(|m| m.to_string()).map_err(|e| e.into())),
+            },
+            Transport::Polling(polling) => polling.recv().await,
+        };
+        if let Some(Ok(packet)) = &packet {
+            trace::record(Direction::Received, name, packet);
+        }
+        packet
+    }
+
+    /// Moves a polling session onto a websocket (Engine.IO probe / upgrade). On failure the
+    /// session stays on polling and the error is returned; on a websocket this does nothing.
+    pub async fn try_upgrade(&mut self, cookie: &str, base_url: &str) -> Result<(), BoxError> {
+        let polling = match self {
+            Transport::Polling(polling) => polling,
+            Transport::WebSocket { .. } => return Ok(()),
+        };
+        if !polling.upgrades.iter().any(|u| u == "websocket") {
+            return Err("server does not offer a websocket upgrade".into());
+        }
+
+        let mut url = Url::parse(base_url)?;
+        url.query_pairs_mut().append_pair("sid", &polling.sid);
+        let mut ws_stream = open_ws_stream(cookie, url.as_str()).await?;
+        trace::record(Direction::Sent, "websocket", "2probe");
+        ws_stream.send(Message::Text("2probe".into())).await?;
+        let reply = tokio::time::timeout(crate::config::get().handshake_timeout, ws_stream.next())
+            .await
+            .map_err(|_| "upgrade probe timed out")?
+            .ok_or("Stream closed")??;
+        trace::record(Direction::Received, "websocket", &reply.to_string());
+        if reply.to_string() != "3probe" {
+            return Err(format!("unexpected probe reply '{}'", reply).into());
+        }
+
+        // From here on the server routes everything to the websocket; keep what the
+        // last poll still delivers so nothing is lost in the switch.
+        trace::record(Direction::Sent, "websocket", "5");
+        ws_stream.send(Message::Text("5".into())).await?;
+        let pending = polling.shutdown().await;
+        let (write, read) = ws_stream.split();
+        *self = Transport::WebSocket { write, read, pending };
+        Ok(())
+    }
+}
+
+async fn open_ws_stream(cookie: &str, url: &str) -> Result<WsStream, BoxError> {
+    let mut headers = HeaderMap::new();
+    headers.insert("Cookie", HeaderValue::from_str(&format!("session={}", cookie))?);
+    headers.insert("User-Agent", HeaderValue::from_static(USER_AGENT));
+    let (ws_stream, timings) = connector::shared()?.connect_websocket(&Url::parse(url)?, &headers).await?;
+    log!("[INFO] Connection set up in {}", timings);
+    Ok(ws_stream)
+}
+
+/// Engine.IO v4 HTTP long-polling. A background task keeps one GET request open at a time
+/// and forwards the packets it returns; packets are sent with POST requests.
+pub struct PollingTransport {
+    http: reqwest::Client,
+    // Sent per request rather than as a default header, so the pin check can go first
+    cookie: reqwest::header::HeaderValue,
+    url: Url,
+    sid: String,
+    upgrades: Vec<String>,
+    incoming: mpsc::UnboundedReceiver<Result<String, String>>,
+    stop: Arc<AtomicBool>,
+    poller: Option<JoinHandle<()>>,
+}
+
+/// `ws(s)://host/socket.io/?EIO=4&transport=websocket` -> the matching `http(s)` polling URL.
+fn polling_url(base_url: &str) -> Result<Url, BoxError> {
+    let mut url = Url::parse(base_url)?;
+    let scheme = if url.scheme() == "wss" || url.scheme() == "https" { "https" } else { "http" };
+    url.set_scheme(scheme).map_err(|_| format!("Could not build a polling URL from '{}'", base_url))?;
+    let pairs: Vec<(String, String)> = url.query_pairs()
+        .filter(|(k, _)| k != "transport")
+        .map(|(k, v)| (k.into_owned(), v.into_owned()))
+        .collect();
+    url.query_pairs_mut().clear().extend_pairs(pairs).append_pair("transport", "polling");
+    Ok(url)
+}
+
+fn split_payload(body: &str) -> impl Iterator<Item = String> + '_ {
+    body.split(PACKET_SEPARATOR).filter(|p| !p.is_empty()).map(|p| p.to_string())
+}
+
+impl PollingTransport {
+    async fn open(cookie: &str, base_url: &str) -> Result<(Self, Handshake), BoxError> {
+        let cookie = reqwest::header::HeaderValue::from_str(&format!("session={}", cookie))?;
+        // Shared, so a polling session reuses pooled connections from earlier runs
+        let http = connector::shared()?.http().clone();
+
+        let url = polling_url(base_url)?;
+        let body = tokio::time::timeout(crate::config::get().handshake_timeout, async {
+            if !crate::config::get().tls_pins.is_empty() {
+                // Check the pin on a request without the cookie first
+                tls::verify_response(&http.head(url.clone()).send().await?)?;
+            }
+            let response = http.get(url.clone()).header(reqwest::header::COOKIE, cookie.clone()).send().await?;
+            tls::verify_response(&response)?;
+            Ok::<_, BoxError>(response.error_for_status()?.text().await?)
+        })
+            .await
+            .map_err(|_| "Connection handshake timed out")??;
+
+        // The open packet comes first; anything after it is already session traffic
+        let mut packets: VecDeque<String> = split_payload(&body).collect();
+        let open_packet = packets.pop_front().ok_or("Stream closed")?;
+        trace::record(Direction::Received, "polling", &open_packet);
+        let handshake = Handshake::parse(&open_packet)?;
+
+        let mut session_url = url;
+        session_url.query_pairs_mut().append_pair("sid", &handshake.sid);
+
+        let (tx, incoming) = mpsc::unbounded_channel();
+        for packet in packets {
+            let _ = tx.send(Ok(packet));
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let poll_timeout = Duration::from_millis(handshake.ping_interval) + POLL_GRACE;
+        let poller = tokio::spawn(poll_loop(http.clone(), cookie.clone(), session_url.clone(), poll_timeout, tx, Arc::clone(&stop)));
+
+        Ok((Self {
+            http,
+            cookie,
+            url: session_url,
+            sid: handshake.sid.clone(),
+            upgrades: handshake.upgrades.clone(),
+            incoming,
+            stop,
+            poller: Some(poller),
+        }, handshake))
+    }
+
+    async fn send(&mut self, packet: &str) -> Result<(), BoxError> {
+        let response = self.http.post(self.url.clone())
+            .header(reqwest::header::COOKIE, self.cookie.clone())
+            .header(reqwest::header::CONTENT_TYPE, "text/plain;charset=UTF-8")
+            .body(packet.to_string())
+            .send().await?;
+        tls::verify_response(&response)?;
+        response.error_for_status()?;
+        Ok(())
+    }
+
+    async fn recv(&mut self) -> Option<Result<String, BoxError>> {
+        self.incoming.recv().await.map(|r| r.map_err(|e| e.into()))
+    }
+
+    /// Stops polling once the request in flight returns, and hands back packets not yet read
+    /// (other than the no-op the server answers an upgrade with).
+    async fn shutdown(&mut self) -> VecDeque<String> {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(poller) = self.poller.take() {
+            let _ = poller.await;
+        }
+        let mut leftover = VecDeque::new();
+        while let Ok(Ok(packet)) = self.incoming.try_recv() {
+            if packet != "6" {
+                leftover.push_back(packet);
+            }
+        }
+        leftover
+    }
+}
+
+impl Drop for PollingTransport {
+    fn drop(&mut self) {
+        if let Some(poller) = self.poller.take() {
+            poller.abort();
+        }
+    }
+}
+
+async fn poll_loop(http: reqwest::Client, cookie: reqwest::header::HeaderValue, url: Url, timeout: Duration, tx: mpsc::UnboundedSender<Result<String, String>>, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::SeqCst) {
+        let response = tokio::time::timeout(timeout, async {
+            let response = http.get(url.clone()).header(reqwest::header::COOKIE, cookie.clone()).send().await?;
+            tls::verify_response(&response)?;
+            Ok::<_, BoxError>(response.error_for_status()?.text().await?)
+        }).await;
+        let body = match response {
+            Ok(Ok(body)) => body,
+            Ok(Err(e)) => {
+                let _ = tx.send(Err(format!("Polling request failed: {}", e)));
+                return;
+            }
+            Err(_) => {
+                let _ = tx.send(Err("Polling request timed out".to_string()));
+                return;
+            }
+        };
+        for packet in split_payload(&body) {
+            if tx.send(Ok(packet)).is_err() {
+                return;
+            }
+        }
+    }
+}
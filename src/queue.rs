@@ -0,0 +1,394 @@
+use chrono::NaiveDate;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use serenity::all::ChannelId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+use crate::db::{Account, AccountFilter};
+use crate::protocol::flow::Flow;
+
+/// Lifecycle of the queue manager.
+/// `Paused` keeps the remaining accounts queued: the account in progress finishes,
+/// then the worker holds until the queue is resumed or stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueState {
+    Running,
+    Paused,
+    Stopped,
+}
+
+impl QueueState {
+    /// A queue worker exists (running or held on pause).
+    pub fn is_active(&self) -> bool {
+        *self != QueueState::Stopped
+    }
+}
+
+/// A run request that arrived while a batch was in progress.
+#[derive(Clone)]
+pub struct PendingRun {
+    pub filter: AccountFilter,
+    /// Overrides each account's own flow
+    pub flow: Option<Flow>,
+    /// Only log in and stop at the server list; nothing is played or recorded
+    pub dry_run: bool,
+    /// Where the request came from; progress for it is posted there
+    pub channel: Option<ChannelId>,
+}
+
+/// What happened to a run request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueStart {
+    /// No batch was running; it started right away.
+    Started,
+    /// A batch is running; the request waits at this position (1-based).
+    Queued(usize),
+    /// Too many requests are already waiting.
+    Full,
+    /// Maintenance mode is on; nothing was started.
+    Maintenance,
+    /// The database can't be saved (read-only mode); nothing was started.
+    ReadOnly,
+}
+
+/// What the queue worker is busy with, so a crashed worker can be cleaned up after and its
+/// request started again.
+/// Also what /debug_snapshot reports about the worker.
+#[derive(Default)]
+pub struct WorkerProgress {
+    pub request: Option<PendingRun>,
+    /// Account whose session is in progress
+    pub account: Option<String>,
+    /// Retry bookkeeping of the batch, as of the last account picked
+    pub retries: BTreeMap<String, RetryCounts>,
+    /// Accounts left out after they crashed the worker
+    pub crashed: Vec<String>,
+}
+
+/// Order in which a new batch runs the `runnable` accounts: an explicit name list in the order
+/// given, otherwise pending accounts first, then ones in error (each in insertion order).
+/// With `last_completed` (the catch-up setting), accounts whose last completed day is oldest go
+/// first within each group, accounts that never completed a run before all others.
+pub fn batch_order<'a>(runnable: impl IntoIterator<Item = &'a Account>, filter: &AccountFilter, last_completed: Option<&HashMap<String, NaiveDate>>) -> Vec<String> {
+    let mut accs: Vec<&Account> = runnable.into_iter().collect();
+    accs.sort_by_key(|a| (filter.position(a), last_completed.map(|days| days.get(&a.name).copied())));
+    let (mut pending, errors): (Vec<&Account>, Vec<&Account>) = accs.into_iter()
+        .partition(|a| !a.status.starts_with("error"));
+    pending.extend(errors);
+    pending.into_iter().map(|a| a.name.clone()).collect()
+}
+
+/// Run requests waiting for the current batch to end, oldest first.
+#[derive(Default)]
+pub struct PendingRuns {
+    requests: VecDeque<PendingRun>,
+}
+
+impl PendingRuns {
+    /// Adds a request, or returns the position of an identical one already waiting.
+    pub fn push(&mut self, filter: AccountFilter, flow: Option<Flow>, dry_run: bool, channel: Option<ChannelId>) -> QueueStart {
+        if let Some(i) = self.requests.iter().position(|r| r.filter == filter && r.flow == flow && r.dry_run == dry_run && r.channel == channel) {
+            return QueueStart::Queued(i + 1);
+        }
+        if self.requests.len() >= crate::config::get().max_pending_runs {
+            return QueueStart::Full;
+        }
+        self.requests.push_back(PendingRun { filter, flow, dry_run, channel });
+        QueueStart::Queued(self.requests.len())
+    }
+
+    pub fn pop(&mut self) -> Option<PendingRun> {
+        self.requests.pop_front()
+    }
+
+    /// Drops every waiting request and returns them.
+    pub fn clear(&mut self) -> Vec<PendingRun> {
+        self.requests.drain(..).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    /// The waiting requests, oldest first.
+    pub fn requests(&self) -> &VecDeque<PendingRun> {
+        &self.requests
+    }
+}
+
+/// Accounts still to run in the current batch, in the order they will run. Built when the
+/// batch starts; /queue_promote and /queue_demote reorder it while the batch is in progress.
+#[derive(Default)]
+pub struct BatchQueue {
+    order: VecDeque<String>,
+    // Every account queued this batch, so a refill doesn't run an account twice
+    seen: HashSet<String>,
+}
+
+impl BatchQueue {
+    /// Starts a new batch.
+    pub fn clear(&mut self) {
+        self.order.clear();
+        self.seen.clear();
+    }
+
+    /// Appends the accounts that weren't queued yet this batch. Returns how many were added.
+    pub fn extend(&mut self, names: Vec<String>) -> usize {
+        let mut added = 0;
+        for name in names {
+            if self.seen.insert(name.clone()) {
+                self.order.push_back(name);
+                added += 1;
+            }
+        }
+        added
+    }
+
+    pub fn pop_front(&mut self) -> Option<String> {
+        self.order.pop_front()
+    }
+
+    /// Puts `name` up next (e.g. to retry it right away).
+    pub fn push_front(&mut self, name: &str) {
+        self.order.retain(|n| n != name);
+        self.order.push_front(name.to_string());
+    }
+
+    /// Sends `name` to the back, after everything that is waiting.
+    pub fn push_back(&mut self, name: &str) {
+        self.order.retain(|n| n != name);
+        self.order.push_back(name.to_string());
+    }
+
+    /// 1-based position of `name`, `None` if it isn't waiting.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.order.iter().position(|n| n == name).map(|i| i + 1)
+    }
+
+    /// Moves a waiting account to the front. Returns its previous position.
+    pub fn promote(&mut self, name: &str) -> Option<usize> {
+        let position = self.position(name)?;
+        self.push_front(name);
+        Some(position)
+    }
+
+    /// Moves a waiting account to the back. Returns its previous position.
+    pub fn demote(&mut self, name: &str) -> Option<usize> {
+        let position = self.position(name)?;
+        self.push_back(name);
+        Some(position)
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// (accounts taken out of the queue this batch, accounts queued this batch). An account sent
+    /// back to wait counts as not taken until it is picked again.
+    pub fn progress(&self) -> (usize, usize) {
+        (self.seen.len().saturating_sub(self.order.len()), self.seen.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// The waiting accounts, next one first.
+    pub fn names(&self) -> Vec<String> {
+        self.order.iter().cloned().collect()
+    }
+}
+
+/// Names of accounts that currently have a live session, shared by every spawn path
+/// (queue manager, single force_run) so the same account is never run twice at once.
+/// Each entry carries the cancellation token of that session.
+#[derive(Clone, Default)]
+pub struct InFlightRegistry {
+    accounts: Arc<DashMap<String, CancellationToken>>,
+}
+
+impl InFlightRegistry {
+    /// Reserves `name` for a run. Returns `None` if it is already running elsewhere.
+    /// The reservation is released when the returned guard is dropped.
+    pub fn try_acquire(&self, name: &str) -> Option<InFlightGuard> {
+        match self.accounts.entry(name.to_string()) {
+            Entry::Occupied(_) => None,
+            Entry::Vacant(slot) => {
+                let cancel = CancellationToken::new();
+                slot.insert(cancel.clone());
+                Some(InFlightGuard { accounts: Arc::clone(&self.accounts), name: name.to_string(), cancel })
+            }
+        }
+    }
+
+    pub fn is_running(&self, name: &str) -> bool {
+        self.accounts.contains_key(name)
+    }
+
+    /// Accounts with a live session, sorted by name.
+    pub fn running(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.accounts.iter().map(|entry| entry.key().clone()).collect();
+        names.sort();
+        names
+    }
+
+    /// Aborts the live session of `name`, if any. Returns false if it isn't running.
+    pub fn cancel(&self, name: &str) -> bool {
+        match self.accounts.get(name) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+pub struct InFlightGuard {
+    accounts: Arc<DashMap<String, CancellationToken>>,
+    name: String,
+    cancel: CancellationToken,
+}
+
+impl InFlightGuard {
+    /// Fires when someone cancels this particular session.
+    pub fn cancellation(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.accounts.remove(&self.name);
+    }
+}
+
+/// Session errors caused by the connection rather than the account; these are retried
+/// with backoff and not counted as a run in the history.
+pub fn is_connection_error(err: &str) -> bool {
+    // A certificate that fails the pin will fail the same way on every retry
+    if err.contains(crate::protocol::tls::PIN_MISMATCH) {
+        return false;
+    }
+    const MARKERS: &[&str] = &[
+        "IDLE_TIMEOUT",
+        "CONNECTION_FAILED",
+        "SERVER_DISCONNECT",
+        "NAMESPACE_ERROR",
+        "Connection handshake timed out",
+        "Failed to handshake",
+        "Stream closed",
+        "Connection failed",
+        "Polling request",
+    ];
+    MARKERS.iter().any(|m| err.contains(m))
+}
+
+/// Failures that say nothing about the account itself: connection problems, a full
+/// server, Zigza, a terminal that stopped accepting commands. They are retried instead of
+/// being reported as the account's status, within the `RetryTracker` budget.
+pub fn is_transient_error(err: &str) -> bool {
+    is_connection_error(err) || err.contains("SERVER_FULL") || err.contains("ZIGZA_DETECTED") || err.contains("INVALID_COMMAND_RESTART")
+}
+
+/// How long an inline retry waits at least after this failure. Zigza and a full server
+/// don't clear up within the usual connection backoff.
+pub fn min_retry_delay(err: &str) -> Duration {
+    if err.contains("ZIGZA_DETECTED") {
+        Duration::from_secs(600)
+    } else if err.contains("SERVER_FULL") {
+        Duration::from_secs(300)
+    } else {
+        Duration::ZERO
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryDecision {
+    /// Try the same account again after a short backoff.
+    RetryInline,
+    /// Move on; the account is picked up again once the other accounts had their turn.
+    Requeue,
+    /// Budget exhausted; leave the account alone for the rest of the batch.
+    GiveUp,
+}
+
+/// Per-batch bookkeeping of transient failures for the queue manager.
+#[derive(Default)]
+pub struct RetryTracker {
+    attempts: HashMap<String, u32>,
+    inline_retries: HashMap<String, u32>,
+    requeues: HashMap<String, u32>,
+    exhausted: HashSet<String>,
+}
+
+impl RetryTracker {
+    /// Counts a new session for `name` and returns the attempt number (1-based).
+    pub fn start_attempt(&mut self, name: &str) -> u32 {
+        let attempts = self.attempts.entry(name.to_string()).or_insert(0);
+        *attempts += 1;
+        *attempts
+    }
+
+    /// Decides what happens after a transient failure of `name`.
+    pub fn on_transient_failure(&mut self, name: &str) -> RetryDecision {
+        let inline = self.inline_retries.entry(name.to_string()).or_insert(0);
+        if *inline < crate::config::get().inline_retries {
+            *inline += 1;
+            return RetryDecision::RetryInline;
+        }
+        // A requeued account gets a fresh inline budget when its turn comes again
+        *inline = 0;
+        let requeues = self.requeues.entry(name.to_string()).or_insert(0);
+        if *requeues < crate::config::get().max_requeues {
+            *requeues += 1;
+            RetryDecision::Requeue
+        } else {
+            self.exhausted.insert(name.to_string());
+            RetryDecision::GiveUp
+        }
+    }
+
+    /// The account reached a final outcome; its counters start over if it runs again.
+    pub fn finish(&mut self, name: &str) {
+        self.attempts.remove(name);
+        self.inline_retries.remove(name);
+        self.requeues.remove(name);
+    }
+
+    pub fn is_exhausted(&self, name: &str) -> bool {
+        self.exhausted.contains(name)
+    }
+
+    /// Counters of every account that has any, for /debug_snapshot.
+    pub fn snapshot(&self) -> BTreeMap<String, RetryCounts> {
+        let mut counts: BTreeMap<String, RetryCounts> = BTreeMap::new();
+        for (name, n) in &self.attempts {
+            counts.entry(name.clone()).or_default().attempts = *n;
+        }
+        for (name, n) in &self.inline_retries {
+            counts.entry(name.clone()).or_default().inline_retries = *n;
+        }
+        for (name, n) in &self.requeues {
+            counts.entry(name.clone()).or_default().requeues = *n;
+        }
+        for name in &self.exhausted {
+            counts.entry(name.clone()).or_default().exhausted = true;
+        }
+        counts
+    }
+}
+
+/// One account's entry in `RetryTracker::snapshot`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RetryCounts {
+    pub attempts: u32,
+    #[serde(rename = "inlineRetries")]
+    pub inline_retries: u32,
+    pub requeues: u32,
+    pub exhausted: bool,
+}
@@ -0,0 +1,35 @@
+use std::time::{Duration, Instant};
+
+/// Token bucket limiting how many sessions the queue starts per hour. The bucket holds up
+/// to an hour's budget, so a rested bot may start that many at once, then one more each
+/// time a token refills.
+pub struct RunLimiter {
+    per_hour: u32,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RunLimiter {
+    /// `None` when `per_hour` is 0 (no limit).
+    pub fn per_hour(per_hour: u32) -> Option<Self> {
+        (per_hour > 0).then(|| Self { per_hour, tokens: per_hour as f64, last_refill: Instant::now() })
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.per_hour
+    }
+
+    /// Takes one run from the budget, or returns how long until one is available.
+    pub fn try_take(&mut self) -> Result<(), Duration> {
+        let refill_per_sec = self.per_hour as f64 / 3600.0;
+        let now = Instant::now();
+        self.tokens = (self.tokens + now.duration_since(self.last_refill).as_secs_f64() * refill_per_sec).min(self.per_hour as f64);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / refill_per_sec))
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use std::sync::RwLock;
+
+pub const MASK: &str = "[REDACTED]";
+
+// Shorter values would mask ordinary words and numbers in the output
+const MIN_SECRET_LEN: usize = 4;
+
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Prints a log line with every known secret masked, tagged with the run ID when called
+/// from inside a session. Use instead of `println!`.
+macro_rules! log {
+    ($($arg:tt)*) => {
+        println!("{}", $crate::redact::redact(&$crate::run_id::tag(format!($($arg)*))))
+    };
+}
+
+/// Replaces the set of values that must never appear in logs or Discord messages
+/// (restore codes, the session cookie). Called whenever the database is loaded or saved.
+pub fn set_secrets<I: IntoIterator<Item = String>>(secrets: I) {
+    let mut list: Vec<String> = secrets.into_iter()
+        .map(|s| s.trim().to_string())
+        .filter(|s| s.len() >= MIN_SECRET_LEN)
+        .collect();
+    // Longest first, so a secret containing another one is masked as a whole
+    list.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+    list.dedup();
+    if let Ok(mut current) = SECRETS.write() {
+        *current = list;
+    }
+}
+
+/// Masks every known secret in `text`.
+pub fn redact(text: &str) -> String {
+    let secrets = match SECRETS.read() {
+        Ok(secrets) => secrets,
+        Err(_) => return text.to_string(),
+    };
+    let mut out = text.to_string();
+    for secret in secrets.iter() {
+        if out.contains(secret.as_str()) {
+            out = out.replace(secret.as_str(), MASK);
+        }
+    }
+    out
+}
@@ -0,0 +1,161 @@
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use serde_json::{json, Map, Value};
+use tokio::sync::{mpsc, oneshot};
+use url::Url;
+
+use crate::redact::redact;
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+tokio::task_local! {
+    // Account whose session the current task is running, attached to panic reports
+    static RUN_ACCOUNT: String;
+}
+
+enum Outgoing {
+    Event(Value),
+    Flush(oneshot::Sender<()>),
+}
+
+static REPORTER: OnceLock<mpsc::UnboundedSender<Outgoing>> = OnceLock::new();
+
+/// Where events for a Sentry DSN (`https://<key>@<host>/<project>`) are posted.
+struct Dsn {
+    store_url: Url,
+    public_key: String,
+}
+
+impl Dsn {
+    fn parse(dsn: &str) -> Result<Self, String> {
+        let url = Url::parse(dsn.trim()).map_err(|e| format!("invalid DSN: {}", e))?;
+        let public_key = url.username().to_string();
+        if public_key.is_empty() {
+            return Err("DSN has no public key".to_string());
+        }
+        let path = url.path().trim_end_matches('/');
+        let (prefix, project) = path.rsplit_once('/').ok_or("DSN has no project id")?;
+        if project.is_empty() {
+            return Err("DSN has no project id".to_string());
+        }
+        let mut store_url = url.clone();
+        store_url.set_username("").map_err(|_| "invalid DSN host")?;
+        store_url.set_password(None).map_err(|_| "invalid DSN host")?;
+        store_url.set_path(&format!("{}/api/{}/store/", prefix, project));
+        Ok(Self { store_url, public_key })
+    }
+
+    fn auth_header(&self) -> String {
+        format!("Sentry sentry_version=7, sentry_client=evertext-bot/{}, sentry_key={}", env!("CARGO_PKG_VERSION"), self.public_key)
+    }
+}
+
+/// Enables reporting when `SENTRY_DSN` is set: starts the sending task and installs a panic
+/// hook, so panics in spawned tasks are reported (and logged) instead of vanishing.
+/// Must be called from inside the Tokio runtime.
+pub fn init() {
+    let dsn = match std::env::var("SENTRY_DSN").ok().filter(|d| !d.trim().is_empty()) {
+        Some(dsn) => dsn,
+        None => return,
+    };
+    let dsn = match Dsn::parse(&dsn) {
+        Ok(dsn) => dsn,
+        Err(e) => {
+            log!("[WARN] Error reporting disabled: {}", e);
+            return;
+        }
+    };
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    if REPORTER.set(tx).is_err() {
+        return;
+    }
+    tokio::spawn(send_loop(dsn, rx));
+
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        previous(info);
+        let message = info.payload().downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| info.payload().downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "panic".to_string());
+        let location = info.location().map(|l| format!("{}:{}", l.file(), l.line())).unwrap_or_default();
+        let thread = std::thread::current().name().unwrap_or("unnamed").to_string();
+        let mut context = vec![("location", location.clone()), ("thread", thread)];
+        if let Ok(account) = RUN_ACCOUNT.try_with(|a| a.clone()) {
+            context.push(("account", account));
+        }
+        log!("[CRITICAL] Panic at {}: {}", location, message);
+        capture("panic", "fatal", &message, &context);
+    }));
+    log!("[INFO] Error reporting enabled.");
+}
+
+/// Runs `session` with `account` attached to any panic it reports.
+pub async fn with_account<F: std::future::Future>(account: &str, session: F) -> F::Output {
+    RUN_ACCOUNT.scope(account.to_string(), session).await
+}
+
+/// Reports an error with context (account, state, output, ...). Does nothing unless `init`
+/// enabled reporting. Text is redacted before it leaves the process.
+pub fn report_error(kind: &str, message: &str, context: &[(&str, String)]) {
+    capture(kind, "error", message, context);
+}
+
+fn capture(kind: &str, level: &str, message: &str, context: &[(&str, String)]) {
+    let tx = match REPORTER.get() {
+        Some(tx) => tx,
+        None => return,
+    };
+    let extra: Map<String, Value> = context.iter()
+        .map(|(k, v)| (k.to_string(), Value::String(redact(v))))
+        .collect();
+    let event = json!({
+        "event_id": format!("{:032x}", rand::random::<u128>()),
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "platform": "other",
+        "level": level,
+        "logger": kind,
+        "release": env!("CARGO_PKG_VERSION"),
+        "tags": { "kind": kind },
+        "message": { "formatted": redact(message) },
+        "extra": extra,
+    });
+    let _ = tx.send(Outgoing::Event(event));
+}
+
+/// Waits (briefly) until reports queued so far have been sent. Used before the process exits.
+pub async fn flush() {
+    let tx = match REPORTER.get() {
+        Some(tx) => tx,
+        None => return,
+    };
+    let (done_tx, done_rx) = oneshot::channel();
+    if tx.send(Outgoing::Flush(done_tx)).is_ok() {
+        let _ = tokio::time::timeout(FLUSH_TIMEOUT, done_rx).await;
+    }
+}
+
+async fn send_loop(dsn: Dsn, mut rx: mpsc::UnboundedReceiver<Outgoing>) {
+    let http = reqwest::Client::new();
+    let auth = dsn.auth_header();
+    while let Some(outgoing) = rx.recv().await {
+        let event = match outgoing {
+            Outgoing::Event(event) => event,
+            Outgoing::Flush(done) => {
+                let _ = done.send(());
+                continue;
+            }
+        };
+        let result = http.post(dsn.store_url.clone())
+            .header("X-Sentry-Auth", &auth)
+            .timeout(SEND_TIMEOUT)
+            .json(&event)
+            .send().await
+            .and_then(|r| r.error_for_status());
+        if let Err(e) = result {
+            log!("[WARN] Failed to send error report: {}", e);
+        }
+    }
+}
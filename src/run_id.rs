@@ -0,0 +1,29 @@
+use std::future::Future;
+
+tokio::task_local! {
+    static RUN_ID: String;
+}
+
+/// Short random ID for one session (e.g. "a3f2"). It tags every log line the session writes
+/// and the Discord messages about it, so a reported run can be found in the logs.
+pub fn generate() -> String {
+    format!("{:04x}", rand::random::<u16>())
+}
+
+/// Runs `session` with `id` as the current run ID.
+pub async fn scope<F: Future>(id: String, session: F) -> F::Output {
+    RUN_ID.scope(id, session).await
+}
+
+/// ID of the session the current task is running, if any.
+pub fn current() -> Option<String> {
+    RUN_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Prefixes a log line with the current run ID. Used by `log!`.
+pub fn tag(line: String) -> String {
+    match current() {
+        Some(id) => format!("[run {}] {}", id, line),
+        None => line,
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc};
+
+use crate::backup::BACKUP_TIME;
+use crate::db::{Account, Schedule};
+
+// Local time (in the configured timezone) the weekly digest is posted at
+pub const DIGEST_TIME: &str = "09:00";
+
+/// Longest the scheduler sleeps even with nothing due, so new schedules and timezone changes are
+/// picked up. Its wake-ups are the heartbeat /diagnose and the dead-scheduler alert watch.
+pub const HEARTBEAT: std::time::Duration = std::time::Duration::from_secs(60);
+
+// A trigger missed by more than this (e.g. the host was suspended) is skipped rather than run late.
+// The daily reset is the exception: its latest missed occurrence is always caught up.
+const MAX_CATCH_UP: Duration = Duration::minutes(10);
+// Only the latest missed occurrence of a trigger fires, so older ones aren't looked for
+const LOOKBACK: Duration = Duration::days(2);
+
+/// Next time the wall clock in `now`'s timezone reads `time` (today if still ahead, else tomorrow).
+/// A time skipped by a DST change moves an hour later; a repeated one counts once, at its
+/// first occurrence.
+pub fn next_occurrence<Tz: TimeZone>(now: &DateTime<Tz>, time: NaiveTime) -> Option<DateTime<Tz>> {
+    let tz = now.timezone();
+    let today = now.date_naive();
+    for day in [today, today + Duration::days(1)] {
+        let local = day.and_time(time);
+        let candidate = tz.from_local_datetime(&local).earliest()
+            .or_else(|| tz.from_local_datetime(&(local + Duration::hours(1))).earliest());
+        if let Some(candidate) = candidate {
+            if candidate > *now {
+                return Some(candidate);
+            }
+        }
+    }
+    None
+}
+
+/// Start of the current day in `now`'s timezone, when the daily reset last ran.
+pub fn last_daily_reset<Tz: TimeZone>(now: &DateTime<Tz>) -> DateTime<Tz> {
+    now.timezone().from_local_datetime(&now.date_naive().and_time(NaiveTime::MIN)).earliest()
+        .filter(|reset| reset <= now)
+        .unwrap_or_else(|| now.clone() - Duration::hours(24))
+}
+
+/// Whether the account finished successfully since the last daily reset, i.e. a further run
+/// today would repeat its dailies.
+pub fn completed_since_reset<Tz: TimeZone>(account: &Account, now: &DateTime<Tz>) -> bool {
+    if account.status != "done" {
+        return false;
+    }
+    account.ran_since(&last_daily_reset(now))
+}
+
+/// Earliest upcoming run for an account: the midnight daily reset or any custom schedule matching it.
+pub fn next_run_for<Tz: TimeZone>(account: &Account, schedules: &[Schedule], now: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+    let mut next = next_occurrence(now, NaiveTime::MIN);
+    for schedule in schedules.iter().filter(|s| s.filter.matches(account)) {
+        if let Ok(time) = NaiveTime::parse_from_str(&schedule.time, "%H:%M") {
+            if let Some(candidate) = next_occurrence(now, time) {
+                if next.as_ref().map(|n| candidate < *n).unwrap_or(true) {
+                    next = Some(candidate);
+                }
+            }
+        }
+    }
+    next
+}
+
+/// Source of the current time, so the scheduler can be driven by a fake clock.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Something the scheduler fires at a fixed local time every day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Trigger {
+    DailyReset,
+    Backup,
+    /// Fires daily; the handler checks the configured weekday
+    Digest,
+    /// A custom schedule, by ID
+    Schedule(u32),
+}
+
+impl Trigger {
+    /// Name the trigger's handled instant is stored under in the database.
+    pub fn key(&self) -> String {
+        match self {
+            Trigger::DailyReset => "dailyReset".to_string(),
+            Trigger::Backup => "backup".to_string(),
+            Trigger::Digest => "digest".to_string(),
+            Trigger::Schedule(id) => format!("schedule:{}", id),
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Trigger> {
+        match key {
+            "dailyReset" => Some(Trigger::DailyReset),
+            "backup" => Some(Trigger::Backup),
+            "digest" => Some(Trigger::Digest),
+            _ => key.strip_prefix("schedule:")?.parse().ok().map(Trigger::Schedule),
+        }
+    }
+}
+
+fn parse_hhmm(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time, "%H:%M").ok()
+}
+
+/// Every trigger with its local time. Schedules with an unreadable time are left out.
+pub fn timetable(schedules: &[Schedule]) -> Vec<(Trigger, NaiveTime)> {
+    let mut table = vec![
+        (Trigger::DailyReset, NaiveTime::MIN),
+        (Trigger::Backup, parse_hhmm(BACKUP_TIME).expect("BACKUP_TIME is HH:MM")),
+        (Trigger::Digest, parse_hhmm(DIGEST_TIME).expect("DIGEST_TIME is HH:MM")),
+    ];
+    table.extend(schedules.iter().filter_map(|s| parse_hhmm(&s.time).map(|time| (Trigger::Schedule(s.id), time))));
+    table
+}
+
+/// Decides which triggers are due. Each trigger remembers the last occurrence it handed out,
+/// so an occurrence is handed out exactly once however late or often the caller wakes up, and
+/// (with `handled` saved in the database) across restarts.
+pub struct Scheduler<C: Clock> {
+    clock: C,
+    // Latest time the scheduler has seen; triggers without a handled occurrence start from here
+    cursor: DateTime<Utc>,
+    // Last occurrence handed out per trigger
+    handled: HashMap<Trigger, DateTime<Utc>>,
+    // `handled` changed since `take_handled` last returned it
+    changed: bool,
+}
+
+impl<C: Clock> Scheduler<C> {
+    /// Starts at the clock's current time. Triggers in `handled` (as saved from `take_handled`)
+    /// continue from their last occurrence, so a daily reset missed while the bot was down fires
+    /// on the first call; the others only fire from now on.
+    pub fn new(clock: C, handled: &BTreeMap<String, DateTime<Utc>>) -> Self {
+        let cursor = clock.now();
+        let handled = handled.iter().filter_map(|(key, at)| Trigger::from_key(key).map(|t| (t, *at))).collect();
+        Self { clock, cursor, handled, changed: false }
+    }
+
+    /// Triggers that came due since they last fired, oldest first, each with its local due time.
+    /// A clock that steps back fires nothing until it passes the handled point again.
+    pub fn due<Tz: TimeZone>(&mut self, timetable: &[(Trigger, NaiveTime)], tz: &Tz) -> Vec<(Trigger, DateTime<Tz>)> {
+        let now = self.clock.now();
+        let known = self.handled.len();
+        self.handled.retain(|trigger, _| timetable.iter().any(|(t, _)| t == trigger));
+        self.changed |= self.handled.len() != known;
+
+        let mut due: Vec<(Trigger, DateTime<Tz>)> = Vec::new();
+        for (trigger, time) in timetable {
+            let from = self.handled.get(trigger).copied().unwrap_or(self.cursor).max(now - LOOKBACK);
+            let mut latest: Option<DateTime<Tz>> = None;
+            let mut after = from.with_timezone(tz);
+            while let Some(at) = next_occurrence(&after, *time).filter(|at| at.with_timezone(&Utc) <= now) {
+                latest = Some(at.clone());
+                after = at;
+            }
+            let Some(at) = latest else { continue };
+            self.handled.insert(*trigger, at.with_timezone(&Utc));
+            self.changed = true;
+
+            let late = now - at.with_timezone(&Utc);
+            if late > MAX_CATCH_UP {
+                if *trigger != Trigger::DailyReset {
+                    log!("[WARN] Scheduler: {:?} due at {} was missed by {} minutes; skipped.", trigger, at.naive_local(), late.num_minutes());
+                    continue;
+                }
+                log!("[INFO] Scheduler: catching up the daily reset due at {} ({} minutes late).", at.naive_local(), late.num_minutes());
+            }
+            due.push((*trigger, at));
+        }
+        self.cursor = self.cursor.max(now);
+        due.sort_by(|a, b| a.1.cmp(&b.1));
+        due
+    }
+
+    /// The last occurrence per trigger, if it changed since the previous call; to be saved so
+    /// a restart continues where this run left off.
+    pub fn take_handled(&mut self) -> Option<BTreeMap<String, DateTime<Utc>>> {
+        if !std::mem::take(&mut self.changed) {
+            return None;
+        }
+        Some(self.handled.iter().map(|(trigger, at)| (trigger.key(), *at)).collect())
+    }
+
+    /// How long to sleep until the next trigger, at most `HEARTBEAT`.
+    pub fn until_next<Tz: TimeZone>(&self, timetable: &[(Trigger, NaiveTime)], tz: &Tz) -> std::time::Duration {
+        let now = self.clock.now().with_timezone(tz);
+        timetable.iter()
+            .filter_map(|(_, time)| next_occurrence(&now, *time))
+            .filter_map(|at| (at - now.clone()).to_std().ok())
+            .min()
+            .map_or(HEARTBEAT, |wait| wait.min(HEARTBEAT))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::Europe::Berlin;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone)]
+    struct FakeClock(Arc<Mutex<DateTime<Utc>>>);
+
+    impl FakeClock {
+        fn at(time: DateTime<Utc>) -> Self {
+            Self(Arc::new(Mutex::new(time)))
+        }
+
+        fn set(&self, time: DateTime<Utc>) {
+            *self.0.lock().unwrap() = time;
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().with_timezone(&Utc)
+    }
+
+    fn hhmm(text: &str) -> NaiveTime {
+        parse_hhmm(text).unwrap()
+    }
+
+    fn table() -> Vec<(Trigger, NaiveTime)> {
+        vec![(Trigger::DailyReset, NaiveTime::MIN), (Trigger::Schedule(1), hhmm("00:05")), (Trigger::Backup, hhmm("23:55"))]
+    }
+
+    fn fired<C: Clock>(scheduler: &mut Scheduler<C>, timetable: &[(Trigger, NaiveTime)]) -> Vec<(Trigger, DateTime<Utc>)> {
+        scheduler.due(timetable, &Berlin).into_iter().map(|(t, at)| (t, at.with_timezone(&Utc))).collect()
+    }
+
+    #[test]
+    fn fires_each_occurrence_once() {
+        // 23:50 Berlin (UTC+1)
+        let clock = FakeClock::at(utc("2024-01-10T22:50:00Z"));
+        let mut scheduler = Scheduler::new(clock.clone(), &BTreeMap::new());
+        assert!(fired(&mut scheduler, &table()).is_empty());
+
+        clock.set(utc("2024-01-10T22:56:00Z"));
+        assert_eq!(fired(&mut scheduler, &table()), vec![(Trigger::Backup, utc("2024-01-10T22:55:00Z"))]);
+        assert!(fired(&mut scheduler, &table()).is_empty());
+
+        clock.set(utc("2024-01-10T23:00:30Z"));
+        assert_eq!(fired(&mut scheduler, &table()), vec![(Trigger::DailyReset, utc("2024-01-10T23:00:00Z"))]);
+        clock.set(utc("2024-01-10T23:06:00Z"));
+        assert_eq!(fired(&mut scheduler, &table()), vec![(Trigger::Schedule(1), utc("2024-01-10T23:05:00Z"))]);
+        assert!(fired(&mut scheduler, &table()).is_empty());
+    }
+
+    #[test]
+    fn late_wake_up_catches_up_recent_triggers_and_the_daily_reset() {
+        let clock = FakeClock::at(utc("2024-01-10T22:58:00Z"));
+        let mut scheduler = Scheduler::new(clock.clone(), &BTreeMap::new());
+
+        // Within MAX_CATCH_UP: everything missed fires, oldest first
+        clock.set(utc("2024-01-10T23:07:00Z"));
+        assert_eq!(fired(&mut scheduler, &table()), vec![
+            (Trigger::DailyReset, utc("2024-01-10T23:00:00Z")),
+            (Trigger::Schedule(1), utc("2024-01-10T23:05:00Z")),
+        ]);
+
+        // Asleep for a day and a half: only the daily reset is caught up, once
+        clock.set(utc("2024-01-12T11:00:00Z"));
+        assert_eq!(fired(&mut scheduler, &table()), vec![(Trigger::DailyReset, utc("2024-01-11T23:00:00Z"))]);
+        assert!(fired(&mut scheduler, &table()).is_empty());
+    }
+
+    #[test]
+    fn clock_stepping_back_does_not_repeat_triggers() {
+        let clock = FakeClock::at(utc("2024-01-10T22:59:00Z"));
+        let mut scheduler = Scheduler::new(clock.clone(), &BTreeMap::new());
+        clock.set(utc("2024-01-10T23:01:00Z"));
+        assert_eq!(fired(&mut scheduler, &table()), vec![(Trigger::DailyReset, utc("2024-01-10T23:00:00Z"))]);
+
+        // Back before midnight, then past it again
+        clock.set(utc("2024-01-10T22:50:00Z"));
+        assert!(fired(&mut scheduler, &table()).is_empty());
+        clock.set(utc("2024-01-10T23:02:00Z"));
+        assert!(fired(&mut scheduler, &table()).is_empty());
+
+        clock.set(utc("2024-01-11T23:00:10Z"));
+        assert_eq!(fired(&mut scheduler, &table()), vec![
+            (Trigger::Backup, utc("2024-01-11T22:55:00Z")),
+            (Trigger::DailyReset, utc("2024-01-11T23:00:00Z")),
+        ]);
+    }
+
+    #[test]
+    fn restart_after_midnight_catches_up_the_missed_daily_reset() {
+        // The first run handled a reset and the next evening's backup, then stopped at 23:58
+        let clock = FakeClock::at(utc("2024-01-09T22:59:00Z"));
+        let mut first = Scheduler::new(clock.clone(), &BTreeMap::new());
+        clock.set(utc("2024-01-09T23:00:30Z"));
+        assert_eq!(fired(&mut first, &table()), vec![(Trigger::DailyReset, utc("2024-01-09T23:00:00Z"))]);
+        clock.set(utc("2024-01-10T22:58:00Z"));
+        assert_eq!(fired(&mut first, &table()), vec![(Trigger::Backup, utc("2024-01-10T22:55:00Z"))]);
+        let saved = first.take_handled().unwrap();
+        assert_eq!(first.take_handled(), None);
+
+        // Back up at 00:40: the reset fires late, the 00:05 schedule is skipped
+        clock.set(utc("2024-01-10T23:40:00Z"));
+        let mut second = Scheduler::new(clock.clone(), &saved);
+        assert_eq!(fired(&mut second, &table()), vec![(Trigger::DailyReset, utc("2024-01-10T23:00:00Z"))]);
+        let saved = second.take_handled().unwrap();
+
+        // Restarting again the same night doesn't repeat it
+        clock.set(utc("2024-01-10T23:45:00Z"));
+        let mut third = Scheduler::new(clock.clone(), &saved);
+        assert!(fired(&mut third, &table()).is_empty());
+    }
+
+    #[test]
+    fn first_start_without_history_fires_nothing_from_the_past() {
+        let clock = FakeClock::at(utc("2024-01-10T23:40:00Z"));
+        let mut scheduler = Scheduler::new(clock, &BTreeMap::new());
+        assert!(fired(&mut scheduler, &table()).is_empty());
+    }
+
+    #[test]
+    fn dst_gap_moves_the_trigger_an_hour_later() {
+        // 2024-03-31 02:00 CET jumps to 03:00 CEST; 02:30 doesn't exist that night
+        let timetable = [(Trigger::Schedule(1), hhmm("02:30"))];
+        let clock = FakeClock::at(utc("2024-03-30T23:00:00Z"));
+        let mut scheduler = Scheduler::new(clock.clone(), &BTreeMap::new());
+        let mut fires = Vec::new();
+        let mut now = utc("2024-03-30T23:00:00Z");
+        while now < utc("2024-03-31T04:00:00Z") {
+            now += Duration::minutes(1);
+            clock.set(now);
+            fires.extend(fired(&mut scheduler, &timetable));
+        }
+        // 03:30 CEST
+        assert_eq!(fires, vec![(Trigger::Schedule(1), utc("2024-03-31T01:30:00Z"))]);
+    }
+
+    #[test]
+    fn dst_repeated_hour_fires_once() {
+        // 2024-10-27 03:00 CEST falls back to 02:00 CET; 02:30 happens twice
+        let timetable = [(Trigger::Schedule(1), hhmm("02:30"))];
+        let clock = FakeClock::at(utc("2024-10-26T22:00:00Z"));
+        let mut scheduler = Scheduler::new(clock.clone(), &BTreeMap::new());
+        let mut fires = Vec::new();
+        let mut now = utc("2024-10-26T22:00:00Z");
+        while now < utc("2024-10-27T04:00:00Z") {
+            now += Duration::minutes(1);
+            clock.set(now);
+            fires.extend(fired(&mut scheduler, &timetable));
+        }
+        // The first 02:30, still CEST
+        assert_eq!(fires, vec![(Trigger::Schedule(1), utc("2024-10-27T00:30:00Z"))]);
+    }
+
+    #[test]
+    fn removed_schedules_are_dropped_from_the_saved_state() {
+        let clock = FakeClock::at(utc("2024-01-10T23:04:00Z"));
+        let mut scheduler = Scheduler::new(clock.clone(), &BTreeMap::new());
+        clock.set(utc("2024-01-10T23:06:00Z"));
+        assert_eq!(fired(&mut scheduler, &table()).len(), 1);
+        assert!(scheduler.take_handled().unwrap().contains_key("schedule:1"));
+
+        fired(&mut scheduler, &table()[..1]);
+        assert_eq!(scheduler.take_handled(), Some(BTreeMap::new()));
+    }
+
+    #[test]
+    fn trigger_keys_round_trip() {
+        for trigger in [Trigger::DailyReset, Trigger::Backup, Trigger::Digest, Trigger::Schedule(12)] {
+            assert_eq!(Trigger::from_key(&trigger.key()), Some(trigger));
+        }
+        assert_eq!(Trigger::from_key("schedule:x"), None);
+    }
+}
@@ -0,0 +1,114 @@
+use magic_crypt::MagicCryptTrait;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::db::DbData;
+
+// Stored next to the database file
+const SECRETS_FILE: &str = "secrets.json";
+
+/// Session cookies and restore codes, kept out of db.json so that file can be shared for
+/// debugging. The whole file is encrypted with ENCRYPTION_KEY when it is set; values that
+/// were already encrypted in db.json (codes, personal cookies) stay encrypted inside it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Secrets {
+    #[serde(default)]
+    cookies: Option<String>,
+    #[serde(default)]
+    accounts: Vec<AccountSecrets>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AccountSecrets {
+    name: String,
+    #[serde(default)]
+    deleted: bool,
+    code: String,
+    #[serde(rename = "cookieOverride", default)]
+    cookie_override: Option<String>,
+}
+
+/// On-disk envelope: `data` is the JSON of `Secrets`, base64 ciphertext when `encrypted`.
+#[derive(Serialize, Deserialize)]
+struct SecretsFile {
+    encrypted: bool,
+    data: String,
+}
+
+fn master_key() -> Option<String> {
+    std::env::var("ENCRYPTION_KEY").ok().filter(|k| !k.is_empty() && k != "default_insecure_key")
+}
+
+/// Whether secrets files are written encrypted (ENCRYPTION_KEY is set).
+pub fn encryption_enabled() -> bool {
+    master_key().is_some()
+}
+
+/// Secrets file belonging to the database at `db_path`.
+pub fn path_for(db_path: &str) -> PathBuf {
+    Path::new(db_path).with_file_name(SECRETS_FILE)
+}
+
+impl Secrets {
+    /// Collects the secrets of `data`.
+    pub fn extract(data: &DbData) -> Self {
+        Self {
+            cookies: data.settings.cookies.clone(),
+            accounts: data.accounts.iter()
+                .map(|a| AccountSecrets { name: a.name.clone(), deleted: a.deleted, code: a.code.clone(), cookie_override: a.cookie_override.clone() })
+                .collect(),
+        }
+    }
+
+    /// Number of accounts with a stored restore code.
+    pub fn code_count(&self) -> usize {
+        self.accounts.iter().filter(|a| !a.code.is_empty()).count()
+    }
+
+    /// Puts the secrets back into `data`. Accounts are matched by name (and deleted flag, since a
+    /// removed account may share its name with a new one); accounts without an entry keep what
+    /// db.json had, so pre-migration files and hand-added accounts still work.
+    pub fn apply(self, data: &mut DbData) {
+        if self.cookies.is_some() {
+            data.settings.cookies = self.cookies;
+        }
+        let mut entries = self.accounts;
+        for acc in data.accounts.iter_mut() {
+            if let Some(i) = entries.iter().position(|e| e.name == acc.name && e.deleted == acc.deleted) {
+                let entry = entries.remove(i);
+                acc.code = entry.code;
+                acc.cookie_override = entry.cookie_override;
+            }
+        }
+    }
+
+    /// Reads the secrets file; `Ok(None)` if there is none yet. A file that cannot be
+    /// decrypted is an error, never silently empty, so a wrong key cannot wipe the codes.
+    pub fn read(path: &Path) -> Result<Option<Self>, String> {
+        let content = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Cannot read {}: {}", path.display(), e)),
+        };
+        let file: SecretsFile = serde_json::from_str(&content).map_err(|e| format!("{} is not a valid secrets file: {}", path.display(), e))?;
+        let json = if file.encrypted {
+            let key = master_key().ok_or(format!("{} is encrypted but ENCRYPTION_KEY is not set", path.display()))?;
+            magic_crypt::new_magic_crypt!(&key, 256).decrypt_base64_to_string(&file.data)
+                .map_err(|_| format!("Cannot decrypt {}: wrong ENCRYPTION_KEY?", path.display()))?
+        } else {
+            file.data
+        };
+        serde_json::from_str(&json).map(Some).map_err(|e| format!("{} has invalid contents: {}", path.display(), e))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        let file = match master_key() {
+            Some(key) => SecretsFile { encrypted: true, data: magic_crypt::new_magic_crypt!(&key, 256).encrypt_str_to_base64(&json) },
+            None => SecretsFile { encrypted: false, data: json },
+        };
+        let content = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?;
+        fs::write(path, content).map_err(|e| format!("Cannot write {}: {}", path.display(), e))
+    }
+}
@@ -0,0 +1,285 @@
+use chrono::Weekday;
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, RoleId};
+use std::collections::BTreeMap;
+
+use crate::db::Schedule;
+use crate::history::digest_weekday;
+use crate::i18n::Locale;
+use crate::notifier::{LogLevel, Severity, Sink, DEFAULT_SINKS};
+use crate::permissions::Tier;
+use crate::protocol::endpoint::normalize_base_url;
+
+/// Used for the daily reset, schedules and the digest until /set_timezone is used.
+pub const DEFAULT_TIMEZONE: Tz = chrono_tz::Asia::Jakarta;
+
+pub const DIGEST_DAY_CHOICES: &[&str] = &["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday", "off"];
+
+/// Bot-wide configuration stored in db.json. Discord IDs are kept as typed IDs (still written
+/// as strings, so older files load unchanged); free-form values go through the setters below,
+/// which reject anything the bot could not use later.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Settings {
+    // Kept in secrets.json
+    #[serde(rename = "cookies", skip_serializing, default)]
+    pub cookies: Option<String>,
+    // When /set_cookies last replaced it; a login failure before then doesn't count against the new one
+    #[serde(rename = "cookiesSetAt", with = "crate::timestamp::optional", default, skip_serializing_if = "Option::is_none")]
+    pub cookies_set_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(rename = "adminRoleId", default, deserialize_with = "lenient_role_id")]
+    pub admin_role_id: Option<RoleId>,
+    #[serde(rename = "logChannelId")]
+    pub log_channel_id: Option<ChannelId>,
+    #[serde(rename = "muteBotMessages")]
+    pub mute_bot_messages: Option<bool>,
+    #[serde(rename = "baseUrl", default)]
+    pub base_url: Option<String>,
+    // Empty = commands are accepted in every channel
+    #[serde(rename = "allowedChannels", default)]
+    pub allowed_channels: Vec<ChannelId>,
+    #[serde(default)]
+    pub schedules: Vec<Schedule>,
+    // Server names seen in the game's server-selection lists, used to validate targetServer
+    #[serde(rename = "knownServers", default)]
+    pub known_servers: Vec<String>,
+    // Role ID -> bot tier (viewer / operator / admin); the admin role above counts as admin
+    #[serde(rename = "roleTiers", default)]
+    pub role_tiers: BTreeMap<RoleId, Tier>,
+    // Weekday the weekly digest is posted on ("monday".."sunday" or "off"); unset = Monday
+    #[serde(rename = "digestDay", default)]
+    pub digest_day: Option<String>,
+    // Put accounts left mid-run or mid-retry by a crash back to pending on startup (default: on)
+    #[serde(rename = "resetInterruptedOnStartup", default)]
+    pub reset_interrupted_on_startup: Option<bool>,
+    // Set during game-server maintenance: no runs start, scheduled ones are skipped
+    #[serde(rename = "maintenanceMode", default)]
+    pub maintenance_mode: Option<bool>,
+    // Start accounts whose last completed day is oldest first, so ones that missed a day catch up
+    #[serde(rename = "catchUpFirst", default, skip_serializing_if = "Option::is_none")]
+    pub catch_up_first: Option<bool>,
+    // Skip the nightly batch when the pre-flight check finds the shared cookie rejected (default: off)
+    #[serde(rename = "preflightHalt", default, skip_serializing_if = "Option::is_none")]
+    pub preflight_halt: Option<bool>,
+    // Open a thread per run under the log channel with its progress (default: off)
+    #[serde(rename = "runThreads", default, skip_serializing_if = "Option::is_none")]
+    pub run_threads: Option<bool>,
+    // Where automation outcomes of each severity are sent (/set_notify); unset = the log channel
+    #[serde(rename = "notifySuccess", default, skip_serializing_if = "Option::is_none")]
+    pub notify_success: Option<Vec<Sink>>,
+    #[serde(rename = "notifyFailure", default, skip_serializing_if = "Option::is_none")]
+    pub notify_failure: Option<Vec<Sink>>,
+    #[serde(rename = "notifyCritical", default, skip_serializing_if = "Option::is_none")]
+    pub notify_critical: Option<Vec<Sink>>,
+    // Messages the log channel gets (/set_log_level); unset = everything
+    #[serde(rename = "logLevel", default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<LogLevel>,
+    // IANA name (e.g. "Europe/Berlin") the scheduler's wall-clock times are in; unset = Asia/Jakarta
+    #[serde(default)]
+    pub timezone: Option<String>,
+    // Language of channel messages and of replies to members without their own choice; unset = English
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<Locale>,
+}
+
+impl Settings {
+    /// Stores the endpoint in its normalized form, or clears it with `None`.
+    pub fn set_base_url(&mut self, input: Option<&str>) -> Result<(), String> {
+        self.base_url = match input {
+            Some(input) => Some(normalize_base_url(input)?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    pub fn set_digest_day(&mut self, input: &str) -> Result<(), String> {
+        let day = input.trim().to_lowercase();
+        if !DIGEST_DAY_CHOICES.contains(&day.as_str()) {
+            return Err(format!("Unknown day '{}'. Use one of: {}.", input.trim(), DIGEST_DAY_CHOICES.join(", ")));
+        }
+        self.digest_day = Some(day);
+        Ok(())
+    }
+
+    /// Accepts IANA timezone names such as "Europe/Berlin" or "UTC".
+    pub fn set_timezone(&mut self, input: &str) -> Result<(), String> {
+        self.timezone = Some(parse_timezone(input)?.name().to_string());
+        Ok(())
+    }
+
+    /// Checks values that came from a file rather than the setters (a database or settings import).
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(name) = &self.timezone {
+            parse_timezone(name)?;
+        }
+        if let Some(url) = &self.base_url {
+            normalize_base_url(url).map_err(|e| format!("baseUrl: {}", e))?;
+        }
+        if let Some(day) = &self.digest_day {
+            if !DIGEST_DAY_CHOICES.contains(&day.as_str()) {
+                return Err(format!("digestDay: unknown day '{}'", day));
+            }
+        }
+        for schedule in &self.schedules {
+            if chrono::NaiveTime::parse_from_str(&schedule.time, "%H:%M").is_err() {
+                return Err(format!("schedule #{} has an invalid time '{}'", schedule.id, schedule.time));
+            }
+        }
+        Ok(())
+    }
+
+    /// Timezone the daily reset, schedules and digest run in. An invalid stored name falls back to the default.
+    pub fn timezone(&self) -> Tz {
+        self.timezone.as_deref().and_then(|name| name.parse().ok()).unwrap_or(DEFAULT_TIMEZONE)
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.mute_bot_messages.unwrap_or(false)
+    }
+
+    /// `None` when the weekly digest is turned off.
+    pub fn digest_weekday(&self) -> Option<Weekday> {
+        digest_weekday(self.digest_day.as_deref())
+    }
+
+    pub fn locale(&self) -> Locale {
+        self.locale.unwrap_or_default()
+    }
+
+    pub fn is_maintenance(&self) -> bool {
+        self.maintenance_mode.unwrap_or(false)
+    }
+
+    pub fn catch_up_first(&self) -> bool {
+        self.catch_up_first.unwrap_or(false)
+    }
+
+    pub fn preflight_halt(&self) -> bool {
+        self.preflight_halt.unwrap_or(false)
+    }
+
+    /// Channel run threads are opened in: the log channel, if run threads are on and the bot isn't muted.
+    pub fn run_thread_parent(&self) -> Option<ChannelId> {
+        if self.run_threads.unwrap_or(false) && !self.is_muted() {
+            self.log_channel_id
+        } else {
+            None
+        }
+    }
+
+    pub fn notify_sinks(&self, severity: Severity) -> &[Sink] {
+        let configured = match severity {
+            Severity::Success => &self.notify_success,
+            Severity::Failure => &self.notify_failure,
+            Severity::Critical => &self.notify_critical,
+        };
+        configured.as_deref().unwrap_or(DEFAULT_SINKS)
+    }
+
+    pub fn set_notify_sinks(&mut self, severity: Severity, sinks: Vec<Sink>) {
+        let configured = match severity {
+            Severity::Success => &mut self.notify_success,
+            Severity::Failure => &mut self.notify_failure,
+            Severity::Critical => &mut self.notify_critical,
+        };
+        *configured = Some(sinks);
+    }
+
+    pub fn log_level(&self) -> LogLevel {
+        self.log_level.unwrap_or_default()
+    }
+
+    pub fn reset_interrupted_on_startup(&self) -> bool {
+        self.reset_interrupted_on_startup.unwrap_or(true)
+    }
+
+    /// Every setting for /settings. The session cookie is only reported as present, never shown.
+    pub fn overview(&self) -> Vec<SettingLine> {
+        let role_tiers = self.role_tiers.iter()
+            .map(|(role, tier)| format!("<@&{}>: {}", role, tier.label()))
+            .collect::<Vec<_>>();
+        let schedules = self.schedules.iter()
+            .map(|s| format!("#{} {} · {} · {}", s.id, s.time, s.filter, s.flow_label()))
+            .collect::<Vec<_>>();
+        let notifications = [&self.notify_success, &self.notify_failure, &self.notify_critical].iter().any(|s| s.is_some())
+            .then(|| Severity::ALL.iter().map(|&severity| {
+                let sinks = self.notify_sinks(severity).iter().map(|s| s.label()).collect::<Vec<_>>();
+                format!("{}: {}", severity.label(), if sinks.is_empty() { "none".to_string() } else { sinks.join(", ") })
+            }).collect::<Vec<_>>().join(" · "));
+        let digest = self.digest_day.as_ref().map(|_| match self.digest_weekday() {
+            Some(day) => format!("{:?}", day),
+            None => "Off".to_string(),
+        });
+
+        vec![
+            SettingLine::new("Session cookie", self.cookies.as_ref().filter(|c| !c.trim().is_empty()).map(|_| "Set".to_string()), None),
+            SettingLine::new("Admin role", self.admin_role_id.map(|r| format!("<@&{}>", r)), None),
+            SettingLine::new("Role tiers", non_empty(role_tiers), Some("only the admin role")),
+            SettingLine::new("Log channel", self.log_channel_id.map(|c| format!("<#{}>", c)), None),
+            SettingLine::new("Log channel level", self.log_level.map(|l| format!("{} ({})", l.label(), l.description())), Some("verbose (everything)")),
+            SettingLine::new("Bot messages", self.mute_bot_messages.map(|m| if m { "Muted" } else { "On" }.to_string()), Some("On")),
+            SettingLine::new("Websocket endpoint", self.base_url.as_ref().map(|u| format!("`{}`", u)), Some("EVERTEXT_WS_URL or the production server")),
+            SettingLine::new("Command channels", non_empty(self.allowed_channels.iter().map(|c| format!("<#{}>", c)).collect()), Some("every channel")),
+            SettingLine::new("Timezone", self.timezone.clone(), Some(DEFAULT_TIMEZONE.name())),
+            SettingLine::new("Language", self.locale.map(|l| l.label().to_string()), Some("English, or each member's Discord language")),
+            SettingLine::new("Schedules", non_empty(schedules), Some("daily reset only")),
+            SettingLine::new("Weekly summary day", digest, Some("Monday")),
+            SettingLine::new("Reset interrupted runs on startup", self.reset_interrupted_on_startup.map(|r| if r { "Yes" } else { "No" }.to_string()), Some("Yes")),
+            SettingLine::new("Maintenance mode", self.maintenance_mode.map(|m| if m { "On" } else { "Off" }.to_string()), Some("Off")),
+            SettingLine::new("Missed accounts first", self.catch_up_first.map(|c| if c { "On" } else { "Off" }.to_string()), Some("Off")),
+            SettingLine::new("Halt on cookie pre-flight failure", self.preflight_halt.map(|h| if h { "On" } else { "Off" }.to_string()), Some("Off")),
+            SettingLine::new("Notifications", notifications, Some("log channel for every outcome")),
+            SettingLine::new("Run threads", self.run_threads.map(|r| if r { "On" } else { "Off" }.to_string()), Some("Off")),
+            SettingLine::new("Known servers", non_empty(self.known_servers.clone()), None),
+        ]
+    }
+}
+
+/// A hand-edited or corrupted admin role id is treated as unset (with a warning) instead of
+/// failing to load the whole database; /set_admin_role can then set it again.
+fn lenient_role_id<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<RoleId>, D::Error> {
+    let value = serde_json::Value::deserialize(deserializer)?;
+    let id = match &value {
+        serde_json::Value::Null => return Ok(None),
+        // What a fresh db.json holds before /set_admin_role
+        serde_json::Value::String(s) if s.trim().is_empty() => return Ok(None),
+        serde_json::Value::String(s) => s.trim().parse::<u64>().ok(),
+        serde_json::Value::Number(n) => n.as_u64(),
+        _ => None,
+    };
+    match id.filter(|id| *id != 0) {
+        Some(id) => Ok(Some(RoleId::new(id))),
+        None => {
+            log!("[WARN] Settings: adminRoleId {} is not a valid role id; treating the admin role as unset.", value);
+            Ok(None)
+        }
+    }
+}
+
+/// Parses an IANA timezone name, with an error message fit for Discord.
+pub fn parse_timezone(input: &str) -> Result<Tz, String> {
+    input.trim().parse()
+        .map_err(|_| format!("Unknown timezone '{}'. Use an IANA name such as Asia/Jakarta, Europe/Berlin or UTC.", input.trim()))
+}
+
+/// One row of the /settings view. `value` is `None` when the setting is unset; `default`
+/// says what the bot does in that case, if anything.
+pub struct SettingLine {
+    pub label: &'static str,
+    pub value: Option<String>,
+    pub default: Option<&'static str>,
+}
+
+impl SettingLine {
+    fn new(label: &'static str, value: Option<String>, default: Option<&'static str>) -> Self {
+        Self { label, value, default }
+    }
+}
+
+fn non_empty(items: Vec<String>) -> Option<String> {
+    if items.is_empty() {
+        None
+    } else {
+        Some(items.join("\n"))
+    }
+}
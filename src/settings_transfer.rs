@@ -0,0 +1,80 @@
+use magic_crypt::MagicCryptTrait;
+use serde::{Deserialize, Serialize};
+
+use crate::settings::Settings;
+
+pub const MIN_PASSPHRASE_LEN: usize = 8;
+
+/// The file /export_settings writes and /import_settings reads, for moving the bot to another
+/// host: every bot-wide setting (roles, channels, schedules...) and, if asked for, the session
+/// cookie. The cookie is encrypted with a passphrase of the admin's choosing, not ENCRYPTION_KEY,
+/// since the new host may use a different key.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsExport {
+    pub exported_at: String,
+    pub version: String,
+    pub settings: Settings,
+    /// `ExportedSecrets` as JSON, encrypted with the passphrase (base64)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secrets: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportedSecrets {
+    cookies: Option<String>,
+}
+
+fn check_passphrase(passphrase: &str) -> Result<(), String> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(format!("The passphrase must be at least {} characters long.", MIN_PASSPHRASE_LEN));
+    }
+    Ok(())
+}
+
+impl SettingsExport {
+    /// Exports `settings`; with a passphrase the session cookie is included, encrypted with it.
+    pub fn new(settings: &Settings, passphrase: Option<&str>) -> Result<Self, String> {
+        let secrets = match passphrase {
+            Some(passphrase) => {
+                check_passphrase(passphrase)?;
+                let json = serde_json::to_string(&ExportedSecrets { cookies: settings.cookies.clone() }).map_err(|e| e.to_string())?;
+                Some(magic_crypt::new_magic_crypt!(passphrase, 256).encrypt_str_to_base64(json))
+            }
+            None => None,
+        };
+        Ok(Self {
+            exported_at: chrono::Utc::now().to_rfc3339(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            settings: settings.clone(),
+            secrets,
+        })
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let export: Self = serde_json::from_slice(bytes).map_err(|e| format!("This is not a settings export: {}", e))?;
+        export.settings.validate()?;
+        Ok(export)
+    }
+
+    pub fn has_secrets(&self) -> bool {
+        self.secrets.is_some()
+    }
+
+    /// The settings to install. The session cookie is only filled in when the file has one and
+    /// `passphrase` is given; a wrong passphrase is an error rather than a silently missing cookie.
+    pub fn into_settings(self, passphrase: Option<&str>) -> Result<Settings, String> {
+        let mut settings = self.settings;
+        settings.cookies = match (self.secrets, passphrase) {
+            (Some(secrets), Some(passphrase)) => {
+                let json = magic_crypt::new_magic_crypt!(passphrase, 256).decrypt_base64_to_string(&secrets)
+                    .map_err(|_| "Wrong passphrase: the secrets in this file could not be decrypted.".to_string())?;
+                let secrets: ExportedSecrets = serde_json::from_str(&json)
+                    .map_err(|_| "Wrong passphrase: the secrets in this file could not be decrypted.".to_string())?;
+                secrets.cookies
+            }
+            _ => None,
+        };
+        Ok(settings)
+    }
+}
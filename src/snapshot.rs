@@ -0,0 +1,133 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::db::{Account, AccountFilter, STATUS_INVALID_CODE};
+use crate::protocol::flow::Flow;
+use crate::queue::{batch_order, PendingRun, RetryCounts};
+
+/// Everything that decides which account the queue runs next, as written by /debug_snapshot.
+/// Restore codes and cookies are never part of it (they are not serialized with accounts).
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueSnapshot {
+    pub taken_at: String,
+    pub version: String,
+    /// "Running", "Paused" or "Stopped"
+    pub state: String,
+    pub maintenance: bool,
+    /// The request the worker is processing
+    pub request: Option<SnapshotRequest>,
+    /// Account of the session in progress
+    pub current_account: Option<String>,
+    /// Accounts still to run in this batch, next one first
+    pub order: Vec<String>,
+    /// Run requests waiting for the batch, oldest first
+    pub pending: Vec<SnapshotRequest>,
+    /// Accounts with a live session (queue or /force_run)
+    pub in_flight: Vec<String>,
+    pub retries: BTreeMap<String, RetryCounts>,
+    pub crashed: Vec<String>,
+    pub accounts: Vec<Account>,
+    /// Whether missed accounts went first (older snapshots: no)
+    #[serde(default)]
+    pub catch_up_first: bool,
+    /// Day of each account's last successful run, as YYYY-MM-DD
+    #[serde(default)]
+    pub last_completed: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SnapshotRequest {
+    pub filter: AccountFilter,
+    pub flow: Option<Flow>,
+    // Absent in snapshots taken before dry runs existed
+    #[serde(default)]
+    pub dry_run: bool,
+    pub channel: Option<String>,
+}
+
+impl From<&PendingRun> for SnapshotRequest {
+    fn from(run: &PendingRun) -> Self {
+        Self { filter: run.filter.clone(), flow: run.flow, dry_run: run.dry_run, channel: run.channel.map(|c| c.to_string()) }
+    }
+}
+
+impl QueueSnapshot {
+    /// Whether the worker would still pick `account` for the recorded request.
+    fn runnable(&self, account: &Account, filter: &AccountFilter) -> bool {
+        !account.deleted
+            && account.status != "done" && account.status != STATUS_INVALID_CODE
+            && !self.retries.get(&account.name).map(|r| r.exhausted).unwrap_or(false)
+            && !self.crashed.contains(&account.name)
+            && filter.matches(account)
+    }
+
+    /// Replays the queue's ordering rules against the snapshot's accounts and compares the result
+    /// with the recorded order. Returns the report lines.
+    pub fn replay(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "Snapshot taken {} (bot {}), queue {}{}.",
+            self.taken_at, self.version, self.state, if self.maintenance { ", maintenance mode on" } else { "" }
+        )];
+        let request = match &self.request {
+            Some(request) => request,
+            None => {
+                lines.push("No batch was running; nothing to replay.".to_string());
+                return lines;
+            }
+        };
+        let filter: String = request.filter.clone().into();
+        lines.push(format!("Request: {} (flow {}{})", filter, request.flow.map(|f| f.name()).unwrap_or("per account"), if request.dry_run { ", dry run" } else { "" }));
+        if let Some(account) = &self.current_account {
+            lines.push(format!("Running: {}", account));
+        }
+        lines.push(format!("Recorded order: {}", list(&self.order)));
+        if self.catch_up_first {
+            lines.push("Missed accounts first: on".to_string());
+        }
+
+        let last_completed: HashMap<String, NaiveDate> = self.last_completed.iter()
+            .filter_map(|(name, day)| day.parse().ok().map(|day| (name.clone(), day)))
+            .collect();
+        let fresh = batch_order(
+            self.accounts.iter().filter(|a| self.runnable(a, &request.filter)),
+            &request.filter,
+            self.catch_up_first.then_some(&last_completed),
+        );
+        lines.push(format!("A new batch for this request would run: {}", list(&fresh)));
+
+        for name in &self.order {
+            match self.accounts.iter().find(|a| a.name == *name) {
+                None => lines.push(format!("[WARN] {} is queued but not in the database; it will be skipped.", name)),
+                Some(acc) if !self.runnable(acc, &request.filter) => {
+                    lines.push(format!("[WARN] {} is queued but would be skipped (status: {}).", name, acc.status))
+                }
+                Some(_) => {}
+            }
+        }
+        for name in fresh.iter().filter(|n| !self.order.contains(n) && self.current_account.as_ref() != Some(*n)) {
+            lines.push(format!("[INFO] {} is runnable but not queued; it joins when the current order is through.", name));
+        }
+        for (name, counts) in &self.retries {
+            lines.push(format!(
+                "Retries of {}: {} attempt(s), {} inline retry(ies), {} requeue(s){}",
+                name, counts.attempts, counts.inline_retries, counts.requeues, if counts.exhausted { ", given up" } else { "" }
+            ));
+        }
+        for (i, waiting) in self.pending.iter().enumerate() {
+            let filter: String = waiting.filter.clone().into();
+            lines.push(format!("Waiting request #{}: {}", i + 1, filter));
+        }
+        lines
+    }
+}
+
+fn list(names: &[String]) -> String {
+    if names.is_empty() {
+        "(none)".to_string()
+    } else {
+        names.join(", ")
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+
+use crate::i18n::{Locale, BUNDLES};
+
+/// A message server admins may reword or translate: its key in the templates file, the built-in
+/// English text and the `{variables}` it can use.
+pub struct Template {
+    pub key: &'static str,
+    pub default: &'static str,
+    pub vars: &'static [&'static str],
+}
+
+pub const QUEUE_STARTED: Template = Template {
+    key: "queue_started",
+    default: "[INFO] Queue Manager: Starting automation sequence...",
+    vars: &[],
+};
+pub const QUEUE_PAUSED: Template = Template {
+    key: "queue_paused",
+    default: "[INFO] Queue Manager: Paused. Remaining accounts are held until /resume_queue.",
+    vars: &[],
+};
+pub const QUEUE_FINISHED: Template = Template {
+    key: "queue_finished",
+    default: "[INFO] Queue Manager: Processing finished.",
+    vars: &[],
+};
+pub const RUN_BUSY: Template = Template {
+    key: "run_busy",
+    default: "[WARN] **{account}** is already running in another session. Skipping it in this batch.",
+    vars: &["account"],
+};
+pub const RUN_SUCCESS: Template = Template {
+    key: "run_success",
+    default: "[SUCCESS] **{account}** completed. {summary}{servers} {stats}",
+    vars: &["account", "summary", "servers", "stats", "duration"],
+};
+pub const RUN_FAILED: Template = Template {
+    key: "run_failed",
+    default: "[ERROR] **{account}** failed: {error}{hint}",
+    vars: &["account", "error", "hint", "duration"],
+};
+pub const RUN_TIMED_OUT: Template = Template {
+    key: "run_timed_out",
+    default: "[ERROR] **{account}** ran longer than {limit} and was aborted.",
+    vars: &["account", "limit"],
+};
+pub const RUN_RETRYING: Template = Template {
+    key: "run_retrying",
+    default: "[WARN] {reason} on **{account}**. Retrying in {delay} (attempt {attempt})...",
+    vars: &["account", "reason", "delay", "attempt"],
+};
+pub const RUN_REQUEUED: Template = Template {
+    key: "run_requeued",
+    default: "[WARN] {reason} on **{account}** after {attempt} attempts. Moving it to the back of the queue.",
+    vars: &["account", "reason", "attempt"],
+};
+pub const RUN_GAVE_UP: Template = Template {
+    key: "run_gave_up",
+    default: "[ERROR] {reason} on **{account}**. Giving up after {attempt} attempts in this batch.",
+    vars: &["account", "reason", "attempt"],
+};
+pub const RUN_CANCELLED: Template = Template {
+    key: "run_cancelled",
+    default: "[CANCELLED] **{account}** was cancelled and is back to pending.",
+    vars: &["account"],
+};
+pub const RUN_INVALID_CODE: Template = Template {
+    key: "run_invalid_code",
+    default: "[ERROR] **{account}** has a malformed restore code. Skipping until it is re-added.",
+    vars: &["account"],
+};
+pub const RUN_NO_COOKIE: Template = Template {
+    key: "run_no_cookie",
+    default: "[ERROR] **{account}** skipped: no session cookie is set. Set one with /set_my_cookie, or ask an admin for /set_cookies.{owner}",
+    vars: &["account", "owner"],
+};
+pub const RUN_OWN_COOKIE_EXPIRED: Template = Template {
+    key: "run_own_cookie_expired",
+    default: "[ERROR] The session cookie set for **{account}** has expired. Update it with /set_my_cookie.",
+    vars: &["account"],
+};
+pub const RUN_COOKIE_EXPIRED: Template = Template {
+    key: "run_cookie_expired",
+    default: "⚠️ **CRITICAL: Session cookie expired!** Stopping queue.",
+    vars: &["account"],
+};
+
+// Command replies
+pub const CHANNEL_NOT_ALLOWED: Template = Template {
+    key: "channel_not_allowed",
+    default: "Bot commands are not enabled in this channel. Please use: {channels}",
+    vars: &["channels"],
+};
+pub const COOLDOWN: Template = Template {
+    key: "cooldown",
+    default: "You used /{command} recently. Try again in {seconds}s.",
+    vars: &["command", "seconds"],
+};
+pub const TIER_REQUIRED: Template = Template {
+    key: "tier_required",
+    default: "This command requires the **{tier}** tier or higher.",
+    vars: &["tier"],
+};
+pub const MAINTENANCE: Template = Template {
+    key: "maintenance",
+    default: "🛠️ The bot is in maintenance mode (the game server is down for maintenance), so no runs are started. Account commands still work.",
+    vars: &[],
+};
+pub const READ_ONLY: Template = Template {
+    key: "read_only",
+    default: "🛑 The bot can't save its database, so it is read-only: no runs are started and no accounts can be added until saving works again.",
+    vars: &[],
+};
+pub const QUEUE_WAITING: Template = Template {
+    key: "queue_waiting",
+    default: "A batch is already running. Your request is #{position} in line and starts when it finishes.",
+    vars: &["position"],
+};
+pub const QUEUE_FULL: Template = Template {
+    key: "queue_full",
+    default: "A batch is already running and {limit} requests are waiting. Try again later.",
+    vars: &["limit"],
+};
+pub const ACCOUNT_NOT_FOUND: Template = Template {
+    key: "account_not_found",
+    default: "Account **{account}** not found.",
+    vars: &["account"],
+};
+pub const ACCOUNT_ADDED: Template = Template {
+    key: "account_added",
+    default: "Successfully added account **{account}**.",
+    vars: &["account"],
+};
+pub const INVALID_CODE: Template = Template {
+    key: "invalid_code",
+    default: "Invalid restore code for **{account}**: {reason}\nDouble-check the code from the game's restore screen and try again.",
+    vars: &["account", "reason"],
+};
+pub const DUPLICATE_CODE: Template = Template {
+    key: "duplicate_code",
+    default: "This restore code is already registered as **{existing}**. Running the same game account under two names gets it flagged by Zigza, so it was not added.\nAn admin can add it anyway with `allow_duplicate: true`.",
+    vars: &["existing"],
+};
+pub const FORCE_RUN_STARTED: Template = Template {
+    key: "force_run_started",
+    default: "Force run initiated for **{account}**.",
+    vars: &["account"],
+};
+pub const LANGUAGE_SET: Template = Template {
+    key: "language_set",
+    default: "Replies to you are now in **{language}**.",
+    vars: &["language"],
+};
+
+pub const NOT_OWNER: Template = Template {
+    key: "not_owner",
+    default: "Only the owner of **{account}** or an admin can change it.",
+    vars: &["account"],
+};
+pub const OWNER_ONLY: Template = Template {
+    key: "owner_only",
+    default: "Only the owner of **{account}** can change that.",
+    vars: &["account"],
+};
+pub const ACCOUNT_REMOVED: Template = Template {
+    key: "account_removed",
+    default: "Successfully removed account **{account}**. It can be brought back with /restore_account until an admin purges deleted accounts.",
+    vars: &["account"],
+};
+pub const ACCOUNT_RESTORED: Template = Template {
+    key: "account_restored",
+    default: "Account **{account}** restored.",
+    vars: &["account"],
+};
+pub const ACCOUNT_NAME_DELETED: Template = Template {
+    key: "account_name_deleted",
+    default: "**{account}** is the name of a removed account. Bring it back with /restore_account, or ask an admin to /purge_deleted before reusing the name.",
+    vars: &["account"],
+};
+pub const DELETED_NOT_FOUND: Template = Template {
+    key: "deleted_not_found",
+    default: "No deleted account named **{account}**.",
+    vars: &["account"],
+};
+pub const RUN_MENU_EMPTY: Template = Template {
+    key: "run_menu_empty",
+    default: "None of your accounts are waiting to run (everything is done or you have no accounts).",
+    vars: &[],
+};
+pub const RUN_COOLDOWN: Template = Template {
+    key: "run_cooldown",
+    default: "**{account}** completed a run recently. It can be run again in {remaining} (<t:{until}:t>).",
+    vars: &["account", "remaining", "until"],
+};
+pub const DONE_TODAY: Template = Template {
+    key: "done_today",
+    default: "**{account}** already completed since the daily reset. Pass force:True to run it again.",
+    vars: &["account"],
+};
+pub const DRY_RUN_STARTED: Template = Template {
+    key: "dry_run_started",
+    default: "Dry run started for **{account}**: it logs in and stops at the server list.",
+    vars: &["account"],
+};
+pub const FORCE_RUN_NOT_OWNER: Template = Template {
+    key: "force_run_not_owner",
+    default: "Only the owner of **{account}** or an operator can force a run of it.",
+    vars: &["account"],
+};
+pub const FORCE_RUN_MINE: Template = Template {
+    key: "force_run_mine",
+    default: "Queued all your accounts for execution.",
+    vars: &[],
+};
+pub const FORCE_RUN_MINE_DRY: Template = Template {
+    key: "force_run_mine_dry",
+    default: "Queued a dry run of your accounts that haven't completed yet.",
+    vars: &[],
+};
+pub const FORCE_RUN_QUEUED: Template = Template {
+    key: "force_run_queued",
+    default: "Queued {count} account(s) in this order: {accounts}",
+    vars: &["count", "accounts"],
+};
+pub const FORCE_RUN_QUEUED_DRY: Template = Template {
+    key: "force_run_queued_dry",
+    default: "Queued a dry run of {count} account(s) in this order: {accounts}",
+    vars: &["count", "accounts"],
+};
+pub const FORCE_RUN_NO_MATCH: Template = Template {
+    key: "force_run_no_match",
+    default: "No accounts match `{pattern}`.",
+    vars: &["pattern"],
+};
+pub const FORCE_RUN_ALL_COOLING: Template = Template {
+    key: "force_run_all_cooling",
+    default: "All matching accounts completed a run recently and are cooling down: {accounts}. Try again later.",
+    vars: &["accounts"],
+};
+pub const FORCE_RUN_ALL_DONE: Template = Template {
+    key: "force_run_all_done",
+    default: "All matching accounts already completed since the daily reset: {accounts}. Pass force:True to run them again.",
+    vars: &["accounts"],
+};
+pub const FORCE_RUN_UNMATCHED: Template = Template {
+    key: "force_run_unmatched",
+    default: "No match for: {entries}",
+    vars: &["entries"],
+};
+pub const FORCE_RUN_SKIPPED_DONE: Template = Template {
+    key: "force_run_skipped_done",
+    default: "Skipped, already completed since the daily reset: {accounts} (use force:True to include them)",
+    vars: &["accounts"],
+};
+pub const FORCE_RUN_SKIPPED_COOLING: Template = Template {
+    key: "force_run_skipped_cooling",
+    default: "Skipped, cooling down after a recent run: {accounts}",
+    vars: &["accounts"],
+};
+
+const ALL: &[&Template] = &[
+    &QUEUE_STARTED, &QUEUE_PAUSED, &QUEUE_FINISHED, &RUN_BUSY, &RUN_SUCCESS, &RUN_FAILED,
+    &RUN_TIMED_OUT, &RUN_RETRYING, &RUN_REQUEUED, &RUN_GAVE_UP, &RUN_CANCELLED, &RUN_INVALID_CODE,
+    &RUN_NO_COOKIE, &RUN_OWN_COOKIE_EXPIRED, &RUN_COOKIE_EXPIRED,
+    &CHANNEL_NOT_ALLOWED, &COOLDOWN, &TIER_REQUIRED, &MAINTENANCE, &READ_ONLY, &QUEUE_WAITING,
+    &QUEUE_FULL, &ACCOUNT_NOT_FOUND, &ACCOUNT_ADDED, &INVALID_CODE, &DUPLICATE_CODE,
+    &FORCE_RUN_STARTED, &LANGUAGE_SET, &NOT_OWNER, &OWNER_ONLY, &ACCOUNT_REMOVED, &ACCOUNT_RESTORED,
+    &ACCOUNT_NAME_DELETED, &DELETED_NOT_FOUND, &RUN_MENU_EMPTY, &RUN_COOLDOWN, &DONE_TODAY, &DRY_RUN_STARTED,
+    &FORCE_RUN_NOT_OWNER, &FORCE_RUN_MINE, &FORCE_RUN_MINE_DRY, &FORCE_RUN_QUEUED,
+    &FORCE_RUN_QUEUED_DRY, &FORCE_RUN_NO_MATCH, &FORCE_RUN_ALL_COOLING, &FORCE_RUN_ALL_DONE,
+    &FORCE_RUN_UNMATCHED, &FORCE_RUN_SKIPPED_DONE, &FORCE_RUN_SKIPPED_COOLING,
+];
+
+/// The wording in effect per language: the built-in English texts, the bundled translations and
+/// the overrides of the templates file, later ones winning.
+#[derive(Debug, Clone, Default)]
+pub struct Templates {
+    overrides: HashMap<(Locale, &'static str), String>,
+    customized: usize,
+}
+
+impl Templates {
+    /// The built-in texts and translations.
+    pub fn bundled() -> Result<Self, String> {
+        let mut templates = Self::default();
+        for (locale, text) in BUNDLES {
+            templates.merge(text, Some(*locale)).map_err(|e| format!("built-in {} translation: {}", locale.code(), e))?;
+        }
+        Ok(templates)
+    }
+
+    /// The bundled texts reworded by a templates file: top-level `key = "text"` lines change the
+    /// English text, lines under `[id]` (or another language code) that translation. Unknown keys
+    /// and variables a template doesn't offer are errors, so typos don't silently fall back.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+        let mut templates = Self::bundled()?;
+        templates.customized = templates.merge(&text, None).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(templates)
+    }
+
+    /// Adds the messages of `text`; `locale` is the language of the whole text, or `None` to take
+    /// it from the section. Returns how many were read.
+    fn merge(&mut self, text: &str, locale: Option<Locale>) -> Result<usize, String> {
+        let entries = crate::config::parse_toml(text)?;
+        let count = entries.len();
+        for (full_key, value) in entries {
+            let (locale, key) = match (locale, full_key.split_once('.')) {
+                (Some(locale), _) => (locale, full_key.as_str()),
+                (None, Some((section, key))) => (Locale::parse(section).map_err(|_| format!("unknown language section [{}]", section))?, key),
+                (None, None) => (Locale::En, full_key.as_str()),
+            };
+            let template = ALL.iter().find(|t| t.key == key)
+                .ok_or_else(|| format!("unknown message '{}'. Known messages: {}", key, ALL.iter().map(|t| t.key).collect::<Vec<_>>().join(", ")))?;
+            if let Some(unknown) = placeholders(&value).into_iter().find(|v| !template.vars.contains(v)) {
+                return Err(format!("{}: unknown variable {{{}}}. It can use: {}", full_key, unknown,
+                    if template.vars.is_empty() { "none".to_string() } else { template.vars.iter().map(|v| format!("{{{}}}", v)).collect::<Vec<_>>().join(", ") }));
+            }
+            self.overrides.insert((locale, template.key), value);
+        }
+        Ok(count)
+    }
+
+    /// How many messages the templates file rewords.
+    pub fn customized(&self) -> usize {
+        self.customized
+    }
+
+    /// Fills in `template` in `locale` with `values`; variables without a value are left empty.
+    /// Untranslated messages use the (possibly reworded) English text.
+    pub fn render(&self, locale: Locale, template: &Template, values: &[(&str, &str)]) -> String {
+        let text = self.overrides.get(&(locale, template.key))
+            .or_else(|| self.overrides.get(&(Locale::En, template.key)))
+            .map(|s| s.as_str())
+            .unwrap_or(template.default);
+        let mut out = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(open) = rest.find('{') {
+            out.push_str(&rest[..open]);
+            let after = &rest[open + 1..];
+            match after.find('}').map(|close| (&after[..close], close)) {
+                Some((name, close)) if is_variable(name) => {
+                    out.push_str(values.iter().find(|(k, _)| *k == name).map(|(_, v)| *v).unwrap_or(""));
+                    rest = &after[close + 1..];
+                }
+                _ => {
+                    out.push('{');
+                    rest = after;
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+}
+
+/// `template` in `locale`, in the configured wording.
+pub fn render(locale: Locale, template: &Template, values: &[(&str, &str)]) -> String {
+    crate::config::get().templates.render(locale, template, values)
+}
+
+fn is_variable(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_')
+}
+
+fn placeholders(text: &str) -> Vec<&str> {
+    let mut found = Vec::new();
+    let mut rest = text;
+    while let Some(open) = rest.find('{') {
+        let after = &rest[open + 1..];
+        match after.find('}') {
+            Some(close) if is_variable(&after[..close]) => {
+                found.push(&after[..close]);
+                rest = &after[close + 1..];
+            }
+            _ => rest = after,
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_translations_load() {
+        let templates = Templates::bundled().unwrap();
+        assert_eq!(
+            templates.render(Locale::Id, &FORCE_RUN_NO_MATCH, &[("pattern", "alt*")]),
+            "Tidak ada akun yang cocok dengan `alt*`."
+        );
+    }
+
+    #[test]
+    fn untranslated_messages_fall_back_to_english() {
+        let mut templates = Templates::default();
+        templates.merge("[id]\naccount_added = \"Akun **{account}** ditambahkan.\"", None).unwrap();
+        assert_eq!(templates.render(Locale::Id, &ACCOUNT_ADDED, &[("account", "alt1")]), "Akun **alt1** ditambahkan.");
+        assert_eq!(templates.render(Locale::Id, &FORCE_RUN_MINE, &[]), FORCE_RUN_MINE.default);
+    }
+
+    #[test]
+    fn unknown_keys_and_variables_are_rejected() {
+        let mut templates = Templates::default();
+        assert!(templates.merge("no_such_message = \"x\"", None).is_err());
+        assert!(templates.merge("account_added = \"{user}\"", None).is_err());
+        assert!(templates.merge("[xx]\naccount_added = \"x\"", None).is_err());
+    }
+}
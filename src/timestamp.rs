@@ -0,0 +1,105 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+/// Reads a stored timestamp: an RFC 3339 string in any offset (what every version so far wrote)
+/// or milliseconds since the epoch (the Node version's `Date.now()`).
+pub fn parse(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::String(text) => DateTime::parse_from_rfc3339(text.trim()).ok().map(|t| t.with_timezone(&Utc)),
+        Value::Number(millis) => DateTime::from_timestamp_millis(millis.as_i64()?),
+        _ => None,
+    }
+}
+
+/// Serde for required timestamps. Written as RFC 3339 in UTC; see `parse` for what is read.
+pub mod required {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(time: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+        time.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        parse(&value).ok_or_else(|| serde::de::Error::custom(format!("invalid timestamp {}", value)))
+    }
+}
+
+/// Serde for optional timestamps. A value that cannot be read becomes `None` instead of failing
+/// the whole database, so one damaged field only loses that field.
+pub mod optional {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(time: &Option<DateTime<Utc>>, serializer: S) -> Result<S::Ok, S::Error> {
+        time.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<Utc>>, D::Error> {
+        Ok(parse(&Value::deserialize(deserializer)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[derive(Deserialize)]
+    struct Required {
+        #[serde(with = "required")]
+        at: DateTime<Utc>,
+    }
+
+    #[derive(Deserialize)]
+    struct Optional {
+        #[serde(with = "optional", default)]
+        at: Option<DateTime<Utc>>,
+    }
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn reads_rfc3339_in_utc() {
+        assert_eq!(parse(&json!("2024-03-01T05:00:00+00:00")), Some(utc("2024-03-01T05:00:00Z")));
+        assert_eq!(parse(&json!("2024-03-01T05:00:00.250Z")), Some(utc("2024-03-01T05:00:00.250Z")));
+    }
+
+    #[test]
+    fn converts_other_offsets_to_utc() {
+        assert_eq!(parse(&json!("2024-03-01T12:00:00+07:00")), Some(utc("2024-03-01T05:00:00Z")));
+        assert_eq!(parse(&json!(" 2024-02-29T23:30:00-05:30 ")), Some(utc("2024-03-01T05:00:00Z")));
+    }
+
+    #[test]
+    fn reads_node_epoch_milliseconds() {
+        // Date.now() of 2024-03-01T05:00:00.250Z
+        assert_eq!(parse(&json!(1709269200250i64)), Some(utc("2024-03-01T05:00:00.250Z")));
+        assert_eq!(parse(&json!(0)), Some(utc("1970-01-01T00:00:00Z")));
+    }
+
+    #[test]
+    fn garbage_is_none_when_optional_and_an_error_when_required() {
+        for garbage in [json!("yesterday"), json!(""), json!(true), json!({"at": 1}), json!(1.5)] {
+            assert_eq!(parse(&garbage), None, "{}", garbage);
+            let optional: Optional = serde_json::from_value(json!({ "at": garbage })).unwrap();
+            assert_eq!(optional.at, None);
+            assert!(serde_json::from_value::<Required>(json!({ "at": garbage })).is_err());
+        }
+        let optional: Optional = serde_json::from_value(json!({ "at": null })).unwrap();
+        assert_eq!(optional.at, None);
+        let missing: Optional = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(missing.at, None);
+    }
+
+    #[test]
+    fn written_timestamps_read_back() {
+        let at = utc("2024-03-01T05:00:00.250Z");
+        let written = serde_json::to_value(at).unwrap();
+        let read: Required = serde_json::from_value(json!({ "at": written })).unwrap();
+        assert_eq!(read.at, at);
+    }
+}
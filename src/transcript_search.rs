@@ -0,0 +1,95 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+
+use crate::db::RunRecord;
+use crate::redact::redact;
+
+// Matches collected per search; the rest are only counted
+const MAX_MATCHES: usize = 200;
+// Longest line shown; transcripts can hold very long progress-bar lines
+const MAX_LINE_CHARS: usize = 200;
+
+/// A transcript line that matched, with the start of the run it is from (lines themselves
+/// carry no time). `line_number` 0 is the run's recorded error.
+pub struct Match {
+    pub run_started: DateTime<Utc>,
+    pub run_id: Option<String>,
+    pub line_number: usize,
+    pub line: String,
+}
+
+#[derive(Default)]
+pub struct SearchResult {
+    pub matches: Vec<Match>,
+    /// Matches beyond `MAX_MATCHES`
+    pub more: usize,
+    pub runs_searched: usize,
+    /// Runs whose transcript was never saved or has been pruned since
+    pub missing: usize,
+}
+
+/// The run ID from a transcript file name (`<time>_<account>_<run id>.log`).
+fn run_id_of(path: &str) -> Option<String> {
+    let stem = Path::new(path).file_stem()?.to_string_lossy().into_owned();
+    stem.rsplit('_').next().filter(|id| *id != "no-run-id" && !id.is_empty()).map(|id| id.to_string())
+}
+
+/// Greps the saved transcripts of `runs`, newest run first. Files are read line by line, so
+/// long sessions are never loaded whole. Runs without a transcript file fall back to their
+/// recorded error.
+pub fn search(runs: &[RunRecord], pattern: &Regex) -> SearchResult {
+    let mut result = SearchResult::default();
+    for run in runs.iter().rev() {
+        let run_started = run.started_at;
+        result.runs_searched += 1;
+        let file = run.detail.transcript_path.as_deref().and_then(|path| File::open(path).ok().map(|f| (path, f)));
+        match file {
+            Some((path, file)) => {
+                let run_id = run_id_of(path);
+                for (i, line) in BufReader::new(file).split(b'\n').enumerate() {
+                    let Ok(line) = line else { break };
+                    let line = String::from_utf8_lossy(&line);
+                    if pattern.is_match(&line) {
+                        result.add(Match { run_started, run_id: run_id.clone(), line_number: i + 1, line: line.into_owned() });
+                    }
+                }
+            }
+            None => {
+                result.missing += 1;
+                if let Some(error) = run.error.as_ref().filter(|e| pattern.is_match(e)) {
+                    result.add(Match { run_started, run_id: None, line_number: 0, line: error.clone() });
+                }
+            }
+        }
+    }
+    result
+}
+
+impl SearchResult {
+    fn add(&mut self, mut found: Match) {
+        if self.matches.len() >= MAX_MATCHES {
+            self.more += 1;
+            return;
+        }
+        let line = redact(found.line.trim());
+        found.line = if line.chars().count() > MAX_LINE_CHARS {
+            format!("{}…", line.chars().take(MAX_LINE_CHARS).collect::<String>())
+        } else {
+            line
+        };
+        self.matches.push(found);
+    }
+}
+
+/// Case-insensitive, like the failure patterns.
+pub fn compile(pattern: &str) -> Result<Regex, String> {
+    regex::RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(1 << 20)
+        .build()
+        .map_err(|e| format!("Invalid pattern: {}", e))
+}
@@ -0,0 +1,135 @@
+//! Runs `--run all` of a `--features sim` build against a throwaway database, with
+//! `EVERTEXT_SIM_FAILURES` making sessions fail, and checks what the queue did with each account.
+#![cfg(feature = "sim")]
+
+use std::path::PathBuf;
+use std::process::Command;
+
+const EXIT_OK: i32 = 0;
+const EXIT_RUN_FAILED: i32 = 1;
+
+struct Outcome {
+    code: i32,
+    log: String,
+    // (name, status) of every account after the run
+    statuses: Vec<(String, String)>,
+}
+
+impl Outcome {
+    fn status(&self, name: &str) -> &str {
+        self.statuses.iter().find(|(n, _)| n == name).map(|(_, s)| s.as_str()).unwrap_or_else(|| panic!("no account {}", name))
+    }
+}
+
+/// Runs `--run all` over fresh `accounts` with the given failure rules. Inline retries are off and
+/// one requeue is allowed, so each outcome is reached within a couple of sessions.
+fn run_all(test: &str, accounts: &[&str], failures: &str) -> Outcome {
+    let accounts: Vec<(&str, Option<&str>)> = accounts.iter().map(|name| (*name, None)).collect();
+    run_batch(test, &accounts, Some("sim-cookie"), failures)
+}
+
+/// `run_all` with a cookie of its own per account (`None` for the shared one) and an optional
+/// shared cookie.
+fn run_batch(test: &str, accounts: &[(&str, Option<&str>)], shared_cookie: Option<&str>, failures: &str) -> Outcome {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(test);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let db_path = dir.join("db.json");
+    let accounts: Vec<String> = accounts.iter()
+        .map(|(name, cookie)| {
+            let cookie = cookie.map(|c| format!(r#","cookieOverride":"{}""#, c)).unwrap_or_default();
+            format!(r#"{{"name":"{}","code":"sim-restore-code","pingEnabled":false,"status":"pending"{}}}"#, name, cookie)
+        })
+        .collect();
+    let shared_cookie = shared_cookie.map(|c| format!(r#""{}""#, c)).unwrap_or_else(|| "null".to_string());
+    std::fs::write(&db_path, format!(
+        r#"{{"accounts":[{}],"settings":{{"cookies":{},"logChannelId":null,"muteBotMessages":null}}}}"#,
+        accounts.join(","), shared_cookie
+    )).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_evertext_bot_rust"))
+        .arg("--run").arg("all")
+        .current_dir(&dir)
+        .env("DATABASE_PATH", &db_path)
+        .env("CONFIG_PATH", dir.join("config.toml"))
+        .env("EVERTEXT_SIM_FAILURES", failures)
+        .env("EVERTEXT_SIM_DELAY_MS", "10")
+        .env("EVERTEXT_INLINE_RETRIES", "0")
+        .env("EVERTEXT_MAX_REQUEUES", "1")
+        .env_remove("EVERTEXT_SIM")
+        .env_remove("ENCRYPTION_KEY")
+        .env_remove("RUST_BACKTRACE")
+        .output()
+        .unwrap();
+
+    let db: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&db_path).unwrap()).unwrap();
+    let statuses = db["accounts"].as_array().unwrap().iter()
+        .map(|a| (a["name"].as_str().unwrap().to_string(), a["status"].as_str().unwrap().to_string()))
+        .collect();
+    Outcome {
+        code: output.status.code().unwrap_or(-1),
+        log: format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr)),
+        statuses,
+    }
+}
+
+#[test]
+fn transient_failure_is_requeued_and_finishes_after_the_others() {
+    let outcome = run_all("requeue", &["alt1", "alt2"], "alt1=SERVER_DISCONNECT*1");
+
+    assert_eq!(outcome.code, EXIT_OK, "{}", outcome.log);
+    assert_eq!(outcome.status("alt1"), "done");
+    assert_eq!(outcome.status("alt2"), "done");
+    let requeued = outcome.log.find("alt1 requeued after 1 attempts").expect(&outcome.log);
+    let alt2_done = outcome.log.find("alt2 completed").expect(&outcome.log);
+    let retried = outcome.log.find("Running account: alt1 (attempt 2)").expect(&outcome.log);
+    assert!(requeued < alt2_done && alt2_done < retried, "alt1 should run again after alt2:\n{}", outcome.log);
+}
+
+#[test]
+fn account_is_given_up_on_once_its_requeues_are_used() {
+    let outcome = run_all("give_up", &["alt1", "alt2"], "alt1=SERVER_DISCONNECT");
+
+    assert_eq!(outcome.code, EXIT_RUN_FAILED, "{}", outcome.log);
+    assert_eq!(outcome.status("alt1"), "error: SERVER_DISCONNECT (gave up after 2 attempts)");
+    assert_eq!(outcome.status("alt2"), "done");
+    assert!(!outcome.log.contains("alt1 (attempt 3)"), "{}", outcome.log);
+}
+
+#[test]
+fn invalid_command_restarts_count_against_the_retry_budget() {
+    let outcome = run_all("invalid_command", &["alt1"], "alt1=INVALID_COMMAND_RESTART");
+
+    assert_eq!(outcome.code, EXIT_RUN_FAILED, "{}", outcome.log);
+    assert_eq!(outcome.status("alt1"), "error: INVALID_COMMAND_RESTART (gave up after 2 attempts)");
+}
+
+#[test]
+fn account_failing_for_good_is_not_retried() {
+    let outcome = run_all("permanent", &["alt1"], "alt1=LOGIN_REQUIRED");
+
+    assert_eq!(outcome.code, EXIT_RUN_FAILED, "{}", outcome.log);
+    assert_eq!(outcome.status("alt1"), "error: LOGIN_REQUIRED");
+    assert!(!outcome.log.contains("requeued"), "{}", outcome.log);
+}
+
+#[test]
+fn panicking_session_fails_only_its_account() {
+    let outcome = run_all("panic", &["alt1", "alt2", "alt3"], "alt2=PANIC");
+
+    assert_eq!(outcome.code, EXIT_RUN_FAILED, "{}", outcome.log);
+    assert_eq!(outcome.status("alt1"), "done");
+    assert_eq!(outcome.status("alt2"), "error: bot crashed during the run");
+    assert_eq!(outcome.status("alt3"), "done");
+    assert!(outcome.log.contains("alt2 crashed"), "{}", outcome.log);
+    assert!(outcome.log.contains("Finished: 2 succeeded, 1 failed."), "{}", outcome.log);
+}
+
+#[test]
+fn account_without_a_cookie_is_skipped_and_the_rest_still_run() {
+    let outcome = run_batch("no_cookie", &[("shared", None), ("own", Some("own-cookie"))], None, "");
+
+    assert_eq!(outcome.code, EXIT_RUN_FAILED, "{}", outcome.log);
+    assert_eq!(outcome.status("shared"), "error: no session cookie");
+    assert_eq!(outcome.status("own"), "done");
+}